@@ -0,0 +1,33 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Makes an FST file that was left behind by a crashed writer readable again,
+// keeping all value change blocks that made it to disk.
+
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(name = "fstrecover")]
+#[command(author = "Kevin Laeufer <laeufer@cornell.edu>")]
+#[command(version)]
+#[command(about = "Repairs an unfinished or truncated FST file in place.", long_about = None)]
+struct Args {
+    #[arg(value_name = "FSTFILE", index = 1)]
+    fst_file: std::path::PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+    let recovery = fst_writer::recover_fst(&args.fst_file).expect("failed to recover file");
+    println!(
+        "recovered {} value change blocks up to time {}",
+        recovery.value_change_blocks, recovery.end_time
+    );
+    if recovery.truncated_bytes > 0 {
+        println!(
+            "removed {} bytes of incomplete data",
+            recovery.truncated_bytes
+        );
+    }
+}
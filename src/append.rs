@@ -0,0 +1,104 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Recovers the state of an existing file, so that further value change blocks can be appended.
+
+use crate::io::{HeaderFinishInfo, invalid_file, read_header};
+use crate::{FstSignalType, Result};
+use fst_reader::{FstFilter, FstHierarchyEntry, FstReader, FstSignalValue, FstVarType};
+use std::io::BufReader;
+use std::path::Path;
+
+/// Everything the writer needs to continue after the last time step of a file.
+pub(crate) struct ExistingFile {
+    pub(crate) info: HeaderFinishInfo,
+    /// type of every signal, indexed by handle
+    pub(crate) signals: Vec<FstSignalType>,
+    /// value of every signal at the last time step, in the layout of the signal buffer
+    pub(crate) values: Vec<u8>,
+}
+
+pub(crate) fn read_existing(path: &Path) -> Result<ExistingFile> {
+    let mut input = BufReader::new(std::fs::File::open(path)?);
+    let info = read_header(&mut input)?;
+    let mut reader = FstReader::open(input).map_err(invalid_file)?;
+
+    let mut signals = vec![None; info.num_signals as usize];
+    let mut unknown_handle = None;
+    reader
+        .read_hierarchy(|entry| {
+            if let FstHierarchyEntry::Var {
+                tpe,
+                length,
+                handle,
+                is_alias: false,
+                ..
+            } = entry
+            {
+                let is_real = matches!(
+                    tpe,
+                    FstVarType::Real
+                        | FstVarType::RealParameter
+                        | FstVarType::RealTime
+                        | FstVarType::ShortReal
+                );
+                let tpe = if is_real {
+                    FstSignalType::real()
+                } else {
+                    FstSignalType::bit_vec(length)
+                };
+                match signals.get_mut(handle.get_index()) {
+                    Some(signal) => *signal = Some(tpe),
+                    None => unknown_handle = Some(handle.get_index()),
+                }
+            }
+        })
+        .map_err(invalid_file)?;
+    if let Some(index) = unknown_handle {
+        return Err(invalid_file(format!(
+            "var refers to unknown signal {}",
+            index + 1
+        )));
+    }
+    let signals = signals
+        .into_iter()
+        .enumerate()
+        .map(|(index, signal)| {
+            signal.ok_or_else(|| invalid_file(format!("no var declares signal {}", index + 1)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // the last change of every signal is its current value
+    let mut offsets = Vec::with_capacity(signals.len());
+    let mut len = 0;
+    for signal in &signals {
+        offsets.push(len);
+        len += signal.len() as usize;
+    }
+    let mut values = vec![b'x'; len];
+    reader
+        .read_signals(&FstFilter::all(), |_, handle, value| {
+            let index = handle.get_index();
+            let start = offsets[index];
+            let real;
+            let value = match value {
+                FstSignalValue::String(value) => value,
+                FstSignalValue::Real(value) => {
+                    real = value.to_le_bytes();
+                    &real[..]
+                }
+            };
+            let end = start + signals[index].len() as usize;
+            if value.len() == end - start {
+                values[start..end].copy_from_slice(value);
+            }
+        })
+        .map_err(invalid_file)?;
+
+    Ok(ExistingFile {
+        info,
+        signals,
+        values,
+    })
+}
@@ -0,0 +1,178 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Writes FST files to tokio outputs. Blocks are encoded in memory and then handed to the
+// output asynchronously.
+
+use crate::{FstBodyWriter, FstHeaderWriter, FstInfo, FstSignalId, Result};
+use std::io::{Seek, SeekFrom, Write};
+use tokio::io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// In-memory output of a writer created with [`crate::open_fst_async`]. Keeps all data that
+/// was not handed to the asynchronous output yet, as well as updates to data that was.
+#[derive(Default)]
+pub struct FstAsyncBuffer {
+    /// data that was not written to the output yet
+    buf: Vec<u8>,
+    /// offset of the first byte in `buf`
+    start: u64,
+    pos: u64,
+    /// updates of data that was already written, e.g., of the header
+    patches: Vec<(u64, Vec<u8>)>,
+}
+
+impl FstAsyncBuffer {
+    /// Hands all data to `out`.
+    async fn drain<W: AsyncWrite + AsyncSeek + Unpin>(&mut self, out: &mut W) -> Result<()> {
+        let patched = !self.patches.is_empty();
+        for (offset, data) in self.patches.drain(..) {
+            out.seek(SeekFrom::Start(offset)).await?;
+            out.write_all(&data).await?;
+        }
+        if patched {
+            out.seek(SeekFrom::Start(self.start)).await?;
+        }
+        out.write_all(&self.buf).await?;
+        self.start += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl Write for FstAsyncBuffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let (patch, rest) = if self.pos < self.start {
+            data.split_at(data.len().min((self.start - self.pos) as usize))
+        } else {
+            (&[][..], data)
+        };
+        if !patch.is_empty() {
+            self.patches.push((self.pos, patch.to_vec()));
+            self.pos += patch.len() as u64;
+        }
+        if rest.is_empty() {
+            return Ok(data.len());
+        }
+        let offset = (self.pos - self.start) as usize;
+        if offset > self.buf.len() {
+            self.buf.resize(offset, 0);
+        }
+        let overlap = rest.len().min(self.buf.len() - offset);
+        self.buf[offset..offset + overlap].copy_from_slice(&rest[..overlap]);
+        self.buf.extend_from_slice(&rest[overlap..]);
+        self.pos += rest.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for FstAsyncBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let end = self.start + self.buf.len() as u64;
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => end.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Declares the hierarchy of a file that is written to an asynchronous output, which is passed
+/// to [`FstHeaderWriter::finish_async`].
+pub fn open_fst_async(info: &FstInfo) -> Result<FstHeaderWriter<FstAsyncBuffer>> {
+    FstHeaderWriter::from_writer(FstAsyncBuffer::default(), info)
+}
+
+impl FstHeaderWriter<FstAsyncBuffer> {
+    /// Starts writing value changes that are handed to `out` asynchronously.
+    pub fn finish_async<W: AsyncWrite + AsyncSeek + Unpin>(
+        self,
+        out: W,
+    ) -> Result<AsyncFstBodyWriter<W>> {
+        Ok(AsyncFstBodyWriter {
+            inner: self.finish()?,
+            out,
+        })
+    }
+}
+
+/// Records value changes like [`FstBodyWriter`], but writes to an asynchronous output.
+/// Blocks are encoded in memory and only handed to the output by [`AsyncFstBodyWriter::flush`]
+/// and [`AsyncFstBodyWriter::finish`], including blocks that were started automatically.
+pub struct AsyncFstBodyWriter<W: AsyncWrite + AsyncSeek + Unpin> {
+    inner: FstBodyWriter<FstAsyncBuffer>,
+    out: W,
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> AsyncFstBodyWriter<W> {
+    pub fn time_change(&mut self, time: u64) -> Result<()> {
+        self.inner.time_change(time)
+    }
+
+    pub fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
+        self.inner.signal_change(signal_id, value)
+    }
+
+    /// Gives access to all other functions of the writer. Data that they write is handed to
+    /// the output by the next [`AsyncFstBodyWriter::flush`].
+    pub fn inner(&mut self) -> &mut FstBodyWriter<FstAsyncBuffer> {
+        &mut self.inner
+    }
+
+    /// Returns the number of bytes held in memory.
+    pub fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    /// Writes all value changes recorded so far as a block to the output.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        self.inner.out()?.drain(&mut self.out).await?;
+        self.out.flush().await?;
+        Ok(())
+    }
+
+    /// Finishes the file and returns the output.
+    pub async fn finish(self) -> Result<W> {
+        let Self { inner, mut out } = self;
+        let mut buffer = inner.finish()?;
+        buffer.drain(&mut out).await?;
+        out.flush().await?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_written_data() {
+        let mut buffer = FstAsyncBuffer::default();
+        buffer.write_all(b"hello").unwrap();
+        let mut out = std::io::Cursor::new(vec![]);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(buffer.drain(&mut out)).unwrap();
+        buffer.write_all(b" world").unwrap();
+        // straddles written and buffered data
+        buffer.seek(SeekFrom::Start(4)).unwrap();
+        buffer.write_all(b"O W").unwrap();
+        buffer.seek(SeekFrom::End(0)).unwrap();
+        buffer.write_all(b"!").unwrap();
+        rt.block_on(buffer.drain(&mut out)).unwrap();
+        assert_eq!(out.into_inner(), b"hellO World!");
+    }
+}
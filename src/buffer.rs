@@ -2,35 +2,83 @@
 // released under BSD 3-Clause License
 // author: Kevin Laeufer <laeufer@cornell.edu>
 
+#[cfg(test)]
+use crate::io::read_variant_u64;
 use crate::io::{
-    write_multi_bit_signal, write_one_bit_signal, write_time_chain_update,
-    write_value_change_section, write_variant_u64,
+    MAX_VARIANT_LEN, checked_usize, encode_variant_u64, one_bit_signal_vli, write_multi_bit_signal,
+    write_time_chain_update, write_value_change_section, write_variable_length_signal,
+};
+use crate::spill::Spill;
+use crate::stats::{FstBlockStats, FstSizeBreakdown, FstWriterStats, timed};
+use crate::{
+    FstCompressionLevels, FstDecimation, FstPackType, FstSignalId, FstSignalType, FstWriteError,
+    Result,
 };
-use crate::{FstSignalId, FstSignalType, FstWriteError, Result};
-use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::io::{Seek, Write};
+use std::ops::Range;
+
+/// Size of the value change buffer that is compressed at once when compression is
+/// enabled, see [`crate::FstWriterConfig::compress_buffer`].
+const COMPRESSED_SEGMENT_SIZE: usize = 1 << 20;
 
 /// keeps track of signal values before writing them to disk
 pub(crate) struct SignalBuffer {
-    start_time: u64,
-    end_time: u64,
     /// constant signal meta-data
     signals: Vec<SignalInfo>,
-    /// time table index of the previous change for each signal
-    prev_time_table_index: Box<[u32]>,
-    /// values for all signals in the first time step of this block
-    frame: Box<[u8]>,
-    /// copy of the frame with all value changes applied
-    values: Box<[u8]>,
-    value_changes: SingleVecLists,
-    /// contains the delta encoded and compressed timetable
-    time_table: Vec<u8>,
-    time_table_index: u32,
+    /// changes of disabled signals are ignored
+    enabled: Vec<bool>,
+    /// only contains the few signals that are decimated
+    decimation: HashMap<usize, DecimationState>,
+    /// current value of all signals
+    values: Vec<u8>,
+    /// value changes are moved to `spill` once `value_changes` grows beyond this size
+    spill_threshold: Option<usize>,
+    /// compress value changes when moving them to `spill`
+    compress_spill: bool,
+    /// otherwise, `spill` keeps the compressed value changes in memory
+    spill_to_file: bool,
     /// keep a vec allocation around for encoding signals
     write_buf: Vec<u8>,
+    /// keep a vec allocation around for extending values that are shorter than their signal
+    expand_buf: Vec<u8>,
     /// is this the first buffer for the file that we are writing?
     first_buffer: bool,
+    /// values of signals without a fixed length before the first time step, which cannot be
+    /// part of the frame, see [`SignalBuffer::record_first_time_step`]
+    initial_variable_length: Vec<(FstSignalId, Vec<u8>)>,
+    /// the block that new time steps are recorded into
+    block: Block,
+    /// empty block that replaces `block` once it is taken for writing
+    spare: Option<Block>,
+}
+
+/// All value changes of a single value change block.
+pub(crate) struct Block {
+    start_time: u64,
+    end_time: u64,
+    /// values for all signals in the first time step of this block
+    frame: Vec<u8>,
+    /// time table index of the previous change for each signal
+    prev_time_table_index: Vec<u32>,
+    value_changes: ChunkedLists,
+    /// signals with at least one entry in `value_changes` or `spill`, may contain duplicates
+    changed: Vec<u32>,
+    spill: Option<Spill>,
+    /// contains the delta encoded and compressed timetable
+    time_table: Vec<u8>,
+    time_table_index: u32,
+    /// first time step in `time_table`
+    first_time: Option<u64>,
+    /// number of value changes recorded in this block
+    num_changes: usize,
+    /// number of value changes of every signal, only valid for signals in `changed`
+    signal_changes: Vec<u32>,
+    /// reused to stage the encoded block before writing it
+    stage: Vec<u8>,
+    pack_type: FstPackType,
+    compression: FstCompressionLevels,
 }
 
 #[derive(Debug, Clone)]
@@ -41,65 +89,289 @@ struct SignalInfo {
     offset: u32,
 }
 
-fn gen_signal_info(signals: &[FstSignalType]) -> (Vec<SignalInfo>, usize) {
-    let mut offset = 0;
+#[derive(Clone)]
+struct DecimationState {
+    decimation: FstDecimation,
+    /// changes seen since the last recorded change, used by `EveryNth`
+    changes: u32,
+    /// time of the last recorded change
+    last_time: Option<u64>,
+}
+
+impl DecimationState {
+    /// Returns whether a change at `time` should be recorded.
+    fn record(&mut self, time: u64) -> bool {
+        let record = match self.decimation {
+            FstDecimation::EveryNth(n) => {
+                let record = self.changes == 0;
+                self.changes = (self.changes + 1) % n.max(1);
+                record
+            }
+            FstDecimation::MinTimeDelta(delta) => self
+                .last_time
+                .is_none_or(|last| time >= last.saturating_add(delta)),
+        };
+        if record {
+            self.last_time = Some(time);
+        }
+        record
+    }
+}
+
+fn gen_signal_info(signals: &[FstSignalType]) -> Result<(Vec<SignalInfo>, usize)> {
+    let mut offset = 0u32;
     let mut out = Vec::with_capacity(signals.len());
     for signal in signals {
         out.push(SignalInfo {
             len: signal.len(),
             offset,
         });
-        offset += signal.len();
+        offset = next_offset(offset, signal.len())?;
     }
-    (out, offset as usize)
+    Ok((out, offset as usize))
+}
+
+/// Offset of the value that follows a value of `len` bytes at `offset`.
+fn next_offset(offset: u32, len: u32) -> Result<u32> {
+    offset.checked_add(len).ok_or(FstWriteError::SignalsTooWide)
 }
 
 impl SignalBuffer {
-    pub(crate) fn new(signals: &[FstSignalType]) -> Result<Self> {
-        let (signals, values_len) = gen_signal_info(signals);
-        let value_changes = SingleVecLists::new(signals.len());
-        let values = vec![b'x'; values_len].into_boxed_slice();
-        let frame = values.clone();
-        let prev_time_table_index = vec![0; signals.len()].into_boxed_slice();
-        let time_table = Vec::with_capacity(16);
+    pub(crate) fn new(
+        signals: &[FstSignalType],
+        pack_type: FstPackType,
+        compression: FstCompressionLevels,
+        spill_threshold: Option<usize>,
+        compress: bool,
+    ) -> Result<Self> {
+        let (signals, values_len) = gen_signal_info(signals)?;
+        // a vector cannot hold more, which matters on 32-bit targets
+        isize::try_from(values_len)
+            .map_err(|_| FstWriteError::TooLarge("the values of all signals"))?;
+        let values = vec![b'x'; values_len];
+        let enabled = vec![true; signals.len()];
+        let block = Block::new(signals.len(), &values, pack_type, compression);
         Ok(Self {
-            start_time: 0,
-            end_time: 0,
             signals,
-            prev_time_table_index,
-            frame,
+            enabled,
+            decimation: HashMap::new(),
             values,
-            value_changes,
-            time_table,
-            time_table_index: 0,
+            // without a file to spill to, compressed changes are kept in memory
+            spill_threshold: spill_threshold.or(compress.then_some(COMPRESSED_SEGMENT_SIZE)),
+            compress_spill: compress,
+            spill_to_file: spill_threshold.is_some(),
             write_buf: vec![],
+            expand_buf: vec![],
             first_buffer: true,
+            initial_variable_length: vec![],
+            block,
+            spare: None,
         })
     }
 
+    /// Creates an empty buffer for the signals in `range` that continues with their current
+    /// values. Signal indices in the new buffer start at zero.
+    pub(crate) fn shard(&self, range: Range<usize>) -> Self {
+        let first_value = self.signals.get(range.start).map_or(0, |s| s.offset);
+        let signals: Vec<_> = self.signals[range.clone()]
+            .iter()
+            .map(|s| SignalInfo {
+                len: s.len,
+                offset: s.offset - first_value,
+            })
+            .collect();
+        let values_len = signals.iter().map(|s| s.len as usize).sum::<usize>();
+        let first_value = first_value as usize;
+        let values = self.values[first_value..first_value + values_len].to_vec();
+        let decimation = self
+            .decimation
+            .iter()
+            .filter(|(idx, _)| range.contains(idx))
+            .map(|(idx, state)| (idx - range.start, state.clone()))
+            .collect();
+        let mut block = Block::new(
+            signals.len(),
+            &values,
+            self.block.pack_type,
+            self.block.compression,
+        );
+        block.start_time = self.block.end_time;
+        block.end_time = self.block.end_time;
+        Self {
+            signals,
+            enabled: self.enabled[range].to_vec(),
+            decimation,
+            values,
+            spill_threshold: self.spill_threshold,
+            compress_spill: self.compress_spill,
+            spill_to_file: self.spill_to_file,
+            write_buf: vec![],
+            expand_buf: vec![],
+            first_buffer: self.first_buffer,
+            initial_variable_length: vec![],
+            block,
+            spare: None,
+        }
+    }
+
+    /// Changes before the first time step set the initial values, like for a new file.
+    #[cfg(feature = "fs")]
+    pub(crate) fn set_first_buffer(&mut self) {
+        self.first_buffer = true;
+    }
+
+    /// Continues after the last time step `time` of an existing file, at which the signals
+    /// had the given `values`.
+    #[cfg(feature = "append")]
+    pub(crate) fn resume(&mut self, values: &[u8], time: u64) {
+        self.values.copy_from_slice(values);
+        self.block.frame.copy_from_slice(values);
+        self.block.start_time = time;
+        self.block.end_time = time;
+        self.first_buffer = false;
+    }
+
+    /// Adds a signal that was declared after the buffer was created.
+    /// Its value is unknown (`x`) until the first change.
+    pub(crate) fn add_signal(&mut self, signal: FstSignalType) -> Result<()> {
+        let len = signal.len();
+        let offset = self.values.len() as u32;
+        next_offset(offset, len)?;
+        self.signals.push(SignalInfo { len, offset });
+        self.enabled.push(true);
+        self.values.resize(self.values.len() + len as usize, b'x');
+        self.block.add_signal(len);
+        if let Some(spare) = &mut self.spare {
+            spare.add_signal(len);
+        }
+        Ok(())
+    }
+
     pub(crate) fn time_change(&mut self, new_time: u64) -> Result<()> {
-        match new_time.cmp(&self.end_time) {
-            Ordering::Less => Err(FstWriteError::TimeDecrease(self.end_time, new_time)),
+        if new_time > self.block.end_time && !self.initial_variable_length.is_empty() {
+            self.record_first_time_step()?;
+        }
+        let block = &mut self.block;
+        match new_time.cmp(&block.end_time) {
+            Ordering::Less => Err(FstWriteError::TimeDecrease(block.end_time, new_time)),
             Ordering::Equal => Ok(()),
             Ordering::Greater => {
-                let first_time_step = self.time_table.is_empty();
+                let first_time_step = block.time_table.is_empty();
+                // the frame already contains the values of the first step
                 if first_time_step {
-                    // at the end of the first step, we copy values over into the frame
-                    self.frame = self.values.clone();
+                    block.first_time = Some(new_time);
                 } else {
                     // the first step is not captured in the time table, but instead in the start_time
-                    self.time_table_index += 1;
+                    block.time_table_index = block
+                        .time_table_index
+                        .checked_add(1)
+                        .ok_or(FstWriteError::TooManyTimeSteps)?;
                 }
-                debug_assert!(self.start_time <= self.end_time);
+                debug_assert!(block.start_time <= block.end_time);
 
                 // in the first step, the time needs to be written relative to 0
-                let delta_to = if first_time_step { 0 } else { self.end_time };
+                let delta_to = if first_time_step { 0 } else { block.end_time };
                 // write timetable in compressed format
-                write_time_chain_update(&mut self.time_table, delta_to, new_time)?;
-                self.end_time = new_time;
+                write_time_chain_update(&mut block.time_table, delta_to, new_time)?;
+                block.end_time = new_time;
+                Ok(())
+            }
+        }
+    }
+
+    /// True if there were no time steps since the last flush.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.block.time_table.is_empty() && self.initial_variable_length.is_empty()
+    }
+
+    pub(crate) fn num_signals(&self) -> usize {
+        self.signals.len()
+    }
+
+    /// True if the time table cannot take another time step without a flush.
+    pub(crate) fn is_full(&self) -> bool {
+        self.block.time_table_index == u32::MAX
+    }
+
+    /// The most recent time step.
+    pub(crate) fn time(&self) -> u64 {
+        self.block.end_time
+    }
+
+    /// The first time step since the last flush.
+    /// Start time of the current block, i.e., the end time of the previous one.
+    #[cfg(feature = "fs")]
+    pub(crate) fn start_time(&self) -> u64 {
+        self.block.start_time
+    }
+
+    pub(crate) fn block_start_time(&self) -> Option<u64> {
+        self.block.first_time
+    }
+
+    /// Number of value changes recorded since the last flush.
+    pub(crate) fn num_changes(&self) -> usize {
+        self.block.num_changes
+    }
+
+    pub(crate) fn set_signal_enabled(
+        &mut self,
+        signal_id: FstSignalId,
+        enabled: bool,
+    ) -> Result<()> {
+        match self.enabled.get_mut(signal_id.to_array_index()) {
+            Some(value) => {
+                *value = enabled;
                 Ok(())
             }
+            None => Err(FstWriteError::InvalidSignalId(signal_id)),
+        }
+    }
+
+    pub(crate) fn set_signal_decimation(
+        &mut self,
+        signal_id: FstSignalId,
+        decimation: Option<FstDecimation>,
+    ) -> Result<()> {
+        let index = signal_id.to_array_index();
+        if index >= self.signals.len() {
+            return Err(FstWriteError::InvalidSignalId(signal_id));
         }
+        match decimation {
+            Some(decimation) => {
+                let state = DecimationState {
+                    decimation,
+                    changes: 0,
+                    last_time: None,
+                };
+                self.decimation.insert(index, state);
+            }
+            None => {
+                self.decimation.remove(&index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Upper bound for the bytes that [`SignalBuffer::size`] grows by when recording a change
+    /// of the signal to a value of `value_len` characters. Unknown signals take up nothing.
+    pub(crate) fn max_change_size(&self, signal_id: FstSignalId, value_len: usize) -> usize {
+        let index = signal_id.to_array_index();
+        let Some(info) = self.signals.get(index) else {
+            return 0;
+        };
+        // time delta followed by the value, which is never longer than its characters
+        let encoded = match info.len {
+            // values without a fixed length are preceded by their length
+            0 => 2 * MAX_VARIANT_LEN + value_len,
+            len => MAX_VARIANT_LEN + len as usize,
+        };
+        let changed = if self.block.value_changes.is_empty(index) {
+            std::mem::size_of::<u32>()
+        } else {
+            0
+        };
+        self.block.value_changes.max_growth(index, encoded) + changed
     }
 
     pub(crate) fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
@@ -107,58 +379,309 @@ impl SignalBuffer {
             Some(info) => info,
             None => return Err(FstWriteError::InvalidSignalId(signal_id)),
         };
+        if !self.enabled[signal_id.to_array_index()] {
+            return Ok(());
+        }
         let len = info.len as usize;
         let start = info.offset as usize;
         let range = start..start + len;
-        let value_cow = if value.len() == len {
-            Cow::Borrowed(value)
+        // signals of length zero take values of any length, which are not part of the frame
+        let variable_length = len == 0;
+        if variable_length && self.first_buffer && self.block.time_table.is_empty() {
+            self.initial_variable_length
+                .push((signal_id, value.to_vec()));
+            return Ok(());
+        }
+        let value = if value.len() == len || variable_length {
+            value
         } else {
-            let expanded = expand_special_vector_cases(value, len).unwrap_or_else(|| {
-                panic!(
-                    "Failed to parse four state value: {} for signal of size {}",
-                    String::from_utf8_lossy(value),
-                    len
-                )
-            });
-            assert_eq!(expanded.len(), len);
-            Cow::Owned(expanded)
+            if !expand_special_vector_cases(value, len, &mut self.expand_buf) {
+                return Err(FstWriteError::InvalidValueLength {
+                    value: String::from_utf8_lossy(value).into_owned(),
+                    len: len as u32,
+                });
+            }
+            debug_assert_eq!(self.expand_buf.len(), len);
+            &self.expand_buf[..]
         };
-        let value = value_cow.as_ref();
-        debug_assert_eq!(value.len(), len);
-        let first_time_step = self.time_table.is_empty();
+        debug_assert!(value.len() == len || variable_length);
+        let first_time_step = self.block.time_table.is_empty();
         if first_time_step && self.first_buffer {
-            self.values[range].copy_from_slice(value);
+            self.values[range.clone()].copy_from_slice(value);
+            self.block.frame[range].copy_from_slice(value);
         } else {
-            if self.time_table.is_empty() {
-                // write_time_chain_update(&mut self.time_table, 0, self.end_time)?;
-                todo!("Currently we only support flushing right before a new time step.")
+            if self.block.time_table.is_empty() {
+                // blocks can only start with a new time step
+                return Err(FstWriteError::MissingTimeStep);
             }
 
             // check to see if there actually was a change
-            if &self.values[range.clone()] == value {
+            if !variable_length && &self.values[range.clone()] == value {
                 return Ok(());
             }
-            self.values[range].copy_from_slice(value);
-            // write down value change
-            let time_table_idx_delta = (self.time_table_index
-                - self.prev_time_table_index[signal_id.to_array_index()])
-                as u64;
-            self.write_buf.clear();
-            match value {
-                [value] => write_one_bit_signal(&mut self.write_buf, time_table_idx_delta, *value)?,
-                values => {
-                    write_multi_bit_signal(&mut self.write_buf, time_table_idx_delta, values)?
+            if !self.decimation.is_empty() {
+                if let Some(state) = self.decimation.get_mut(&signal_id.to_array_index()) {
+                    if !state.record(self.block.end_time) {
+                        return Ok(());
+                    }
                 }
             }
-            self.value_changes
-                .append(signal_id.to_array_index(), &self.write_buf, None);
+            let index = signal_id.to_array_index();
+            self.block
+                .record_change(&mut self.write_buf, index, variable_length, value)?;
+            // only once the change was recorded
+            if !variable_length {
+                self.values[range].copy_from_slice(value);
+            }
+            self.spill_if_needed()?;
+        }
+        Ok(())
+    }
+
+    /// Adds the current time to the time table, so that values without a fixed length can be
+    /// recorded before the first time step. fst-reader only reads the frame of the first block
+    /// if its time table starts after the start of the block, thus the values of all other
+    /// signals are recorded as changes as well.
+    fn record_first_time_step(&mut self) -> Result<()> {
+        let time = self.block.end_time;
+        write_time_chain_update(&mut self.block.time_table, 0, time)?;
+        self.block.first_time = Some(time);
+        let initial = std::mem::take(&mut self.initial_variable_length);
+        let values = std::mem::take(&mut self.values);
+        let recorded = self
+            .signals
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, info)| {
+                let range = info.offset as usize..(info.offset + info.len) as usize;
+                if range.is_empty() {
+                    return Ok(());
+                }
+                self.block
+                    .record_change(&mut self.write_buf, index, false, &values[range])
+            });
+        self.values = values;
+        recorded?;
+        self.spill_if_needed()?;
+        for (signal_id, value) in initial {
+            self.signal_change(signal_id, &value)?;
+        }
+        Ok(())
+    }
+
+    fn spill_if_needed(&mut self) -> Result<()> {
+        match self.spill_threshold {
+            Some(threshold) if self.block.value_changes.size() > threshold => self.spill(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves all buffered value changes into the spill file.
+    fn spill(&mut self) -> Result<()> {
+        let block = &mut self.block;
+        block.changed.sort_unstable();
+        block.changed.dedup();
+        let spill = match &mut block.spill {
+            Some(spill) => spill,
+            #[cfg(feature = "fs")]
+            None if self.spill_to_file => block.spill.insert(Spill::file(self.compress_spill)?),
+            None => block.spill.insert(Spill::memory()),
+        };
+        let value_changes = &block.value_changes;
+        spill.write(&block.changed, |signal_idx, data| {
+            value_changes.append_list_to(signal_idx, None, data)
+        })?;
+        block.value_changes.clear();
+        Ok(())
+    }
+
+    pub(crate) fn flush(
+        &mut self,
+        output: &mut (impl Write + Seek),
+        stats: &mut FstWriterStats,
+    ) -> Result<u64> {
+        let mut block = self.take_block()?;
+        let block_stats = block.write(output)?;
+        block.count_signal_changes(&mut stats.signal_changes, 0);
+        stats.blocks.push(block_stats);
+        let end_time = block.end_time;
+        self.recycle(block)?;
+        Ok(end_time)
+    }
+
+    /// Starts a new block and returns the previous one, so that it can be written while
+    /// new time steps are recorded.
+    pub(crate) fn take_block(&mut self) -> Result<Block> {
+        if !self.initial_variable_length.is_empty() {
+            self.record_first_time_step()?;
+        }
+        let mut next = match self.spare.take() {
+            Some(spare) => spare,
+            None => Block::new(
+                self.signals.len(),
+                &self.values,
+                self.block.pack_type,
+                self.block.compression,
+            ),
+        };
+        // only signals that changed in the previous block are out of date
+        self.update_frame(&mut next.frame, &self.block.changed);
+        debug_assert_eq!(next.frame, self.values);
+        next.reserve_like(&self.block);
+        next.start_time = self.block.end_time;
+        next.end_time = self.block.end_time;
+        self.write_buf.clear();
+        self.first_buffer = false;
+        Ok(std::mem::replace(&mut self.block, next))
+    }
 
-            // remember previous time-table index
-            self.prev_time_table_index[signal_id.to_array_index()] = self.time_table_index;
+    /// Keeps the allocations of a written block around for reuse.
+    pub(crate) fn recycle(&mut self, mut block: Block) -> Result<()> {
+        // blocks taken before signals were added are missing their lists
+        if block.prev_time_table_index.len() == self.signals.len() {
+            // afterwards, the frame is only missing the changes of the current block
+            self.update_frame(&mut block.frame, &block.changed);
+            block.clear()?;
+            self.spare = Some(block);
         }
         Ok(())
     }
 
+    /// Copies the current values of the `changed` signals into `frame`, which avoids copying
+    /// the values of all signals at the start of every block.
+    fn update_frame(&self, frame: &mut [u8], changed: &[u32]) {
+        for &signal_idx in changed.iter() {
+            let info = &self.signals[signal_idx as usize];
+            let range = info.offset as usize..(info.offset + info.len) as usize;
+            frame[range.clone()].copy_from_slice(&self.values[range]);
+        }
+    }
+
+    /// Returns the number of bytes needed to record the current block. Unlike
+    /// [`SignalBuffer::size_breakdown`], this does not depend on whether a block is still
+    /// written in the background, so that automatic flushes happen at the same time steps.
+    pub(crate) fn size(&self) -> usize {
+        self.recording_size().total()
+    }
+
+    fn recording_size(&self) -> FstSizeBreakdown {
+        let decimation = std::mem::size_of::<(usize, DecimationState)>();
+        let mut size = FstSizeBreakdown {
+            frames: self.values.len(),
+            fixed: self.signals.len() * std::mem::size_of::<SignalInfo>()
+                + self.enabled.len()
+                + self.decimation.len() * decimation,
+            ..Default::default()
+        };
+        self.block.add_size(&mut size);
+        size
+    }
+
+    /// Returns the number of bytes held in memory, split by their use, including the spare
+    /// block and scratch buffers.
+    pub(crate) fn size_breakdown(&self) -> FstSizeBreakdown {
+        let mut size = self.recording_size();
+        size.fixed += self.write_buf.capacity() + self.expand_buf.capacity();
+        size.fixed += self.block.stage.capacity();
+        if let Some(spare) = &self.spare {
+            spare.add_size(&mut size);
+            size.fixed += spare.stage.capacity();
+        }
+        size
+    }
+
+    /// Returns the number of bytes that were moved out of memory into the spill file.
+    pub(crate) fn spilled(&self) -> u64 {
+        match &self.block.spill {
+            Some(spill) if !spill.is_in_memory() => spill.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl Block {
+    /// Appends a change of the signal at `index`, even if it repeats the current value.
+    /// The value needs to be extended to the length of the signal already.
+    fn record_change(
+        &mut self,
+        write_buf: &mut Vec<u8>,
+        index: usize,
+        variable_length: bool,
+        value: &[u8],
+    ) -> Result<()> {
+        let time_table_idx_delta =
+            (self.time_table_index - self.prev_time_table_index[index]) as u64;
+        let first_change = self.value_changes.is_empty(index);
+        match value {
+            _ if variable_length => {
+                write_buf.clear();
+                write_variable_length_signal(write_buf, time_table_idx_delta, value)?;
+                self.value_changes.append(index, write_buf, None)?;
+            }
+            [value] => {
+                // single bit changes are encoded on the stack
+                let vli = one_bit_signal_vli(time_table_idx_delta, *value)?;
+                let (bytes, len) = encode_variant_u64(vli);
+                self.value_changes.append(index, &bytes[..len], None)?;
+            }
+            values => {
+                write_buf.clear();
+                write_multi_bit_signal(write_buf, time_table_idx_delta, values)?;
+                self.value_changes.append(index, write_buf, None)?;
+            }
+        }
+        if first_change {
+            self.changed.push(index as u32);
+        }
+        self.num_changes += 1;
+        let changes = &mut self.signal_changes[index];
+        *changes = changes.saturating_add(1);
+
+        // remember previous time-table index
+        self.prev_time_table_index[index] = self.time_table_index;
+        Ok(())
+    }
+
+    fn new(
+        num_signals: usize,
+        values: &[u8],
+        pack_type: FstPackType,
+        compression: FstCompressionLevels,
+    ) -> Self {
+        Self {
+            start_time: 0,
+            end_time: 0,
+            frame: values.to_vec(),
+            prev_time_table_index: vec![0; num_signals],
+            value_changes: ChunkedLists::new(num_signals),
+            changed: vec![],
+            spill: None,
+            time_table: Vec::with_capacity(16),
+            time_table_index: 0,
+            first_time: None,
+            num_changes: 0,
+            signal_changes: vec![0; num_signals],
+            stage: vec![],
+            pack_type,
+            compression,
+        }
+    }
+
+    /// Reserves space for as many time steps and changes as `previous` recorded, which avoids
+    /// growing the buffers step by step.
+    fn reserve_like(&mut self, previous: &Block) {
+        self.value_changes.reserve_like(&previous.value_changes);
+        self.time_table.reserve(previous.time_table.len());
+        self.changed.reserve(previous.changed.len());
+    }
+
+    fn add_signal(&mut self, len: u32) {
+        self.prev_time_table_index.push(0);
+        self.signal_changes.push(0);
+        self.frame.resize(self.frame.len() + len as usize, b'x');
+        self.value_changes.add_list();
+    }
+
     fn num_time_table_entries(&self) -> u64 {
         if self.time_table.is_empty() {
             0
@@ -167,119 +690,478 @@ impl SignalBuffer {
         }
     }
 
-    pub(crate) fn flush(&mut self, output: &mut (impl Write + Seek)) -> Result<u64> {
-        // write data
-        write_value_change_section(
-            output,
-            self.start_time,
-            self.end_time,
-            &self.frame,
-            &self.time_table,
-            self.num_time_table_entries(),
-            |signal_idx: usize| self.value_changes.extract_list(signal_idx, None),
-            self.signals.len(),
-        )?;
-
-        // reset data
+    /// Writes the value change section.
+    pub(crate) fn write(&mut self, output: &mut impl Write) -> Result<FstBlockStats> {
+        // constant signals are only encoded through the frame
+        self.changed.sort_unstable();
+        self.changed.dedup();
+        let mut stage = std::mem::take(&mut self.stage);
+        let (result, duration) = timed(|| {
+            write_value_change_section(
+                output,
+                &mut stage,
+                self.start_time,
+                self.end_time,
+                &self.frame,
+                &self.time_table,
+                self.num_time_table_entries(),
+                &self.changed,
+                |signal_idx: usize, data: &mut Vec<u8>| self.signal_data(signal_idx, data),
+                self.num_signals(),
+                self.pack_type,
+                &self.compression,
+            )
+        });
+        self.stage = stage;
+        let (bytes, uncompressed_bytes) = result?;
+        Ok(FstBlockStats {
+            start_time: self.start_time,
+            end_time: self.end_time,
+            num_changes: self.num_changes as u64,
+            bytes,
+            uncompressed_bytes,
+            duration,
+        })
+    }
+
+    /// Adds the number of value changes of every signal to `counts`, starting at
+    /// `first_signal`. Only valid once the block was written.
+    pub(crate) fn count_signal_changes(&self, counts: &mut Vec<u64>, first_signal: usize) {
+        let end = first_signal + self.num_signals();
+        if counts.len() < end {
+            counts.resize(end, 0);
+        }
+        for &signal_idx in self.changed.iter() {
+            counts[first_signal + signal_idx as usize] +=
+                self.signal_changes[signal_idx as usize] as u64;
+        }
+    }
+
+    /// Writes the blocks of all shards as a single value change section. All shards need to
+    /// have recorded the same time steps.
+    pub(crate) fn write_shards(
+        blocks: &mut [Block],
+        output: &mut impl Write,
+    ) -> Result<Option<FstBlockStats>> {
+        let Some(first) = blocks.first() else {
+            return Ok(None);
+        };
+        let same_time_steps = blocks.iter().all(|b| {
+            b.start_time == first.start_time
+                && b.end_time == first.end_time
+                && b.time_table_index == first.time_table_index
+                && b.time_table == first.time_table
+        });
+        if !same_time_steps {
+            return Err(FstWriteError::InconsistentShards);
+        }
+        // signals of later shards follow the ones of earlier shards
+        let mut first_signal = Vec::with_capacity(blocks.len());
+        let mut num_signals = 0;
+        let mut frame = vec![];
+        let mut changed = vec![];
+        for block in blocks.iter_mut() {
+            block.changed.sort_unstable();
+            block.changed.dedup();
+            first_signal.push(num_signals);
+            changed.extend(block.changed.iter().map(|&idx| idx + num_signals as u32));
+            frame.extend_from_slice(&block.frame);
+            num_signals += block.num_signals();
+        }
+        let mut stage = std::mem::take(&mut blocks[0].stage);
+        let first = &blocks[0];
+        let (result, duration) = timed(|| {
+            write_value_change_section(
+                output,
+                &mut stage,
+                first.start_time,
+                first.end_time,
+                &frame,
+                &first.time_table,
+                first.num_time_table_entries(),
+                &changed,
+                |signal_idx: usize, data: &mut Vec<u8>| {
+                    let shard = first_signal.partition_point(|&start| start <= signal_idx) - 1;
+                    blocks[shard].signal_data(signal_idx - first_signal[shard], data)
+                },
+                num_signals,
+                first.pack_type,
+                &first.compression,
+            )
+        });
+        let (start_time, end_time) = (first.start_time, first.end_time);
+        let num_changes = blocks.iter().map(|b| b.num_changes as u64).sum();
+        blocks[0].stage = stage;
+        let (bytes, uncompressed_bytes) = result?;
+        Ok(Some(FstBlockStats {
+            start_time,
+            end_time,
+            num_changes,
+            bytes,
+            uncompressed_bytes,
+            duration,
+        }))
+    }
+
+    pub(crate) fn end_time(&self) -> u64 {
+        self.end_time
+    }
+
+    /// Appends all value changes of a signal to `data`.
+    fn signal_data(&self, signal_idx: usize, data: &mut Vec<u8>) -> Result<()> {
+        if let Some(spill) = &self.spill {
+            spill.read(signal_idx, data)?;
+        }
+        self.value_changes.append_list_to(signal_idx, None, data)
+    }
+
+    fn num_signals(&self) -> usize {
+        self.prev_time_table_index.len()
+    }
+
+    fn add_size(&self, size: &mut FstSizeBreakdown) {
+        let compressed = match &self.spill {
+            Some(spill) if spill.is_in_memory() => spill.len() as usize,
+            _ => 0,
+        };
+        size.value_changes += self.value_changes.data.len() + compressed;
+        size.time_table += self.time_table.len();
+        size.frames += self.frame.len();
+        size.fixed += self.value_changes.lists.len() * std::mem::size_of::<ChunkedList>()
+            + (self.prev_time_table_index.len() + self.signal_changes.len() + self.changed.len())
+                * std::mem::size_of::<u32>();
+    }
+
+    /// Removes all value changes, keeping the allocations.
+    fn clear(&mut self) -> Result<()> {
         self.time_table_index = 0;
         for idx in self.prev_time_table_index.iter_mut() {
             *idx = 0;
         }
-        self.start_time = self.end_time;
         self.time_table.clear();
-        self.write_buf.clear();
+        self.first_time = None;
+        self.num_changes = 0;
+        for &signal_idx in self.changed.iter() {
+            self.signal_changes[signal_idx as usize] = 0;
+        }
         self.value_changes.clear();
-        self.first_buffer = false;
-
-        // TODO: recycle?
-        Ok(self.end_time)
-    }
-
-    /// Returns the estimated size of all data structures that grow over time.
-    pub(crate) fn size(&self) -> usize {
-        self.time_table.len() + self.write_buf.len() + self.value_changes.size()
+        if let Some(spill) = &mut self.spill {
+            spill.clear()?;
+        }
+        self.changed.clear();
+        Ok(())
     }
 }
 
 /// Implements several append only lists inside a single `Vec` to store value changes.
-struct SingleVecLists {
-    /// offset in bytes of the last list entry
-    lists_last: Box<[u32]>,
+/// Every list is a chain of chunks that are read front to back. Chunks start with the offset of
+/// the next chunk and double in size, so that rarely changing signals waste little space.
+struct ChunkedLists {
+    lists: Vec<ChunkedList>,
+    /// offset of `data`, only tests move it to simulate buffers of several GiB
+    base: u64,
     data: Vec<u8>,
 }
 
+#[derive(Clone, Copy)]
+struct ChunkedList {
+    /// offset of the first chunk plus one, zero for an empty list
+    first: u64,
+    /// offset of the last chunk
+    last: u64,
+    /// number of bytes used in the last chunk
+    used: u16,
+    /// number of bytes that fit into the last chunk
+    capacity: u16,
+    /// size of the first chunk, lists that were busy in the previous block start bigger
+    first_capacity: u16,
+}
+
+impl ChunkedList {
+    const EMPTY: Self = Self {
+        first: 0,
+        last: 0,
+        used: 0,
+        capacity: 0,
+        first_capacity: MIN_CHUNK_SIZE,
+    };
+}
+
+/// offset of the next chunk plus one, zero for the last chunk
+const CHUNK_HEADER_SIZE: usize = std::mem::size_of::<u64>();
+const MIN_CHUNK_SIZE: u16 = 16;
+const MAX_CHUNK_SIZE: u16 = 4096;
+const VALUE_CHANGES: &str = "the buffered value changes";
+/// Most bytes that a list grows by at once, when it starts a new chunk.
+pub(crate) const MAX_CHUNK_ALLOCATION: usize = CHUNK_HEADER_SIZE + MAX_CHUNK_SIZE as usize;
+
 trait ValueLists {
     fn new(num_lists: usize) -> Self;
-    fn append(&mut self, list_id: usize, data: &[u8], fixed_size: Option<usize>);
-    fn extract_list(&self, list_id: usize, fixed_size: Option<usize>) -> Vec<u8>;
+    fn append(&mut self, list_id: usize, data: &[u8], fixed_size: Option<usize>) -> Result<()>;
+    /// Appends all entries of a list to `out`.
+    fn append_list_to(
+        &self,
+        list_id: usize,
+        fixed_size: Option<usize>,
+        out: &mut Vec<u8>,
+    ) -> Result<()>;
+    #[cfg(test)]
+    fn extract_list(&self, list_id: usize, fixed_size: Option<usize>) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        self.append_list_to(list_id, fixed_size, &mut out)?;
+        Ok(out)
+    }
+    fn is_empty(&self, list_id: usize) -> bool;
     fn clear(&mut self);
     fn size(&self) -> usize;
 }
 
+impl ValueLists for ChunkedLists {
+    fn new(num_lists: usize) -> Self {
+        let lists = vec![ChunkedList::EMPTY; num_lists];
+        Self {
+            lists,
+            base: 0,
+            data: vec![],
+        }
+    }
+
+    fn append(
+        &mut self,
+        list_id: usize,
+        mut data: &[u8],
+        _fixed_size: Option<usize>,
+    ) -> Result<()> {
+        // everything is reserved up front, thus a list is never left half updated
+        let growth = self.max_growth(list_id, data.len());
+        let end = self.data.len() as u64 + growth as u64;
+        checked_usize(end, VALUE_CHANGES)?;
+        self.base
+            .checked_add(end)
+            .ok_or(FstWriteError::TooLarge(VALUE_CHANGES))?;
+        self.data
+            .try_reserve(growth)
+            .map_err(|_| FstWriteError::TooLarge(VALUE_CHANGES))?;
+        let mut list = self.lists[list_id];
+        while !data.is_empty() {
+            if list.first == 0 || list.used == list.capacity {
+                let start = self.base + self.data.len() as u64;
+                let capacity = if list.first == 0 {
+                    list.first = start + 1;
+                    list.first_capacity
+                } else {
+                    let header = self.index(list.last)?;
+                    self.data[header..header + CHUNK_HEADER_SIZE]
+                        .copy_from_slice(&(start + 1).to_le_bytes());
+                    (list.capacity * 2).min(MAX_CHUNK_SIZE)
+                };
+                self.data
+                    .resize(self.data.len() + CHUNK_HEADER_SIZE + capacity as usize, 0);
+                list.last = start;
+                list.used = 0;
+                list.capacity = capacity;
+            }
+            let len = data.len().min((list.capacity - list.used) as usize);
+            let dst = self.index(list.last)? + CHUNK_HEADER_SIZE + list.used as usize;
+            self.data[dst..dst + len].copy_from_slice(&data[..len]);
+            list.used += len as u16;
+            data = &data[len..];
+        }
+        self.lists[list_id] = list;
+        Ok(())
+    }
+
+    fn append_list_to(
+        &self,
+        list_id: usize,
+        _fixed_size: Option<usize>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        for chunk in self.chunks(list_id) {
+            out.extend_from_slice(chunk?);
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self, list_id: usize) -> bool {
+        self.lists[list_id].first == 0
+    }
+
+    fn clear(&mut self) {
+        self.lists.fill(ChunkedList::EMPTY);
+        self.data.clear();
+    }
+
+    fn size(&self) -> usize {
+        self.lists.len() * std::mem::size_of::<ChunkedList>() + self.data.len()
+    }
+}
+
+impl ChunkedLists {
+    fn add_list(&mut self) {
+        self.lists.push(ChunkedList::EMPTY);
+    }
+
+    /// Prepares empty lists for as many changes as `previous` recorded. Lists that were busy
+    /// start with the chunk size they ended with.
+    fn reserve_like(&mut self, previous: &ChunkedLists) {
+        debug_assert!(self.data.is_empty());
+        self.data.reserve(previous.data.len());
+        for (list, prev) in self.lists.iter_mut().zip(previous.lists.iter()) {
+            list.first_capacity = if prev.first == 0 {
+                MIN_CHUNK_SIZE
+            } else {
+                prev.capacity
+            };
+        }
+    }
+
+    /// Bytes that appending `len` bytes to the list adds to the data, including new chunks.
+    fn max_growth(&self, list_id: usize, len: usize) -> usize {
+        let list = self.lists[list_id];
+        let (mut remaining, mut capacity) = if list.first == 0 {
+            (len, list.first_capacity)
+        } else {
+            let free = (list.capacity - list.used) as usize;
+            (
+                len.saturating_sub(free),
+                (list.capacity * 2).min(MAX_CHUNK_SIZE),
+            )
+        };
+        let mut growth = 0;
+        while remaining > 0 {
+            growth += CHUNK_HEADER_SIZE + capacity as usize;
+            remaining = remaining.saturating_sub(capacity as usize);
+            capacity = (capacity * 2).min(MAX_CHUNK_SIZE);
+        }
+        growth
+    }
+
+    /// Data of all chunks, starting with the first one. Chunks are always appended behind
+    /// the previous one, thus a header that points backwards, or ends the chain early, can
+    /// only come from a corrupted buffer and ends the list with an error.
+    fn chunks(&self, list_id: usize) -> impl Iterator<Item = Result<&[u8]>> + '_ {
+        let list = self.lists[list_id];
+        let mut next = list.first;
+        let mut capacity = list.first_capacity as usize;
+        std::iter::from_fn(move || {
+            let start = next.checked_sub(1)?;
+            let chunk = self.index(start).and_then(|header| {
+                let data_start = header + CHUNK_HEADER_SIZE;
+                if start == list.last {
+                    next = 0;
+                    return self.slice(data_start, list.used as usize);
+                }
+                let bytes = self.slice(header, CHUNK_HEADER_SIZE)?;
+                next = u64::from_le_bytes(bytes.try_into().expect("header size"));
+                if next <= start + 1 || next - 1 > list.last {
+                    return Err(FstWriteError::CorruptBuffer("chunk outside of the chain"));
+                }
+                self.slice(data_start, capacity)
+            });
+            // a broken chain ends the list
+            if chunk.is_err() {
+                next = 0;
+            }
+            capacity = (capacity * 2).min(MAX_CHUNK_SIZE as usize);
+            Some(chunk)
+        })
+    }
+
+    /// Index into `data` of the chunk at `offset`.
+    fn index(&self, offset: u64) -> Result<usize> {
+        let index = offset
+            .checked_sub(self.base)
+            .ok_or(FstWriteError::CorruptBuffer(
+                "chunk before the start of the data",
+            ))?;
+        checked_usize(index, VALUE_CHANGES)
+    }
+
+    fn slice(&self, start: usize, len: usize) -> Result<&[u8]> {
+        start
+            .checked_add(len)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or(FstWriteError::CorruptBuffer("chunk outside of the data"))
+    }
+}
+
+#[cfg(test)]
+impl ChunkedLists {
+    fn with_base(num_lists: usize, base: u64) -> Self {
+        Self {
+            base,
+            ..Self::new(num_lists)
+        }
+    }
+}
+
+#[cfg(test)]
+/// Reference implementation in order to test `ChunkedLists`, which stores every list backwards.
+/// Every entry starts with the varint encoded distance to the previous entry of the same list,
+/// which is usually short and never limits how much data can be buffered.
+struct SingleVecLists {
+    /// offset in bytes of the last list entry plus one, zero for an empty list
+    lists_last: Vec<u64>,
+    data: Vec<u8>,
+}
+
+#[cfg(test)]
 impl ValueLists for SingleVecLists {
     fn new(num_lists: usize) -> Self {
-        let lists_last = vec![0u32; num_lists].into_boxed_slice();
+        let lists_last = vec![0u64; num_lists];
         let data = vec![];
         Self { lists_last, data }
     }
 
-    fn append(&mut self, list_id: usize, data: &[u8], fixed_size: Option<usize>) {
-        let back_pointer = self.lists_last[list_id];
-        // new "last" entry, we add 1 to distinguish an empty list
-        self.lists_last[list_id] = self.data.len() as u32 + 1;
-        // remember the previous entry
-        self.data.extend_from_slice(&back_pointer.to_le_bytes());
+    fn append(&mut self, list_id: usize, data: &[u8], fixed_size: Option<usize>) -> Result<()> {
+        if fixed_size.is_some_and(|len| len != data.len()) {
+            return Err(FstWriteError::CorruptBuffer("entry without the fixed size"));
+        }
+        let start = self.data.len() as u64;
+        let last = self.lists_last[list_id];
+        // zero marks the first entry of a list
+        let distance = if last == 0 { 0 } else { start + 1 - last };
+        self.lists_last[list_id] = start + 1;
+        let (bytes, len) = encode_variant_u64(distance);
+        self.data.extend_from_slice(&bytes[..len]);
         // write the new data
         match fixed_size {
-            Some(len) => {
-                debug_assert_eq!(data.len(), len);
+            Some(_) => {
                 self.data.extend_from_slice(data);
             }
             None => {
                 // variable length
-                write_variant_u64(&mut self.data, data.len() as u64).unwrap();
+                let (bytes, len) = encode_variant_u64(data.len() as u64);
+                self.data.extend_from_slice(&bytes[..len]);
                 self.data.extend_from_slice(data);
             }
         }
+        Ok(())
     }
 
-    fn extract_list(&self, list_id: usize, fixed_size: Option<usize>) -> Vec<u8> {
-        let mut last = self.lists_last[list_id];
-        // no list entries
-        if last == 0 {
-            vec![]
-        } else {
-            // find the first entry and calculate length
-            let len = self.list_len(list_id, fixed_size);
-            let mut out = vec![0; len];
-            let mut remaining_len = len;
-            match fixed_size {
-                Some(len) => {
-                    while last > 0 {
-                        let start = last as usize - 1;
-                        last = self.read_back_pointer(start);
-                        remaining_len -= len;
-                        let start = start + 4; // skip back pointer
-                        let src = &self.data[start..start + len];
-                        out[remaining_len..remaining_len + len].copy_from_slice(src);
-                    }
-                }
-                None => {
-                    while last > 0 {
-                        let start = last as usize - 1;
-                        last = self.read_back_pointer(start);
-                        let (len, len_skip) = read_variant_u64(self.data[start + 4..].as_ref());
-                        let len = len as usize;
-                        remaining_len -= len;
-                        let start = start + 4 + len_skip; // skip back pointer and length
-                        let src = &self.data[start..start + len];
-                        out[remaining_len..remaining_len + len].copy_from_slice(src);
-                    }
-                }
-            }
-            debug_assert_eq!(remaining_len, 0);
-            out
+    fn append_list_to(
+        &self,
+        list_id: usize,
+        fixed_size: Option<usize>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        // entries come last to first, thus they are collected before copying
+        let entries = self
+            .entries(list_id, fixed_size)
+            .collect::<Result<Vec<_>>>()?;
+        for range in entries.into_iter().rev() {
+            let data = self
+                .data
+                .get(range)
+                .ok_or(FstWriteError::CorruptBuffer("entry outside of the data"))?;
+            out.extend_from_slice(data);
         }
+        Ok(())
+    }
+
+    fn is_empty(&self, list_id: usize) -> bool {
+        self.lists_last[list_id] == 0
     }
 
     fn clear(&mut self) {
@@ -290,47 +1172,67 @@ impl ValueLists for SingleVecLists {
     }
 
     fn size(&self) -> usize {
-        self.lists_last.len() * std::mem::size_of::<u32>() + self.data.len()
+        self.lists_last.len() * std::mem::size_of::<u64>() + self.data.len()
     }
 }
 
+#[cfg(test)]
 impl SingleVecLists {
-    #[inline]
-    fn read_back_pointer(&self, start: usize) -> u32 {
-        u32::from_le_bytes(self.data[start..start + 4].as_ref().try_into().unwrap())
+    /// Data of all entries, starting with the last one.
+    fn entries(
+        &self,
+        list_id: usize,
+        fixed_size: Option<usize>,
+    ) -> impl Iterator<Item = Result<std::ops::Range<usize>>> + '_ {
+        let last = self.lists_last[list_id];
+        let mut next = (last > 0).then(|| checked_usize(last - 1, VALUE_CHANGES));
+        std::iter::from_fn(move || {
+            let start = match next.take()? {
+                Ok(start) => start,
+                Err(e) => return Some(Err(e)),
+            };
+            Some(self.entry(start, fixed_size).map(|(distance, range)| {
+                next = (distance > 0).then_some(Ok(start - distance));
+                range
+            }))
+        })
     }
 
-    /// Iterates from the back of the list to find the total size of all elements.
-    fn list_len(&self, list_id: usize, fixed_size: Option<usize>) -> usize {
-        let mut last = self.lists_last[list_id];
-        if last == 0 {
-            return 0;
-        }
-        let mut total_len = 0;
-        match fixed_size {
-            Some(len) => {
-                while last > 0 {
-                    let start = last as usize - 1;
-                    last = self.read_back_pointer(start);
-                    total_len += len;
-                }
-            }
+    /// Decodes the entry at `start` into the distance to the previous entry and its data.
+    fn entry(
+        &self,
+        start: usize,
+        fixed_size: Option<usize>,
+    ) -> Result<(usize, std::ops::Range<usize>)> {
+        let (distance, skip) = read_variant_u64(self.data.get(start..).unwrap_or_default())
+            .ok_or(FstWriteError::CorruptBuffer("unterminated varint"))?;
+        let distance = usize::try_from(distance)
+            .ok()
+            .filter(|&d| d <= start)
+            .ok_or(FstWriteError::CorruptBuffer(
+                "entry before the start of the data",
+            ))?;
+        let data_start = start + skip;
+        let (len, data_start) = match fixed_size {
+            Some(len) => (len, data_start),
             None => {
-                while last > 0 {
-                    let start = last as usize - 1;
-                    last = self.read_back_pointer(start);
-                    let (len, _) = read_variant_u64(self.data[start + 4..].as_ref());
-                    total_len += len as usize;
-                }
+                let (len, skip) = read_variant_u64(self.data.get(data_start..).unwrap_or_default())
+                    .ok_or(FstWriteError::CorruptBuffer("unterminated varint"))?;
+                let len = usize::try_from(len)
+                    .map_err(|_| FstWriteError::CorruptBuffer("entry length"))?;
+                (len, data_start + skip)
             }
-        }
-
-        total_len
+        };
+        let end = data_start
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(FstWriteError::CorruptBuffer("entry outside of the data"))?;
+        Ok((distance, data_start..end))
     }
 }
 
-/// Reference implementation in order to test `SingleVecLists`.
 #[cfg(test)]
+/// The simplest lists, one `Vec` each, as the reference for the other implementations.
 struct MultiVecLists {
     lists: Vec<Vec<u8>>,
 }
@@ -338,68 +1240,58 @@ struct MultiVecLists {
 #[cfg(test)]
 impl ValueLists for MultiVecLists {
     fn new(num_lists: usize) -> Self {
-        let lists = vec![vec![]; num_lists];
-        Self { lists }
+        Self {
+            lists: vec![vec![]; num_lists],
+        }
     }
 
-    fn append(&mut self, list_id: usize, data: &[u8], _fixed_size: Option<usize>) {
+    fn append(&mut self, list_id: usize, data: &[u8], _fixed_size: Option<usize>) -> Result<()> {
         self.lists[list_id].extend_from_slice(data);
+        Ok(())
     }
 
-    fn extract_list(&self, list_id: usize, _fixed_size: Option<usize>) -> Vec<u8> {
-        self.lists[list_id].clone()
+    fn append_list_to(
+        &self,
+        list_id: usize,
+        _fixed_size: Option<usize>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        out.extend_from_slice(&self.lists[list_id]);
+        Ok(())
     }
 
-    fn clear(&mut self) {
-        for list in self.lists.iter_mut() {
-            list.clear();
-        }
+    fn is_empty(&self, list_id: usize) -> bool {
+        self.lists[list_id].is_empty()
     }
 
-    fn size(&self) -> usize {
-        self.lists.len() * std::mem::size_of::<Vec<u8>>()
-            + self.lists.iter().map(|l| l.len()).sum::<usize>()
+    fn clear(&mut self) {
+        self.lists.iter_mut().for_each(Vec::clear);
     }
-}
 
-#[inline]
-pub(crate) fn read_variant_u64(input: &[u8]) -> (u64, usize) {
-    let mut res = 0u64;
-    for (ii, byte) in input.iter().take(10).enumerate() {
-        // 64bit / 7bit = ~9.1
-        let value = (*byte as u64) & 0x7f;
-        res |= value << (7 * ii);
-        if (*byte & 0x80) == 0 {
-            return (res, ii + 1);
-        }
+    fn size(&self) -> usize {
+        self.lists.iter().map(Vec::len).sum()
     }
-    unreachable!("should never get here!")
 }
 
-/// tries to expand common shortenings used in VCD encodings
+/// tries to expand common shortenings used in VCD encodings, extending `value` to `len` bits
+/// into `extended`. Returns false if that is not possible.
 #[inline]
-fn expand_special_vector_cases(value: &[u8], len: usize) -> Option<Vec<u8>> {
+fn expand_special_vector_cases(value: &[u8], len: usize, extended: &mut Vec<u8>) -> bool {
     // if the value is actually longer than expected, there is nothing we can do
     if value.len() >= len {
-        return None;
+        return false;
     }
 
     // zero, x or z extend
-    match value[0] {
-        b'1' | b'0' => {
-            let mut extended = Vec::with_capacity(len);
-            extended.resize(len - value.len(), b'0');
-            extended.extend_from_slice(value);
-            Some(extended)
-        }
-        b'x' | b'X' | b'z' | b'Z' => {
-            let mut extended = Vec::with_capacity(len);
-            extended.resize(len - value.len(), value[0]);
-            extended.extend_from_slice(value);
-            Some(extended)
-        }
-        _ => None, // failed
-    }
+    let fill = match value.first() {
+        Some(b'1' | b'0') => b'0',
+        Some(&first @ (b'x' | b'X' | b'z' | b'Z')) => first,
+        _ => return false, // failed
+    };
+    extended.clear();
+    extended.resize(len - value.len(), fill);
+    extended.extend_from_slice(value);
+    true
 }
 
 #[cfg(test)]
@@ -407,23 +1299,105 @@ mod tests {
     use super::*;
     use proptest::prelude::*;
 
+    #[test]
+    fn time_table_index_overflow() {
+        let signals = [FstSignalType::bit_vec(1)];
+        let mut buffer =
+            SignalBuffer::new(&signals, FstPackType::Lz4, Default::default(), None, false).unwrap();
+        buffer.time_change(1).unwrap();
+        buffer.block.time_table_index = u32::MAX - 1;
+        assert!(!buffer.is_full());
+        buffer.time_change(2).unwrap();
+        assert!(buffer.is_full());
+        assert!(matches!(
+            buffer.time_change(3),
+            Err(FstWriteError::TooManyTimeSteps)
+        ));
+        // after a flush, there is room for more time steps
+        buffer
+            .flush(&mut std::io::Cursor::new(vec![]), &mut Default::default())
+            .unwrap();
+        assert!(!buffer.is_full());
+        buffer.time_change(3).unwrap();
+    }
+
+    #[test]
+    fn invalid_changes_are_errors() {
+        let signals = [FstSignalType::bit_vec(4)];
+        let mut buffer =
+            SignalBuffer::new(&signals, FstPackType::Lz4, Default::default(), None, false).unwrap();
+        let a = FstSignalId::from_index(1);
+        buffer.time_change(1).unwrap();
+        for value in [&b""[..], b"b1", b"10101"] {
+            assert!(matches!(
+                buffer.signal_change(a, value),
+                Err(FstWriteError::InvalidValueLength { len: 4, .. })
+            ));
+        }
+        buffer.signal_change(a, b"1").unwrap();
+        buffer
+            .flush(&mut std::io::Cursor::new(vec![]), &mut Default::default())
+            .unwrap();
+        assert!(matches!(
+            buffer.signal_change(a, b"0"),
+            Err(FstWriteError::MissingTimeStep)
+        ));
+        buffer.time_change(2).unwrap();
+        buffer.signal_change(a, b"0").unwrap();
+    }
+
+    #[test]
+    fn record_while_writing_taken_block() {
+        let signals = [FstSignalType::bit_vec(8), FstSignalType::bit_vec(1)];
+        let new_buffer = || {
+            SignalBuffer::new(&signals, FstPackType::Lz4, Default::default(), None, false).unwrap()
+        };
+        let record = |buffer: &mut SignalBuffer, times: std::ops::Range<u64>| {
+            for time in times {
+                buffer.time_change(time).unwrap();
+                let (a, b) = (FstSignalId::from_index(1), FstSignalId::from_index(2));
+                buffer
+                    .signal_change(a, format!("{:08b}", time / 3).as_bytes())
+                    .unwrap();
+                buffer.signal_change(b, &[b'0' + (time % 2) as u8]).unwrap();
+            }
+        };
+        let (mut reference, mut dut) = (new_buffer(), new_buffer());
+        let mut expected = std::io::Cursor::new(vec![]);
+        let mut actual = std::io::Cursor::new(vec![]);
+        for (start, end) in [(0, 10), (10, 25), (25, 30)] {
+            record(&mut reference, start..end);
+            reference
+                .flush(&mut expected, &mut Default::default())
+                .unwrap();
+            record(&mut dut, start..end);
+            let mut block = dut.take_block().unwrap();
+            // new time steps go into the next block
+            dut.time_change(end).unwrap();
+            block.write(&mut actual).unwrap();
+            dut.recycle(block).unwrap();
+            reference.time_change(end).unwrap();
+        }
+        assert_eq!(actual.into_inner(), expected.into_inner());
+    }
+
     fn do_test_lists_var_len(data: &[(usize, Vec<u8>)]) {
         let num_lists = 16;
-        let mut dut = SingleVecLists::new(num_lists);
-        let mut reference = MultiVecLists::new(num_lists);
+        let mut dut = ChunkedLists::new(num_lists);
+        let mut reference = SingleVecLists::new(num_lists);
 
         // write data
         for (list_id, data) in data.iter() {
             let list_id = *list_id % num_lists;
-            dut.append(list_id, data, None);
-            reference.append(list_id, data, None);
+            dut.append(list_id, data, None).unwrap();
+            reference.append(list_id, data, None).unwrap();
         }
 
         // check results
         for list_id in 0..num_lists {
             assert_eq!(
-                dut.extract_list(list_id, None),
-                reference.extract_list(list_id, None)
+                dut.extract_list(list_id, None).unwrap(),
+                reference.extract_list(list_id, None).unwrap()
             );
         }
     }
@@ -431,15 +1405,15 @@ mod tests {
     fn do_test_lists_fixed_len(len: u8, list_data: &[Vec<u8>]) {
         let len = len as usize + 1;
         let num_lists = list_data.len();
-        let mut dut = SingleVecLists::new(num_lists);
-        let mut reference = MultiVecLists::new(num_lists);
+        let mut dut = ChunkedLists::new(num_lists);
+        let mut reference = SingleVecLists::new(num_lists);
 
         // write data
         for (list_id, data) in list_data.iter().enumerate() {
             for entry in data.as_slice().chunks(len) {
                 if entry.len() == len {
-                    dut.append(list_id, entry, Some(len));
-                    reference.append(list_id, entry, Some(len));
+                    dut.append(list_id, entry, Some(len)).unwrap();
+                    reference.append(list_id, entry, Some(len)).unwrap();
                 }
             }
         }
@@ -447,17 +1421,187 @@ mod tests {
         // check results
         for list_id in 0..num_lists {
             assert_eq!(
-                dut.extract_list(list_id, Some(len)),
-                reference.extract_list(list_id, Some(len))
+                dut.extract_list(list_id, Some(len)).unwrap(),
+                reference.extract_list(list_id, Some(len)).unwrap()
             );
         }
     }
 
+    #[derive(Debug, Clone)]
+    enum ListOp {
+        Append {
+            list_id: usize,
+            len: usize,
+            byte: u8,
+        },
+        Extract(usize),
+        Clear,
+    }
+
+    /// Mostly appends, with lengths around the varint and chunk size boundaries.
+    fn list_op(num_lists: usize) -> impl Strategy<Value = ListOp> {
+        let max_chunk = MAX_CHUNK_SIZE as usize;
+        let len = prop_oneof![
+            0..=20usize,
+            120..=136usize,
+            max_chunk - 8..=max_chunk + 8,
+            Just(3 * MAX_CHUNK_ALLOCATION),
+            16_380..=16_390usize,
+        ];
+        prop_oneof![
+            8 => (0..num_lists, len, any::<u8>())
+                .prop_map(|(list_id, len, byte)| ListOp::Append { list_id, len, byte }),
+            2 => (0..num_lists).prop_map(ListOp::Extract),
+            1 => Just(ListOp::Clear),
+        ]
+    }
+
+    /// Runs the same operations on `ChunkedLists` and the reference. `base` places the data
+    /// of `ChunkedLists` at a simulated offset.
+    fn do_test_list_operations(base: u64, fixed_size: Option<usize>, ops: &[ListOp]) {
+        let num_lists = 4;
+        let mut reference = MultiVecLists::new(num_lists);
+        let mut dut = ChunkedLists::with_base(num_lists, base);
+        let check = |reference: &MultiVecLists, dut: &ChunkedLists, list_id: usize| {
+            let expected = reference.extract_list(list_id, fixed_size).unwrap();
+            assert_eq!(dut.extract_list(list_id, fixed_size).unwrap(), expected);
+            assert_eq!(dut.is_empty(list_id), reference.is_empty(list_id));
+        };
+        for op in ops {
+            match *op {
+                ListOp::Append { list_id, len, byte } => {
+                    let len = fixed_size.unwrap_or(len);
+                    let data: Vec<u8> = (0..len).map(|i| byte.wrapping_add(i as u8)).collect();
+                    reference.append(list_id, &data, fixed_size).unwrap();
+                    dut.append(list_id, &data, fixed_size).unwrap();
+                }
+                ListOp::Extract(list_id) => check(&reference, &dut, list_id),
+                ListOp::Clear => {
+                    reference.clear();
+                    dut.clear();
+                }
+            }
+        }
+        for list_id in 0..num_lists {
+            check(&reference, &dut, list_id);
+        }
+    }
+
+    #[test]
+    fn unit_test_lists_beyond_4_gib() {
+        let ops = [
+            ListOp::Append {
+                list_id: 0,
+                len: 3,
+                byte: 1,
+            },
+            ListOp::Append {
+                list_id: 1,
+                len: 5000,
+                byte: 2,
+            },
+            ListOp::Append {
+                list_id: 0,
+                len: 200,
+                byte: 3,
+            },
+        ];
+        for base in [u32::MAX as u64 - 4, 5 << 30, u64::MAX / 2] {
+            do_test_list_operations(base, None, &ops);
+        }
+        // an offset before the data is corrupt instead of wrapping around
+        let mut lists = ChunkedLists::with_base(1, 5 << 30);
+        lists.append(0, b"a", None).unwrap();
+        lists.base += 1;
+        assert!(matches!(
+            lists.extract_list(0, None),
+            Err(FstWriteError::CorruptBuffer(_))
+        ));
+    }
+
+    #[test]
+    fn expand_into_reused_buffer() {
+        let mut extended = vec![];
+        assert!(expand_special_vector_cases(b"1", 4, &mut extended));
+        assert_eq!(extended, b"0001");
+        assert!(expand_special_vector_cases(b"z0", 3, &mut extended));
+        assert_eq!(extended, b"zz0");
+        assert!(!expand_special_vector_cases(b"1010", 4, &mut extended));
+        assert!(!expand_special_vector_cases(b"b1", 4, &mut extended));
+    }
+
     #[test]
     fn unit_test_fixed_len_lists() {
-        let mut dut = SingleVecLists::new(2);
-        dut.append(0, &[0], Some(1));
-        assert_eq!(dut.extract_list(0, Some(1)), [0]);
+        let mut dut = ChunkedLists::new(2);
+        dut.append(0, &[0], Some(1)).unwrap();
+        assert_eq!(dut.extract_list(0, Some(1)).unwrap(), [0]);
+    }
+
+    #[test]
+    fn unit_test_far_apart_entries() {
+        let mut dut = ChunkedLists::new(2);
+        dut.append(0, b"a", None).unwrap();
+        dut.append(1, &vec![7; 100_000], None).unwrap();
+        dut.append(0, b"b", None).unwrap();
+        assert_eq!(dut.extract_list(0, None).unwrap(), b"ab");
+        assert_eq!(dut.extract_list(1, None).unwrap().len(), 100_000);
+        // entries of frequently changing signals do not need any bookkeeping
+        let mut dut = ChunkedLists::new(2);
+        for _ in 0..100 {
+            dut.append(0, &[0], Some(1)).unwrap();
+            dut.append(1, &[1], Some(1)).unwrap();
+        }
+        assert_eq!(dut.data.len(), 2 * (3 * CHUNK_HEADER_SIZE + 16 + 32 + 64));
+        assert_eq!(dut.extract_list(1, Some(1)).unwrap(), [1; 100]);
+    }
+
+    #[test]
+    fn unit_test_lists_reserve_like_previous() {
+        let mut previous = ChunkedLists::new(3);
+        previous.append(0, &[0; 100], None).unwrap();
+        previous.append(1, &[1], None).unwrap();
+        let mut dut = ChunkedLists::new(3);
+        dut.reserve_like(&previous);
+        assert!(dut.data.capacity() >= previous.data.len());
+        // the busy list starts with the size of its last chunk
+        dut.append(0, &[2; 60], None).unwrap();
+        dut.append(1, &[3], None).unwrap();
+        dut.append(2, &[4], None).unwrap();
+        assert_eq!(dut.data.len(), 3 * CHUNK_HEADER_SIZE + 64 + 16 + 16);
+        assert_eq!(dut.extract_list(0, None).unwrap(), [2; 60]);
+        assert_eq!(dut.extract_list(1, None).unwrap(), [3]);
+        assert_eq!(dut.extract_list(2, None).unwrap(), [4]);
+    }
+
+    #[test]
+    fn unit_test_corrupt_chain() {
+        let mut dut = ChunkedLists::new(1);
+        dut.append(0, &[1; 100], None).unwrap();
+        let second = CHUNK_HEADER_SIZE + MIN_CHUNK_SIZE as usize;
+        // a header pointing back to the first chunk would otherwise loop forever
+        let mut looped = ChunkedLists {
+            lists: dut.lists.clone(),
+            base: dut.base,
+            data: dut.data.clone(),
+        };
+        looped.data[second..second + CHUNK_HEADER_SIZE].copy_from_slice(&1u64.to_le_bytes());
+        assert!(matches!(
+            looped.extract_list(0, None),
+            Err(FstWriteError::CorruptBuffer(_))
+        ));
+        // so would a header pointing to itself
+        looped.data[second..second + CHUNK_HEADER_SIZE]
+            .copy_from_slice(&(second as u64 + 1).to_le_bytes());
+        assert!(matches!(
+            looped.extract_list(0, None),
+            Err(FstWriteError::CorruptBuffer(_))
+        ));
+        // a chain that ends before the last chunk is corrupt as well
+        dut.data[..CHUNK_HEADER_SIZE].fill(0);
+        assert!(matches!(
+            dut.extract_list(0, None),
+            Err(FstWriteError::CorruptBuffer(_))
+        ));
     }
 
     proptest! {
@@ -466,8 +1610,70 @@ mod tests {
             do_test_lists_var_len(&data);
         }
         #[test]
+        fn test_corrupt_lists(data: Vec<(usize, Vec<u8>)>, flips: Vec<(usize, u8)>) {
+            let num_lists = 4;
+            let mut lists = ChunkedLists::new(num_lists);
+            for (list_id, data) in data.iter() {
+                lists.append(list_id % num_lists, data, None).unwrap();
+            }
+            for (index, byte) in flips {
+                if let Some(dst) = lists.data.get_mut(index % 64) {
+                    *dst = byte;
+                }
+            }
+            for list_id in 0..num_lists {
+                match lists.extract_list(list_id, None) {
+                    Ok(_) | Err(FstWriteError::CorruptBuffer(_)) => {}
+                    Err(e) => panic!("unexpected error: {e}"),
+                }
+            }
+        }
+        #[test]
+        fn test_signal_widths(widths in prop::collection::vec(u32::MAX / 4..=u32::MAX, 0..6)) {
+            let signals: Vec<_> = widths.iter().map(|&w| FstSignalType::bit_vec(w)).collect();
+            let total: u64 = widths.iter().map(|&w| w as u64).sum();
+            match gen_signal_info(&signals) {
+                Ok((_, len)) => prop_assert_eq!(len as u64, total),
+                Err(e) => {
+                    prop_assert!(total > u32::MAX as u64);
+                    prop_assert!(matches!(e, FstWriteError::SignalsTooWide));
+                }
+            }
+        }
+        #[test]
+        fn test_arbitrary_changes(len in 1u32..70, values: Vec<Vec<u8>>) {
+            let signals = [FstSignalType::bit_vec(len)];
+            let mut buffer =
+                SignalBuffer::new(&signals, FstPackType::Lz4, Default::default(), None, false)
+                    .unwrap();
+            let a = FstSignalId::from_index(1);
+            for (time, value) in values.iter().enumerate() {
+                buffer.time_change(time as u64).unwrap();
+                match buffer.signal_change(a, value) {
+                    Ok(()) => {}
+                    Err(FstWriteError::InvalidValueLength { .. }) => {
+                        prop_assert!(value.len() != len as usize)
+                    }
+                    Err(FstWriteError::InvalidCharacter(_)) => prop_assert_eq!(len, 1),
+                    Err(e) => panic!("unexpected error: {e}"),
+                }
+            }
+            buffer
+                .flush(&mut std::io::Cursor::new(vec![]), &mut Default::default())
+                .unwrap();
+        }
+        #[test]
         fn test_lists_fixed_len(len: u8, data: Vec<Vec<u8>>) {
             do_test_lists_fixed_len(len, &data);
         }
+        #[test]
+        fn test_list_operations(
+            base in prop_oneof![Just(0u64), Just(u32::MAX as u64 - 8), Just(5 << 30), any::<u64>()],
+            fixed_size in prop::option::of(1..=16usize),
+            ops in prop::collection::vec(list_op(4), 0..64),
+        ) {
+            // leave room for the data behind the simulated offset
+            do_test_list_operations(base.min(u64::MAX / 2), fixed_size, &ops);
+        }
     }
 }
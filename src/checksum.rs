@@ -0,0 +1,145 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// CRC32 checksums of all blocks, stored in a trailing skip block that other readers ignore.
+
+use crate::io::{BlockType, write_u64};
+use crate::{FstWriteError, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Identifies the skip block that contains the checksums.
+const CHECKSUM_MAGIC: &[u8; 8] = b"FSTCRC32";
+
+/// Computes the checksum of every block in the finished file at `path` and appends them.
+/// Checksums from before the file was continued with `append_fst` are superseded, since
+/// only the last checksum block is verified.
+pub(crate) fn append_checksums(path: &Path) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let (checksums, _) = read_blocks(&mut std::io::BufReader::new(&mut file), true)?;
+    let mut out = std::io::BufWriter::new(file);
+    out.seek(SeekFrom::End(0))?;
+    out.write_all(&[BlockType::Skip as u8])?;
+    // the section length includes the length field itself
+    write_u64(
+        &mut out,
+        8 + CHECKSUM_MAGIC.len() as u64 + 8 + checksums.len() as u64 * 12,
+    )?;
+    out.write_all(CHECKSUM_MAGIC)?;
+    write_u64(&mut out, checksums.len() as u64)?;
+    for (offset, crc) in checksums {
+        write_u64(&mut out, offset)?;
+        out.write_all(&crc.to_be_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Checks a file written with [`crate::FstWriterConfig::checksums`] and returns the offsets of
+/// all blocks that were corrupted. Files repacked into a gzip wrapper need to be unpacked first.
+pub fn verify_fst_checksums(path: impl AsRef<Path>) -> Result<Vec<u64>> {
+    let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+    let (actual, stored) = read_blocks(&mut input, true)?;
+    let Some(stored) = stored else {
+        return Err(invalid("no checksums found"));
+    };
+    let mut corrupted = vec![];
+    let mut stored = stored.into_iter().peekable();
+    for (offset, crc) in actual {
+        // skip stored entries for blocks that no longer exist
+        while stored.next_if(|&(o, _)| o < offset).is_some() {}
+        match stored.next_if(|&(o, _)| o == offset) {
+            Some((_, expected)) if expected == crc => {}
+            _ => corrupted.push(offset),
+        }
+    }
+    Ok(corrupted)
+}
+
+fn invalid(msg: impl Into<String>) -> FstWriteError {
+    FstWriteError::InvalidChecksums(msg.into())
+}
+
+/// Whether the file at `path` contains checksums, which need to be updated when appending.
+#[cfg(feature = "append")]
+pub(crate) fn has_checksums(path: &Path) -> Result<bool> {
+    let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+    Ok(read_blocks(&mut input, false)?.1.is_some())
+}
+
+type Checksums = Vec<(u64, u32)>;
+
+/// Returns the offset and CRC32 of every block, as well as the checksums stored in the file.
+/// Without `hash`, only the stored checksums are read.
+fn read_blocks(
+    input: &mut (impl Read + Seek),
+    hash: bool,
+) -> Result<(Checksums, Option<Checksums>)> {
+    let file_len = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+    let mut checksums = vec![];
+    let mut stored = None;
+    let mut offset = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    while offset < file_len {
+        let mut head = [0u8; 9];
+        input
+            .read_exact(&mut head)
+            .map_err(|_| invalid("truncated block"))?;
+        let section_len = u64::from_be_bytes(head[1..].try_into().unwrap());
+        let block_len = section_len.saturating_add(1);
+        if section_len < 8 || offset.saturating_add(block_len) > file_len {
+            return Err(invalid(format!("invalid block length at {offset}")));
+        }
+        if head[0] == BlockType::Skip as u8 && section_len >= 8 + 16 {
+            let mut magic = [0u8; 8];
+            input.read_exact(&mut magic)?;
+            if &magic == CHECKSUM_MAGIC {
+                stored = Some(read_checksums(input, section_len - 16)?);
+                offset += block_len;
+                continue;
+            }
+            input.seek(SeekFrom::Start(offset + 9))?;
+        }
+        if !hash {
+            offset += block_len;
+            input.seek(SeekFrom::Start(offset))?;
+            continue;
+        }
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&head);
+        let mut remaining = block_len - head.len() as u64;
+        while remaining > 0 {
+            let chunk = remaining.min(buf.len() as u64) as usize;
+            input.read_exact(&mut buf[..chunk])?;
+            hasher.update(&buf[..chunk]);
+            remaining -= chunk as u64;
+        }
+        checksums.push((offset, hasher.finalize()));
+        offset += block_len;
+    }
+    Ok((checksums, stored))
+}
+
+/// Reads the count and entries of the checksum block, `len` bytes after the magic.
+fn read_checksums(input: &mut impl Read, len: u64) -> Result<Checksums> {
+    let mut bytes = vec![0u8; len as usize];
+    input.read_exact(&mut bytes)?;
+    let (count, entries) = bytes.split_at(8);
+    let count = u64::from_be_bytes(count.try_into().unwrap());
+    if count.checked_mul(12) != Some(entries.len() as u64) {
+        return Err(invalid("checksum block length does not match its entries"));
+    }
+    Ok(entries
+        .chunks_exact(12)
+        .map(|entry| {
+            let offset = u64::from_be_bytes(entry[..8].try_into().unwrap());
+            let crc = u32::from_be_bytes(entry[8..].try_into().unwrap());
+            (offset, crc)
+        })
+        .collect())
+}
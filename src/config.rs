@@ -0,0 +1,181 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+
+/// Options that influence how an FST file is written.
+/// Install them with [`crate::FstHeaderWriter::with_config`].
+#[derive(Debug, Clone, Default)]
+pub struct FstWriterConfig {
+    /// Compress the hierarchy while it is being declared instead of keeping it in memory
+    /// uncompressed until the header is finished. This keeps memory usage low for designs
+    /// with millions of variables, but uses the slower zlib instead of LZ4. With the `fs`
+    /// feature, the compressed hierarchy is moved into a temporary file.
+    /// Declared scopes and variables are not kept in memory either, unless
+    /// [`FstWriterConfig::retain_declarations`] is set.
+    pub incremental_hierarchy_compression: bool,
+    /// Keep all declared scopes and variables in memory, even with
+    /// [`FstWriterConfig::incremental_hierarchy_compression`]. They are needed for
+    /// [`crate::FstHeaderWriter::scopes`], [`crate::FstHeaderWriter::vars`],
+    /// [`crate::FstHeaderWriter::alias_scope`], [`crate::FstBodyWriter::set_scope_enabled`]
+    /// and for naming signals in errors. Always on without incremental compression.
+    pub retain_declarations: bool,
+    /// Accept scope and variable declarations while writing value changes.
+    /// The hierarchy and geometry blocks are written by [`crate::FstBodyWriter::finish`]
+    /// instead of [`crate::FstHeaderWriter::finish`].
+    /// Note that readers based on the `fst-reader` crate (e.g., wellen) expect every
+    /// value change block to contain all signals. Thus, for these readers, all variables
+    /// need to be declared before the first [`crate::FstBodyWriter::flush`].
+    pub deferred_hierarchy: bool,
+    /// Order in which signal ids are assigned. See [`FstSignalOrder`].
+    pub signal_order: FstSignalOrder,
+    /// Compress the whole file into a gzip wrapper once it is finished, like
+    /// `fstWriterSetRepackOnClose`. This produces much smaller files for long-term storage,
+    /// but readers first need to decompress the file before they can access it.
+    pub repack_on_close: bool,
+    /// Compression used for the value changes of each signal.
+    pub pack_type: FstPackType,
+    /// Trade write throughput against file size.
+    pub compression: FstCompressionLevels,
+    /// Stop recording value changes once the file would grow beyond this many bytes,
+    /// like `fstWriterSetDumpSizeLimit`. The file is still finished correctly.
+    /// See [`crate::FstBodyWriter::dump_size_limit_reached`].
+    pub dump_size_limit: Option<u64>,
+    /// Once a file grew beyond this many bytes, finish it and continue in `trace_0001.fst`,
+    /// `trace_0002.fst`, … next to the original `trace.fst`. Every file starts with the
+    /// hierarchy and the current value of all signals, so that it can be read on its own.
+    /// Checked before every block written by [`crate::FstBodyWriter::flush`], thus files
+    /// grow beyond the limit by up to one block. Only available for files opened with
+    /// [`crate::open_fst`] or [`crate::open_fst_with_options`], whose options apply to every
+    /// file. Cannot be combined with [`FstWriterConfig::deferred_hierarchy`].
+    pub rollover_size: Option<u64>,
+    /// Ignore all time and value changes after this time. The file is still finished correctly.
+    pub record_until: Option<u64>,
+    /// Append a CRC32 checksum of every block once the file is finished, so that corruption
+    /// can be detected with [`crate::verify_fst_checksums`]. The checksums are stored in a
+    /// skip block which other readers ignore. Only available for files opened with
+    /// [`crate::open_fst`] or [`crate::open_fst_with_options`].
+    pub checksums: bool,
+    /// Once the value changes buffered in memory exceed this many bytes, they are moved
+    /// into a temporary file and merged back in when the block is written.
+    /// Without the `fs` feature, they are LZ4 compressed in memory instead.
+    pub spill_threshold: Option<usize>,
+    /// LZ4 compress buffered value changes, trading CPU time for less memory between flushes.
+    /// Compressed changes stay in memory unless a [`FstWriterConfig::spill_threshold`] is set.
+    pub compress_buffer: bool,
+    /// Start a new value change block in [`crate::FstBodyWriter::time_change`] once the
+    /// buffered data grows beyond this many bytes, see [`crate::FstBodyWriter::size`].
+    pub auto_flush_size: Option<usize>,
+    /// Hard limit for the bytes buffered in memory, see [`crate::FstBodyWriter::size`].
+    /// A block is written at a new time step once less room than the largest time step so far
+    /// is left. Changes that would exceed the limit anyway, e.g., within a single giant time
+    /// step, are rejected with [`crate::FstWriteError::MemoryLimitExceeded`] instead of
+    /// growing the buffer further.
+    pub memory_limit: Option<usize>,
+    /// Start a new value change block whenever the time crosses a multiple of this interval,
+    /// so that every block covers at most one interval.
+    pub auto_flush_interval: Option<u64>,
+    /// Hold back this many of the most recent time steps, so that changes can arrive
+    /// slightly out of order. Only times before all held back time steps are rejected.
+    pub reorder_depth: Option<usize>,
+    /// Record the changes of a time step that the writer collects before writing, i.e., from
+    /// [`crate::FstSignalSender`], [`crate::SharedFstWriter`] or through
+    /// [`FstWriterConfig::reorder_depth`], in signal id order instead of the order in which
+    /// they arrived. Changes of the same signal keep their order. Thus, the file does not
+    /// depend on how threads were interleaved.
+    pub canonical_order: bool,
+    /// Keep the file readable while it is written, e.g., to watch a running simulation in
+    /// GTKWave or Surfer. After every value change block, the output is flushed and the
+    /// header is updated. Combine with [`FstWriterConfig::auto_flush_interval`] so that new
+    /// changes show up regularly. Requires the hierarchy to be written up front, thus it
+    /// cannot be combined with [`FstWriterConfig::deferred_hierarchy`].
+    pub live: bool,
+}
+
+/// Controls how [`crate::open_fst_with_options`] creates the output file.
+#[derive(Debug, Clone, Default)]
+pub struct FstOpenOptions {
+    /// Capacity of the `BufWriter` around the file. Large buffers reduce the number of
+    /// system calls when writing big value change blocks. Uses the standard library default
+    /// if `None`.
+    pub buffer_capacity: Option<usize>,
+    /// Fail with [`crate::FstWriteError::FileExists`] if the file already exists instead of
+    /// replacing it.
+    pub create_new: bool,
+    /// Permissions of a newly created file, e.g., `0o640`. Only supported on Unix.
+    pub mode: Option<u32>,
+    /// Write to `<path>.tmp` and only rename it to `path` once the file was finished, so that
+    /// tools watching the directory never pick up a partially written file.
+    pub atomic: bool,
+    /// Flush the output and force the file to disk after every value change block.
+    pub sync_on_flush: bool,
+    /// Force the file and its directory entry to disk before [`crate::FstBodyWriter::finish`]
+    /// returns, so that the file survives a crash of the machine right afterwards.
+    pub sync_on_finish: bool,
+}
+
+/// zlib compression levels (0: none, 1: fastest, 10: smallest) for the parts of the file
+/// that are compressed with zlib. LZ4 and FastLZ have no adjustable level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FstCompressionLevels {
+    /// used with [`FstWriterConfig::incremental_hierarchy_compression`]
+    pub hierarchy: u8,
+    /// used with [`FstPackType::Zlib`]
+    pub value_changes: u8,
+    /// initial values of all signals in a value change block
+    pub frame: u8,
+    pub time_table: u8,
+    /// used with [`FstWriterConfig::repack_on_close`]
+    pub repack: u8,
+}
+
+impl Default for FstCompressionLevels {
+    fn default() -> Self {
+        // by unscientific experiment, we observed that these levels might be good enough :)
+        Self {
+            hierarchy: 4,
+            value_changes: 4,
+            frame: 3,
+            time_table: 3,
+            // same as fstapi
+            repack: 4,
+        }
+    }
+}
+
+/// FST assigns signal ids in the order in which variables appear in the hierarchy.
+/// Value changes are stored in the same order, thus placing similar signals next to each
+/// other improves compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FstSignalOrder {
+    /// Signal ids follow the order of the `var` calls.
+    #[default]
+    Declaration,
+    /// Variables declared next to each other inside the same scope are sorted by width.
+    /// Opening or closing a scope, a source stem or an aliased scope ends such a run, thus
+    /// variables are never moved across them.
+    /// `var` returns provisional ids which need to be translated through
+    /// [`crate::FstBodyWriter::signal_remap`].
+    Width,
+}
+
+/// Compression algorithm for value changes. All of them are supported by common FST readers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FstPackType {
+    /// fast compression and decompression
+    #[default]
+    Lz4,
+    /// smaller output, but slower to write
+    Zlib,
+    /// the original default of fstapi
+    FastLz,
+}
+
+/// Records only some of the changes of a signal, e.g., for analog values that are sampled
+/// every cycle. Install with [`crate::FstBodyWriter::set_signal_decimation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FstDecimation {
+    /// Only record the first of every `n` changes.
+    EveryNth(u32),
+    /// Only record a change if at least this much time passed since the last recorded change.
+    MinTimeDelta(u64),
+}
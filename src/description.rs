@@ -0,0 +1,111 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+
+use crate::{
+    FstHeaderWriter, FstScopeType, FstSignalId, FstSignalType, FstVarDirection, FstVarType,
+    FstWriteError, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Entry of a hierarchy description, e.g., parsed from JSON.
+/// Declare it with [`FstHeaderWriter::declare_hierarchy`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FstHierarchyItem {
+    Scope(FstScopeDescription),
+    Var(FstVarDescription),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FstScopeDescription {
+    pub name: String,
+    #[serde(default)]
+    pub component: String,
+    #[serde(default = "default_scope_type")]
+    pub tpe: FstScopeType,
+    #[serde(default)]
+    pub items: Vec<FstHierarchyItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FstVarDescription {
+    pub name: String,
+    /// number of bits, ignored for real variables
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default)]
+    pub real: bool,
+    #[serde(default = "default_var_type")]
+    pub tpe: FstVarType,
+    #[serde(default = "default_var_direction")]
+    pub dir: FstVarDirection,
+    /// full name of a previously declared variable that this variable is an alias of
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+fn default_scope_type() -> FstScopeType {
+    FstScopeType::Module
+}
+
+fn default_width() -> u32 {
+    1
+}
+
+fn default_var_type() -> FstVarType {
+    FstVarType::Wire
+}
+
+fn default_var_direction() -> FstVarDirection {
+    FstVarDirection::Implicit
+}
+
+impl<W: std::io::Write + std::io::Seek> FstHeaderWriter<W> {
+    /// Declares all scopes and variables in `items`. Returns the signal id of every variable,
+    /// indexed by its dot separated full name.
+    pub fn declare_hierarchy(
+        &mut self,
+        items: &[FstHierarchyItem],
+    ) -> Result<HashMap<String, FstSignalId>> {
+        let mut ids = HashMap::new();
+        self.declare_items(items, "", &mut ids)?;
+        Ok(ids)
+    }
+
+    fn declare_items(
+        &mut self,
+        items: &[FstHierarchyItem],
+        prefix: &str,
+        ids: &mut HashMap<String, FstSignalId>,
+    ) -> Result<()> {
+        for item in items.iter() {
+            match item {
+                FstHierarchyItem::Scope(scope) => {
+                    let prefix = format!("{prefix}{}.", scope.name);
+                    self.with_scope(&scope.name, &scope.component, scope.tpe, |w| {
+                        w.declare_items(&scope.items, &prefix, ids)
+                    })?;
+                }
+                FstHierarchyItem::Var(var) => {
+                    let alias = match &var.alias {
+                        None => None,
+                        Some(name) => match ids.get(name) {
+                            Some(id) => Some(*id),
+                            None => return Err(FstWriteError::UnknownAlias(name.clone())),
+                        },
+                    };
+                    let signal_tpe = if var.real {
+                        FstSignalType::real()
+                    } else {
+                        FstSignalType::bit_vec(var.width)
+                    };
+                    let id = self.var(&var.name, signal_tpe, var.tpe, var.dir, alias)?;
+                    ids.insert(format!("{prefix}{}", var.name), id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
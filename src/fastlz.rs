@@ -0,0 +1,128 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Simple Rust implementation of the FastLZ level 1 compressor: https://github.com/ariya/FastLZ
+
+const MIN_MATCH: usize = 3;
+/// longest match that can be encoded with a single length byte
+const MAX_MATCH: usize = 264;
+/// 13-bit distances
+const MAX_DISTANCE: usize = 8192;
+const MAX_LITERAL_RUN: usize = 32;
+const HASH_LOG: u32 = 13;
+
+#[inline]
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (value.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+/// Compresses `input` with FastLZ level 1.
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() + input.len() / MAX_LITERAL_RUN + 1);
+    // position + 1 of the last occurrence of each hash, 0 means none
+    let mut table = vec![0u32; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= input.len() {
+        let h = hash(&input[pos..]);
+        let candidate = table[h] as usize;
+        table[h] = pos as u32 + 1;
+        if candidate > 0 {
+            let start = candidate - 1;
+            let distance = pos - start;
+            if distance <= MAX_DISTANCE
+                && input[start..start + MIN_MATCH] == input[pos..pos + MIN_MATCH]
+            {
+                let mut len = MIN_MATCH;
+                while pos + len < input.len()
+                    && len < MAX_MATCH
+                    && input[start + len] == input[pos + len]
+                {
+                    len += 1;
+                }
+                write_literals(&mut out, &input[anchor..pos]);
+                write_match(&mut out, len, distance);
+                pos += len;
+                anchor = pos;
+                continue;
+            }
+        }
+        pos += 1;
+    }
+    write_literals(&mut out, &input[anchor..]);
+    out
+}
+
+fn write_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for run in literals.chunks(MAX_LITERAL_RUN) {
+        out.push(run.len() as u8 - 1);
+        out.extend_from_slice(run);
+    }
+}
+
+fn write_match(out: &mut Vec<u8>, len: usize, distance: usize) {
+    let len = len - 2;
+    let distance = distance - 1;
+    let distance_high = (distance >> 8) as u8;
+    if len < 7 {
+        out.push(((len as u8) << 5) + distance_high);
+    } else {
+        out.push((7 << 5) + distance_high);
+        out.push((len - 7) as u8);
+    }
+    out.push((distance & 0xff) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Level 1 decompressor in order to test the compressor.
+    fn decompress(input: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![];
+        let mut ii = 0;
+        while ii < input.len() {
+            let op = input[ii] as usize;
+            ii += 1;
+            if op < 32 {
+                out.extend_from_slice(&input[ii..ii + op + 1]);
+                ii += op + 1;
+            } else {
+                let mut len = (op >> 5) + 2;
+                if len == 9 {
+                    len += input[ii] as usize;
+                    ii += 1;
+                }
+                let distance = ((op & 0x1f) << 8) + input[ii] as usize + 1;
+                ii += 1;
+                let start = out.len() - distance;
+                for jj in start..start + len {
+                    out.push(out[jj]);
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn compress_repetitive() {
+        let input = b"0101010101010101010101010101010101010101010101".repeat(100);
+        let compressed = compress(&input);
+        assert!(compressed.len() < input.len() / 10);
+        assert_eq!(decompress(&compressed), input);
+    }
+
+    proptest! {
+        #[test]
+        fn test_compress_round_trip(data: Vec<u8>) {
+            prop_assert_eq!(decompress(&compress(&data)), data);
+        }
+        #[test]
+        fn test_compress_round_trip_small_alphabet(data in prop::collection::vec(0u8..3, 0..20000)) {
+            prop_assert_eq!(decompress(&compress(&data)), data);
+        }
+    }
+}
@@ -0,0 +1,575 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+
+use crate::io::{
+    HierarchyBuffer, check_hierarchy_name, write_geometry, write_hierarchy_path_name,
+    write_hierarchy_scope, write_hierarchy_source_stem, write_hierarchy_up_scope,
+    write_hierarchy_var,
+};
+use crate::{
+    FstScopeInfo, FstScopeType, FstSignalId, FstSignalOrder, FstSignalType, FstVarDirection,
+    FstVarInfo, FstVarType, FstWriteError, Result,
+};
+use std::collections::{HashMap, HashSet};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Keeps track of all declarations and encodes them into the hierarchy section.
+pub(crate) struct Hierarchy {
+    /// collect hierarchy section before writing it
+    buf: HierarchyBuffer,
+    signals: Vec<FstSignalType>,
+    /// every distinct scope, component and var name is only stored once
+    names: HashSet<Arc<str>>,
+    /// keep `scopes` and `vars`, which grow with every declaration
+    retain: bool,
+    /// all scopes declared so far, if retained
+    scopes: Vec<FstScopeInfo>,
+    /// all vars declared so far in declaration order, if retained
+    vars: Vec<FstVarInfo>,
+    /// signal id of every entry in `vars` after reordering, `None` while it is pending
+    var_signals: Vec<Option<FstSignalId>>,
+    num_scopes: usize,
+    num_vars: usize,
+    /// index and name of all scopes that are currently open
+    open_scopes: Vec<(usize, Arc<str>)>,
+    /// every source path is only declared once and then referred to by its id
+    path_ids: HashMap<PathBuf, u64>,
+    order: FstSignalOrder,
+    /// vars that have not been written yet because their signal id depends on sorting
+    pending: Vec<PendingVar>,
+    /// signal types indexed by provisional id, only used when reordering
+    provisional: Vec<FstSignalType>,
+    /// maps provisional ids to signal ids, only used when reordering
+    remap: Vec<Option<FstSignalId>>,
+}
+
+struct PendingVar {
+    name: Arc<str>,
+    signal_tpe: FstSignalType,
+    tpe: FstVarType,
+    dir: FstVarDirection,
+    /// provisional id of the aliased signal
+    alias: Option<FstSignalId>,
+    /// provisional id
+    id: FstSignalId,
+    /// index into `vars`, if retained
+    info: Option<usize>,
+}
+
+impl Hierarchy {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: HierarchyBuffer::new(None),
+            signals: vec![],
+            names: HashSet::new(),
+            retain: true,
+            scopes: vec![],
+            vars: vec![],
+            var_signals: vec![],
+            num_scopes: 0,
+            num_vars: 0,
+            open_scopes: vec![],
+            path_ids: HashMap::new(),
+            order: FstSignalOrder::Declaration,
+            pending: vec![],
+            provisional: vec![],
+            remap: vec![],
+        }
+    }
+
+    /// Returns a shared copy of `name`, allocating only the first time a name is seen.
+    pub(crate) fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(interned) = self.names.get(name) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(name);
+        self.names.insert(interned.clone());
+        interned
+    }
+
+    pub(crate) fn set_signal_order(&mut self, order: FstSignalOrder) -> Result<()> {
+        // provisional and final ids must not be mixed
+        if order != self.order && !(self.signals.is_empty() && self.pending.is_empty()) {
+            return Err(FstWriteError::ConfigAfterDeclarations("signal_order"));
+        }
+        self.order = order;
+        Ok(())
+    }
+
+    /// Compresses the hierarchy while it is declared with the given zlib level.
+    pub(crate) fn set_incremental_compression(&mut self, level: Option<u8>) -> Result<()> {
+        self.buf.set_incremental_compression(level)
+    }
+
+    /// Keeps all declared scopes and vars around, e.g., for [`Hierarchy::alias_scope`].
+    /// Retention can only be turned on again before anything was declared.
+    pub(crate) fn set_retain_declarations(&mut self, retain: bool) -> Result<()> {
+        if retain && !self.retain && self.num_scopes + self.num_vars > 0 {
+            return Err(FstWriteError::ConfigAfterDeclarations(
+                "retain_declarations",
+            ));
+        }
+        if !retain {
+            self.scopes = vec![];
+            self.vars = vec![];
+            self.var_signals = vec![];
+            self.names = HashSet::new();
+        }
+        self.retain = retain;
+        Ok(())
+    }
+
+    pub(crate) fn scope(
+        &mut self,
+        name: impl AsRef<str>,
+        component: impl AsRef<str>,
+        tpe: FstScopeType,
+    ) -> Result<usize> {
+        self.flush_pending()?;
+        write_hierarchy_scope(&mut self.buf, &name, &component, tpe)?;
+        let index = self.num_scopes;
+        self.num_scopes += 1;
+        let parent = self.open_scopes.last().map(|(parent, _)| *parent);
+        if self.retain {
+            let name = self.intern(name.as_ref());
+            let component = self.intern(component.as_ref());
+            self.open_scopes.push((index, name.clone()));
+            self.scopes.push(FstScopeInfo {
+                name,
+                component,
+                tpe,
+                parent,
+            });
+        } else {
+            self.open_scopes.push((index, Arc::from(name.as_ref())));
+        }
+        Ok(index)
+    }
+
+    pub(crate) fn up_scope(&mut self) -> Result<()> {
+        if self.open_scopes.is_empty() {
+            return Err(FstWriteError::NoOpenScope);
+        }
+        self.flush_pending()?;
+        self.open_scopes.pop();
+        write_hierarchy_up_scope(&mut self.buf)
+    }
+
+    /// Declares a var. Returns a provisional id when reordering signals.
+    pub(crate) fn var(
+        &mut self,
+        name: impl AsRef<str>,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+        alias: Option<FstSignalId>,
+    ) -> Result<FstSignalId> {
+        if self.order == FstSignalOrder::Declaration {
+            let id = self.write_var(&name, signal_tpe, tpe, dir, alias)?;
+            self.record_var(
+                name.as_ref(),
+                id,
+                signal_tpe,
+                tpe,
+                dir,
+                alias.is_some(),
+                Some(id),
+            );
+            return Ok(id);
+        }
+        check_hierarchy_name(name.as_ref())?;
+        let id = if let Some(alias) = alias {
+            if alias.to_array_index() >= self.provisional.len() {
+                return Err(FstWriteError::InvalidSignalId(alias));
+            }
+            alias
+        } else {
+            self.provisional.push(signal_tpe);
+            self.remap.push(None);
+            FstSignalId::from_index(self.provisional.len() as u32)
+        };
+        let info = self.retain.then_some(self.vars.len());
+        self.record_var(
+            name.as_ref(),
+            id,
+            signal_tpe,
+            tpe,
+            dir,
+            alias.is_some(),
+            None,
+        );
+        let name = self.intern(name.as_ref());
+        self.pending.push(PendingVar {
+            name,
+            signal_tpe,
+            tpe,
+            dir,
+            alias,
+            id,
+            info,
+        });
+        Ok(id)
+    }
+
+    /// Declares a var without reordering. `alias` and the returned id are never provisional.
+    pub(crate) fn var_immediate(
+        &mut self,
+        name: impl AsRef<str>,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+        alias: Option<FstSignalId>,
+    ) -> Result<FstSignalId> {
+        self.flush_pending()?;
+        let id = self.write_var(&name, signal_tpe, tpe, dir, alias)?;
+        self.record_var(
+            name.as_ref(),
+            id,
+            signal_tpe,
+            tpe,
+            dir,
+            alias.is_some(),
+            Some(id),
+        );
+        if self.order != FstSignalOrder::Declaration && alias.is_none() {
+            // provisional and final ids are the same after all pending vars were written
+            self.provisional.push(signal_tpe);
+            self.remap.push(Some(id));
+        }
+        Ok(id)
+    }
+
+    /// Sorts all pending vars and writes them to the hierarchy.
+    pub(crate) fn flush_pending(&mut self) -> Result<()> {
+        let mut pending = std::mem::take(&mut self.pending);
+        // stable sort keeps aliases behind the signal they refer to
+        pending.sort_by_key(|var| self.provisional[var.id.to_array_index()].len());
+        for var in pending {
+            let alias = match var.alias {
+                Some(alias) => match self.remap[alias.to_array_index()] {
+                    Some(id) => Some(id),
+                    None => return Err(FstWriteError::InvalidSignalId(alias)),
+                },
+                None => None,
+            };
+            let id = self.write_var(var.name, var.signal_tpe, var.tpe, var.dir, alias)?;
+            if alias.is_none() {
+                self.remap[var.id.to_array_index()] = Some(id);
+            }
+            if let Some(info) = var.info {
+                self.var_signals[info] = Some(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps provisional ids to signal ids. Only complete after [`Hierarchy::flush_pending`].
+    pub(crate) fn signal_remap(&self) -> Vec<FstSignalId> {
+        if self.order == FstSignalOrder::Declaration {
+            (1..=self.signals.len() as u32)
+                .map(FstSignalId::from_index)
+                .collect()
+        } else {
+            self.remap.iter().flatten().cloned().collect()
+        }
+    }
+
+    /// Type of a signal as it was declared, i.e., indexed by provisional id.
+    pub(crate) fn declared_signal_type(&self, id: FstSignalId) -> Option<FstSignalType> {
+        if self.order == FstSignalOrder::Declaration {
+            self.signals.get(id.to_array_index()).cloned()
+        } else {
+            self.provisional.get(id.to_array_index()).cloned()
+        }
+    }
+
+    pub(crate) fn num_declared_signals(&self) -> usize {
+        if self.order == FstSignalOrder::Declaration {
+            self.signals.len()
+        } else {
+            self.provisional.len()
+        }
+    }
+
+    fn write_var(
+        &mut self,
+        name: impl AsRef<str>,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+        alias: Option<FstSignalId>,
+    ) -> Result<FstSignalId> {
+        if let Some(alias) = alias.filter(|a| a.to_array_index() >= self.signals.len()) {
+            return Err(FstWriteError::InvalidSignalId(alias));
+        }
+        write_hierarchy_var(&mut self.buf, tpe, dir, &name, signal_tpe, alias)?;
+        let signal_id = if let Some(alias) = alias {
+            alias
+        } else {
+            self.signals.push(signal_tpe);
+            FstSignalId::from_index(self.signals.len() as u32)
+        };
+        self.num_vars += 1;
+        Ok(signal_id)
+    }
+
+    /// Keeps a declared var with the id that was returned for it, if retained.
+    /// `signal` is the id it was written with, unless it is still pending.
+    #[allow(clippy::too_many_arguments)]
+    fn record_var(
+        &mut self,
+        name: &str,
+        signal_id: FstSignalId,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+        is_alias: bool,
+        signal: Option<FstSignalId>,
+    ) {
+        if self.retain {
+            let name = self.intern(name);
+            self.vars.push(FstVarInfo {
+                name,
+                scope: self.open_scopes.last().map(|(scope, _)| *scope),
+                signal_id,
+                signal_tpe,
+                tpe,
+                dir,
+                is_alias,
+            });
+            self.var_signals.push(signal);
+        }
+    }
+
+    /// Opens a new scope and declares an alias for every var and sub-scope of `source`.
+    pub(crate) fn alias_scope(
+        &mut self,
+        name: impl AsRef<str>,
+        component: impl AsRef<str>,
+        source: usize,
+    ) -> Result<usize> {
+        if !self.retain {
+            return Err(FstWriteError::DeclarationsNotRetained);
+        }
+        if source >= self.scopes.len() {
+            return Err(FstWriteError::InvalidScope(source));
+        }
+        if self.open_scopes.iter().any(|(open, _)| *open == source) {
+            return Err(FstWriteError::ScopeStillOpen(source));
+        }
+        // all descendants of a scope are declared right after it
+        let end = (source + 1..self.scopes.len())
+            .find(|&ii| !self.is_descendant(ii, source))
+            .unwrap_or(self.scopes.len());
+        let mut children = vec![vec![]; end - source];
+        for ii in source + 1..end {
+            children[self.scopes[ii].parent.unwrap() - source].push(ii);
+        }
+        let mut vars = vec![vec![]; end - source];
+        for (ii, var) in self.vars.iter().enumerate() {
+            match var.scope {
+                Some(scope) if (source..end).contains(&scope) => vars[scope - source].push(ii),
+                _ => {}
+            }
+        }
+        let index = self.scope(name, component, self.scopes[source].tpe)?;
+        self.alias_scope_content(source, source, &children, &vars)?;
+        self.up_scope()?;
+        Ok(index)
+    }
+
+    fn alias_scope_content(
+        &mut self,
+        scope: usize,
+        source: usize,
+        children: &[Vec<usize>],
+        vars: &[Vec<usize>],
+    ) -> Result<()> {
+        for &var in vars[scope - source].iter() {
+            // the scope was closed, thus none of its vars are pending anymore
+            let info = self.vars[var].clone();
+            let signal =
+                self.var_signals[var].ok_or(FstWriteError::InvalidSignalId(info.signal_id))?;
+            self.var_immediate(info.name, info.signal_tpe, info.tpe, info.dir, Some(signal))?;
+        }
+        for &child in children[scope - source].iter() {
+            let info = self.scopes[child].clone();
+            self.scope(info.name, info.component, info.tpe)?;
+            self.alias_scope_content(child, source, children, vars)?;
+            self.up_scope()?;
+        }
+        Ok(())
+    }
+
+    fn is_descendant(&self, scope: usize, ancestor: usize) -> bool {
+        let mut parent = self.scopes[scope].parent;
+        while let Some(index) = parent {
+            if index == ancestor {
+                return true;
+            }
+            parent = self.scopes[index].parent;
+        }
+        false
+    }
+
+    pub(crate) fn source_stem(
+        &mut self,
+        path: &Path,
+        line: u64,
+        is_instantiation: bool,
+    ) -> Result<()> {
+        self.flush_pending()?;
+        let path_id = self.path_id(path)?;
+        write_hierarchy_source_stem(&mut self.buf, path_id, line, is_instantiation)
+    }
+
+    /// Returns the id of the path name entry, declaring it the first time a path is used.
+    fn path_id(&mut self, path: &Path) -> Result<u64> {
+        if let Some(id) = self.path_ids.get(path) {
+            return Ok(*id);
+        }
+        // ids start at one
+        let id = self.path_ids.len() as u64 + 1;
+        write_hierarchy_path_name(&mut self.buf, &path.to_string_lossy(), id)?;
+        self.path_ids.insert(path.to_path_buf(), id);
+        Ok(id)
+    }
+
+    /// Number of bytes of the encoded hierarchy that are held in memory.
+    pub(crate) fn buffer_size(&self) -> usize {
+        self.buf.size()
+    }
+
+    pub(crate) fn signals(&self) -> &[FstSignalType] {
+        &self.signals
+    }
+
+    pub(crate) fn scopes(&self) -> &[FstScopeInfo] {
+        &self.scopes
+    }
+
+    pub(crate) fn vars(&self) -> &[FstVarInfo] {
+        &self.vars
+    }
+
+    pub(crate) fn num_scopes(&self) -> usize {
+        self.num_scopes
+    }
+
+    pub(crate) fn num_vars(&self) -> usize {
+        self.num_vars
+    }
+
+    /// Names of all open scopes, starting with the outermost one.
+    pub(crate) fn open_scope_names(&self) -> impl Iterator<Item = &str> {
+        self.open_scopes.iter().map(|(_, name)| name.as_ref())
+    }
+
+    pub(crate) fn scope_depth(&self) -> usize {
+        self.open_scopes.len()
+    }
+
+    pub(crate) fn full_name(&self, var: &FstVarInfo) -> String {
+        let mut names = vec![var.name.as_ref()];
+        let mut scope = var.scope;
+        while let Some(index) = scope {
+            names.push(&self.scopes[index].name);
+            scope = self.scopes[index].parent;
+        }
+        names.reverse();
+        names.join(".")
+    }
+
+    /// Full name of the first var of the signal.
+    pub(crate) fn signal_name(&self, signal_id: FstSignalId) -> Option<String> {
+        let var = self
+            .var_signals
+            .iter()
+            .position(|&signal| signal == Some(signal_id))?;
+        Some(self.full_name(&self.vars[var]))
+    }
+
+    pub(crate) fn scope_tree(&self) -> ScopeTree {
+        // pending vars have no signal yet
+        let written = || {
+            self.vars
+                .iter()
+                .zip(self.var_signals.iter())
+                .filter_map(|(var, signal)| signal.map(|signal| (var, signal)))
+        };
+        ScopeTree {
+            discarded: !self.retain,
+            parents: self.scopes.iter().map(|s| s.parent).collect(),
+            vars: written().map(|(v, signal)| (v.scope, signal)).collect(),
+            scope_names: self.scopes.iter().map(|s| s.name.clone()).collect(),
+            var_names: written().map(|(v, _)| v.name.clone()).collect(),
+        }
+    }
+
+    /// Writes the hierarchy and geometry blocks.
+    pub(crate) fn write_blocks(mut self, output: &mut (impl Write + Seek)) -> Result<()> {
+        self.flush_pending()?;
+        if !self.open_scopes.is_empty() {
+            return Err(FstWriteError::UnbalancedScopes {
+                open: self.open_scopes.len(),
+            });
+        }
+        self.buf.write_block(output)?;
+        write_geometry(output, &self.signals)?;
+        Ok(())
+    }
+}
+
+/// Scope parents and the signal of every var. Enough to find all signals of a scope subtree
+/// after the hierarchy was written.
+#[derive(Default)]
+pub(crate) struct ScopeTree {
+    /// the hierarchy did not retain its declarations, thus the tree is empty
+    discarded: bool,
+    parents: Vec<Option<usize>>,
+    /// surrounding scope and signal of every var
+    vars: Vec<(Option<usize>, FstSignalId)>,
+    /// names are interned, thus shared with the hierarchy and between instances
+    scope_names: Vec<Arc<str>>,
+    var_names: Vec<Arc<str>>,
+}
+
+impl ScopeTree {
+    /// Full name of the first var of the signal, e.g., for error messages.
+    pub(crate) fn signal_name(&self, signal_id: FstSignalId) -> Option<String> {
+        let var = self.vars.iter().position(|&(_, id)| id == signal_id)?;
+        let mut names = vec![self.var_names.get(var)?.as_ref()];
+        let mut scope = self.vars[var].0;
+        while let Some(index) = scope {
+            names.push(&self.scope_names[index]);
+            scope = self.parents[index];
+        }
+        names.reverse();
+        Some(names.join("."))
+    }
+
+    /// Signals of all vars in `scope` and its sub-scopes.
+    pub(crate) fn signals(&self, scope: usize) -> Result<Vec<FstSignalId>> {
+        if self.discarded {
+            return Err(FstWriteError::DeclarationsNotRetained);
+        }
+        if scope >= self.parents.len() {
+            return Err(FstWriteError::InvalidScope(scope));
+        }
+        // every scope is declared after its parent
+        let mut inside = vec![false; self.parents.len()];
+        inside[scope] = true;
+        for ii in scope + 1..self.parents.len() {
+            inside[ii] = self.parents[ii].is_some_and(|parent| inside[parent]);
+        }
+        Ok(self
+            .vars
+            .iter()
+            .filter(|(scope, _)| scope.is_some_and(|scope| inside[scope]))
+            .map(|(_, signal_id)| *signal_id)
+            .collect())
+    }
+}
@@ -4,58 +4,102 @@
 // author: Kevin Laeufer <laeufer@cornell.edu>
 
 use crate::FstWriteError::InvalidCharacter;
+#[cfg(feature = "fs")]
+use crate::spill::TempFile;
 use crate::{
-    FstInfo, FstScopeType, FstSignalId, FstSignalType, FstVarDirection, FstVarType, FstWriteError,
-    Result,
+    FstCompressionLevels, FstInfo, FstPackType, FstScopeType, FstSignalId, FstSignalType,
+    FstVarDirection, FstVarType, FstWriteError, Result,
 };
-use std::io::{Seek, SeekFrom, Write};
+use miniz_oxide::deflate::core::{
+    CompressorOxide, TDEFLFlush, TDEFLStatus, compress_to_output, create_comp_flags_from_zip_params,
+};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Maximum number of bytes of a LEB128 encoded 64-bit value.
+pub(crate) const MAX_VARIANT_LEN: usize = 10;
+
+/// Converts an offset or length that is tracked as `u64`, which might not fit into memory
+/// on 32-bit targets.
+pub(crate) fn checked_usize(value: u64, what: &'static str) -> Result<usize> {
+    usize::try_from(value).map_err(|_| FstWriteError::TooLarge(what))
+}
 
+/// Converts a length for a 32-bit field.
+pub(crate) fn checked_u32(value: usize, what: &'static str) -> Result<u32> {
+    u32::try_from(value).map_err(|_| FstWriteError::TooLarge(what))
+}
+
+/// Decodes a value written by [`encode_variant_u64`] and returns it with the number of bytes
+/// it took up, `None` if the input ends before the value does.
 #[inline]
-pub(crate) fn write_variant_u64(output: &mut impl Write, mut value: u64) -> Result<usize> {
-    // often, the value is small
-    if value <= 0x7f {
-        let byte = [value as u8; 1];
-        output.write_all(&byte)?;
-        return Ok(1);
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+pub(crate) fn read_variant_u64(input: &[u8]) -> Option<(u64, usize)> {
+    let mut res = 0u64;
+    for (ii, byte) in input.iter().take(MAX_VARIANT_LEN).enumerate() {
+        res |= ((byte & 0x7f) as u64) << (7 * ii);
+        if byte & 0x80 == 0 {
+            return Some((res, ii + 1));
+        }
     }
+    None
+}
 
-    let mut bytes = Vec::with_capacity(10);
-    while value != 0 {
+/// Error for files that are read back, e.g., to recover or append to them.
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+pub(crate) fn invalid_file(reason: impl ToString) -> FstWriteError {
+    FstWriteError::InvalidFile(reason.to_string())
+}
+
+/// Encodes the value without allocating and returns the number of bytes used.
+#[inline]
+pub(crate) fn encode_variant_u64(mut value: u64) -> ([u8; MAX_VARIANT_LEN], usize) {
+    let mut bytes = [0u8; MAX_VARIANT_LEN];
+    let mut len = 0;
+    loop {
         let next_value = value >> 7;
         let mask: u8 = if next_value == 0 { 0 } else { 0x80 };
-        bytes.push((value & 0x7f) as u8 | mask);
+        bytes[len] = (value & 0x7f) as u8 | mask;
+        len += 1;
         value = next_value;
+        if value == 0 {
+            return (bytes, len);
+        }
     }
-    assert!(bytes.len() <= 10);
-    output.write_all(&bytes)?;
-    Ok(bytes.len())
 }
 
+/// Encodes the value without allocating and returns the number of bytes used.
 #[inline]
-pub(crate) fn write_variant_i64(output: &mut impl Write, mut value: i64) -> Result<usize> {
-    // often, the value is small
-    if (-64..=63).contains(&value) {
-        let byte = [value as u8 & 0x7f; 1];
-        output.write_all(&byte)?;
-        return Ok(1);
-    }
-
+pub(crate) fn encode_variant_i64(mut value: i64) -> ([u8; MAX_VARIANT_LEN], usize) {
     // calculate the number of bits we need to represent
     let bits = if value >= 0 {
         64 - value.leading_zeros() + 1
     } else {
         64 - value.leading_ones() + 1
     };
-    let num_bytes = bits.div_ceil(7) as usize;
-
-    let mut bytes = Vec::with_capacity(num_bytes);
-    for ii in 0..num_bytes {
-        let mark = if ii == num_bytes - 1 { 0 } else { 0x80 };
-        bytes.push((value & 0x7f) as u8 | mark);
+    let len = bits.div_ceil(7) as usize;
+    let mut bytes = [0u8; MAX_VARIANT_LEN];
+    for (ii, byte) in bytes.iter_mut().take(len).enumerate() {
+        let mark = if ii == len - 1 { 0 } else { 0x80 };
+        *byte = (value & 0x7f) as u8 | mark;
         value >>= 7;
     }
-    output.write_all(&bytes)?;
-    Ok(bytes.len())
+    (bytes, len)
+}
+
+#[inline]
+pub(crate) fn write_variant_u64(output: &mut impl Write, value: u64) -> Result<usize> {
+    let (bytes, len) = encode_variant_u64(value);
+    output.write_all(&bytes[..len])?;
+    Ok(len)
+}
+
+#[inline]
+pub(crate) fn write_variant_i64(output: &mut impl Write, value: i64) -> Result<usize> {
+    let (bytes, len) = encode_variant_i64(value);
+    output.write_all(&bytes[..len])?;
+    Ok(len)
 }
 
 #[inline]
@@ -85,6 +129,16 @@ fn write_c_str(output: &mut impl Write, value: impl AsRef<str>) -> Result<()> {
     Ok(())
 }
 
+/// Makes sure that `value` plus its zero terminator fits into `max_len` bytes.
+#[inline]
+fn check_c_str_len(value: &str, max_len: usize) -> Result<()> {
+    if value.len() >= max_len {
+        Err(FstWriteError::StringTooLong(max_len, value.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
 #[inline]
 fn write_c_str_fixed_length(output: &mut impl Write, value: &str, max_len: usize) -> Result<()> {
     let bytes = value.as_bytes();
@@ -105,18 +159,28 @@ fn write_f64(output: &mut impl Write, value: f64) -> Result<()> {
     Ok(())
 }
 
-const HEADER_LENGTH: u64 = 329;
+pub(crate) const HEADER_LENGTH: u64 = 329;
 const HEADER_VERSION_MAX_LEN: usize = 128;
 const HEADER_DATE_MAX_LEN: usize = 119;
 const DOUBLE_ENDIAN_TEST: f64 = std::f64::consts::E;
 
 #[repr(u8)]
-#[derive(Debug, PartialEq)]
-enum BlockType {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BlockType {
     Header = 0,
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    VcData = 1,
+    Blackout = 2,
     Geometry = 3,
+    Hierarchy = 4,
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    VcDataDynamicAlias = 5,
     HierarchyLZ4 = 6,
+    HierarchyLZ4Duo = 7,
     VcDataDynamicAlias2 = 8,
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    GZipWrapper = 254,
+    Skip = 255,
 }
 
 //////////////// Header
@@ -124,9 +188,11 @@ const HEADER_POS: u64 = 0;
 
 /// Writes the user supplied meta-data to the header. We will come back to the header later to
 /// fill in other data.
+/// Writes the header with the `start_time` of the first value change block.
 pub(crate) fn write_header_meta_data(
     output: &mut (impl Write + Seek),
     info: &FstInfo,
+    start_time: u64,
 ) -> Result<()> {
     debug_assert_eq!(
         output.stream_position().unwrap(),
@@ -135,10 +201,10 @@ pub(crate) fn write_header_meta_data(
     );
     write_u8(output, BlockType::Header as u8)?;
     write_u64(output, HEADER_LENGTH)?;
-    write_u64(output, 0)?; // start time is always zero
+    write_u64(output, start_time)?;
     write_u64(output, 0)?; // dummy end time
     write_f64(output, DOUBLE_ENDIAN_TEST)?;
-    write_u64(output, 0)?; // memory used by writer is always zero, we do not compute this
+    write_u64(output, 0)?; // dummy memory used by writer
     write_u64(output, 0)?; // dummy scope count
     write_u64(output, 0)?; // dummy var count
     write_u64(output, 0)?; // dummy num signals
@@ -157,6 +223,8 @@ pub(crate) struct HeaderFinishInfo {
     pub(crate) var_count: u64,
     pub(crate) num_signals: u64,
     pub(crate) num_value_change_sections: u64,
+    /// peak number of bytes that the writer buffered in memory
+    pub(crate) memory_used: u64,
 }
 
 pub(crate) fn update_header(
@@ -166,8 +234,9 @@ pub(crate) fn update_header(
     // go to start of header + skip block type, length and start time
     output.seek(SeekFrom::Start(HEADER_POS + 1 + 2 * 8))?;
     write_u64(output, info.end_time)?;
-    // skip endian test + writer memory
-    output.seek(SeekFrom::Current(2 * 8))?;
+    // skip endian test
+    output.seek(SeekFrom::Current(8))?;
+    write_u64(output, info.memory_used)?;
     write_u64(output, info.scope_count)?;
     write_u64(output, info.var_count)?;
     write_u64(output, info.num_signals)?;
@@ -175,35 +244,303 @@ pub(crate) fn update_header(
     Ok(())
 }
 
+/// Reads back the fields written by [`update_header`], e.g., to append to an existing file.
+#[cfg(feature = "fs")]
+pub(crate) fn read_header(input: &mut (impl Read + Seek)) -> Result<HeaderFinishInfo> {
+    // block type followed by eight fields up to the number of value change sections
+    let mut header = [0u8; 1 + 9 * 8];
+    input.seek(SeekFrom::Start(HEADER_POS))?;
+    input.read_exact(&mut header)?;
+    if header[0] != BlockType::Header as u8 {
+        return Err(FstWriteError::InvalidFile(
+            "expected an uncompressed FST header".to_string(),
+        ));
+    }
+    let field = |index: usize| {
+        let start = 1 + index * 8;
+        u64::from_be_bytes(header[start..start + 8].try_into().unwrap())
+    };
+    Ok(HeaderFinishInfo {
+        end_time: field(2),
+        memory_used: field(4),
+        scope_count: field(5),
+        var_count: field(6),
+        num_signals: field(7),
+        num_value_change_sections: field(8),
+    })
+}
+
 //////////////// Hierarchy
 
-const HIERARCHY_TPE_VCD_SCOPE: u8 = 254;
-const HIERARCHY_TPE_VCD_UP_SCOPE: u8 = 255;
-// const HIERARCHY_TPE_VCD_ATTRIBUTE_BEGIN: u8 = 252;
-// const HIERARCHY_TPE_VCD_ATTRIBUTE_END: u8 = 253;
+pub(crate) const HIERARCHY_TPE_VCD_SCOPE: u8 = 254;
+pub(crate) const HIERARCHY_TPE_VCD_UP_SCOPE: u8 = 255;
+pub(crate) const HIERARCHY_TPE_VCD_ATTRIBUTE_BEGIN: u8 = 252;
+pub(crate) const HIERARCHY_TPE_VCD_ATTRIBUTE_END: u8 = 253;
 const HIERARCHY_NAME_MAX_SIZE: usize = 512;
-// const HIERARCHY_ATTRIBUTE_MAX_SIZE: usize = 65536 + 4096;
+const HIERARCHY_ATTRIBUTE_MAX_SIZE: usize = 65536 + 4096;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttributeType {
+    Misc = 0,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MiscType {
+    PathName = 3,
+    SourceStem = 4,
+    SourceInstantiationStem = 5,
+}
+
+/// Collects the hierarchy section until it can be written to the output.
+pub(crate) enum HierarchyBuffer {
+    /// uncompressed bytes which get LZ4 compressed in one go
+    Uncompressed(Vec<u8>),
+    /// compressed with deflate while the hierarchy is being declared
+    Deflate(Box<DeflateStream>),
+}
+
+pub(crate) struct DeflateStream {
+    compressor: CompressorOxide,
+    /// compressed bytes that were not spooled yet
+    compressed: Vec<u8>,
+    /// holds the compressed bytes of huge hierarchies, created once the first chunk is full
+    #[cfg(feature = "fs")]
+    spool: Option<TempFile>,
+    /// number of compressed bytes in `spool`
+    spooled_len: u64,
+    crc: crc32fast::Hasher,
+    uncompressed_len: u64,
+}
+
+impl HierarchyBuffer {
+    /// Compresses incrementally with the given zlib level, or not at all if `None`.
+    pub(crate) fn new(incremental_level: Option<u8>) -> Self {
+        match incremental_level {
+            Some(level) => Self::Deflate(Box::new(DeflateStream::new(level))),
+            None => Self::Uncompressed(vec![]),
+        }
+    }
+
+    /// Switches compression mode while keeping all entries written so far. Once compressed,
+    /// the hierarchy cannot be turned back into uncompressed form.
+    pub(crate) fn set_incremental_compression(&mut self, level: Option<u8>) -> Result<()> {
+        match self {
+            HierarchyBuffer::Uncompressed(bytes) if level.is_some() => {
+                let bytes = std::mem::take(bytes);
+                *self = Self::new(level);
+                self.write_all(&bytes)?;
+            }
+            HierarchyBuffer::Deflate(stream) if stream.uncompressed_len == 0 => {
+                *self = Self::new(level);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Number of bytes held in memory. With the `fs` feature, compressed bytes are moved into
+    /// a temporary file in chunks, thus memory stays bounded for arbitrarily large hierarchies.
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            HierarchyBuffer::Uncompressed(bytes) => bytes.len(),
+            HierarchyBuffer::Deflate(stream) => stream.compressed.len(),
+        }
+    }
+
+    /// Writes the hierarchy block.
+    pub(crate) fn write_block(self, output: &mut (impl Write + Seek)) -> Result<()> {
+        match self {
+            HierarchyBuffer::Uncompressed(bytes) => write_hierarchy_bytes(output, &bytes),
+            HierarchyBuffer::Deflate(mut stream) => {
+                stream.deflate(&[], TDEFLFlush::Finish)?;
+                write_u8(output, BlockType::Hierarchy as u8)?;
+                let compressed_len = GZIP_HEADER.len() as u64
+                    + stream.spooled_len
+                    + stream.compressed.len() as u64
+                    + 2 * 4;
+                write_u64(output, 2 * 8 + compressed_len)?; // section length
+                write_u64(output, stream.uncompressed_len)?;
+                output.write_all(&GZIP_HEADER)?;
+                #[cfg(feature = "fs")]
+                stream.copy_spooled(output)?;
+                output.write_all(&stream.compressed)?;
+                stream.write_trailer(output)
+            }
+        }
+    }
+}
+
+impl DeflateStream {
+    fn new(level: u8) -> Self {
+        // negative window bits result in a raw deflate stream without zlib header
+        let flags = create_comp_flags_from_zip_params(level as i32, -15, 0);
+        Self {
+            compressor: CompressorOxide::new(flags),
+            compressed: vec![],
+            #[cfg(feature = "fs")]
+            spool: None,
+            spooled_len: 0,
+            crc: crc32fast::Hasher::new(),
+            uncompressed_len: 0,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.deflate(bytes, TDEFLFlush::None)?;
+        self.crc.update(bytes);
+        self.uncompressed_len += bytes.len() as u64;
+        #[cfg(feature = "fs")]
+        if self.compressed.len() >= SPOOL_CHUNK_SIZE {
+            self.spool_compressed()?;
+        }
+        Ok(())
+    }
+
+    /// Moves all compressed bytes from memory into the temporary file.
+    #[cfg(feature = "fs")]
+    fn spool_compressed(&mut self) -> std::io::Result<()> {
+        let spool = match &mut self.spool {
+            Some(spool) => spool,
+            None => self.spool.insert(TempFile::new()?),
+        };
+        spool.file.write_all(&self.compressed)?;
+        self.spooled_len += self.compressed.len() as u64;
+        self.compressed.clear();
+        Ok(())
+    }
+
+    /// Copies the compressed bytes that were moved into the temporary file to `output`.
+    #[cfg(feature = "fs")]
+    fn copy_spooled(&mut self, output: &mut impl Write) -> Result<()> {
+        if let Some(spool) = &mut self.spool {
+            spool.file.seek(SeekFrom::Start(0))?;
+            let copied = std::io::copy(&mut (&spool.file).take(self.spooled_len), output)?;
+            if copied != self.spooled_len {
+                return Err(FstWriteError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_trailer(self, output: &mut impl Write) -> Result<()> {
+        output.write_all(&self.crc.finalize().to_le_bytes())?;
+        output.write_all(&(self.uncompressed_len as u32).to_le_bytes())?;
+        Ok(())
+    }
+
+    fn deflate(&mut self, bytes: &[u8], flush: TDEFLFlush) -> std::io::Result<()> {
+        let compressed = &mut self.compressed;
+        let (status, consumed) = compress_to_output(&mut self.compressor, bytes, flush, |out| {
+            compressed.extend_from_slice(out);
+            true
+        });
+        debug_assert_eq!(consumed, bytes.len());
+        match status {
+            TDEFLStatus::Okay | TDEFLStatus::Done => Ok(()),
+            other => Err(std::io::Error::other(format!(
+                "failed to compress: {other:?}"
+            ))),
+        }
+    }
+}
+
+impl Write for HierarchyBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            HierarchyBuffer::Uncompressed(bytes) => bytes.extend_from_slice(buf),
+            HierarchyBuffer::Deflate(stream) => stream.update(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compressed hierarchy bytes that are collected in memory before they are moved into a
+/// temporary file.
+#[cfg(feature = "fs")]
+const SPOOL_CHUNK_SIZE: usize = 64 * 1024;
+
+const GZIP_HEADER: [u8; 10] = [
+    0x1f, 0x8b, // magic bytes
+    8,    // using deflate
+    0,    // no flags
+    0, 0, 0, 0,   // timestamp = 0
+    0,   // compression level (does not really matter)
+    255, // OS set to 255 by default
+];
+
+/// Compresses a complete FST file from `input` into a gzip wrapper block.
+#[cfg(feature = "fs")]
+pub(crate) fn write_gzip_wrapper(
+    input: &mut impl Read,
+    output: &mut (impl Write + Seek),
+    level: u8,
+) -> Result<()> {
+    let start = output.stream_position()?;
+    write_u8(output, BlockType::GZipWrapper as u8)?;
+    write_u64(output, 0)?; // dummy section length, zero marks an unfinished wrapper
+    write_u64(output, 0)?; // dummy uncompressed length
+    output.write_all(&GZIP_HEADER)?;
+    let mut stream = DeflateStream::new(level);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let len = input.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        stream.update(&buf[..len])?;
+        output.write_all(&stream.compressed)?;
+        stream.compressed.clear();
+    }
+    stream.deflate(&[], TDEFLFlush::Finish)?;
+    output.write_all(&stream.compressed)?;
+    let uncompressed_len = stream.uncompressed_len;
+    stream.write_trailer(output)?;
 
-pub(crate) fn write_hierarchy_bytes(output: &mut (impl Write + Seek), bytes: &[u8]) -> Result<()> {
-    write_u8(output, BlockType::HierarchyLZ4 as u8)?;
+    // fix lengths
+    let end = output.stream_position()?;
+    output.seek(SeekFrom::Start(start + 1))?;
+    write_u64(output, end - start - 1)?;
+    write_u64(output, uncompressed_len)?;
+    output.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+fn write_hierarchy_bytes(output: &mut (impl Write + Seek), bytes: &[u8]) -> Result<()> {
+    // The hierarchy block cannot be stored uncompressed. Instead, we compress a second time
+    // when that actually shrinks the data, like fstapi does.
+    let compressed = lz4_flex::compress(bytes);
+    let compressed_twice = lz4_flex::compress(&compressed);
+    let duo = compressed_twice.len() < compressed.len();
+    let tpe = if duo {
+        BlockType::HierarchyLZ4Duo
+    } else {
+        BlockType::HierarchyLZ4
+    };
+    write_u8(output, tpe as u8)?;
     // remember start to fix the section length afterward
     let start = output.stream_position()?;
     write_u64(output, 0)?; // dummy section length
     let uncompressed_length = bytes.len() as u64;
     write_u64(output, uncompressed_length)?;
-
-    // we only support single LZ4 compression
-    let out2 = {
-        let compressed = lz4_flex::compress(bytes);
+    if duo {
+        // length after the first compression
+        write_variant_u64(output, compressed.len() as u64)?;
+        output.write_all(&compressed_twice)?;
+    } else {
         output.write_all(&compressed)?;
-        output
-    };
+    }
 
     // fix section length
-    let end = out2.stream_position()?;
-    out2.seek(SeekFrom::Start(start))?;
-    write_u64(out2, end - start)?;
-    out2.seek(SeekFrom::Start(end))?;
+    let end = output.stream_position()?;
+    output.seek(SeekFrom::Start(start))?;
+    write_u64(output, end - start)?;
+    output.seek(SeekFrom::Start(end))?;
     Ok(())
 }
 
@@ -213,11 +550,12 @@ pub(crate) fn write_hierarchy_scope(
     component: impl AsRef<str>,
     tpe: FstScopeType,
 ) -> Result<()> {
+    // check before writing anything in order to never leave a partial entry behind
+    check_c_str_len(name.as_ref(), HIERARCHY_NAME_MAX_SIZE)?;
+    check_c_str_len(component.as_ref(), HIERARCHY_NAME_MAX_SIZE)?;
     write_u8(output, HIERARCHY_TPE_VCD_SCOPE)?;
     write_u8(output, tpe as u8)?;
-    debug_assert!(name.as_ref().len() <= HIERARCHY_NAME_MAX_SIZE);
     write_c_str(output, name)?;
-    debug_assert!(component.as_ref().len() <= HIERARCHY_NAME_MAX_SIZE);
     write_c_str(output, component)?;
     Ok(())
 }
@@ -226,6 +564,11 @@ pub(crate) fn write_hierarchy_up_scope(output: &mut impl Write) -> Result<()> {
     write_u8(output, HIERARCHY_TPE_VCD_UP_SCOPE)
 }
 
+/// Makes sure that `name` can be used for a scope or var.
+pub(crate) fn check_hierarchy_name(name: &str) -> Result<()> {
+    check_c_str_len(name, HIERARCHY_NAME_MAX_SIZE)
+}
+
 pub(crate) fn write_hierarchy_var(
     output: &mut impl Write,
     tpe: FstVarType,
@@ -234,9 +577,9 @@ pub(crate) fn write_hierarchy_var(
     signal_tpe: FstSignalType,
     alias: Option<FstSignalId>,
 ) -> Result<()> {
+    check_c_str_len(name.as_ref(), HIERARCHY_NAME_MAX_SIZE)?;
     write_u8(output, tpe as u8)?;
     write_u8(output, direction as u8)?;
-    debug_assert!(name.as_ref().len() <= HIERARCHY_NAME_MAX_SIZE);
     write_c_str(output, name)?;
     let length = signal_tpe.len();
     let raw_length = if tpe == FstVarType::Port {
@@ -252,6 +595,62 @@ pub(crate) fn write_hierarchy_var(
     Ok(())
 }
 
+fn write_hierarchy_attribute_begin(
+    output: &mut impl Write,
+    tpe: AttributeType,
+    subtype: MiscType,
+    name: &[u8],
+    arg: u64,
+) -> Result<()> {
+    write_u8(output, HIERARCHY_TPE_VCD_ATTRIBUTE_BEGIN)?;
+    write_u8(output, tpe as u8)?;
+    write_u8(output, subtype as u8)?;
+    debug_assert!(name.len() <= HIERARCHY_ATTRIBUTE_MAX_SIZE);
+    output.write_all(name)?;
+    write_u8(output, 0)?;
+    write_variant_u64(output, arg)?;
+    Ok(())
+}
+
+fn write_hierarchy_attribute_end(output: &mut impl Write) -> Result<()> {
+    write_u8(output, HIERARCHY_TPE_VCD_ATTRIBUTE_END)
+}
+
+/// Declares a path that source stems can refer to through `id`.
+pub(crate) fn write_hierarchy_path_name(
+    output: &mut impl Write,
+    name: &str,
+    id: u64,
+) -> Result<()> {
+    check_c_str_len(name, HIERARCHY_ATTRIBUTE_MAX_SIZE)?;
+    write_hierarchy_attribute_begin(
+        output,
+        AttributeType::Misc,
+        MiscType::PathName,
+        name.as_bytes(),
+        id,
+    )?;
+    write_hierarchy_attribute_end(output)
+}
+
+pub(crate) fn write_hierarchy_source_stem(
+    output: &mut impl Write,
+    path_id: u64,
+    line: u64,
+    is_instantiation: bool,
+) -> Result<()> {
+    let subtype = if is_instantiation {
+        MiscType::SourceInstantiationStem
+    } else {
+        MiscType::SourceStem
+    };
+    // for source stems, the path id is stored as a varint in place of the name
+    let mut name = Vec::with_capacity(10);
+    write_variant_u64(&mut name, path_id)?;
+    write_hierarchy_attribute_begin(output, AttributeType::Misc, subtype, &name, line)?;
+    write_hierarchy_attribute_end(output)
+}
+
 //////////////// Geometry
 
 pub(crate) fn write_geometry(
@@ -283,14 +682,51 @@ pub(crate) fn write_geometry(
     Ok(())
 }
 
+//////////////// Blackout
+
+/// Writes the times at which dumping was switched off (`false`) or on (`true`).
+pub(crate) fn write_blackout(
+    output: &mut (impl Write + Seek),
+    dump_activity: &[(u64, bool)],
+) -> Result<()> {
+    write_u8(output, BlockType::Blackout as u8)?;
+    // remember start to fix the section header
+    let start = output.stream_position()?;
+    write_u64(output, 0)?; // dummy section length
+    write_variant_u64(output, dump_activity.len() as u64)?;
+    let mut prev_time = 0;
+    for &(time, active) in dump_activity.iter() {
+        write_u8(output, active as u8)?;
+        write_variant_u64(output, time - prev_time)?;
+        prev_time = time;
+    }
+
+    // fix section length
+    let end = output.stream_position()?;
+    output.seek(SeekFrom::Start(start))?;
+    write_u64(output, end - start)?;
+    output.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+//////////////// Skip
+
+/// Writes a block that readers ignore, followed by `padding` zero bytes.
+/// Returns the position of the padding.
+pub(crate) fn write_skip(output: &mut (impl Write + Seek), padding: u64) -> Result<u64> {
+    write_u8(output, BlockType::Skip as u8)?;
+    // the section length includes the length field itself
+    write_u64(output, padding + 8)?;
+    let start = output.stream_position()?;
+    std::io::copy(&mut std::io::repeat(0).take(padding), output)?;
+    Ok(start)
+}
+
 //////////////// Value Change Data
 
+/// Value and time delta of a single bit change, to be encoded as a varint.
 #[inline]
-pub(crate) fn write_one_bit_signal(
-    output: &mut impl Write,
-    time_delta: u64,
-    value: u8,
-) -> Result<()> {
+pub(crate) fn one_bit_signal_vli(time_delta: u64, value: u8) -> Result<u64> {
     let vli = match value {
         b'0' | b'1' => {
             let bit = value - b'0';
@@ -308,8 +744,7 @@ pub(crate) fn write_one_bit_signal(
             }
         }
     };
-    write_variant_u64(output, vli)?;
-    Ok(())
+    Ok(vli)
 }
 
 #[inline]
@@ -342,6 +777,19 @@ pub(crate) fn write_multi_bit_signal(
     Ok(())
 }
 
+/// Values of signals without a fixed length, e.g., strings, are prefixed by their length.
+#[inline]
+pub(crate) fn write_variable_length_signal(
+    output: &mut impl Write,
+    time_delta: u64,
+    value: &[u8],
+) -> Result<()> {
+    write_variant_u64(output, time_delta << 1)?;
+    write_variant_u64(output, value.len() as u64)?;
+    output.write_all(value)?;
+    Ok(())
+}
+
 #[allow(dead_code)]
 #[inline]
 pub(crate) fn write_real_signal(
@@ -388,94 +836,286 @@ pub(crate) fn write_time_chain_update(
 }
 
 const VALUE_CHANGE_PACK_TYPE_LZ4: u8 = b'4';
+const VALUE_CHANGE_PACK_TYPE_ZLIB: u8 = b'Z';
+const VALUE_CHANGE_PACK_TYPE_FASTLZ: u8 = b'F';
 
-#[inline]
-fn flush_zeros(output: &mut impl Write, zeros: &mut u32) -> Result<()> {
-    if *zeros > 0 {
-        // shifted by one because bit0 indicates whether we are dealing with a zero or a real offset
-        let value = *zeros << 1;
-        write_variant_u64(output, value as u64)?;
-        *zeros = 0;
-    }
-    debug_assert_eq!(*zeros, 0);
-    Ok(())
+/// Position table of a `VcDataDynamicAlias2` block, encoded the same way as in fstapi.
+/// Each entry is a varint whose lowest bit distinguishes between a run of signals without
+/// changes in this block (bit cleared, readers fall back to the frame) and a signal with data
+/// (bit set). Signals with data are encoded as a signed value: positive values are the offset
+/// delta to the previous signal with data, negative values `-(idx + 1)` refer to the identical
+/// data of signal `idx` and zero repeats the previous alias.
+struct ChainTable<W: Write> {
+    output: W,
+    zeros: u64,
+    prev_offset: u64,
+    prev_alias: Option<usize>,
 }
 
-/// For any signal change streams smaller than this size, we won't even attempt LZ4 compression
+impl<W: Write> ChainTable<W> {
+    /// `start` is the position of the pack type byte that precedes the value change data.
+    fn new(output: W, start: u64) -> Self {
+        Self {
+            output,
+            zeros: 0,
+            prev_offset: start,
+            prev_alias: None,
+        }
+    }
+
+    /// `count` signals without any changes in this block.
+    fn idle(&mut self, count: u64) {
+        self.zeros += count;
+    }
+
+    /// Signal whose value changes start at `offset`.
+    fn data(&mut self, offset: u64) -> Result<()> {
+        self.flush_zeros()?;
+        debug_assert!(offset > self.prev_offset);
+        let offset_delta = (offset - self.prev_offset) as i64;
+        write_variant_i64(&mut self.output, (offset_delta << 1) | 1)?;
+        self.prev_offset = offset;
+        Ok(())
+    }
+
+    /// Signal whose value changes are the same as the ones of the earlier signal `idx`.
+    fn alias(&mut self, idx: usize) -> Result<()> {
+        self.flush_zeros()?;
+        if self.prev_alias == Some(idx) {
+            write_variant_i64(&mut self.output, 1)?;
+        } else {
+            write_variant_i64(&mut self.output, (-(idx as i64 + 1) << 1) | 1)?;
+            self.prev_alias = Some(idx);
+        }
+        Ok(())
+    }
+
+    /// Writes any trailing run of idle signals.
+    fn finish(mut self) -> Result<W> {
+        self.flush_zeros()?;
+        Ok(self.output)
+    }
+
+    fn flush_zeros(&mut self) -> Result<()> {
+        if self.zeros > 0 {
+            // shifted by one because bit0 indicates whether we are dealing with a zero or a real offset
+            write_variant_u64(&mut self.output, self.zeros << 1)?;
+            self.zeros = 0;
+        }
+        Ok(())
+    }
+}
+
+/// For any signal change streams smaller than this size, we won't even attempt compression
 const MIN_SIZE_TO_ATTEMPT_COMPRESSION: usize = 32;
 
+/// Only the signals in `changed` (sorted) are passed to `get_signal_data`, which appends the
+/// value changes of a signal to the buffer, all others are idle in this block.
+#[allow(clippy::too_many_arguments)]
 fn write_value_changes(
     output: &mut (impl Write + Seek),
-    get_signal_data: impl Fn(usize) -> Vec<u8>,
+    changed: &[u32],
+    get_signal_data: impl Fn(usize, &mut Vec<u8>) -> Result<()>,
     num_signals: usize,
-    signal_offsets: &mut impl Write,
+    signal_offsets: &mut Vec<u8>,
     memory_required: &mut u64,
+    pack_type: FstPackType,
+    zlib_level: u8,
 ) -> Result<()> {
     write_variant_u64(output, num_signals as u64)?;
-    // we always use lz4 for compression
-    write_u8(output, VALUE_CHANGE_PACK_TYPE_LZ4)?;
+    let pack_type_id = match pack_type {
+        FstPackType::Lz4 => VALUE_CHANGE_PACK_TYPE_LZ4,
+        FstPackType::Zlib => VALUE_CHANGE_PACK_TYPE_ZLIB,
+        FstPackType::FastLz => VALUE_CHANGE_PACK_TYPE_FASTLZ,
+    };
+    write_u8(output, pack_type_id)?;
 
-    let mut zero_count = 0;
-    let mut prev_offset = output.stream_position()? - 1;
+    let mut chain = ChainTable::new(signal_offsets, output.stream_position()? - 1);
+    // first signal with the exact same value changes, readers decode them with the type of the
+    // aliasing signal, thus even signals of different types can share their data
+    // (the signal is identified by its index and its position in `changed`)
+    let hasher = std::collections::hash_map::RandomState::new();
+    let mut first_with_data: HashMap<u64, (usize, usize)> = HashMap::new();
 
-    for signal_idx in 0..num_signals {
-        let data = get_signal_data(signal_idx);
-        if data.is_empty() {
-            zero_count += 1;
+    // the value changes of all signals are compressed in parallel before writing them in order
+    #[cfg(feature = "parallel")]
+    let prepared = {
+        use rayon::prelude::*;
+        let data = changed
+            .iter()
+            .map(|&signal_idx| {
+                let mut data = vec![];
+                get_signal_data(signal_idx as usize, &mut data)?;
+                Ok(data)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        data.into_par_iter()
+            .map(|data| {
+                let mut compressed = vec![];
+                let use_compressed =
+                    compress_signal_data(&data, pack_type, zlib_level, &mut compressed);
+                (data, use_compressed.then_some(compressed))
+            })
+            .collect::<Vec<_>>()
+    };
+    // buffers are reused for all signals
+    #[cfg(not(feature = "parallel"))]
+    let (mut data, mut other, mut compressed) = (vec![], vec![], vec![]);
+
+    let mut next_idx = 0;
+    for (pos, &signal_idx) in changed.iter().enumerate() {
+        let signal_idx = signal_idx as usize;
+        chain.idle((signal_idx - next_idx) as u64);
+        next_idx = signal_idx + 1;
+        #[cfg(feature = "parallel")]
+        let (data, compressed) = (&prepared[pos].0, prepared[pos].1.as_deref());
+        #[cfg(not(feature = "parallel"))]
+        {
+            data.clear();
+            get_signal_data(signal_idx, &mut data)?;
+        }
+        debug_assert!(!data.is_empty());
+        let hash = hasher.hash_one(&data[..]);
+        // the hash might collide, thus we compare with the data of the first signal
+        let alias = match first_with_data.get(&hash) {
+            #[cfg(feature = "parallel")]
+            Some(&(idx, first_pos)) => (prepared[first_pos].0 == *data).then_some(idx),
+            #[cfg(not(feature = "parallel"))]
+            Some(&(idx, _)) => {
+                other.clear();
+                get_signal_data(idx, &mut other)?;
+                (other == data).then_some(idx)
+            }
+            None => None,
+        };
+        if let Some(idx) = alias {
+            chain.alias(idx)?;
         } else {
-            flush_zeros(signal_offsets, &mut zero_count)?;
             let start = output.stream_position()?;
             *memory_required += data.len() as u64;
 
-            // TODO: dedup with hashmap
-            if data.len() < MIN_SIZE_TO_ATTEMPT_COMPRESSION {
-                // it is better not to compress the data
-                write_variant_u64(output, 0)?;
-                output.write_all(&data)?;
-            } else {
-                // try to compress the data
-                let compressed = lz4_flex::compress(&data);
-                if compressed.len() < data.len() {
+            #[cfg(not(feature = "parallel"))]
+            let compressed = compress_signal_data(&data, pack_type, zlib_level, &mut compressed)
+                .then_some(&compressed[..]);
+            match compressed {
+                Some(compressed) => {
                     // we use the compressed version
                     write_variant_u64(output, data.len() as u64)?;
-                    output.write_all(&compressed)?;
-                } else {
+                    output.write_all(compressed)?;
+                }
+                None => {
                     // it is better not to compress the data
                     write_variant_u64(output, 0)?;
-                    output.write_all(&data)?;
-                };
+                    output.write_all(&data[..])?;
+                }
             }
-
-            // write new incremental offset
-            let offset_delta = (start - prev_offset) as i64;
-            write_variant_i64(signal_offsets, (offset_delta << 1) | 1)?;
-            prev_offset = start;
+            chain.data(start)?;
+            first_with_data.entry(hash).or_insert((signal_idx, pos));
         }
     }
-    flush_zeros(signal_offsets, &mut zero_count)?;
+    chain.idle((num_signals - next_idx) as u64);
+    chain.finish()?;
     Ok(())
 }
 
-fn write_frame(output: &mut impl Write, frame: &[u8], num_signals: usize) -> Result<()> {
-    // we never compress the frame since we do not support zlib compression
-    write_variant_u64(output, frame.len() as u64)?;
+/// Compresses the value changes of a signal into `out`. Returns false if compression does not
+/// pay off.
+fn compress_signal_data(
+    data: &[u8],
+    pack_type: FstPackType,
+    zlib_level: u8,
+    out: &mut Vec<u8>,
+) -> bool {
+    if data.len() < MIN_SIZE_TO_ATTEMPT_COMPRESSION {
+        return false;
+    }
+    match pack_type {
+        FstPackType::Lz4 => {
+            // compress into the reused buffer instead of a fresh allocation
+            out.resize(lz4_flex::block::get_maximum_output_size(data.len()), 0);
+            let len = lz4_flex::block::compress_into(data, out)
+                .expect("output buffer has the maximum size");
+            out.truncate(len);
+        }
+        FstPackType::Zlib => *out = miniz_oxide::deflate::compress_to_vec_zlib(data, zlib_level),
+        FstPackType::FastLz => *out = crate::fastlz::compress(data),
+    };
+    out.len() < data.len()
+}
+
+fn write_frame(
+    output: &mut impl Write,
+    frame: &[u8],
+    num_signals: usize,
+    zlib_level: u8,
+) -> Result<()> {
+    // equal lengths signal that the frame is stored uncompressed
+    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(frame, zlib_level);
+    let data = if compressed.len() < frame.len() {
+        compressed.as_slice()
+    } else {
+        frame
+    };
     write_variant_u64(output, frame.len() as u64)?;
+    write_variant_u64(output, data.len() as u64)?;
     write_variant_u64(output, num_signals as u64)?;
-    output.write_all(frame)?;
+    output.write_all(data)?;
     Ok(())
 }
 
+/// The section is staged in `stage`, a buffer that is reused across sections, and then
+/// written to `output` at once, since many small writes are slow on some file systems.
+/// Returns the size of the section and of the uncompressed value changes.
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn write_value_change_section(
-    output: &mut (impl Write + Seek),
+    output: &mut impl Write,
+    stage: &mut Vec<u8>,
     start_time: u64,
     end_time: u64,
     frame: &[u8],
     time_table: &[u8],
     time_table_entries: u64,
-    get_signal_data: impl Fn(usize) -> Vec<u8>,
+    changed: &[u32],
+    get_signal_data: impl Fn(usize, &mut Vec<u8>) -> Result<()>,
     num_signals: usize,
-) -> Result<()> {
+    pack_type: FstPackType,
+    levels: &FstCompressionLevels,
+) -> Result<(u64, u64)> {
+    stage.clear();
+    let mut staged = std::io::Cursor::new(std::mem::take(stage));
+    let result = write_value_change_section_staged(
+        &mut staged,
+        start_time,
+        end_time,
+        frame,
+        time_table,
+        time_table_entries,
+        changed,
+        get_signal_data,
+        num_signals,
+        pack_type,
+        levels,
+    );
+    *stage = staged.into_inner();
+    let uncompressed = result?;
+    output.write_all(stage)?;
+    Ok((stage.len() as u64, uncompressed))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_value_change_section_staged(
+    output: &mut std::io::Cursor<Vec<u8>>,
+    start_time: u64,
+    end_time: u64,
+    frame: &[u8],
+    time_table: &[u8],
+    time_table_entries: u64,
+    changed: &[u32],
+    get_signal_data: impl Fn(usize, &mut Vec<u8>) -> Result<()>,
+    num_signals: usize,
+    pack_type: FstPackType,
+    levels: &FstCompressionLevels,
+) -> Result<u64> {
     // section header
     write_u8(output, BlockType::VcDataDynamicAlias2 as u8)?;
     // remember start to fix the section header
@@ -487,16 +1127,19 @@ pub(crate) fn write_value_change_section(
     write_u64(output, memory_required)?;
 
     // frame, i.e., the initial values
-    write_frame(output, frame, num_signals)?;
+    write_frame(output, frame, num_signals, levels.frame)?;
 
     // value change data
     let mut signal_offsets = vec![];
     write_value_changes(
         output,
+        changed,
         get_signal_data,
         num_signals,
         &mut signal_offsets,
         &mut memory_required,
+        pack_type,
+        levels.value_changes,
     )?;
 
     // offset table
@@ -504,7 +1147,7 @@ pub(crate) fn write_value_change_section(
     write_u64(output, signal_offsets.len() as u64)?;
 
     // time table at the end
-    write_time_table(output, time_table, time_table_entries)?;
+    write_time_table(output, time_table, time_table_entries, levels.time_table)?;
 
     // fix section length + memory requirement
     let end = output.stream_position()?;
@@ -515,31 +1158,274 @@ pub(crate) fn write_value_change_section(
     // the memory required for traversal is just the uncompressed length of all signals summed up
     write_u64(output, memory_required)?;
     output.seek(SeekFrom::Start(end))?;
-    Ok(())
+    Ok(memory_required)
 }
 
-/// by unscientific experiment, we observed that this level might be good enough :)
-const ZLIB_LEVEL: u8 = 3;
-
 fn write_time_table(
     output: &mut (impl Write + Seek),
     time_table: &[u8],
     time_table_entries: u64,
+    zlib_level: u8,
 ) -> Result<()> {
-    // zlib compress
-    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(time_table, ZLIB_LEVEL);
-
-    // is compression worth it?
-    if compressed.len() > time_table.len() {
-        // it is more space efficient to stick with the uncompressed version
-        output.write_all(time_table)?;
-        write_u64(output, time_table.len() as u64)?;
-        write_u64(output, time_table.len() as u64)?;
+    // zlib compress, unless the table is tiny
+    let compressed = if time_table.len() < MIN_SIZE_TO_ATTEMPT_COMPRESSION {
+        None
     } else {
-        output.write_all(compressed.as_slice())?;
-        write_u64(output, time_table.len() as u64)?;
-        write_u64(output, compressed.len() as u64)?;
+        Some(miniz_oxide::deflate::compress_to_vec_zlib(
+            time_table, zlib_level,
+        ))
+    };
+
+    // is compression worth it? Readers interpret equal lengths as uncompressed data.
+    match compressed {
+        Some(compressed) if compressed.len() < time_table.len() => {
+            output.write_all(compressed.as_slice())?;
+            write_u64(output, time_table.len() as u64)?;
+            write_u64(output, compressed.len() as u64)?;
+        }
+        _ => {
+            // it is more space efficient to stick with the uncompressed version
+            output.write_all(time_table)?;
+            write_u64(output, time_table.len() as u64)?;
+            write_u64(output, time_table.len() as u64)?;
+        }
     }
     write_u64(output, time_table_entries)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_conversions() {
+        assert_eq!(checked_u32(7, "test").unwrap(), 7);
+        // lengths on 32-bit targets always fit
+        if let Some(len) = (u32::MAX as usize).checked_add(1) {
+            assert!(matches!(
+                checked_u32(len, "test"),
+                Err(FstWriteError::TooLarge("test"))
+            ));
+        }
+        assert_eq!(checked_usize(7, "test").unwrap(), 7);
+        let fits = usize::try_from(u64::MAX).is_ok();
+        assert_eq!(checked_usize(u64::MAX, "test").is_ok(), fits);
+    }
+
+    #[test]
+    fn variant_encoding() {
+        let u64_bytes = |value| {
+            let (bytes, len) = encode_variant_u64(value);
+            bytes[..len].to_vec()
+        };
+        assert_eq!(u64_bytes(0), [0]);
+        assert_eq!(u64_bytes(0x7f), [0x7f]);
+        assert_eq!(u64_bytes(0x80), [0x80, 0x01]);
+        assert_eq!(u64_bytes(u64::MAX).len(), MAX_VARIANT_LEN);
+        for value in [0, 1, 300, 1 << 35, u64::MAX] {
+            let bytes = u64_bytes(value);
+            assert_eq!(read_variant_u64(&bytes).unwrap(), (value, bytes.len()));
+        }
+        let i64_bytes = |value| {
+            let (bytes, len) = encode_variant_i64(value);
+            bytes[..len].to_vec()
+        };
+        assert_eq!(i64_bytes(0), [0]);
+        assert_eq!(i64_bytes(-1), [0x7f]);
+        assert_eq!(i64_bytes(63), [0x3f]);
+        assert_eq!(i64_bytes(-64), [0x40]);
+        assert_eq!(i64_bytes(64), [0xc0, 0x00]);
+        assert_eq!(i64_bytes(-65), [0xbf, 0x7f]);
+        assert_eq!(i64_bytes(i64::MIN).len(), MAX_VARIANT_LEN);
+        assert_eq!(i64_bytes(i64::MAX).len(), MAX_VARIANT_LEN);
+    }
+
+    /// returns (uncompressed length, compressed length)
+    fn time_table_lengths(time_table: &[u8]) -> (u64, u64) {
+        let mut out = std::io::Cursor::new(vec![]);
+        write_time_table(&mut out, time_table, 1, 3).unwrap();
+        let out = out.into_inner();
+        let footer = &out[out.len() - 24..];
+        let uncompressed = u64::from_be_bytes(footer[0..8].try_into().unwrap());
+        let compressed = u64::from_be_bytes(footer[8..16].try_into().unwrap());
+        assert_eq!(out.len() as u64, compressed + 24);
+        (uncompressed, compressed)
+    }
+
+    #[test]
+    fn chain_table_idle_runs() {
+        let mut chain = ChainTable::new(vec![], 100);
+        chain.idle(1);
+        chain.data(101).unwrap();
+        chain.idle(2);
+        chain.idle(1);
+        chain.data(120).unwrap();
+        chain.idle(0);
+        chain.data(121).unwrap();
+        chain.idle(2);
+        // runs of idle signals are counted, data offsets are deltas with the lowest bit set
+        assert_eq!(
+            chain.finish().unwrap(),
+            [1 << 1, 3, 3 << 1, 19 << 1 | 1, 3, 2 << 1]
+        );
+    }
+
+    #[test]
+    fn chain_table_aliases() {
+        let mut chain = ChainTable::new(vec![], 100);
+        chain.data(101).unwrap();
+        chain.data(102).unwrap();
+        chain.alias(0).unwrap();
+        chain.alias(0).unwrap();
+        chain.idle(1);
+        chain.alias(1).unwrap();
+        chain.alias(0).unwrap();
+        // aliases are negative, repeating the previous alias is encoded as zero
+        let expected = [
+            3,
+            3,
+            -1i8 as u8 & 0x7f,
+            1,
+            1 << 1,
+            -3i8 as u8 & 0x7f,
+            -1i8 as u8 & 0x7f,
+        ];
+        assert_eq!(chain.finish().unwrap(), expected);
+    }
+
+    #[test]
+    fn blackout() {
+        let mut out = std::io::Cursor::new(vec![]);
+        write_blackout(&mut out, &[(10, false), (200, true)]).unwrap();
+        let out = out.into_inner();
+        assert_eq!(out[0], BlockType::Blackout as u8);
+        assert_eq!(u64::from_be_bytes(out[1..9].try_into().unwrap()), 8 + 6);
+        // count, then activity and time delta for each entry
+        assert_eq!(out[9..], [2, 0, 10, 1, 190, 1]);
+    }
+
+    #[test]
+    fn one_bit_signal_encoding() {
+        let encode = |time_delta, value| {
+            let (bytes, len) = encode_variant_u64(one_bit_signal_vli(time_delta, value).unwrap());
+            bytes[..len].to_vec()
+        };
+        // a clock toggling every step only needs a single byte per change
+        assert_eq!(encode(1, b'0'), [0b100]);
+        assert_eq!(encode(1, b'1'), [0b110]);
+        assert_eq!(encode(31, b'1'), [0b111_1110]);
+        assert_eq!(encode(32, b'0'), [0x80, 1]);
+        // all other values need two more bits
+        assert_eq!(encode(1, b'x'), [0b1_0001]);
+        assert_eq!(encode(1, b'?'), [0b1_1111]);
+        assert!(encode(7, b'?').len() == 1 && encode(8, b'?').len() == 2);
+    }
+
+    #[test]
+    fn hierarchy_lz4_duo() {
+        let mut out = std::io::Cursor::new(vec![]);
+        write_hierarchy_bytes(&mut out, &b"$var wire 1 ! clk $end".repeat(10_000)).unwrap();
+        assert_eq!(out.get_ref()[0], BlockType::HierarchyLZ4Duo as u8);
+        let mut out = std::io::Cursor::new(vec![]);
+        write_hierarchy_bytes(&mut out, b"top").unwrap();
+        assert_eq!(out.get_ref()[0], BlockType::HierarchyLZ4 as u8);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn hierarchy_deflate_spool() {
+        let mut state = 0x2545f491u32;
+        let random = (0..300_000)
+            .map(|_| {
+                // xorshift
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let mut buf = HierarchyBuffer::new(Some(4));
+        for chunk in random.chunks(1000) {
+            buf.write_all(chunk).unwrap();
+            // compressed bytes do not pile up in memory
+            assert!(buf.size() < 2 * SPOOL_CHUNK_SIZE);
+        }
+        let mut out = std::io::Cursor::new(vec![]);
+        buf.write_block(&mut out).unwrap();
+        let out = out.into_inner();
+        assert_eq!(out[0], BlockType::Hierarchy as u8);
+        let section_len = u64::from_be_bytes(out[1..9].try_into().unwrap());
+        assert_eq!(section_len as usize + 1, out.len());
+        let deflate = &out[1 + 2 * 8 + GZIP_HEADER.len()..out.len() - 2 * 4];
+        assert_eq!(
+            miniz_oxide::inflate::decompress_to_vec(deflate).unwrap(),
+            random
+        );
+    }
+
+    #[test]
+    fn time_table_compression() {
+        // tiny tables are never compressed
+        assert_eq!(time_table_lengths(&[1; 8]), (8, 8));
+        // long runs of equal deltas compress well
+        let (uncompressed, compressed) = time_table_lengths(&[1; 100_000]);
+        assert_eq!(uncompressed, 100_000);
+        assert!(compressed < 1000);
+        // random data does not compress and is stored as is
+        let mut state = 0x2545f491u32;
+        let random = (0..1000)
+            .map(|_| {
+                // xorshift
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(time_table_lengths(&random), (1000, 1000));
+    }
+
+    #[test]
+    fn value_change_section_single_write() {
+        /// counts calls to `write`
+        struct CountingWriter(usize, Vec<u8>);
+        impl Write for CountingWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0 += 1;
+                self.1.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut out = CountingWriter(0, vec![]);
+        let mut stage = vec![];
+        let mut time_table = vec![];
+        write_time_chain_update(&mut time_table, 0, 5).unwrap();
+        write_value_change_section(
+            &mut out,
+            &mut stage,
+            0,
+            5,
+            b"0x",
+            &time_table,
+            1,
+            &[1],
+            |_, data| {
+                data.extend_from_slice(&[1, 2, 3]);
+                Ok(())
+            },
+            2,
+            FstPackType::Lz4,
+            &FstCompressionLevels::default(),
+        )
+        .unwrap();
+        assert_eq!(out.0, 1);
+        assert_eq!(out.1, stage);
+        assert_eq!(out.1[0], BlockType::VcDataDynamicAlias2 as u8);
+        let section_len = u64::from_be_bytes(out.1[1..9].try_into().unwrap());
+        assert_eq!(section_len as usize + 1, out.1.len());
+    }
+}
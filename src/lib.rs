@@ -2,17 +2,56 @@
 // released under BSD 3-Clause License
 // author: Kevin Laeufer <laeufer@cornell.edu>
 
+#[cfg(feature = "append")]
+mod append;
+#[cfg(feature = "tokio")]
+mod async_writer;
 mod buffer;
+#[cfg(feature = "fs")]
+mod checksum;
+mod config;
+#[cfg(feature = "serde")]
+mod description;
+mod fastlz;
+mod hierarchy;
 mod io;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "fs")]
+mod recover;
+mod reorder;
+#[cfg(feature = "testing")]
+mod replay;
+mod sender;
+mod spill;
+mod stats;
+mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod types;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(feature = "vcd")]
+pub mod vcd;
+#[cfg(not(feature = "vcd"))]
+mod vcd;
+#[cfg(feature = "verify")]
+mod verify;
+#[cfg(feature = "wellen")]
+pub mod wellen;
 mod writer;
 
 type Result<T> = std::result::Result<T, FstWriteError>;
 
+/// Everything that can go wrong while writing. New variants can be added in any release,
+/// thus bindings, e.g., for C or Python, should rely on [`FstWriteError::code`] instead.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum FstWriteError {
-    #[error("I/O operation failed")]
+    #[error("I/O operation failed: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Refusing to replace the existing file {0:?}")]
+    FileExists(std::path::PathBuf),
     #[error("The string is too large (max length: {0}): {1}")]
     StringTooLong(usize, String),
     #[error("Cannot change the time from {0} to {1}. Time must always increase!")]
@@ -21,7 +60,247 @@ pub enum FstWriteError {
     InvalidSignalId(FstSignalId),
     #[error("Invalid bit-vector signal character: {0}")]
     InvalidCharacter(char),
+    #[error("{open} scope(s) are still open. Every call to scope needs a matching up_scope.")]
+    UnbalancedScopes { open: usize },
+    #[error(
+        "The hierarchy was already written. Enable `deferred_hierarchy` to declare variables while writing value changes."
+    )]
+    HierarchyAlreadyWritten,
+    #[error("No variable was declared for external id {0}")]
+    UnknownExternalId(usize),
+    #[error("Cannot alias unknown variable: {0}")]
+    UnknownAlias(String),
+    #[error("Failed to parse VCD: {0}")]
+    VcdParse(String),
+    #[error("Invalid scope index: {0}")]
+    InvalidScope(usize),
+    #[error("Cannot verify checksums: {0}")]
+    InvalidChecksums(String),
+    #[error("Too many time steps in a single value change block. Flush more often.")]
+    TooManyTimeSteps,
+    #[error("All shards need to record the same time steps.")]
+    InconsistentShards,
+    #[error("The writer thread stopped. Join it to find out why.")]
+    WriterStopped,
+    #[error("{0} is not supported when streaming to an output that cannot seek.")]
+    UnsupportedWhenStreaming(&'static str),
+    #[error("The options `{0}` and `{1}` cannot be combined.")]
+    IncompatibleConfig(&'static str, &'static str),
+    #[error("Invalid FST file: {0}")]
+    InvalidFile(String),
+    #[error(
+        "Buffering the change requires {required} bytes, more than the memory limit of {limit}."
+    )]
+    MemoryLimitExceeded { limit: usize, required: usize },
+    #[error("Cannot use {value:?} as the value of a signal with {len} bits.")]
+    InvalidValueLength { value: String, len: u32 },
+    #[error("Value changes need a time step. Call time_change after a flush.")]
+    MissingTimeStep,
+    #[error("The values of all signals together take up more than 4 GiB.")]
+    SignalsTooWide,
+    #[error("Corrupted value change buffer: {0}")]
+    CorruptBuffer(&'static str),
+    #[error("{0} exceed what the file format or this platform can address.")]
+    TooLarge(&'static str),
+    #[error("The signal written to {0:?} was not loaded into the waveform.")]
+    SignalNotLoaded(FstSignalId),
+    #[error("The option `{0}` needs to be configured before declaring variables.")]
+    ConfigAfterDeclarations(&'static str),
+    #[error("Cannot alias scope {0} while it is still open.")]
+    ScopeStillOpen(usize),
+    #[error("There is no open scope that up_scope could close.")]
+    NoOpenScope,
+    #[error(
+        "Declared scopes and variables were not kept in memory. Enable `retain_declarations` to use them with `incremental_hierarchy_compression`."
+    )]
+    DeclarationsNotRetained,
+    #[error("The scope {0} was already closed and cannot be opened again.")]
+    ScopeAlreadyClosed(String),
+    #[error("{source} ({context})")]
+    Context {
+        context: Box<FstErrorContext>,
+        source: Box<FstWriteError>,
+    },
 }
 
+impl FstWriteError {
+    /// Where the error happened, if known.
+    pub fn context(&self) -> Option<&FstErrorContext> {
+        match self {
+            FstWriteError::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context.
+    pub fn root(&self) -> &FstWriteError {
+        match self {
+            FstWriteError::Context { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// What went wrong, independent of any context.
+    pub fn kind(&self) -> FstErrorKind {
+        match self.root() {
+            FstWriteError::Io(_) => FstErrorKind::Io,
+            FstWriteError::FileExists(_) => FstErrorKind::FileExists,
+            FstWriteError::StringTooLong(..) => FstErrorKind::StringTooLong,
+            FstWriteError::TimeDecrease(..) => FstErrorKind::TimeDecrease,
+            FstWriteError::InvalidSignalId(_) => FstErrorKind::InvalidSignalId,
+            FstWriteError::InvalidCharacter(_) => FstErrorKind::InvalidCharacter,
+            FstWriteError::UnbalancedScopes { .. } => FstErrorKind::UnbalancedScopes,
+            FstWriteError::HierarchyAlreadyWritten => FstErrorKind::HierarchyAlreadyWritten,
+            FstWriteError::UnknownExternalId(_) => FstErrorKind::UnknownExternalId,
+            FstWriteError::UnknownAlias(_) => FstErrorKind::UnknownAlias,
+            FstWriteError::VcdParse(_) => FstErrorKind::VcdParse,
+            FstWriteError::InvalidScope(_) => FstErrorKind::InvalidScope,
+            FstWriteError::InvalidChecksums(_) => FstErrorKind::InvalidChecksums,
+            FstWriteError::TooManyTimeSteps => FstErrorKind::TooManyTimeSteps,
+            FstWriteError::InconsistentShards => FstErrorKind::InconsistentShards,
+            FstWriteError::WriterStopped => FstErrorKind::WriterStopped,
+            FstWriteError::UnsupportedWhenStreaming(_) => FstErrorKind::UnsupportedWhenStreaming,
+            FstWriteError::IncompatibleConfig(..) => FstErrorKind::IncompatibleConfig,
+            FstWriteError::InvalidFile(_) => FstErrorKind::InvalidFile,
+            FstWriteError::MemoryLimitExceeded { .. } => FstErrorKind::MemoryLimitExceeded,
+            FstWriteError::InvalidValueLength { .. } => FstErrorKind::InvalidValueLength,
+            FstWriteError::MissingTimeStep => FstErrorKind::MissingTimeStep,
+            FstWriteError::SignalsTooWide => FstErrorKind::SignalsTooWide,
+            FstWriteError::CorruptBuffer(_) => FstErrorKind::CorruptBuffer,
+            FstWriteError::TooLarge(_) => FstErrorKind::TooLarge,
+            FstWriteError::SignalNotLoaded(_) => FstErrorKind::SignalNotLoaded,
+            FstWriteError::ConfigAfterDeclarations(_) => FstErrorKind::ConfigAfterDeclarations,
+            FstWriteError::ScopeStillOpen(_) => FstErrorKind::ScopeStillOpen,
+            FstWriteError::NoOpenScope => FstErrorKind::NoOpenScope,
+            FstWriteError::DeclarationsNotRetained => FstErrorKind::DeclarationsNotRetained,
+            FstWriteError::ScopeAlreadyClosed(_) => FstErrorKind::ScopeAlreadyClosed,
+            FstWriteError::Context { .. } => unreachable!("the root has no context"),
+        }
+    }
+
+    /// Number that identifies the [`FstErrorKind`], e.g., for C or Python bindings. Codes
+    /// never change between releases and zero is never used.
+    pub fn code(&self) -> u32 {
+        self.kind() as u32
+    }
+
+    /// Adds context to an error that does not have any yet.
+    pub(crate) fn with_context(self, context: impl FnOnce() -> FstErrorContext) -> Self {
+        match self {
+            FstWriteError::Context { .. } => self,
+            source => FstWriteError::Context {
+                context: Box::new(context()),
+                source: Box::new(source),
+            },
+        }
+    }
+}
+
+/// Every kind of [`FstWriteError`] with its stable [`FstWriteError::code`]. New kinds get new
+/// codes, existing codes are never reused.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FstErrorKind {
+    Io = 1,
+    FileExists = 2,
+    StringTooLong = 3,
+    TimeDecrease = 4,
+    InvalidSignalId = 5,
+    InvalidCharacter = 6,
+    UnbalancedScopes = 7,
+    HierarchyAlreadyWritten = 8,
+    UnknownExternalId = 9,
+    UnknownAlias = 10,
+    VcdParse = 11,
+    InvalidScope = 12,
+    InvalidChecksums = 13,
+    TooManyTimeSteps = 14,
+    InconsistentShards = 15,
+    WriterStopped = 16,
+    UnsupportedWhenStreaming = 17,
+    IncompatibleConfig = 18,
+    InvalidFile = 19,
+    MemoryLimitExceeded = 20,
+    InvalidValueLength = 21,
+    MissingTimeStep = 22,
+    SignalsTooWide = 23,
+    CorruptBuffer = 24,
+    TooLarge = 25,
+    SignalNotLoaded = 26,
+    ConfigAfterDeclarations = 27,
+    ScopeStillOpen = 28,
+    NoOpenScope = 29,
+    DeclarationsNotRetained = 30,
+    ScopeAlreadyClosed = 31,
+}
+
+/// What the writer was doing when an error happened, see [`FstWriteError::context`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FstErrorContext {
+    /// e.g., `"writing a value change block"`
+    pub phase: &'static str,
+    pub signal: Option<FstSignalId>,
+    /// hierarchical name of the signal, if the writer still knows it
+    pub signal_name: Option<String>,
+    /// latest simulation time
+    pub time: Option<u64>,
+    /// number of the value change block, starting at zero
+    pub block: Option<u64>,
+    /// offset in the output at which the failed write started
+    pub offset: Option<u64>,
+}
+
+impl std::fmt::Display for FstErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "while {}", self.phase)?;
+        if let Some(signal) = self.signal {
+            write!(f, ", signal {}", signal.to_index())?;
+            if let Some(name) = &self.signal_name {
+                write!(f, " ({name})")?;
+            }
+        }
+        if let Some(time) = self.time {
+            write!(f, ", time {time}")?;
+        }
+        if let Some(block) = self.block {
+            write!(f, ", block {block}")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", offset {offset}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use async_writer::{AsyncFstBodyWriter, FstAsyncBuffer, open_fst_async};
+#[cfg(feature = "fs")]
+pub use checksum::verify_fst_checksums;
+pub use config::{
+    FstCompressionLevels, FstDecimation, FstOpenOptions, FstPackType, FstSignalOrder,
+    FstWriterConfig,
+};
+#[cfg(feature = "serde")]
+pub use description::{FstHierarchyItem, FstScopeDescription, FstVarDescription};
+#[cfg(feature = "fs")]
+pub use recover::{FstRecovery, recover_fst};
+pub use sender::{FstSignalSender, SharedFstWriter};
+pub use stats::{FstBlockStats, FstSizeBreakdown, FstWriterHooks, FstWriterStats};
+pub use stream::FstStream;
 pub use types::*;
-pub use writer::{FstBodyWriter, FstHeaderWriter, open_fst};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::FstUringFile;
+#[cfg(feature = "verify")]
+pub use verify::{FstVerification, verify_fst};
+#[cfg(feature = "append")]
+pub use writer::append_fst;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use writer::open_fst_uring;
+pub use writer::{
+    DEFAULT_FLUSH_SIZE, FstBodyWriter, FstHeaderWriter, FstShard, FstVarBuilder,
+    open_fst_in_memory, open_fst_stream,
+};
+#[cfg(feature = "fs")]
+pub use writer::{FstPart, open_fst, open_fst_with_options, repack_fst};
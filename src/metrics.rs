@@ -0,0 +1,54 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Publishes writer activity through the `metrics` facade, so that writers embedded in
+// long-running services can be monitored with any `metrics` exporter.
+
+use crate::FstBlockStats;
+use ::metrics::{Counter, Gauge, counter, gauge};
+
+/// Handles of all metrics that a writer updates. All writers of a process share the same
+/// metrics, e.g., the buffered bytes are the sum over all writers.
+pub(crate) struct WriterMetrics {
+    /// number of value changes written, the rate gives the changes per second
+    value_changes: Counter,
+    blocks: Counter,
+    bytes: Counter,
+    buffered_bytes: Gauge,
+    /// contribution of this writer to `buffered_bytes`
+    buffered: usize,
+}
+
+impl WriterMetrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            value_changes: counter!("fst_writer_value_changes_total"),
+            blocks: counter!("fst_writer_blocks_written_total"),
+            bytes: counter!("fst_writer_block_bytes_total"),
+            buffered_bytes: gauge!("fst_writer_buffered_bytes"),
+            buffered: 0,
+        }
+    }
+
+    pub(crate) fn block_written(&self, block: &FstBlockStats) {
+        self.value_changes.increment(block.num_changes);
+        self.blocks.increment(1);
+        self.bytes.increment(block.bytes);
+    }
+
+    /// Updates the number of bytes that this writer holds in memory.
+    pub(crate) fn set_buffered(&mut self, bytes: usize) {
+        if bytes != self.buffered {
+            self.buffered_bytes
+                .increment(bytes as f64 - self.buffered as f64);
+            self.buffered = bytes;
+        }
+    }
+}
+
+impl Drop for WriterMetrics {
+    fn drop(&mut self) {
+        self.set_buffered(0);
+    }
+}
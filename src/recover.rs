@@ -0,0 +1,223 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Turns a file left behind by a writer that crashed into a readable file, by dropping a
+// trailing partial block and rewriting the header from the blocks that made it to disk.
+
+use crate::Result;
+use crate::io::{
+    BlockType, HEADER_LENGTH, HIERARCHY_TPE_VCD_ATTRIBUTE_BEGIN, HIERARCHY_TPE_VCD_ATTRIBUTE_END,
+    HIERARCHY_TPE_VCD_SCOPE, HIERARCHY_TPE_VCD_UP_SCOPE, invalid_file, read_header,
+    read_variant_u64, update_header,
+};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The header block including its type, after which the first block starts.
+pub(crate) const HEADER_BLOCK_LEN: u64 = 1 + HEADER_LENGTH;
+
+const BLOCK_TYPES: [BlockType; 11] = [
+    BlockType::Header,
+    BlockType::VcData,
+    BlockType::Blackout,
+    BlockType::Geometry,
+    BlockType::Hierarchy,
+    BlockType::VcDataDynamicAlias,
+    BlockType::HierarchyLZ4,
+    BlockType::HierarchyLZ4Duo,
+    BlockType::VcDataDynamicAlias2,
+    BlockType::GZipWrapper,
+    BlockType::Skip,
+];
+
+/// What [`recover_fst`] found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstRecovery {
+    /// number of complete value change blocks
+    pub value_change_blocks: u64,
+    /// last time step of the last complete value change block
+    pub end_time: u64,
+    /// bytes removed from the end of the file, e.g., a partially written block
+    pub truncated_bytes: u64,
+}
+
+/// Repairs the file at `path` in place, e.g., after the simulation that wrote it was killed
+/// before the file was finished. A trailing block that was not completely written is removed
+/// and the header is recomputed from all complete blocks. Fails, without changing the file, if
+/// the hierarchy never made it to disk, e.g., with [`crate::FstWriterConfig::deferred_hierarchy`].
+pub fn recover_fst(path: impl AsRef<Path>) -> Result<FstRecovery> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut input = BufReader::new(&mut file);
+    if file_len < HEADER_BLOCK_LEN {
+        return Err(invalid_file("the header is incomplete"));
+    }
+    let mut info = read_header(&mut input)?;
+    info.end_time = 0;
+    info.num_value_change_sections = 0;
+    let mut hierarchy = None;
+    let mut num_signals = None;
+
+    let mut offset = HEADER_BLOCK_LEN;
+    while let Some((tpe, section_len)) = complete_block(&mut input, offset, file_len)? {
+        match tpe {
+            BlockType::VcData | BlockType::VcDataDynamicAlias | BlockType::VcDataDynamicAlias2 => {
+                let _start_time = read_u64(&mut input)?;
+                info.end_time = read_u64(&mut input)?;
+                info.num_value_change_sections += 1;
+            }
+            BlockType::Geometry => {
+                let _uncompressed_len = read_u64(&mut input)?;
+                num_signals = Some(read_u64(&mut input)?);
+            }
+            BlockType::Hierarchy | BlockType::HierarchyLZ4 | BlockType::HierarchyLZ4Duo => {
+                let mut bytes = vec![0u8; section_len as usize - 8];
+                input.read_exact(&mut bytes)?;
+                hierarchy = Some(count_hierarchy(tpe, &bytes)?);
+            }
+            _ => {}
+        }
+        offset += 1 + section_len;
+        input.seek(SeekFrom::Start(offset))?;
+    }
+
+    let (Some((scope_count, var_count)), Some(num_signals)) = (hierarchy, num_signals) else {
+        return Err(invalid_file("the hierarchy was never written"));
+    };
+    info.scope_count = scope_count;
+    info.var_count = var_count;
+    info.num_signals = num_signals;
+    drop(input);
+    file.set_len(offset)?;
+    update_header(&mut file, &info)?;
+    file.flush()?;
+    Ok(FstRecovery {
+        value_change_blocks: info.num_value_change_sections,
+        end_time: info.end_time,
+        truncated_bytes: file_len - offset,
+    })
+}
+
+/// Returns the type and section length of the block at `offset` if it was written completely.
+/// Reading continues after the section length.
+pub(crate) fn complete_block(
+    input: &mut (impl Read + Seek),
+    offset: u64,
+    file_len: u64,
+) -> Result<Option<(BlockType, u64)>> {
+    if offset + 9 > file_len {
+        return Ok(None);
+    }
+    input.seek(SeekFrom::Start(offset))?;
+    let tpe = read_u8(input)?;
+    let section_len = read_u64(input)?;
+    // anything else is garbage, e.g., from a file system that extended the file before the
+    // data arrived
+    let Some(tpe) = BLOCK_TYPES.into_iter().find(|&t| t as u8 == tpe) else {
+        return Ok(None);
+    };
+    let min_len = match tpe {
+        BlockType::Header | BlockType::GZipWrapper => return Ok(None),
+        BlockType::Skip | BlockType::Blackout => 8,
+        // the uncompressed length comes first
+        BlockType::Hierarchy | BlockType::HierarchyLZ4 | BlockType::HierarchyLZ4Duo => 8 + 8,
+        // followed by start and end time or uncompressed length and number of signals
+        BlockType::VcData
+        | BlockType::VcDataDynamicAlias
+        | BlockType::VcDataDynamicAlias2
+        | BlockType::Geometry => 8 + 16,
+    };
+    let complete = offset.saturating_add(1).saturating_add(section_len) <= file_len;
+    Ok((section_len >= min_len && complete).then_some((tpe, section_len)))
+}
+
+/// Counts the scopes and vars of a hierarchy block without its type and section length.
+pub(crate) fn count_hierarchy(tpe: BlockType, bytes: &[u8]) -> Result<(u64, u64)> {
+    let (uncompressed_len, compressed) = bytes.split_at(8);
+    let uncompressed_len = u64::from_be_bytes(uncompressed_len.try_into().unwrap()) as usize;
+    let bytes = if tpe == BlockType::Hierarchy {
+        // gzip member: fixed header, raw deflate stream and an 8 byte trailer
+        let deflate = compressed
+            .get(10..compressed.len().saturating_sub(8))
+            .ok_or_else(|| invalid_file("the hierarchy is incomplete"))?;
+        miniz_oxide::inflate::decompress_to_vec(deflate)
+            .map_err(|e| invalid_file(format!("failed to decompress the hierarchy: {e}")))?
+    } else {
+        let lz4 = |input: &[u8], len: usize| {
+            lz4_flex::decompress(input, len)
+                .map_err(|e| invalid_file(format!("failed to decompress the hierarchy: {e}")))
+        };
+        if tpe == BlockType::HierarchyLZ4Duo {
+            let (once_len, skip) = read_variant_u64(compressed)
+                .ok_or_else(|| invalid_file("the hierarchy is incomplete"))?;
+            lz4(
+                &lz4(&compressed[skip..], once_len as usize)?,
+                uncompressed_len,
+            )?
+        } else {
+            lz4(compressed, uncompressed_len)?
+        }
+    };
+
+    let (mut scopes, mut vars) = (0, 0);
+    let mut input = bytes.as_slice();
+    while let Some((&tpe, rest)) = input.split_first() {
+        input = rest;
+        match tpe {
+            HIERARCHY_TPE_VCD_SCOPE => {
+                input = skip(input, 1)?;
+                input = skip_c_str(skip_c_str(input)?)?;
+                scopes += 1;
+            }
+            HIERARCHY_TPE_VCD_UP_SCOPE | HIERARCHY_TPE_VCD_ATTRIBUTE_END => {}
+            HIERARCHY_TPE_VCD_ATTRIBUTE_BEGIN => {
+                input = skip_c_str(skip(input, 2)?)?;
+                input = skip_variant(input)?;
+            }
+            _ => {
+                // direction, name, length and alias
+                input = skip_c_str(skip(input, 1)?)?;
+                input = skip_variant(input)?;
+                input = skip_variant(input)?;
+                vars += 1;
+            }
+        }
+    }
+    Ok((scopes, vars))
+}
+
+fn skip(input: &[u8], len: usize) -> Result<&[u8]> {
+    input
+        .get(len..)
+        .ok_or_else(|| invalid_file("the hierarchy is incomplete"))
+}
+
+fn skip_variant(input: &[u8]) -> Result<&[u8]> {
+    let (_, len) =
+        read_variant_u64(input).ok_or_else(|| invalid_file("the hierarchy is incomplete"))?;
+    Ok(&input[len..])
+}
+
+fn skip_c_str(input: &[u8]) -> Result<&[u8]> {
+    let end = input
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid_file("the hierarchy is incomplete"))?;
+    Ok(&input[end + 1..])
+}
+
+pub(crate) fn read_u8(input: &mut impl Read) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u64(input: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
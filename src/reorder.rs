@@ -0,0 +1,68 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Holds back the most recent time steps so that changes can arrive slightly out of order.
+
+use crate::{FstSignalId, FstWriteError, Result};
+use std::collections::BTreeMap;
+
+pub(crate) type TimeStep = (u64, Vec<(FstSignalId, Vec<u8>)>);
+
+pub(crate) struct ReorderBuffer {
+    /// number of time steps that are held back
+    depth: usize,
+    time: u64,
+    /// the last time step that was handed out
+    committed: Option<u64>,
+    pending: BTreeMap<u64, Vec<(FstSignalId, Vec<u8>)>>,
+}
+
+impl ReorderBuffer {
+    pub(crate) fn new(depth: usize, time: u64) -> Self {
+        Self {
+            depth,
+            time,
+            committed: None,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the time steps that left the reorder window, in time order.
+    pub(crate) fn time_change(&mut self, time: u64) -> Result<Vec<TimeStep>> {
+        if let Some(committed) = self.committed {
+            if time < committed {
+                return Err(FstWriteError::TimeDecrease(committed, time));
+            }
+        }
+        self.time = time;
+        self.pending.entry(time).or_default();
+        let mut ready = vec![];
+        while self.pending.len() > self.depth {
+            let step = self.pending.pop_first().unwrap();
+            self.committed = Some(step.0);
+            ready.push(step);
+        }
+        Ok(ready)
+    }
+
+    /// The most recent time step, which might still be held back.
+    pub(crate) fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub(crate) fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) {
+        self.pending
+            .entry(self.time)
+            .or_default()
+            .push((signal_id, value.to_vec()));
+    }
+
+    /// Returns all time steps that are still held back, in time order.
+    pub(crate) fn drain(&mut self) -> Vec<TimeStep> {
+        if let Some((&last, _)) = self.pending.last_key_value() {
+            self.committed = Some(last);
+        }
+        std::mem::take(&mut self.pending).into_iter().collect()
+    }
+}
@@ -0,0 +1,440 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Records the calls made to a writer, so that bugs that only show up in huge files can be
+// reproduced without sharing the waveform.
+
+use crate::{
+    FstBodyWriter, FstFileType, FstHeaderWriter, FstInfo, FstScopeType, FstSignalId, FstSignalType,
+    FstVarDirection, FstVarType, FstWriteError, FstWriterConfig, Result, open_fst,
+};
+use std::io::{BufRead, Seek, Write};
+use std::path::Path;
+
+/// First line of every replay log, followed by the [`FstInfo`] and one call per line.
+const MAGIC: &str = "fst-replay 1";
+
+/// A call of the writer API as recorded by [`FstRecordingWriter`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum FstCall {
+    Scope {
+        name: String,
+        component: String,
+        tpe: FstScopeType,
+    },
+    UpScope,
+    Var {
+        name: String,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+        alias: Option<FstSignalId>,
+    },
+    /// [`FstHeaderWriter::finish`]
+    FinishHeader,
+    TimeChange(u64),
+    SignalChange(FstSignalId, Vec<u8>),
+    Flush,
+    /// [`FstBodyWriter::finish`]
+    Finish,
+}
+
+enum Phase<W: Write + Seek> {
+    Header(Box<FstHeaderWriter<W>>),
+    Body(Box<FstBodyWriter<W>>),
+    Finished,
+}
+
+/// Forwards calls to a writer and records them in a replay log, see [`replay_fst`].
+pub struct FstRecordingWriter<W: Write + Seek, L: Write> {
+    phase: Phase<W>,
+    log: L,
+}
+
+impl<W: Write + Seek, L: Write> FstRecordingWriter<W, L> {
+    /// `info` needs to be the one that `writer` was opened with.
+    pub fn new(writer: FstHeaderWriter<W>, info: &FstInfo, mut log: L) -> Result<Self> {
+        writeln!(log, "{MAGIC}")?;
+        writeln!(
+            log,
+            "info\t{}\t{}\t{}\t{}\t{}",
+            info.start_time,
+            info.timescale_exponent,
+            info.file_type as u8,
+            escape(&info.version),
+            escape(&info.date)
+        )?;
+        Ok(Self {
+            phase: Phase::Header(Box::new(writer)),
+            log,
+        })
+    }
+
+    /// Records the call and then forwards it. Returns the id of a new var.
+    pub fn call(&mut self, call: FstCall) -> Result<Option<FstSignalId>> {
+        writeln!(self.log, "{}", format_call(&call))?;
+        match (&mut self.phase, call) {
+            (
+                Phase::Header(writer),
+                FstCall::Scope {
+                    name,
+                    component,
+                    tpe,
+                },
+            ) => {
+                writer.scope(name, component, tpe)?;
+            }
+            (Phase::Header(writer), FstCall::UpScope) => writer.up_scope()?,
+            (
+                Phase::Header(writer),
+                FstCall::Var {
+                    name,
+                    signal_tpe,
+                    tpe,
+                    dir,
+                    alias,
+                },
+            ) => return Ok(Some(writer.var(name, signal_tpe, tpe, dir, alias)?)),
+            (Phase::Header(_), FstCall::FinishHeader) => {
+                if let Phase::Header(writer) = std::mem::replace(&mut self.phase, Phase::Finished) {
+                    self.phase = Phase::Body(Box::new((*writer).finish()?));
+                }
+            }
+            (Phase::Body(writer), FstCall::TimeChange(time)) => writer.time_change(time)?,
+            (Phase::Body(writer), FstCall::SignalChange(signal_id, value)) => {
+                writer.signal_change(signal_id, &value)?
+            }
+            (Phase::Body(writer), FstCall::Flush) => writer.flush()?,
+            (Phase::Body(_), FstCall::Finish) => {
+                if let Phase::Body(writer) = std::mem::replace(&mut self.phase, Phase::Finished) {
+                    (*writer).finish()?;
+                }
+            }
+            (_, call) => {
+                return Err(FstWriteError::InvalidFile(format!(
+                    "{call:?} is not allowed at this point"
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the log, which is complete once [`FstCall::Finish`] was recorded.
+    pub fn into_log(mut self) -> Result<L> {
+        self.log.flush()?;
+        Ok(self.log)
+    }
+}
+
+/// Writes the file recorded in `log` to `output` with the given configuration, which is not
+/// part of the log.
+pub fn replay_fst(
+    log: impl BufRead,
+    output: impl AsRef<Path>,
+    config: FstWriterConfig,
+) -> Result<()> {
+    let mut lines = log.lines().enumerate();
+    let mut next_line = || -> Result<Option<(usize, String)>> {
+        lines
+            .next()
+            .map(|(index, line)| Ok((index + 1, line?)))
+            .transpose()
+    };
+    match next_line()? {
+        Some((_, line)) if line == MAGIC => {}
+        _ => return Err(invalid(1, "not a replay log")),
+    }
+    let Some((number, line)) = next_line()? else {
+        return Err(invalid(2, "missing info"));
+    };
+    let info = parse_info(&line).ok_or_else(|| invalid(number, "invalid info"))?;
+    let writer = open_fst(output, &info)?.with_config(config)?;
+    let mut writer = FstRecordingWriter::new(writer, &info, std::io::sink())?;
+    while let Some((number, line)) = next_line()? {
+        let call = parse_call(&line).ok_or_else(|| invalid(number, "invalid call"))?;
+        writer.call(call)?;
+    }
+    match writer.phase {
+        Phase::Finished => Ok(()),
+        _ => Err(FstWriteError::InvalidFile(
+            "the replay log ends before the file was finished".to_string(),
+        )),
+    }
+}
+
+fn format_call(call: &FstCall) -> String {
+    match call {
+        FstCall::Scope {
+            name,
+            component,
+            tpe,
+        } => format!(
+            "scope\t{}\t{}\t{}",
+            *tpe as u8,
+            escape(name),
+            escape(component)
+        ),
+        FstCall::UpScope => "upscope".to_string(),
+        FstCall::Var {
+            name,
+            signal_tpe,
+            tpe,
+            dir,
+            alias,
+        } => {
+            let width = match signal_tpe.bit_vec_len() {
+                Some(width) => width.to_string(),
+                None => "real".to_string(),
+            };
+            let alias = alias.map_or("-".to_string(), |id| id.to_index().to_string());
+            format!(
+                "var\t{}\t{}\t{width}\t{alias}\t{}",
+                *tpe as u8,
+                *dir as u8,
+                escape(name)
+            )
+        }
+        FstCall::FinishHeader => "finish_header".to_string(),
+        FstCall::TimeChange(time) => format!("time\t{time}"),
+        FstCall::SignalChange(signal_id, value) => {
+            let value = match std::str::from_utf8(value) {
+                Ok(value) if value.bytes().all(|b| b.is_ascii_graphic() && b != b'#') => {
+                    value.to_string()
+                }
+                // e.g., the bytes of a real
+                _ => std::iter::once("#".to_string())
+                    .chain(value.iter().map(|b| format!("{b:02x}")))
+                    .collect(),
+            };
+            format!("change\t{}\t{value}", signal_id.to_index())
+        }
+        FstCall::Flush => "flush".to_string(),
+        FstCall::Finish => "finish".to_string(),
+    }
+}
+
+fn parse_info(line: &str) -> Option<FstInfo> {
+    let ["info", start_time, timescale, file_type, version, date] =
+        line.split('\t').collect::<Vec<_>>()[..]
+    else {
+        return None;
+    };
+    let file_type = match file_type.parse::<u8>().ok()? {
+        0 => FstFileType::Verilog,
+        1 => FstFileType::Vhdl,
+        2 => FstFileType::VerilogVhdl,
+        _ => return None,
+    };
+    Some(FstInfo {
+        start_time: start_time.parse().ok()?,
+        timescale_exponent: timescale.parse().ok()?,
+        version: unescape(version)?,
+        date: unescape(date)?,
+        file_type,
+    })
+}
+
+fn parse_call(line: &str) -> Option<FstCall> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    let call = match fields[..] {
+        ["scope", tpe, name, component] => FstCall::Scope {
+            name: unescape(name)?,
+            component: unescape(component)?,
+            tpe: *SCOPE_TYPES.get(tpe.parse::<usize>().ok()?)?,
+        },
+        ["upscope"] => FstCall::UpScope,
+        ["var", tpe, dir, width, alias, name] => FstCall::Var {
+            name: unescape(name)?,
+            signal_tpe: match width {
+                "real" => FstSignalType::real(),
+                width => FstSignalType::bit_vec(width.parse().ok()?),
+            },
+            tpe: *VAR_TYPES.get(tpe.parse::<usize>().ok()?)?,
+            dir: *VAR_DIRECTIONS.get(dir.parse::<usize>().ok()?)?,
+            alias: match alias {
+                "-" => None,
+                alias => Some(parse_signal_id(alias)?),
+            },
+        },
+        ["finish_header"] => FstCall::FinishHeader,
+        ["time", time] => FstCall::TimeChange(time.parse().ok()?),
+        ["change", signal_id, value] => {
+            let value = match value.strip_prefix('#') {
+                Some(hex) if hex.len() % 2 == 0 => (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<Vec<_>>>()?,
+                Some(_) => return None,
+                None => value.as_bytes().to_vec(),
+            };
+            FstCall::SignalChange(parse_signal_id(signal_id)?, value)
+        }
+        ["flush"] => FstCall::Flush,
+        ["finish"] => FstCall::Finish,
+        _ => return None,
+    };
+    Some(call)
+}
+
+fn parse_signal_id(value: &str) -> Option<FstSignalId> {
+    let index = value.parse::<u32>().ok().filter(|&index| index > 0)?;
+    Some(FstSignalId::from_index(index))
+}
+
+/// Names may contain anything but the field and line separators.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out += "\\\\",
+            '\t' => out += "\\t",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(value: &str) -> Option<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(out)
+}
+
+fn invalid(line: usize, reason: &str) -> FstWriteError {
+    FstWriteError::InvalidFile(format!("replay log line {line}: {reason}"))
+}
+
+/// All scope types by their value in the file format.
+const SCOPE_TYPES: [FstScopeType; 22] = [
+    FstScopeType::Module,
+    FstScopeType::Task,
+    FstScopeType::Function,
+    FstScopeType::Begin,
+    FstScopeType::Fork,
+    FstScopeType::Generate,
+    FstScopeType::Struct,
+    FstScopeType::Union,
+    FstScopeType::Class,
+    FstScopeType::Interface,
+    FstScopeType::Package,
+    FstScopeType::Program,
+    FstScopeType::VhdlArchitecture,
+    FstScopeType::VhdlProcedure,
+    FstScopeType::VhdlFunction,
+    FstScopeType::VhdlRecord,
+    FstScopeType::VhdlProcess,
+    FstScopeType::VhdlBlock,
+    FstScopeType::VhdlForGenerate,
+    FstScopeType::VhdlIfGenerate,
+    FstScopeType::VhdlGenerate,
+    FstScopeType::VhdlPackage,
+];
+
+/// All var types by their value in the file format.
+const VAR_TYPES: [FstVarType; 30] = [
+    FstVarType::Event,
+    FstVarType::Integer,
+    FstVarType::Parameter,
+    FstVarType::Real,
+    FstVarType::RealParameter,
+    FstVarType::Reg,
+    FstVarType::Supply0,
+    FstVarType::Supply1,
+    FstVarType::Time,
+    FstVarType::Tri,
+    FstVarType::TriAnd,
+    FstVarType::TriOr,
+    FstVarType::TriReg,
+    FstVarType::Tri0,
+    FstVarType::Tri1,
+    FstVarType::Wand,
+    FstVarType::Wire,
+    FstVarType::Wor,
+    FstVarType::Port,
+    FstVarType::SparseArray,
+    FstVarType::RealTime,
+    FstVarType::GenericString,
+    FstVarType::Bit,
+    FstVarType::Logic,
+    FstVarType::Int,
+    FstVarType::ShortInt,
+    FstVarType::LongInt,
+    FstVarType::Byte,
+    FstVarType::Enum,
+    FstVarType::ShortReal,
+];
+
+/// All directions by their value in the file format.
+const VAR_DIRECTIONS: [FstVarDirection; 6] = [
+    FstVarDirection::Implicit,
+    FstVarDirection::Input,
+    FstVarDirection::Output,
+    FstVarDirection::InOut,
+    FstVarDirection::Buffer,
+    FstVarDirection::Linkage,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_round_trip() {
+        let calls = [
+            FstCall::Scope {
+                name: "top\tlevel".to_string(),
+                component: "C:\\top".to_string(),
+                tpe: FstScopeType::VhdlPackage,
+            },
+            FstCall::Var {
+                name: "data [7:0]".to_string(),
+                signal_tpe: FstSignalType::bit_vec(8),
+                tpe: FstVarType::ShortReal,
+                dir: FstVarDirection::Linkage,
+                alias: None,
+            },
+            FstCall::Var {
+                name: "r".to_string(),
+                signal_tpe: FstSignalType::real(),
+                tpe: FstVarType::Real,
+                dir: FstVarDirection::Implicit,
+                alias: Some(FstSignalId::from_index(1)),
+            },
+            FstCall::UpScope,
+            FstCall::FinishHeader,
+            FstCall::TimeChange(u64::MAX),
+            FstCall::SignalChange(FstSignalId::from_index(1), b"01xz".to_vec()),
+            FstCall::SignalChange(FstSignalId::from_index(2), 1.5f64.to_le_bytes().to_vec()),
+            FstCall::SignalChange(FstSignalId::from_index(1), b"#1".to_vec()),
+            FstCall::Flush,
+            FstCall::Finish,
+        ];
+        for call in calls {
+            assert_eq!(parse_call(&format_call(&call)), Some(call));
+        }
+        for line in [
+            "",
+            "scope\t22\ta\tb",
+            "var\t5\t0\t8\t0\ta",
+            "change\t1\t#123",
+        ] {
+            assert_eq!(parse_call(line), None, "{line}");
+        }
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Records value changes from several threads, either through a channel to a writer thread
+// or through a writer that is shared behind a lock.
+
+use crate::{FstBodyWriter, FstSignalId, FstWriteError, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+enum Message {
+    /// a new sender that will not send anything before `time`
+    Register {
+        sender: u64,
+        time: u64,
+    },
+    Time {
+        sender: u64,
+        time: u64,
+    },
+    Change {
+        time: u64,
+        signal_id: FstSignalId,
+        value: Vec<u8>,
+    },
+    Done {
+        sender: u64,
+    },
+}
+
+/// Cloneable handle that sends value changes to the thread started by
+/// [`FstBodyWriter::into_sender`]. Every handle advances its own time, changes from
+/// different handles are merged in time order. Once all handles are dropped, the
+/// writer thread finishes the file.
+pub struct FstSignalSender {
+    id: u64,
+    time: u64,
+    tx: Sender<Message>,
+}
+
+static NEXT_SENDER_ID: AtomicU64 = AtomicU64::new(0);
+
+impl FstSignalSender {
+    fn new(tx: Sender<Message>, time: u64) -> Self {
+        let id = NEXT_SENDER_ID.fetch_add(1, Ordering::Relaxed);
+        // if the writer thread stopped, the error is reported by the next change
+        let _ = tx.send(Message::Register { sender: id, time });
+        Self { id, time, tx }
+    }
+
+    pub fn time_change(&mut self, time: u64) -> Result<()> {
+        if time < self.time {
+            return Err(FstWriteError::TimeDecrease(self.time, time));
+        }
+        if time > self.time {
+            self.time = time;
+            send(
+                &self.tx,
+                Message::Time {
+                    sender: self.id,
+                    time,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn signal_change(&self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
+        send(
+            &self.tx,
+            Message::Change {
+                time: self.time,
+                signal_id,
+                value: value.to_vec(),
+            },
+        )
+    }
+}
+
+impl Clone for FstSignalSender {
+    /// The new handle starts at the current time of this handle.
+    fn clone(&self) -> Self {
+        Self::new(self.tx.clone(), self.time)
+    }
+}
+
+impl Drop for FstSignalSender {
+    fn drop(&mut self) {
+        let _ = self.tx.send(Message::Done { sender: self.id });
+    }
+}
+
+fn send(tx: &Sender<Message>, message: Message) -> Result<()> {
+    tx.send(message).map_err(|_| FstWriteError::WriterStopped)
+}
+
+pub(crate) fn spawn_writer<W: std::io::Write + std::io::Seek + Send + 'static>(
+    writer: FstBodyWriter<W>,
+    time: u64,
+) -> (FstSignalSender, std::thread::JoinHandle<Result<()>>) {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let sender = FstSignalSender::new(tx, time);
+    let handle = std::thread::spawn(move || run_writer(writer, rx));
+    (sender, handle)
+}
+
+fn run_writer<W: std::io::Write + std::io::Seek>(
+    mut writer: FstBodyWriter<W>,
+    rx: Receiver<Message>,
+) -> Result<()> {
+    let mut merger = Merger::default();
+    for message in rx.iter() {
+        merger.apply(message);
+        merger.write_ready(&mut writer)?;
+    }
+    merger.write_ready(&mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Merges the changes of several senders in time order.
+#[derive(Default)]
+struct Merger {
+    /// changes are only written once no sender can go back to their time
+    pending: BTreeMap<u64, Vec<(FstSignalId, Vec<u8>)>>,
+    sender_times: HashMap<u64, u64>,
+}
+
+impl Merger {
+    fn apply(&mut self, message: Message) {
+        match message {
+            Message::Register { sender, time } | Message::Time { sender, time } => {
+                self.sender_times.insert(sender, time);
+            }
+            Message::Change {
+                time,
+                signal_id,
+                value,
+            } => {
+                self.pending
+                    .entry(time)
+                    .or_default()
+                    .push((signal_id, value));
+            }
+            Message::Done { sender } => {
+                self.sender_times.remove(&sender);
+            }
+        }
+    }
+
+    /// Writes all changes that happened before the current time of every sender.
+    fn write_ready<W: std::io::Write + std::io::Seek>(
+        &mut self,
+        writer: &mut FstBodyWriter<W>,
+    ) -> Result<()> {
+        let ready = match self.sender_times.values().min() {
+            Some(&min_time) => {
+                let later = self.pending.split_off(&min_time);
+                std::mem::replace(&mut self.pending, later)
+            }
+            None => std::mem::take(&mut self.pending),
+        };
+        for (time, changes) in ready {
+            writer.time_step(time, changes)?;
+        }
+        Ok(())
+    }
+
+    fn has_senders(&self) -> bool {
+        !self.sender_times.is_empty()
+    }
+}
+
+/// Number of changes that a [`SharedFstWriter`] collects before taking the lock.
+const BATCH_SIZE: usize = 1024;
+
+/// Handle to a writer that is shared by several threads. Every handle collects changes
+/// locally and only takes the lock to hand over a whole batch, changes from different handles
+/// are merged in time order. The file is finished once the last handle is finished or
+/// dropped. Created by [`FstBodyWriter::into_shared`].
+pub struct SharedFstWriter<W: std::io::Write + std::io::Seek> {
+    id: u64,
+    time: u64,
+    batch: Vec<Message>,
+    shared: Arc<Mutex<Shared<W>>>,
+}
+
+struct Shared<W: std::io::Write + std::io::Seek> {
+    /// taken once the file is finished
+    writer: Option<FstBodyWriter<W>>,
+    merger: Merger,
+}
+
+impl<W: std::io::Write + std::io::Seek> SharedFstWriter<W> {
+    pub(crate) fn new(writer: FstBodyWriter<W>, time: u64) -> Self {
+        let shared = Shared {
+            writer: Some(writer),
+            merger: Merger::default(),
+        };
+        Self::register(Arc::new(Mutex::new(shared)), time)
+    }
+
+    fn register(shared: Arc<Mutex<Shared<W>>>, time: u64) -> Self {
+        let id = NEXT_SENDER_ID.fetch_add(1, Ordering::Relaxed);
+        lock(&shared)
+            .merger
+            .apply(Message::Register { sender: id, time });
+        Self {
+            id,
+            time,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            shared,
+        }
+    }
+
+    pub fn time_change(&mut self, time: u64) -> Result<()> {
+        if time < self.time {
+            return Err(FstWriteError::TimeDecrease(self.time, time));
+        }
+        if time > self.time {
+            self.time = time;
+            self.push(Message::Time {
+                sender: self.id,
+                time,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
+        self.push(Message::Change {
+            time: self.time,
+            signal_id,
+            value: value.to_vec(),
+        })
+    }
+
+    fn push(&mut self, message: Message) -> Result<()> {
+        self.batch.push(message);
+        if self.batch.len() >= BATCH_SIZE {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Hands all changes of this handle over to the shared writer.
+    pub fn sync(&mut self) -> Result<()> {
+        let mut shared = lock(&self.shared);
+        for message in self.batch.drain(..) {
+            shared.merger.apply(message);
+        }
+        shared.write_ready()
+    }
+
+    /// Hands over all remaining changes. The last handle to be finished finishes the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.finish_handle()
+    }
+
+    fn finish_handle(&mut self) -> Result<()> {
+        self.batch.push(Message::Done { sender: self.id });
+        self.sync()?;
+        let mut shared = lock(&self.shared);
+        if shared.merger.has_senders() {
+            return Ok(());
+        }
+        if let Some(writer) = shared.writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: std::io::Write + std::io::Seek> Shared<W> {
+    fn write_ready(&mut self) -> Result<()> {
+        match &mut self.writer {
+            Some(writer) => self.merger.write_ready(writer),
+            None => Err(FstWriteError::WriterStopped),
+        }
+    }
+}
+
+impl<W: std::io::Write + std::io::Seek> Clone for SharedFstWriter<W> {
+    /// The new handle starts at the current time of this handle.
+    fn clone(&self) -> Self {
+        Self::register(self.shared.clone(), self.time)
+    }
+}
+
+impl<W: std::io::Write + std::io::Seek> Drop for SharedFstWriter<W> {
+    fn drop(&mut self) {
+        // handles that were finished explicitly are already unregistered
+        if lock(&self.shared)
+            .merger
+            .sender_times
+            .contains_key(&self.id)
+        {
+            let _ = self.finish_handle();
+        }
+    }
+}
+
+/// A panic while holding the lock leaves the writer in a consistent state.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// handles are moved to the threads that record changes
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<FstSignalSender>();
+    assert_send::<SharedFstWriter<std::io::Cursor<Vec<u8>>>>();
+};
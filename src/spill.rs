@@ -0,0 +1,247 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Moves buffered value changes out of the growing in-memory lists, either into a temporary
+// file or into compressed segments in memory.
+
+use crate::Result;
+use crate::io::{checked_u32, checked_usize};
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+#[cfg(feature = "fs")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Value changes of one block that were moved out of the in-memory lists.
+pub(crate) struct Spill {
+    storage: Storage,
+    /// LZ4 compress the value changes of every signal
+    compress: bool,
+    /// for every spill, sorted by signal index
+    segments: Vec<Vec<Segment>>,
+    len: u64,
+}
+
+enum Storage {
+    #[cfg(feature = "fs")]
+    File(TempFile),
+    Memory(Vec<u8>),
+}
+
+struct Segment {
+    signal_idx: u32,
+    offset: u64,
+    /// number of stored bytes
+    len: u32,
+    /// number of bytes after decompression
+    raw_len: u32,
+}
+
+impl Spill {
+    /// Stores value changes in a temporary file.
+    #[cfg(feature = "fs")]
+    pub(crate) fn file(compress: bool) -> Result<Self> {
+        Ok(Self::new(Storage::File(TempFile::new()?), compress))
+    }
+
+    /// Stores compressed value changes in memory.
+    pub(crate) fn memory() -> Self {
+        Self::new(Storage::Memory(vec![]), true)
+    }
+
+    fn new(storage: Storage, compress: bool) -> Self {
+        Self {
+            storage,
+            compress,
+            segments: vec![],
+            len: 0,
+        }
+    }
+
+    /// Number of stored bytes.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn is_in_memory(&self) -> bool {
+        matches!(self.storage, Storage::Memory(_))
+    }
+
+    /// Appends the value changes of all `signals` (sorted).
+    pub(crate) fn write(
+        &mut self,
+        signals: &[u32],
+        get_signal_data: impl Fn(usize, &mut Vec<u8>) -> Result<()>,
+    ) -> Result<()> {
+        let mut out: Box<dyn Write + '_> = match &mut self.storage {
+            #[cfg(feature = "fs")]
+            Storage::File(file) => {
+                let mut out = std::io::BufWriter::new(&file.file);
+                out.seek(SeekFrom::Start(self.len))?;
+                Box::new(out)
+            }
+            Storage::Memory(data) => Box::new(data),
+        };
+        let mut segment = Vec::with_capacity(signals.len());
+        // buffers are reused for all signals
+        let (mut data, mut compressed) = (vec![], vec![]);
+        for &signal_idx in signals.iter() {
+            data.clear();
+            get_signal_data(signal_idx as usize, &mut data)?;
+            if data.is_empty() {
+                continue;
+            }
+            let raw_len = checked_u32(data.len(), "the spilled value changes of a signal")?;
+            let stored = if self.compress {
+                compressed.resize(lz4_flex::block::get_maximum_output_size(data.len()), 0);
+                let len = lz4_flex::block::compress_into(&data, &mut compressed)
+                    .expect("output buffer has the maximum size");
+                &compressed[..len]
+            } else {
+                &data[..]
+            };
+            out.write_all(stored)?;
+            segment.push(Segment {
+                signal_idx,
+                offset: self.len,
+                len: checked_u32(stored.len(), "the spilled value changes of a signal")?,
+                raw_len,
+            });
+            self.len += stored.len() as u64;
+        }
+        out.flush()?;
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    /// Appends all stored value changes of a signal to `out`, in the order they were written.
+    pub(crate) fn read(&self, signal_idx: usize, out: &mut Vec<u8>) -> Result<()> {
+        #[cfg(feature = "fs")]
+        let mut stored = vec![];
+        for segment in self.segments.iter() {
+            let Ok(ii) = segment.binary_search_by_key(&(signal_idx as u32), |s| s.signal_idx)
+            else {
+                continue;
+            };
+            let s = &segment[ii];
+            let data = match &self.storage {
+                #[cfg(feature = "fs")]
+                Storage::File(file) => {
+                    let mut file = &file.file;
+                    file.seek(SeekFrom::Start(s.offset))?;
+                    stored.resize(s.len as usize, 0);
+                    file.read_exact(&mut stored)?;
+                    &stored[..]
+                }
+                Storage::Memory(data) => {
+                    let start = checked_usize(s.offset, "the spilled value changes")?;
+                    &data[start..start + s.len as usize]
+                }
+            };
+            if self.compress {
+                let start = out.len();
+                out.resize(start + s.raw_len as usize, 0);
+                let len = lz4_flex::decompress_into(data, &mut out[start..])
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                debug_assert_eq!(len, s.raw_len as usize);
+            } else {
+                out.extend_from_slice(data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets all value changes, e.g., after they were written to the output.
+    pub(crate) fn clear(&mut self) -> Result<()> {
+        match &mut self.storage {
+            #[cfg(feature = "fs")]
+            Storage::File(file) => file.file.set_len(0)?,
+            Storage::Memory(data) => data.clear(),
+        }
+        self.segments.clear();
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// File in the system's temporary directory that is deleted when dropped.
+#[cfg(feature = "fs")]
+pub(crate) struct TempFile {
+    pub(crate) file: File,
+    path: PathBuf,
+}
+
+#[cfg(feature = "fs")]
+impl TempFile {
+    pub(crate) fn new() -> std::io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "fst-writer-{}-{}.tmp",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self { file, path })
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn do_test_spill(mut spill: Spill) {
+        let data = |idx: usize| vec![idx as u8; idx * 20];
+        let get = |idx: usize, out: &mut Vec<u8>| {
+            out.extend_from_slice(&data(idx));
+            Ok(())
+        };
+        spill.write(&[1, 3, 4], get).unwrap();
+        spill.write(&[0, 3], get).unwrap();
+        let mut out = vec![];
+        spill.read(3, &mut out).unwrap();
+        assert_eq!(out, [data(3), data(3)].concat());
+        out.clear();
+        spill.read(2, &mut out).unwrap();
+        assert!(out.is_empty());
+        spill.clear().unwrap();
+        spill.read(3, &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn spill_file() {
+        do_test_spill(Spill::file(false).unwrap());
+        do_test_spill(Spill::file(true).unwrap());
+    }
+
+    #[test]
+    fn spill_memory_compressed() {
+        let mut spill = Spill::memory();
+        spill
+            .write(&[7], |_, out| {
+                out.resize(1000, 0);
+                Ok(())
+            })
+            .unwrap();
+        assert!(spill.len() < 100);
+        do_test_spill(spill);
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Statistics about the written value changes, e.g., to find the signals that dominate the
+// size of a trace, and hooks that observe them while the file is written.
+
+use std::time::Duration;
+
+/// Statistics of all value change blocks that were written so far.
+/// See [`crate::FstBodyWriter::stats`] and [`crate::FstBodyWriter::finish_with_stats`].
+#[derive(Debug, Clone, Default)]
+pub struct FstWriterStats {
+    /// Number of value changes of every signal, indexed by [`crate::FstSignalId::to_array_index`]
+    /// of the ids passed to `signal_change`. Changes that were not flushed yet are missing.
+    pub signal_changes: Vec<u64>,
+    /// One entry per value change block, in the order in which they were written.
+    pub blocks: Vec<FstBlockStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstBlockStats {
+    pub start_time: u64,
+    pub end_time: u64,
+    /// number of value changes in the block
+    pub num_changes: u64,
+    /// size of the value change section in the output
+    pub bytes: u64,
+    /// size of the value changes of all signals before compression
+    pub uncompressed_bytes: u64,
+    /// time it took to encode, compress and write the block
+    pub duration: Duration,
+}
+
+impl FstWriterStats {
+    /// Adds the statistics of a part that was appended to the file.
+    #[cfg(feature = "fs")]
+    pub(crate) fn append(&mut self, other: FstWriterStats) {
+        if self.signal_changes.len() < other.signal_changes.len() {
+            self.signal_changes.resize(other.signal_changes.len(), 0);
+        }
+        for (total, changes) in self.signal_changes.iter_mut().zip(other.signal_changes) {
+            *total += changes;
+        }
+        self.blocks.extend(other.blocks);
+    }
+}
+
+/// Memory held by the writer in bytes, split by what it is used for.
+/// See [`crate::FstBodyWriter::size_breakdown`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FstSizeBreakdown {
+    /// chains of value changes, including changes that were compressed in memory
+    pub value_changes: usize,
+    /// delta encoded time steps
+    pub time_table: usize,
+    /// current value of every signal and the values at the start of every block
+    pub frames: usize,
+    /// per-signal bookkeeping and reused scratch buffers, independent of the number of changes
+    pub fixed: usize,
+}
+
+impl FstSizeBreakdown {
+    pub fn total(&self) -> usize {
+        self.value_changes + self.time_table + self.frames + self.fixed
+    }
+}
+
+/// Callbacks that are notified while the file is written, e.g., to show the progress of a long
+/// conversion. Installed with [`crate::FstBodyWriter::set_hooks`].
+pub trait FstWriterHooks: Send {
+    /// Called once a value change block was written to the output.
+    fn on_block_flushed(&mut self, _block: &FstBlockStats) {}
+    /// Called once the file was finished with all its blocks.
+    fn on_finish(&mut self, _stats: &FstWriterStats) {}
+}
+
+/// Runs `f` and measures how long it took. `Instant` is not available on
+/// wasm32-unknown-unknown, where all durations are zero.
+pub(crate) fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        let start = std::time::Instant::now();
+        let result = f();
+        (result, start.elapsed())
+    }
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        (f(), Duration::ZERO)
+    }
+}
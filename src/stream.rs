@@ -0,0 +1,97 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Adapts outputs that cannot seek, like pipes or sockets, to the writer, which patches the
+// length of every section after writing it.
+
+use std::io::{Seek, SeekFrom, Write};
+
+/// Output that can only be written front to back, e.g., stdout or a socket.
+/// Data is kept in memory until [`Write::flush`] streams everything before the current
+/// position. Afterwards, seeking back into the streamed data fails.
+/// Created by [`crate::open_fst_stream`].
+pub struct FstStream<W: Write> {
+    out: W,
+    /// data that was not streamed yet
+    buf: Vec<u8>,
+    /// offset of the first byte in `buf`
+    start: u64,
+    pos: u64,
+}
+
+impl<W: Write> FstStream<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Self {
+            out,
+            buf: vec![],
+            start: 0,
+            pos: 0,
+        }
+    }
+
+    /// Returns the underlying output. Data that was not flushed is lost.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Write for FstStream<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let offset = (self.pos - self.start) as usize;
+        let overlap = data.len().min(self.buf.len() - offset);
+        self.buf[offset..offset + overlap].copy_from_slice(&data[..overlap]);
+        self.buf.extend_from_slice(&data[overlap..]);
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let len = (self.pos - self.start) as usize;
+        self.out.write_all(&self.buf[..len])?;
+        self.buf.drain(..len);
+        self.start = self.pos;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Seek for FstStream<W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let end = self.start + self.buf.len() as u64;
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => end.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        match target {
+            Some(target) if (self.start..=end).contains(&target) => {
+                self.pos = target;
+                Ok(target)
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "cannot seek outside of the data that was not streamed yet",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_before_flush() {
+        let mut stream = FstStream::new(vec![]);
+        stream.write_all(b"hello world").unwrap();
+        stream.seek(SeekFrom::Start(6)).unwrap();
+        stream.write_all(b"W").unwrap();
+        stream.seek(SeekFrom::End(0)).unwrap();
+        stream.flush().unwrap();
+        assert!(stream.seek(SeekFrom::Start(0)).is_err());
+        stream.write_all(b"!").unwrap();
+        assert_eq!(stream.stream_position().unwrap(), 12);
+        stream.flush().unwrap();
+        assert_eq!(stream.into_inner(), b"hello World!");
+    }
+}
@@ -0,0 +1,492 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Helpers to test writers and converters by comparing the waveforms or block structures of
+// two files, and to replay recorded writer calls.
+
+use crate::Result;
+use crate::io::{BlockType, invalid_file, read_variant_u64};
+use crate::recover::{HEADER_BLOCK_LEN, complete_block, count_hierarchy, read_u8, read_u64};
+use fst_reader::{FstFilter, FstHierarchyEntry, FstReader, FstSignalValue};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub use crate::replay::{FstCall, FstRecordingWriter, replay_fst};
+
+/// Differences of the same kind that are listed before the rest is summarized.
+const MAX_DIFFERENCES: usize = 10;
+
+/// Compares the waveforms of two files, independent of how they were written, e.g., with
+/// different block sizes, compression or signal order. Time tables, scopes, vars and the
+/// value changes of every signal, identified by the full name of its first var, need to
+/// match. Returns a description of every difference, thus an empty list if the files are
+/// equivalent.
+pub fn compare_fst(expected: impl AsRef<Path>, actual: impl AsRef<Path>) -> Result<Vec<String>> {
+    let expected = Waveform::read(expected.as_ref())?;
+    let actual = Waveform::read(actual.as_ref())?;
+    let mut differences = vec![];
+    compare_lists(
+        "time table",
+        &expected.time_table,
+        &actual.time_table,
+        &mut differences,
+    );
+    compare_lists(
+        "hierarchy",
+        &expected.hierarchy,
+        &actual.hierarchy,
+        &mut differences,
+    );
+    let mut mismatched_signals = 0;
+    for (name, expected_changes) in expected.signals.iter() {
+        let Some(actual_changes) = actual.signals.get(name) else {
+            // already reported as part of the hierarchy
+            continue;
+        };
+        if expected_changes != actual_changes {
+            mismatched_signals += 1;
+            if mismatched_signals <= MAX_DIFFERENCES {
+                let changes = |changes: &[(u64, String)]| {
+                    changes
+                        .iter()
+                        .map(|(time, value)| format!("{value} at {time}"))
+                        .collect::<Vec<_>>()
+                };
+                compare_lists(
+                    &format!("signal {name}"),
+                    &changes(expected_changes),
+                    &changes(actual_changes),
+                    &mut differences,
+                );
+            }
+        }
+    }
+    if mismatched_signals > MAX_DIFFERENCES {
+        differences.push(format!(
+            "{} more signals differ",
+            mismatched_signals - MAX_DIFFERENCES
+        ));
+    }
+    Ok(differences)
+}
+
+/// Panics with all differences if the waveforms of the two files are not equivalent,
+/// see [`compare_fst`].
+#[track_caller]
+pub fn assert_fst_equivalent(expected: impl AsRef<Path>, actual: impl AsRef<Path>) {
+    let (expected, actual) = (expected.as_ref(), actual.as_ref());
+    let differences = compare_fst(expected, actual).expect("failed to read files");
+    if !differences.is_empty() {
+        panic!(
+            "{expected:?} and {actual:?} differ:\n  {}",
+            differences.join("\n  ")
+        );
+    }
+}
+
+/// Reports where two lists start to differ.
+fn compare_lists<T: PartialEq + std::fmt::Display>(
+    what: &str,
+    expected: &[T],
+    actual: &[T],
+    differences: &mut Vec<String>,
+) {
+    if expected.len() != actual.len() {
+        differences.push(format!(
+            "{what}: expected {} entries, found {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    let mismatches = expected
+        .iter()
+        .zip(actual.iter())
+        .enumerate()
+        .filter(|(_, (e, a))| e != a);
+    let mut count = 0;
+    for (index, (e, a)) in mismatches {
+        count += 1;
+        if count <= MAX_DIFFERENCES {
+            differences.push(format!("{what}[{index}]: expected {e}, found {a}"));
+        }
+    }
+    if count > MAX_DIFFERENCES {
+        differences.push(format!(
+            "{what}: {} more entries differ",
+            count - MAX_DIFFERENCES
+        ));
+    }
+}
+
+/// Everything that makes up a waveform, in a form that does not depend on how it was written.
+struct Waveform {
+    /// without repeated entries, which occur at block boundaries
+    time_table: Vec<u64>,
+    /// a line for every scope and var
+    hierarchy: Vec<String>,
+    /// changes of every signal by the name of its first var, without repeated values
+    signals: BTreeMap<String, Vec<(u64, String)>>,
+}
+
+impl Waveform {
+    fn read(path: &Path) -> Result<Self> {
+        let input = BufReader::new(std::fs::File::open(path)?);
+        let mut reader = FstReader::open_and_read_time_table(input).map_err(invalid_file)?;
+        let mut time_table = reader.get_time_table().unwrap_or_default().to_vec();
+        time_table.dedup();
+
+        let mut hierarchy = vec![];
+        let mut scopes: Vec<String> = vec![];
+        // name of the first var of every signal
+        let mut names: HashMap<usize, String> = HashMap::new();
+        reader
+            .read_hierarchy(|entry| match entry {
+                FstHierarchyEntry::Scope {
+                    tpe,
+                    name,
+                    component,
+                } => {
+                    scopes.push(name);
+                    hierarchy.push(format!(
+                        "scope {} ({tpe:?}, component {component:?})",
+                        scopes.join(".")
+                    ));
+                }
+                FstHierarchyEntry::UpScope => {
+                    scopes.pop();
+                }
+                FstHierarchyEntry::Var {
+                    tpe,
+                    direction,
+                    name,
+                    length,
+                    handle,
+                    ..
+                } => {
+                    let full_name = scopes
+                        .iter()
+                        .map(String::as_str)
+                        .chain([name.as_str()])
+                        .collect::<Vec<_>>()
+                        .join(".");
+                    let mut line =
+                        format!("var {full_name} ({tpe:?}, {direction:?}, {length} bits)");
+                    match names.get(&handle.get_index()) {
+                        Some(first) => line += &format!(" alias of {first}"),
+                        None => {
+                            names.insert(handle.get_index(), full_name);
+                        }
+                    }
+                    hierarchy.push(line);
+                }
+                _ => {}
+            })
+            .map_err(invalid_file)?;
+
+        let mut changes: HashMap<usize, Vec<(u64, String)>> = HashMap::new();
+        reader
+            .read_signals(&FstFilter::all(), |time, handle, value| {
+                let value = match value {
+                    FstSignalValue::String(value) => String::from_utf8_lossy(value).into_owned(),
+                    FstSignalValue::Real(value) => value.to_string(),
+                };
+                let signal = changes.entry(handle.get_index()).or_default();
+                if signal.last().is_none_or(|(_, last)| *last != value) {
+                    signal.push((time, value));
+                }
+            })
+            .map_err(invalid_file)?;
+        let signals = names
+            .into_iter()
+            .map(|(index, name)| (name, changes.remove(&index).unwrap_or_default()))
+            .collect();
+
+        Ok(Self {
+            time_table,
+            hierarchy,
+            signals,
+        })
+    }
+}
+
+/// The fields of a block that describe the waveform and not how it was encoded, i.e., without
+/// section lengths, compression, memory requirements or the version and date strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FstBlock {
+    Header {
+        start_time: u64,
+        end_time: u64,
+        scope_count: u64,
+        var_count: u64,
+        num_signals: u64,
+        num_value_change_sections: u64,
+        timescale_exponent: i8,
+        file_type: u8,
+        time_zero: u64,
+    },
+    ValueChanges {
+        start_time: u64,
+        end_time: u64,
+        /// all entries as stored in the file. fst-writer keeps the values at the start time
+        /// in the frame and only lists later time steps, while fstapi lists the start time
+        /// as well once it has value changes.
+        time_table: Vec<u64>,
+    },
+    Blackout,
+    /// length of every signal in the encoding of the file, i.e., zero for reals
+    Geometry {
+        signals: Vec<u64>,
+    },
+    Hierarchy {
+        scopes: u64,
+        vars: u64,
+    },
+    Skip,
+}
+
+impl FstBlock {
+    fn kind(&self) -> &'static str {
+        match self {
+            FstBlock::Header { .. } => "header",
+            FstBlock::ValueChanges { .. } => "value changes",
+            FstBlock::Blackout => "blackout",
+            FstBlock::Geometry { .. } => "geometry",
+            FstBlock::Hierarchy { .. } => "hierarchy",
+            FstBlock::Skip => "skip",
+        }
+    }
+
+    /// All fields but the time table, which is compared entry by entry.
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            FstBlock::Header {
+                start_time,
+                end_time,
+                scope_count,
+                var_count,
+                num_signals,
+                num_value_change_sections,
+                timescale_exponent,
+                file_type,
+                time_zero,
+            } => vec![
+                ("start time", start_time.to_string()),
+                ("end time", end_time.to_string()),
+                ("scope count", scope_count.to_string()),
+                ("var count", var_count.to_string()),
+                ("signals", num_signals.to_string()),
+                (
+                    "value change sections",
+                    num_value_change_sections.to_string(),
+                ),
+                ("timescale", timescale_exponent.to_string()),
+                ("file type", file_type.to_string()),
+                ("time zero", time_zero.to_string()),
+            ],
+            FstBlock::ValueChanges {
+                start_time,
+                end_time,
+                ..
+            } => vec![
+                ("start time", start_time.to_string()),
+                ("end time", end_time.to_string()),
+            ],
+            FstBlock::Geometry { signals } => vec![("signals", format!("{signals:?}"))],
+            FstBlock::Hierarchy { scopes, vars } => {
+                vec![("scopes", scopes.to_string()), ("vars", vars.to_string())]
+            }
+            FstBlock::Blackout | FstBlock::Skip => vec![],
+        }
+    }
+}
+
+/// Parses the header and every block of a file that is not compressed as a whole.
+pub fn read_blocks(path: impl AsRef<Path>) -> Result<Vec<FstBlock>> {
+    let mut input = BufReader::new(std::fs::File::open(path)?);
+    let file_len = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+    if read_u8(&mut input)? != BlockType::Header as u8 {
+        return Err(invalid_file("expected an uncompressed FST header"));
+    }
+    let mut header = [0u8; HEADER_BLOCK_LEN as usize - 1];
+    input.read_exact(&mut header)?;
+    let field = |start: usize| u64::from_be_bytes(header[start..start + 8].try_into().unwrap());
+    // section length, start and end time, endian test, memory used, counts, timescale,
+    // version, date, file type and time zero
+    let mut blocks = vec![FstBlock::Header {
+        start_time: field(8),
+        end_time: field(16),
+        scope_count: field(40),
+        var_count: field(48),
+        num_signals: field(56),
+        num_value_change_sections: field(64),
+        timescale_exponent: header[72] as i8,
+        file_type: header[320],
+        time_zero: field(321),
+    }];
+
+    let mut offset = HEADER_BLOCK_LEN;
+    while offset < file_len {
+        let Some((tpe, section_len)) = complete_block(&mut input, offset, file_len)? else {
+            return Err(invalid_file(format!(
+                "incomplete or unknown block at offset {offset}"
+            )));
+        };
+        let mut bytes = vec![0u8; section_len as usize - 8];
+        input.read_exact(&mut bytes)?;
+        blocks.push(match tpe {
+            BlockType::VcData | BlockType::VcDataDynamicAlias | BlockType::VcDataDynamicAlias2 => {
+                read_value_changes(&bytes)?
+            }
+            BlockType::Geometry => read_geometry(&bytes)?,
+            BlockType::Hierarchy | BlockType::HierarchyLZ4 | BlockType::HierarchyLZ4Duo => {
+                let (scopes, vars) = count_hierarchy(tpe, &bytes)?;
+                FstBlock::Hierarchy { scopes, vars }
+            }
+            BlockType::Blackout => FstBlock::Blackout,
+            _ => FstBlock::Skip,
+        });
+        offset += 1 + section_len;
+        input.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(blocks)
+}
+
+/// Compares the blocks of two files field by field, see [`read_blocks`]. Blocks of the same
+/// kind are compared in order, but where the hierarchy and geometry are placed in the file does
+/// not matter. Unlike [`compare_fst`], this also catches differences that readers tolerate,
+/// e.g., repeated time table entries. Returns a description of every difference.
+pub fn compare_blocks(expected: impl AsRef<Path>, actual: impl AsRef<Path>) -> Result<Vec<String>> {
+    Ok(compare_block_lists(
+        read_blocks(expected)?,
+        read_blocks(actual)?,
+    ))
+}
+
+/// Compares blocks that were already read with [`read_blocks`], e.g., to leave out known
+/// differences first. See [`compare_blocks`].
+pub fn compare_block_lists(expected: Vec<FstBlock>, actual: Vec<FstBlock>) -> Vec<String> {
+    let by_kind = |blocks: Vec<FstBlock>| {
+        let mut by_kind: BTreeMap<&'static str, Vec<FstBlock>> = BTreeMap::new();
+        for block in blocks {
+            by_kind.entry(block.kind()).or_default().push(block);
+        }
+        by_kind
+    };
+    let expected = by_kind(expected);
+    let mut actual = by_kind(actual);
+    let mut differences = vec![];
+    for (kind, expected) in expected.iter() {
+        let actual = actual.remove(kind).unwrap_or_default();
+        if expected.len() != actual.len() {
+            differences.push(format!(
+                "expected {} {kind} blocks, found {}",
+                expected.len(),
+                actual.len()
+            ));
+        }
+        for (index, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+            for ((name, e), (_, a)) in e.fields().into_iter().zip(a.fields()) {
+                if e != a {
+                    differences.push(format!(
+                        "{kind} block {index} {name}: expected {e}, found {a}"
+                    ));
+                }
+            }
+            if let (
+                FstBlock::ValueChanges { time_table: e, .. },
+                FstBlock::ValueChanges { time_table: a, .. },
+            ) = (e, a)
+            {
+                let what = format!("{kind} block {index} time table");
+                compare_lists(&what, e, a, &mut differences);
+            }
+        }
+    }
+    for (kind, actual) in actual {
+        differences.push(format!("expected 0 {kind} blocks, found {}", actual.len()));
+    }
+    differences
+}
+
+/// Start and end time followed by the time table at the very end of the block.
+fn read_value_changes(bytes: &[u8]) -> Result<FstBlock> {
+    let u64_at = |start: usize| -> Result<u64> {
+        let field = bytes
+            .get(start..start + 8)
+            .ok_or_else(|| invalid_file("value change block is too short"))?;
+        Ok(u64::from_be_bytes(field.try_into().unwrap()))
+    };
+    let (start_time, end_time) = (u64_at(0)?, u64_at(8)?);
+    let lengths = bytes
+        .len()
+        .checked_sub(3 * 8)
+        .ok_or_else(|| invalid_file("value change block is too short"))?;
+    let (uncompressed_len, compressed_len) = (u64_at(lengths)?, u64_at(lengths + 8)?);
+    let entries = u64_at(lengths + 16)?;
+    let table = lengths
+        .checked_sub(compressed_len as usize)
+        .map(|start| &bytes[start..lengths])
+        .ok_or_else(|| invalid_file("time table is longer than its block"))?;
+    let table = decompress_zlib(table, uncompressed_len)?;
+    let mut time_table = vec![];
+    let mut time = 0u64;
+    let mut input = table.as_slice();
+    while !input.is_empty() {
+        let (delta, len) = read_variant_u64(input)
+            .ok_or_else(|| invalid_file("incomplete variable length integer"))?;
+        time = time
+            .checked_add(delta)
+            .ok_or_else(|| invalid_file("time table overflows"))?;
+        time_table.push(time);
+        input = &input[len..];
+    }
+    if time_table.len() as u64 != entries {
+        return Err(invalid_file(format!(
+            "time table has {} instead of {entries} entries",
+            time_table.len()
+        )));
+    }
+    Ok(FstBlock::ValueChanges {
+        start_time,
+        end_time,
+        time_table,
+    })
+}
+
+/// Uncompressed length and number of signals, followed by the lengths, which are zlib
+/// compressed if that made them smaller.
+fn read_geometry(bytes: &[u8]) -> Result<FstBlock> {
+    let mut input = bytes;
+    let uncompressed_len = read_u64(&mut input)?;
+    let num_signals = read_u64(&mut input)?;
+    let lengths = decompress_zlib(input, uncompressed_len)?;
+    let mut signals = vec![];
+    let mut input = lengths.as_slice();
+    while !input.is_empty() {
+        let (length, len) = read_variant_u64(input)
+            .ok_or_else(|| invalid_file("incomplete variable length integer"))?;
+        signals.push(length);
+        input = &input[len..];
+    }
+    if signals.len() as u64 != num_signals {
+        return Err(invalid_file(format!(
+            "geometry has {} instead of {num_signals} signals",
+            signals.len()
+        )));
+    }
+    Ok(FstBlock::Geometry { signals })
+}
+
+/// Readers interpret data that is as long as its uncompressed length as uncompressed.
+fn decompress_zlib(bytes: &[u8], uncompressed_len: u64) -> Result<Vec<u8>> {
+    if bytes.len() as u64 == uncompressed_len {
+        Ok(bytes.to_vec())
+    } else {
+        miniz_oxide::inflate::decompress_to_vec_zlib(bytes)
+            .map_err(|e| invalid_file(format!("failed to decompress: {e}")))
+    }
+}
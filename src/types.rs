@@ -3,6 +3,7 @@
 // author: Kevin Laeufer <laeufer@cornell.edu>
 
 use std::num::NonZeroU32;
+use std::sync::Arc;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -23,7 +24,7 @@ pub struct FstInfo {
     pub file_type: FstFileType,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FstSignalId(NonZeroU32);
 
 impl FstSignalId {
@@ -36,7 +37,8 @@ impl FstSignalId {
         self.0.get()
     }
 
-    pub(crate) fn to_array_index(self) -> usize {
+    /// Zero based index, e.g., into [`crate::FstBodyWriter::signal_remap`].
+    pub fn to_array_index(self) -> usize {
         self.0.get() as usize - 1
     }
 }
@@ -51,6 +53,7 @@ enum SignalType {
 }
 
 impl FstSignalType {
+    /// Values of a length of zero can be of any length, e.g., strings.
     pub fn bit_vec(len: u32) -> Self {
         Self(SignalType::BitVec(NonZeroU32::new(len + 1).unwrap()))
     }
@@ -69,6 +72,18 @@ impl FstSignalType {
         }
     }
 
+    /// Number of bits, `None` for real signals.
+    pub fn bit_vec_len(&self) -> Option<u32> {
+        match self.0 {
+            SignalType::BitVec(value) => Some(value.get() - 1),
+            SignalType::Real => None,
+        }
+    }
+
+    pub fn is_real(&self) -> bool {
+        matches!(self.0, SignalType::Real)
+    }
+
     #[inline]
     pub(crate) fn len(&self) -> u32 {
         match self.0 {
@@ -78,8 +93,32 @@ impl FstSignalType {
     }
 }
 
+/// A scope that was declared through [`crate::FstHeaderWriter::scope`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FstScopeInfo {
+    pub name: Arc<str>,
+    pub component: Arc<str>,
+    pub tpe: FstScopeType,
+    /// index of the parent in [`crate::FstHeaderWriter::scopes`]
+    pub parent: Option<usize>,
+}
+
+/// A variable that was declared through [`crate::FstHeaderWriter::var`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FstVarInfo {
+    pub name: Arc<str>,
+    /// index of the surrounding scope in [`crate::FstHeaderWriter::scopes`]
+    pub scope: Option<usize>,
+    pub signal_id: FstSignalId,
+    pub signal_tpe: FstSignalType,
+    pub tpe: FstVarType,
+    pub dir: FstVarDirection,
+    pub is_alias: bool,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FstScopeType {
     // VCD
     Module = 0,
@@ -109,6 +148,7 @@ pub enum FstScopeType {
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FstVarType {
     // VCD
     Event = 0,
@@ -146,6 +186,7 @@ pub enum FstVarType {
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FstVarDirection {
     Implicit = 0,
     Input = 1,
@@ -0,0 +1,206 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Output file that submits its writes through io_uring, so that the next block can be
+// compressed while the kernel is still writing the previous one.
+
+use io_uring::{IoUring, opcode, types};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+/// number of writes that can be in flight at the same time
+const QUEUE_DEPTH: usize = 8;
+/// writes are collected until they reach this size
+const DEFAULT_BUFFER_CAPACITY: usize = 1 << 20;
+
+/// File that is written asynchronously through io_uring. Writes and seeks only wait for the
+/// kernel once all buffers are in flight, [`Write::flush`] waits for all of them.
+/// Created by [`crate::open_fst_uring`].
+pub struct FstUringFile {
+    file: File,
+    ring: IoUring,
+    /// data that was not submitted yet
+    buf: Vec<u8>,
+    /// file offset of the first byte in `buf`
+    buf_start: u64,
+    capacity: usize,
+    /// largest offset written so far, needed for [`SeekFrom::End`]
+    len: u64,
+    /// buffers of submitted writes, the slot is the user data of the submission
+    in_flight: [Option<InFlight>; QUEUE_DEPTH],
+    /// buffers of completed writes that can be reused
+    free: Vec<Vec<u8>>,
+}
+
+struct InFlight {
+    data: Vec<u8>,
+    offset: u64,
+}
+
+impl InFlight {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        start < self.offset + self.data.len() as u64 && self.offset < end
+    }
+}
+
+impl FstUringFile {
+    pub(crate) fn new(file: File, capacity: Option<usize>) -> std::io::Result<Self> {
+        let ring = IoUring::new(QUEUE_DEPTH as u32)?;
+        let len = file.metadata()?.len();
+        let capacity = capacity.unwrap_or(DEFAULT_BUFFER_CAPACITY).max(1);
+        Ok(Self {
+            file,
+            ring,
+            buf: Vec::with_capacity(capacity),
+            buf_start: 0,
+            capacity,
+            len,
+            in_flight: Default::default(),
+            free: vec![],
+        })
+    }
+
+    fn position(&self) -> u64 {
+        self.buf_start + self.buf.len() as u64
+    }
+
+    /// Hands the buffered data to the kernel without waiting for it to be written.
+    fn submit(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        // writes that are in flight together can complete in any order, thus overwriting
+        // data, e.g., when updating the header, needs to wait for the original write
+        let (start, end) = (self.buf_start, self.position());
+        while self
+            .in_flight
+            .iter()
+            .flatten()
+            .any(|w| w.overlaps(start, end))
+        {
+            self.wait_for_one()?;
+        }
+        let slot = match self.in_flight.iter().position(|s| s.is_none()) {
+            Some(slot) => slot,
+            None => self.wait_for_one()?,
+        };
+        let next = self
+            .free
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity));
+        let data = std::mem::replace(&mut self.buf, next);
+        let offset = self.buf_start;
+        self.buf_start += data.len() as u64;
+        let entry = opcode::Write::new(
+            types::Fd(self.file.as_raw_fd()),
+            data.as_ptr(),
+            data.len() as u32,
+        )
+        .offset(offset)
+        .build()
+        .user_data(slot as u64);
+        // the buffer stays alive in `in_flight` until the write completes
+        self.in_flight[slot] = Some(InFlight { data, offset });
+        // SAFETY: the entry refers to a buffer that is only dropped after its completion
+        unsafe {
+            self.ring
+                .submission()
+                .push(&entry)
+                .expect("at most QUEUE_DEPTH writes are in flight");
+        }
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    /// Waits for one write to complete and returns its slot.
+    fn wait_for_one(&mut self) -> std::io::Result<usize> {
+        loop {
+            if let Some(cqe) = self.ring.completion().next() {
+                let slot = cqe.user_data() as usize;
+                let InFlight { mut data, offset } = self.in_flight[slot].take().unwrap();
+                let result = cqe.result();
+                if result < 0 {
+                    return Err(std::io::Error::from_raw_os_error(-result));
+                }
+                // short writes are rare, we finish them synchronously
+                let written = result as usize;
+                if written < data.len() {
+                    self.file
+                        .write_all_at(&data[written..], offset + written as u64)?;
+                }
+                data.clear();
+                self.free.push(data);
+                return Ok(slot);
+            }
+            self.ring.submit_and_wait(1)?;
+        }
+    }
+
+    fn wait_for_all(&mut self) -> std::io::Result<()> {
+        while self.in_flight.iter().any(|s| s.is_some()) {
+            self.wait_for_one()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for FstUringFile {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let len = data.len().min(self.capacity - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        self.len = self.len.max(self.position());
+        if self.buf.len() == self.capacity {
+            self.submit()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.submit()?;
+        self.wait_for_all()
+    }
+}
+
+impl Seek for FstUringFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position().checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        // asking for the position must not interrupt buffering
+        if target != self.position() {
+            self.submit()?;
+            self.buf_start = target;
+        }
+        Ok(target)
+    }
+}
+
+impl Drop for FstUringFile {
+    fn drop(&mut self) {
+        // the kernel might still access the buffers
+        let _ = self.flush();
+        while self.in_flight.iter().any(|s| s.is_some()) {
+            if self.ring.submit_and_wait(1).is_err() {
+                // we cannot know when the kernel is done with the buffers
+                for slot in self.in_flight.iter_mut() {
+                    std::mem::forget(slot.take());
+                }
+                return;
+            }
+            while let Some(cqe) = self.ring.completion().next() {
+                self.in_flight[cqe.user_data() as usize] = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Reads VCD declarations into a header writer. With the `vcd` feature, whole files can be
+// converted through `convert_vcd` or `vcd_to_fst`.
+
+#[cfg(feature = "vcd")]
+mod convert;
+
+use crate::{
+    FstHeaderWriter, FstScopeType, FstSignalId, FstSignalType, FstVarDirection, FstVarType,
+    FstWriteError, Result,
+};
+#[cfg(feature = "vcd")]
+pub use convert::convert_vcd;
+#[cfg(all(feature = "vcd", feature = "fs"))]
+pub use convert::vcd_to_fst;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+impl<W: std::io::Write + std::io::Seek> FstHeaderWriter<W> {
+    /// Reads the declarations of a VCD file up to and including `$enddefinitions $end` and
+    /// declares all scopes and variables. `input` is left at the start of the value changes.
+    /// Returns the signal id of every VCD id code.
+    pub fn import_vcd_header(
+        &mut self,
+        input: &mut impl BufRead,
+    ) -> Result<HashMap<String, FstSignalId>> {
+        let mut ids = HashMap::new();
+        let mut tokens = Tokens { input };
+        loop {
+            let Some(cmd) = tokens.next()? else {
+                return Err(vcd_error("missing $enddefinitions"));
+            };
+            match cmd.as_str() {
+                "$scope" => {
+                    let body = tokens.until_end()?;
+                    let [tpe, name] = body.as_slice() else {
+                        return Err(vcd_error(format!("invalid scope: {body:?}")));
+                    };
+                    self.scope(name, "", parse_scope_type(tpe)?)?;
+                }
+                "$upscope" => {
+                    tokens.until_end()?;
+                    self.up_scope()?;
+                }
+                "$var" => {
+                    let body = tokens.until_end()?;
+                    let (tpe, size, id, name) = match body.as_slice() {
+                        [tpe, size, id, name] => (tpe, size, id, name.clone()),
+                        [tpe, size, id, name, range] => (tpe, size, id, format!("{name} {range}")),
+                        _ => return Err(vcd_error(format!("invalid var: {body:?}"))),
+                    };
+                    let tpe = parse_var_type(tpe)?;
+                    let signal_tpe = if is_real(tpe) {
+                        FstSignalType::real()
+                    } else if tpe == FstVarType::GenericString {
+                        // strings have no fixed length
+                        FstSignalType::bit_vec(0)
+                    } else {
+                        let size = size
+                            .parse::<u32>()
+                            .map_err(|_| vcd_error(format!("invalid var size: {size}")))?;
+                        FstSignalType::bit_vec(size)
+                    };
+                    let alias = ids.get(id).cloned();
+                    let signal_id =
+                        self.var(name, signal_tpe, tpe, FstVarDirection::Implicit, alias)?;
+                    ids.insert(id.clone(), signal_id);
+                }
+                "$enddefinitions" => {
+                    tokens.until_end()?;
+                    return Ok(ids);
+                }
+                // $date, $version, $timescale, $comment, ...
+                _ => {
+                    tokens.until_end()?;
+                }
+            }
+        }
+    }
+}
+
+fn vcd_error(msg: impl Into<String>) -> FstWriteError {
+    FstWriteError::VcdParse(msg.into())
+}
+
+/// Splits the input into whitespace separated tokens without reading past the last token.
+struct Tokens<'a, R: BufRead> {
+    input: &'a mut R,
+}
+
+impl<R: BufRead> Tokens<'_, R> {
+    fn next(&mut self) -> Result<Option<String>> {
+        let mut token = vec![];
+        loop {
+            let buf = self.input.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let mut consumed = 0;
+            let mut done = false;
+            for &b in buf.iter() {
+                if b.is_ascii_whitespace() {
+                    if !token.is_empty() {
+                        done = true;
+                        break;
+                    }
+                } else {
+                    token.push(b);
+                }
+                consumed += 1;
+            }
+            self.input.consume(consumed);
+            if done {
+                break;
+            }
+        }
+        if token.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(String::from_utf8_lossy(&token).into_owned()))
+        }
+    }
+
+    /// Returns all tokens up to the next `$end`.
+    fn until_end(&mut self) -> Result<Vec<String>> {
+        let mut out = vec![];
+        loop {
+            match self.next()? {
+                None => return Err(vcd_error("missing $end")),
+                Some(token) if token == "$end" => return Ok(out),
+                Some(token) => out.push(token),
+            }
+        }
+    }
+}
+
+fn parse_scope_type(value: &str) -> Result<FstScopeType> {
+    let tpe = match value {
+        "module" => FstScopeType::Module,
+        "task" => FstScopeType::Task,
+        "function" => FstScopeType::Function,
+        "begin" => FstScopeType::Begin,
+        "fork" => FstScopeType::Fork,
+        "generate" => FstScopeType::Generate,
+        "struct" => FstScopeType::Struct,
+        "union" => FstScopeType::Union,
+        "class" => FstScopeType::Class,
+        "interface" => FstScopeType::Interface,
+        "package" => FstScopeType::Package,
+        "program" => FstScopeType::Program,
+        "vhdl_architecture" => FstScopeType::VhdlArchitecture,
+        "vhdl_procedure" => FstScopeType::VhdlProcedure,
+        "vhdl_function" => FstScopeType::VhdlFunction,
+        "vhdl_record" => FstScopeType::VhdlRecord,
+        "vhdl_process" => FstScopeType::VhdlProcess,
+        "vhdl_block" => FstScopeType::VhdlBlock,
+        "vhdl_for_generate" => FstScopeType::VhdlForGenerate,
+        "vhdl_if_generate" => FstScopeType::VhdlIfGenerate,
+        "vhdl_generate" => FstScopeType::VhdlGenerate,
+        "vhdl_package" => FstScopeType::VhdlPackage,
+        other => return Err(vcd_error(format!("unknown scope type: {other}"))),
+    };
+    Ok(tpe)
+}
+
+fn parse_var_type(value: &str) -> Result<FstVarType> {
+    let tpe = match value {
+        "event" => FstVarType::Event,
+        "integer" => FstVarType::Integer,
+        "parameter" => FstVarType::Parameter,
+        "real" => FstVarType::Real,
+        "real_parameter" => FstVarType::RealParameter,
+        "reg" => FstVarType::Reg,
+        "supply0" => FstVarType::Supply0,
+        "supply1" => FstVarType::Supply1,
+        "time" => FstVarType::Time,
+        "tri" => FstVarType::Tri,
+        "triand" => FstVarType::TriAnd,
+        "trior" => FstVarType::TriOr,
+        "trireg" => FstVarType::TriReg,
+        "tri0" => FstVarType::Tri0,
+        "tri1" => FstVarType::Tri1,
+        "wand" => FstVarType::Wand,
+        "wire" => FstVarType::Wire,
+        "wor" => FstVarType::Wor,
+        "port" => FstVarType::Port,
+        "sparray" => FstVarType::SparseArray,
+        "realtime" => FstVarType::RealTime,
+        "string" => FstVarType::GenericString,
+        "bit" => FstVarType::Bit,
+        "logic" => FstVarType::Logic,
+        "int" => FstVarType::Int,
+        "shortint" => FstVarType::ShortInt,
+        "longint" => FstVarType::LongInt,
+        "byte" => FstVarType::Byte,
+        "enum" => FstVarType::Enum,
+        "shortreal" => FstVarType::ShortReal,
+        other => return Err(vcd_error(format!("unknown var type: {other}"))),
+    };
+    Ok(tpe)
+}
+
+fn is_real(tpe: FstVarType) -> bool {
+    matches!(
+        tpe,
+        FstVarType::Real | FstVarType::RealParameter | FstVarType::RealTime | FstVarType::ShortReal
+    )
+}
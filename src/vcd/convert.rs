@@ -0,0 +1,217 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Converts VCD files into FST while reading them, like `vcd2fst`. Only the declarations are
+// kept in memory, value changes are written out in blocks.
+
+use super::{Tokens, vcd_error};
+use crate::{
+    DEFAULT_FLUSH_SIZE, FstBodyWriter, FstFileType, FstHeaderWriter, FstInfo, FstSignalId, Result,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, Seek, Write};
+
+/// Converts the VCD `input` into an FST file at `output` with the given configuration.
+#[cfg(feature = "fs")]
+pub fn vcd_to_fst(
+    input: impl BufRead,
+    output: impl AsRef<std::path::Path>,
+    config: crate::FstWriterConfig,
+) -> Result<()> {
+    convert_vcd(input, |info| {
+        crate::open_fst(output, info)?.with_config(config)
+    })?;
+    Ok(())
+}
+
+/// Converts the VCD `input` into an FST file, which is written by the writer that `open`
+/// creates from the date, version, timescale and start time of the VCD. Returns the output
+/// of the finished writer. Unless [`crate::FstWriterConfig::auto_flush_size`] is set, a
+/// value change block is written every [`DEFAULT_FLUSH_SIZE`] bytes.
+pub fn convert_vcd<R: BufRead, W: Write + Seek>(
+    mut input: R,
+    open: impl FnOnce(&FstInfo) -> Result<FstHeaderWriter<W>>,
+) -> Result<W> {
+    let mut tokens = Tokens { input: &mut input };
+    let (declarations, mut info) = read_declarations(&mut tokens)?;
+    // changes before the first time step, e.g., `$dumpvars` without `#0`, happen at time 0
+    let mut initial = vec![];
+    let mut first_time = None;
+    while let Some(token) = tokens.next()? {
+        if let Some(time) = token.strip_prefix('#') {
+            first_time = Some(parse_time(time)?);
+            break;
+        }
+        if token == "$comment" {
+            tokens.until_end()?;
+            continue;
+        }
+        // the id code that follows may start with `#`
+        let has_id = token.starts_with(['b', 'B', 'r', 'R', 's', 'S']);
+        initial.push(token);
+        if has_id {
+            initial.extend(tokens.next()?);
+        }
+    }
+
+    if initial.is_empty() {
+        info.start_time = first_time.unwrap_or_default();
+    }
+    let mut writer = open(&info)?;
+    let ids = writer.import_vcd_header(&mut declarations.as_slice())?;
+    let auto_flush = writer.config().auto_flush_size.is_none();
+    let mut body = Body {
+        writer: writer.finish()?,
+        ids,
+        auto_flush,
+    };
+    let mut initial = initial.into_iter();
+    body.read(|| Ok(initial.next()))?;
+    if let Some(time) = first_time {
+        body.time_change(time)?;
+    }
+    body.read(|| tokens.next())?;
+    body.writer.finish()
+}
+
+/// Reads up to and including `$enddefinitions $end`. Returns the declarations to be imported
+/// through [`FstHeaderWriter::import_vcd_header`] and what goes into the FST header.
+fn read_declarations(tokens: &mut Tokens<'_, impl BufRead>) -> Result<(Vec<u8>, FstInfo)> {
+    let mut info = FstInfo {
+        start_time: 0,
+        timescale_exponent: 0,
+        version: String::new(),
+        date: String::new(),
+        file_type: FstFileType::Verilog,
+    };
+    let mut declarations = vec![];
+    loop {
+        let Some(cmd) = tokens.next()? else {
+            return Err(vcd_error("missing $enddefinitions"));
+        };
+        let body = tokens.until_end()?;
+        match cmd.as_str() {
+            "$date" => info.date = body.join(" "),
+            "$version" => info.version = body.join(" "),
+            "$timescale" => info.timescale_exponent = parse_timescale(&body.concat())?,
+            "$scope" | "$upscope" | "$var" => {
+                writeln!(declarations, "{cmd} {} $end", body.join(" "))?;
+            }
+            "$enddefinitions" => {
+                declarations.extend_from_slice(b"$enddefinitions $end\n");
+                return Ok((declarations, info));
+            }
+            // $comment, $timezero, ...
+            _ => {}
+        }
+    }
+}
+
+/// FST only stores the exponent, thus a factor of 10 or 100 moves into the exponent.
+fn parse_timescale(value: &str) -> Result<i8> {
+    let digits = value.bytes().take_while(u8::is_ascii_digit).count();
+    let factor = match &value[..digits] {
+        "1" => 0,
+        "10" => 1,
+        "100" => 2,
+        _ => return Err(vcd_error(format!("invalid timescale: {value}"))),
+    };
+    let unit = match &value[digits..] {
+        "s" => 0,
+        "ms" => -3,
+        "us" => -6,
+        "ns" => -9,
+        "ps" => -12,
+        "fs" => -15,
+        _ => return Err(vcd_error(format!("invalid timescale: {value}"))),
+    };
+    Ok(unit + factor)
+}
+
+fn parse_time(value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|_| vcd_error(format!("invalid time: #{value}")))
+}
+
+struct Body<W: Write + Seek> {
+    writer: FstBodyWriter<W>,
+    ids: HashMap<String, FstSignalId>,
+    auto_flush: bool,
+}
+
+impl<W: Write + Seek> Body<W> {
+    /// Records all time steps and value changes returned by `next`.
+    fn read(&mut self, mut next: impl FnMut() -> Result<Option<String>>) -> Result<()> {
+        while let Some(token) = next()? {
+            let mut chars = token.chars();
+            match chars.next() {
+                Some('#') => self.time_change(parse_time(chars.as_str())?)?,
+                Some('$') => match token.as_str() {
+                    "$dumpoff" => self.writer.dump_off(),
+                    "$dumpon" => self.writer.dump_on(),
+                    "$comment" => while next()?.is_some_and(|token| token != "$end") {},
+                    // $dumpvars, $dumpall and the $end of all of them
+                    _ => {}
+                },
+                Some('b' | 'B') => {
+                    let id = next_id(&mut next)?;
+                    self.signal_change(&id, chars.as_str().as_bytes())?;
+                }
+                Some('r' | 'R') => {
+                    let value: f64 = chars
+                        .as_str()
+                        .parse()
+                        .map_err(|_| vcd_error(format!("invalid real: {token}")))?;
+                    let id = next_id(&mut next)?;
+                    self.signal_change(&id, &value.to_le_bytes())?;
+                }
+                Some('s' | 'S') => {
+                    let id = next_id(&mut next)?;
+                    self.signal_change(&id, chars.as_str().as_bytes())?;
+                }
+                Some(value) => {
+                    // scalars are directly followed by their id
+                    let mut buf = [0u8; 4];
+                    self.signal_change(chars.as_str(), value.encode_utf8(&mut buf).as_bytes())?;
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn time_change(&mut self, time: u64) -> Result<()> {
+        let writer = &mut self.writer;
+        if self.auto_flush && time > writer.current_time() && writer.size() >= DEFAULT_FLUSH_SIZE {
+            writer.flush()?;
+        }
+        writer.time_change(time)
+    }
+
+    fn signal_change(&mut self, id: &str, value: &[u8]) -> Result<()> {
+        let Some(&signal_id) = self.ids.get(id) else {
+            return Err(vcd_error(format!("unknown id code: {id}")));
+        };
+        self.writer.signal_change(signal_id, value)
+    }
+}
+
+fn next_id(next: &mut impl FnMut() -> Result<Option<String>>) -> Result<String> {
+    next()?.ok_or_else(|| vcd_error("value change without an id code"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timescales() {
+        assert_eq!(parse_timescale("1s").unwrap(), 0);
+        assert_eq!(parse_timescale("10ns").unwrap(), -8);
+        assert_eq!(parse_timescale("100fs").unwrap(), -13);
+        assert!(parse_timescale("1000ps").is_err());
+        assert!(parse_timescale("1min").is_err());
+    }
+}
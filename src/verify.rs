@@ -0,0 +1,219 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Checks a finished file, first block by block and then by reading it back with `fst-reader`.
+
+use crate::Result;
+use crate::io::{BlockType, read_header};
+use crate::recover::{HEADER_BLOCK_LEN, complete_block, count_hierarchy, read_u8, read_u64};
+use fst_reader::{FstFilter, FstHierarchyEntry, FstReader};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// What [`verify_fst`] found in a file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FstVerification {
+    pub value_change_blocks: u64,
+    pub scope_count: u64,
+    pub var_count: u64,
+    pub num_signals: u64,
+    /// first and last entry of the time table
+    pub first_time: Option<u64>,
+    pub last_time: Option<u64>,
+    /// number of value changes that were read back, including the initial values
+    pub value_changes: u64,
+    /// everything that is wrong with the file, empty if it is valid
+    pub problems: Vec<String>,
+}
+
+impl FstVerification {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks the block structure of the file at `path` against its header, e.g., the number of
+/// value change blocks, scopes, vars and signals and the time range of every block. Then the
+/// file is read back with `fst-reader` to check the time table and all signal handles.
+/// Files that are compressed as a whole are only read back. Fails only if the file cannot be
+/// read at all, all other findings are reported in [`FstVerification::problems`].
+pub fn verify_fst(path: impl AsRef<Path>) -> Result<FstVerification> {
+    let mut input = BufReader::new(std::fs::File::open(path)?);
+    let mut report = FstVerification::default();
+    let wrapped = read_u8(&mut input)? == BlockType::GZipWrapper as u8;
+    let start_time = if wrapped {
+        None
+    } else {
+        check_blocks(&mut input, &mut report)?
+    };
+    input.seek(SeekFrom::Start(0))?;
+    if let Err(e) = read_back(input, start_time, &mut report) {
+        report.problems.push(format!("fst-reader failed: {e}"));
+    }
+    Ok(report)
+}
+
+/// Walks over all blocks and compares them with the header. Returns the start time of the
+/// first value change block.
+fn check_blocks(
+    input: &mut (impl Read + Seek),
+    report: &mut FstVerification,
+) -> Result<Option<u64>> {
+    let file_len = input.seek(SeekFrom::End(0))?;
+    let header = read_header(input)?;
+    let problems = &mut report.problems;
+    let mut start_time = None;
+    let mut end_time = None;
+    let mut geometry = None;
+    let mut hierarchy = None;
+
+    let mut offset = HEADER_BLOCK_LEN;
+    while offset < file_len {
+        let Some((tpe, section_len)) = complete_block(input, offset, file_len)? else {
+            problems.push(format!("incomplete or unknown block at offset {offset}"));
+            break;
+        };
+        match tpe {
+            BlockType::VcData | BlockType::VcDataDynamicAlias | BlockType::VcDataDynamicAlias2 => {
+                let (start, end) = (read_u64(input)?, read_u64(input)?);
+                if start > end {
+                    problems.push(format!(
+                        "block {} ends at {end} before it starts at {start}",
+                        report.value_change_blocks
+                    ));
+                }
+                if let Some(prev) = end_time.filter(|&prev| start < prev) {
+                    problems.push(format!(
+                        "block {} starts at {start} before the previous block ends at {prev}",
+                        report.value_change_blocks
+                    ));
+                }
+                start_time.get_or_insert(start);
+                end_time = Some(end);
+                report.value_change_blocks += 1;
+            }
+            BlockType::Geometry => {
+                let _uncompressed_len = read_u64(input)?;
+                if geometry.replace(read_u64(input)?).is_some() {
+                    problems.push("more than one geometry block".to_string());
+                }
+            }
+            BlockType::Hierarchy | BlockType::HierarchyLZ4 | BlockType::HierarchyLZ4Duo => {
+                let mut bytes = vec![0u8; section_len as usize - 8];
+                input.read_exact(&mut bytes)?;
+                match count_hierarchy(tpe, &bytes) {
+                    Ok(counts) if hierarchy.is_none() => hierarchy = Some(counts),
+                    Ok(_) => problems.push("more than one hierarchy block".to_string()),
+                    Err(e) => problems.push(e.to_string()),
+                }
+            }
+            _ => {}
+        }
+        offset += 1 + section_len;
+        input.seek(SeekFrom::Start(offset))?;
+    }
+
+    let mut compare = |what: &str, in_header: u64, found: u64| {
+        if in_header != found {
+            problems.push(format!(
+                "header says {in_header} {what}, but the file has {found}"
+            ));
+        }
+    };
+    compare(
+        "value change blocks",
+        header.num_value_change_sections,
+        report.value_change_blocks,
+    );
+    if let Some((scopes, vars)) = hierarchy {
+        compare("scopes", header.scope_count, scopes);
+        compare("vars", header.var_count, vars);
+        (report.scope_count, report.var_count) = (scopes, vars);
+    }
+    if let Some(num_signals) = geometry {
+        compare("signals", header.num_signals, num_signals);
+        report.num_signals = num_signals;
+    }
+    if let Some(end_time) = end_time {
+        if header.end_time != end_time {
+            problems.push(format!(
+                "header ends at {}, but the last block ends at {end_time}",
+                header.end_time
+            ));
+        }
+    }
+    if report.value_change_blocks == 0 {
+        problems.push("no value change block".to_string());
+    }
+    if hierarchy.is_none() {
+        problems.push("no hierarchy".to_string());
+    }
+    if geometry.is_none() {
+        problems.push("no geometry".to_string());
+    }
+    Ok(start_time)
+}
+
+/// Reads the hierarchy and all value changes like a waveform viewer would.
+fn read_back(
+    input: BufReader<std::fs::File>,
+    start_time: Option<u64>,
+    report: &mut FstVerification,
+) -> std::result::Result<(), fst_reader::ReaderError> {
+    let mut reader = FstReader::open_and_read_time_table(input)?;
+    let header = reader.get_header();
+    let problems = &mut report.problems;
+    if let Some(start) = start_time.filter(|&start| start != header.start_time) {
+        problems.push(format!(
+            "header starts at {}, but the first block starts at {start}",
+            header.start_time
+        ));
+    }
+    let time_table = reader.get_time_table().unwrap_or_default();
+    if let Some(pos) = time_table.windows(2).position(|w| w[0] > w[1]) {
+        problems.push(format!(
+            "time table goes back from {} to {}",
+            time_table[pos],
+            time_table[pos + 1]
+        ));
+    }
+    report.first_time = time_table.first().copied();
+    report.last_time = time_table.last().copied();
+
+    // every signal needs to be declared by a var
+    let num_signals = header.max_handle as usize;
+    let mut declared = vec![false; num_signals];
+    let mut unknown = vec![];
+    reader.read_hierarchy(|entry| {
+        if let FstHierarchyEntry::Var { handle, .. } = entry {
+            match declared.get_mut(handle.get_index()) {
+                Some(declared) => *declared = true,
+                None => unknown.push(handle.get_index() + 1),
+            }
+        }
+    })?;
+    if let Some(handle) = unknown.first() {
+        problems.push(format!("var refers to unknown signal {handle}"));
+    }
+    if let Some(index) = declared.iter().position(|&declared| !declared) {
+        problems.push(format!("no var declares signal {}", index + 1));
+    }
+
+    let (first, last) = (report.first_time, report.last_time);
+    let mut outside = None;
+    let mut value_changes = 0;
+    reader.read_signals(&FstFilter::all(), |time, _, _| {
+        value_changes += 1;
+        let inside =
+            first.is_some_and(|first| time >= first) && last.is_some_and(|last| time <= last);
+        if !inside {
+            outside.get_or_insert(time);
+        }
+    })?;
+    report.value_changes = value_changes;
+    if let Some(time) = outside {
+        problems.push(format!("value change at {time} outside of the time table"));
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+// Copyright 2025 Cornell University
+// released under BSD 3-Clause License
+// author: Kevin Laeufer <laeufer@cornell.edu>
+//
+// Writes waveforms that were loaded with `wellen`, e.g., from a VCD, GHW or FST file.
+
+use crate::{
+    FstBodyWriter, FstFileType, FstHeaderWriter, FstInfo, FstScopeType, FstSignalId, FstSignalType,
+    FstVarDirection, FstVarType, FstWriteError, Result,
+};
+use ::wellen::simple::Waveform;
+use ::wellen::{
+    FileFormat, Hierarchy, HierarchyItem, Scope, ScopeType, SignalEncoding, SignalRef, SignalValue,
+    TimeTableIdx, Var, VarDirection, VarType,
+};
+use std::collections::HashMap;
+use std::io::{Seek, Write};
+
+/// Maps every signal of the waveform to the FST signal that its values are written to.
+pub type SignalMap = HashMap<SignalRef, FstSignalId>;
+
+/// Date, version, timescale and start time of the waveform, as they go into the FST header.
+pub fn info(wave: &Waveform) -> FstInfo {
+    let hierarchy = wave.hierarchy();
+    let (timescale_exponent, factor) = timescale(hierarchy);
+    let file_type = match hierarchy.file_format() {
+        FileFormat::Ghw => FstFileType::Vhdl,
+        _ => FstFileType::Verilog,
+    };
+    FstInfo {
+        start_time: wave.time_table().first().map_or(0, |time| time * factor),
+        timescale_exponent,
+        version: hierarchy.version().to_string(),
+        date: hierarchy.date().to_string(),
+        file_type,
+    }
+}
+
+/// Declares all scopes and variables of the hierarchy. Variables that share a signal are
+/// declared as aliases.
+pub fn write_hierarchy<W: Write + Seek>(
+    hierarchy: &Hierarchy,
+    out: &mut FstHeaderWriter<W>,
+) -> Result<SignalMap> {
+    let mut signals = SignalMap::new();
+    for item in hierarchy.items() {
+        write_item(hierarchy, out, &mut signals, item)?;
+    }
+    Ok(signals)
+}
+
+/// Writes the value changes of all signals in `signals`, which need to be loaded already,
+/// e.g., through [`Waveform::load_signals`]. Blocks are flushed like in
+/// [`FstBodyWriter::append_sorted`].
+pub fn write_signals<W: Write + Seek>(
+    wave: &Waveform,
+    signals: &SignalMap,
+    out: &mut FstBodyWriter<W>,
+) -> Result<()> {
+    let (_, factor) = timescale(wave.hierarchy());
+    // sorted for a deterministic output
+    let mut ids: Vec<_> = signals.iter().map(|(&r, &id)| (r, id)).collect();
+    ids.sort_by_key(|(signal_ref, _)| *signal_ref);
+    let mut changes = Vec::with_capacity(ids.len());
+    for (signal_ref, id) in ids {
+        let signal = wave
+            .get_signal(signal_ref)
+            .ok_or(FstWriteError::SignalNotLoaded(id))?;
+        changes.push((id, signal.iter_changes().peekable()));
+    }
+
+    let mut step = vec![];
+    for (idx, time) in wave.time_table().iter().enumerate() {
+        let idx = idx as TimeTableIdx;
+        let time = time * factor;
+        for (id, signal) in changes.iter_mut() {
+            while let Some((_, value)) = signal.next_if(|(change, _)| *change == idx) {
+                step.push((time, *id, value_bytes(value)));
+            }
+        }
+        if step.is_empty() {
+            out.time_change(time)?;
+        } else {
+            out.append_sorted(step.drain(..))?;
+        }
+    }
+    Ok(())
+}
+
+/// FST only stores the exponent, thus the factor moves into the exponent as far as possible.
+/// Returns the exponent and what all times need to be multiplied with.
+fn timescale(hierarchy: &Hierarchy) -> (i8, u64) {
+    let Some(timescale) = hierarchy.timescale() else {
+        return (0, 1);
+    };
+    let mut exponent = timescale.unit.to_exponent().unwrap_or(0);
+    let mut factor = timescale.factor.max(1) as u64;
+    while factor % 10 == 0 {
+        factor /= 10;
+        exponent += 1;
+    }
+    (exponent, factor)
+}
+
+fn value_bytes(value: SignalValue<'_>) -> Vec<u8> {
+    match value {
+        SignalValue::Real(value) => value.to_le_bytes().to_vec(),
+        SignalValue::String(value) => value.as_bytes().to_vec(),
+        SignalValue::Binary(..) | SignalValue::FourValue(..) | SignalValue::NineValue(..) => {
+            value.to_bit_string().unwrap().into_bytes()
+        }
+    }
+}
+
+fn write_item<W: Write + Seek>(
+    hierarchy: &Hierarchy,
+    out: &mut FstHeaderWriter<W>,
+    signals: &mut SignalMap,
+    item: HierarchyItem<'_>,
+) -> Result<()> {
+    match item {
+        HierarchyItem::Scope(scope) => write_scope(hierarchy, out, signals, scope),
+        HierarchyItem::Var(var) => write_var(hierarchy, out, signals, var),
+    }
+}
+
+fn write_scope<W: Write + Seek>(
+    hierarchy: &Hierarchy,
+    out: &mut FstHeaderWriter<W>,
+    signals: &mut SignalMap,
+    scope: &Scope,
+) -> Result<()> {
+    let component = scope.component(hierarchy).unwrap_or("");
+    out.scope(
+        scope.name(hierarchy),
+        component,
+        scope_type(scope.scope_type()),
+    )?;
+    for item in scope.items(hierarchy) {
+        write_item(hierarchy, out, signals, item)?;
+    }
+    out.up_scope()
+}
+
+fn write_var<W: Write + Seek>(
+    hierarchy: &Hierarchy,
+    out: &mut FstHeaderWriter<W>,
+    signals: &mut SignalMap,
+    var: &Var,
+) -> Result<()> {
+    let encoding = var.signal_encoding();
+    let signal_type = match encoding {
+        SignalEncoding::String => FstSignalType::bit_vec(0),
+        SignalEncoding::Real => FstSignalType::real(),
+        SignalEncoding::BitVector(len) => FstSignalType::bit_vec(len.get()),
+    };
+    let var_type = match var.var_type() {
+        VarType::Parameter if encoding == SignalEncoding::Real => FstVarType::RealParameter,
+        other => var_type(other),
+    };
+    let alias = signals.get(&var.signal_ref()).copied();
+    let id = out.var(
+        var.name(hierarchy),
+        signal_type,
+        var_type,
+        direction(var.direction()),
+        alias,
+    )?;
+    signals.entry(var.signal_ref()).or_insert(id);
+    Ok(())
+}
+
+fn scope_type(tpe: ScopeType) -> FstScopeType {
+    match tpe {
+        ScopeType::Module => FstScopeType::Module,
+        ScopeType::Task => FstScopeType::Task,
+        ScopeType::Function => FstScopeType::Function,
+        ScopeType::Begin => FstScopeType::Begin,
+        ScopeType::Fork => FstScopeType::Fork,
+        ScopeType::Generate => FstScopeType::Generate,
+        ScopeType::Struct => FstScopeType::Struct,
+        ScopeType::Union => FstScopeType::Union,
+        ScopeType::Class => FstScopeType::Class,
+        ScopeType::Interface => FstScopeType::Interface,
+        ScopeType::Package => FstScopeType::Package,
+        ScopeType::Program => FstScopeType::Program,
+        ScopeType::VhdlArchitecture => FstScopeType::VhdlArchitecture,
+        ScopeType::VhdlProcedure => FstScopeType::VhdlProcedure,
+        ScopeType::VhdlFunction => FstScopeType::VhdlFunction,
+        ScopeType::VhdlRecord => FstScopeType::VhdlRecord,
+        ScopeType::VhdlProcess => FstScopeType::VhdlProcess,
+        ScopeType::VhdlBlock => FstScopeType::VhdlBlock,
+        ScopeType::VhdlForGenerate => FstScopeType::VhdlForGenerate,
+        ScopeType::VhdlIfGenerate => FstScopeType::VhdlIfGenerate,
+        ScopeType::VhdlGenerate => FstScopeType::VhdlGenerate,
+        ScopeType::VhdlPackage => FstScopeType::VhdlPackage,
+        // GHW only, FST has no equivalent: generics are grouped like a block and the
+        // elements of an array like the fields of a record
+        ScopeType::GhwGeneric => FstScopeType::VhdlBlock,
+        ScopeType::VhdlArray => FstScopeType::VhdlRecord,
+    }
+}
+
+fn var_type(tpe: VarType) -> FstVarType {
+    match tpe {
+        VarType::Event => FstVarType::Event,
+        VarType::Integer => FstVarType::Integer,
+        VarType::Parameter => FstVarType::Parameter,
+        VarType::Real => FstVarType::Real,
+        VarType::Reg => FstVarType::Reg,
+        VarType::Supply0 => FstVarType::Supply0,
+        VarType::Supply1 => FstVarType::Supply1,
+        VarType::Time => FstVarType::Time,
+        VarType::Tri => FstVarType::Tri,
+        VarType::TriAnd => FstVarType::TriAnd,
+        VarType::TriOr => FstVarType::TriOr,
+        VarType::TriReg => FstVarType::TriReg,
+        VarType::Tri0 => FstVarType::Tri0,
+        VarType::Tri1 => FstVarType::Tri1,
+        VarType::WAnd => FstVarType::Wand,
+        VarType::Wire => FstVarType::Wire,
+        VarType::WOr => FstVarType::Wor,
+        VarType::String => FstVarType::GenericString,
+        VarType::Port => FstVarType::Port,
+        VarType::SparseArray => FstVarType::SparseArray,
+        VarType::RealTime => FstVarType::RealTime,
+        VarType::Bit => FstVarType::Bit,
+        VarType::Logic => FstVarType::Logic,
+        VarType::Int => FstVarType::Int,
+        VarType::ShortInt => FstVarType::ShortInt,
+        VarType::LongInt => FstVarType::LongInt,
+        VarType::Byte => FstVarType::Byte,
+        VarType::Enum => FstVarType::Enum,
+        VarType::ShortReal => FstVarType::ShortReal,
+        // VHDL types, which FST only records through attributes
+        VarType::Boolean | VarType::BitVector => FstVarType::Bit,
+        VarType::StdLogic
+        | VarType::StdLogicVector
+        | VarType::StdULogic
+        | VarType::StdULogicVector => FstVarType::Logic,
+    }
+}
+
+fn direction(dir: VarDirection) -> FstVarDirection {
+    match dir {
+        VarDirection::Unknown | VarDirection::Implicit => FstVarDirection::Implicit,
+        VarDirection::Input => FstVarDirection::Input,
+        VarDirection::Output => FstVarDirection::Output,
+        VarDirection::InOut => FstVarDirection::InOut,
+        VarDirection::Buffer => FstVarDirection::Buffer,
+        VarDirection::Linkage => FstVarDirection::Linkage,
+    }
+}
@@ -2,63 +2,524 @@
 // released under BSD 3-Clause License
 // author: Kevin Laeufer <laeufer@cornell.edu>
 
-use crate::buffer::SignalBuffer;
+use crate::buffer::{Block, MAX_CHUNK_ALLOCATION, SignalBuffer};
+use crate::hierarchy::{Hierarchy, ScopeTree};
 use crate::io::{
-    HeaderFinishInfo, update_header, write_geometry, write_header_meta_data, write_hierarchy_bytes,
-    write_hierarchy_scope, write_hierarchy_up_scope, write_hierarchy_var,
+    HeaderFinishInfo, update_header, write_blackout, write_header_meta_data, write_skip,
 };
+use crate::reorder::{ReorderBuffer, TimeStep};
+use crate::stats::{FstBlockStats, FstSizeBreakdown, FstWriterHooks, FstWriterStats};
+use crate::stream::FstStream;
 use crate::{
-    FstInfo, FstScopeType, FstSignalId, FstSignalType, FstVarDirection, FstVarType, Result,
+    FstDecimation, FstErrorContext, FstInfo, FstScopeInfo, FstScopeType, FstSignalId,
+    FstSignalSender, FstSignalType, FstVarDirection, FstVarInfo, FstVarType, FstWriteError,
+    FstWriterConfig, Result, SharedFstWriter,
 };
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+// writing files by path
+#[cfg(feature = "fs")]
+use crate::{
+    FstCompressionLevels, FstOpenOptions, checksum::append_checksums, io::write_gzip_wrapper,
+    spill::TempFile,
+};
+#[cfg(feature = "fs")]
+use std::io::{Seek, Write};
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::JoinHandle;
 
+/// Creates the file at `path`, replacing an existing file. Use [`open_fst_with_options`] with
+/// [`FstOpenOptions::create_new`] to protect existing files.
+#[cfg(feature = "fs")]
 pub fn open_fst<P: AsRef<std::path::Path>>(
     path: P,
     info: &FstInfo,
 ) -> Result<FstHeaderWriter<std::io::BufWriter<std::fs::File>>> {
-    FstHeaderWriter::open(path, info)
+    FstHeaderWriter::open(path, info, &FstOpenOptions::default())
+}
+
+/// Like [`open_fst`], but with control over how the file is created and buffered.
+#[cfg(feature = "fs")]
+pub fn open_fst_with_options<P: AsRef<std::path::Path>>(
+    path: P,
+    info: &FstInfo,
+    options: &FstOpenOptions,
+) -> Result<FstHeaderWriter<std::io::BufWriter<std::fs::File>>> {
+    FstHeaderWriter::open(path, info, options)
+}
+
+/// Like [`open_fst_with_options`], but all writes are submitted through io_uring, so that
+/// value change blocks are written while the next one is compressed. The `buffer_capacity`
+/// option sets the size of every submitted write.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub fn open_fst_uring<P: AsRef<std::path::Path>>(
+    path: P,
+    info: &FstInfo,
+    options: &FstOpenOptions,
+) -> Result<FstHeaderWriter<crate::FstUringFile>> {
+    FstHeaderWriter::open_uring(path, info, options)
+}
+
+/// Streams the FST file to an output that cannot seek, e.g., stdout or a socket. Every
+/// section is kept in memory until it is complete. Since the header is written before all
+/// value changes, the `end_time` needs to be known up front, use `u64::MAX` if it is not.
+/// [`FstWriterConfig::deferred_hierarchy`] is not supported.
+pub fn open_fst_stream<W: std::io::Write>(
+    out: W,
+    info: &FstInfo,
+    end_time: u64,
+) -> Result<FstHeaderWriter<FstStream<W>>> {
+    let mut writer = FstHeaderWriter::from_writer(FstStream::new(out), info)?;
+    writer.stream_end_time = Some(end_time);
+    Ok(writer)
+}
+
+/// Writes the FST file into memory. Take the bytes with [`FstBodyWriter::finish`].
+pub fn open_fst_in_memory(info: &FstInfo) -> Result<FstHeaderWriter<std::io::Cursor<Vec<u8>>>> {
+    FstHeaderWriter::from_writer(std::io::Cursor::new(vec![]), info)
+}
+
+/// Opens the file at `path`, e.g., one that a simulation wrote before it was restarted from a
+/// checkpoint, to add further value change blocks after its last time step. The file does not
+/// need to be finished. Signals keep the ids they were declared with, which
+/// [`FstBodyWriter::signal_remap`] returns in declaration order, and the hierarchy cannot be
+/// extended. Files repacked into a gzip wrapper need to be unpacked first.
+///
+/// The appended blocks are written with `config`. Options that concern the hierarchy and
+/// [`FstWriterConfig::rollover_size`] are ignored. Files that contain checksums always get
+/// new ones.
+#[cfg(feature = "append")]
+pub fn append_fst<P: AsRef<std::path::Path>>(
+    path: P,
+    config: FstWriterConfig,
+) -> Result<FstBodyWriter<std::io::BufWriter<std::fs::File>>> {
+    let existing = crate::append::read_existing(path.as_ref())?;
+    // the checksums need to cover the appended blocks as well
+    let checksums = crate::checksum::has_checksums(path.as_ref())?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path.as_ref())?;
+    file.seek(std::io::SeekFrom::End(0))?;
+    let header = FstHeaderWriter {
+        out: std::io::BufWriter::new(file),
+        // the existing file must never be deleted by `abort`
+        path: None,
+        rename_to: None,
+        sync: FileSync::default(),
+        reopen: None,
+        config,
+        hierarchy: Hierarchy::new(),
+        external_ids: HashMap::new(),
+        scope_paths: HashSet::new(),
+        stream_end_time: None,
+    };
+    let checksums = checksums || header.config.checksums;
+    let repack = header
+        .config
+        .repack_on_close
+        .then_some(header.config.compression.repack);
+    let mut buffer = SignalBuffer::new(
+        &existing.signals,
+        header.config.pack_type,
+        header.config.compression,
+        header.config.spill_threshold,
+        header.config.compress_buffer,
+    )?;
+    buffer.resume(&existing.values, existing.info.end_time);
+    let signal_remap = (1..=existing.signals.len() as u32)
+        .map(FstSignalId::from_index)
+        .collect();
+    let mut body = header.into_body(
+        buffer,
+        existing.info,
+        None,
+        ScopeTree::default(),
+        signal_remap,
+    )?;
+    // the path is not passed on, so that `abort` keeps the file
+    body.checksums = checksums.then(|| path.as_ref().to_path_buf());
+    body.repack = repack.map(|level| (path.as_ref().to_path_buf(), level));
+    Ok(body)
+}
+
+/// Compresses the finished FST file at `input` into a gzip wrapper at `output`.
+/// See [`FstWriterConfig::repack_on_close`].
+#[cfg(feature = "fs")]
+pub fn repack_fst(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<()> {
+    repack_fst_with_level(input, output, FstCompressionLevels::default().repack)
+}
+
+#[cfg(feature = "fs")]
+fn repack_fst_with_level(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    level: u8,
+) -> Result<()> {
+    let mut input = std::io::BufReader::new(std::fs::File::open(input)?);
+    let mut output = std::io::BufWriter::new(std::fs::File::create(output)?);
+    write_gzip_wrapper(&mut input, &mut output, level)?;
+    output.flush()?;
+    Ok(())
 }
 
 pub struct FstHeaderWriter<W: std::io::Write + std::io::Seek> {
     out: W,
-    /// collect hierarchy section before compressing it
-    hierarchy_buf: std::io::Cursor<Vec<u8>>,
-    signals: Vec<FstSignalType>,
-    scope_depth: u64,
-    var_count: u64,
-    scope_count: u64,
+    /// location of the output file, required for repacking
+    #[cfg(feature = "fs")]
+    path: Option<PathBuf>,
+    /// final location of an atomically written file, see [`FstOpenOptions::atomic`]
+    #[cfg(feature = "fs")]
+    rename_to: Option<PathBuf>,
+    #[cfg(feature = "fs")]
+    sync: FileSync,
+    /// only available for files opened by path
+    #[cfg(feature = "fs")]
+    reopen: Option<Reopen<W>>,
+    config: FstWriterConfig,
+    hierarchy: Hierarchy,
+    /// maps ids chosen by the user to FST signal ids, which can be sparse
+    external_ids: HashMap<usize, FstSignalId>,
+    /// dot separated paths of all scopes opened by [`FstHeaderWriter::scope_path`]
+    scope_paths: HashSet<String>,
+    /// end time for the header of a streamed file, which cannot be updated at the end
+    stream_end_time: Option<u64>,
 }
 
+#[cfg(feature = "fs")]
 impl FstHeaderWriter<std::io::BufWriter<std::fs::File>> {
-    fn open<P: AsRef<std::path::Path>>(path: P, info: &FstInfo) -> Result<Self> {
-        let f = std::fs::File::create(path)?;
-        let mut out = std::io::BufWriter::new(f);
-        write_header_meta_data(&mut out, info)?;
+    fn open<P: AsRef<std::path::Path>>(
+        path: P,
+        info: &FstInfo,
+        options: &FstOpenOptions,
+    ) -> Result<Self> {
+        Self::with_file_output(Self::create_output, path.as_ref(), options, info)
+    }
+
+    fn create_output(
+        path: &Path,
+        options: &FstOpenOptions,
+    ) -> Result<FileOutput<std::io::BufWriter<std::fs::File>>> {
+        let (f, written) = create_file(path, options)?;
+        let sync = FileSync::new(&f, options)?;
+        let out = match options.buffer_capacity {
+            Some(capacity) => std::io::BufWriter::with_capacity(capacity, f),
+            None => std::io::BufWriter::new(f),
+        };
+        Ok((out, written, sync))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl FstHeaderWriter<crate::FstUringFile> {
+    fn open_uring<P: AsRef<std::path::Path>>(
+        path: P,
+        info: &FstInfo,
+        options: &FstOpenOptions,
+    ) -> Result<Self> {
+        Self::with_file_output(Self::create_output, path.as_ref(), options, info)
+    }
+
+    fn create_output(
+        path: &Path,
+        options: &FstOpenOptions,
+    ) -> Result<FileOutput<crate::FstUringFile>> {
+        let (f, written) = create_file(path, options)?;
+        let sync = FileSync::new(&f, options)?;
+        let out = crate::FstUringFile::new(f, options.buffer_capacity)?;
+        Ok((out, written, sync))
+    }
+}
+
+/// Output for a file created by path, the path that is actually written and how to sync it.
+#[cfg(feature = "fs")]
+type FileOutput<W> = (W, PathBuf, FileSync);
+
+/// Everything needed to create further files, see [`FstWriterConfig::rollover_size`].
+#[cfg(feature = "fs")]
+struct Reopen<W> {
+    create: fn(&Path, &FstOpenOptions) -> Result<FileOutput<W>>,
+    options: FstOpenOptions,
+    info: FstInfo,
+}
+
+/// Continues in a new file once the current one is large enough.
+/// See [`FstWriterConfig::rollover_size`].
+#[cfg(feature = "fs")]
+struct Rollover<W> {
+    reopen: Reopen<W>,
+    size: u64,
+    /// path of the first file, which the names of all following files are derived from
+    path: PathBuf,
+    /// number of the file that is currently written, 0 for the first one
+    index: u32,
+    /// hierarchy and geometry blocks that every file starts with
+    hierarchy: Vec<u8>,
+}
+
+/// `trace.fst` becomes `trace_0001.fst` for `index` 1.
+#[cfg(feature = "fs")]
+fn rollover_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!("_{index:04}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Opens the file that is written, which is `<path>.tmp` for atomic writes.
+#[cfg(feature = "fs")]
+fn create_file(path: &Path, options: &FstOpenOptions) -> Result<(std::fs::File, PathBuf)> {
+    let written = if options.atomic {
+        // the temporary file does not protect the file that is replaced in the end
+        if options.create_new && path.exists() {
+            return Err(FstWriteError::FileExists(path.to_path_buf()));
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        PathBuf::from(tmp)
+    } else {
+        path.to_path_buf()
+    };
+    let mut open_options = std::fs::OpenOptions::new();
+    open_options.write(true);
+    // a temporary file left behind by an earlier run is replaced
+    if options.create_new && !options.atomic {
+        open_options.create_new(true);
+    } else {
+        open_options.create(true).truncate(true);
+    }
+    if let Some(mode) = options.mode {
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut open_options, mode);
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("file permissions {mode:o} are only supported on Unix"),
+        )
+        .into());
+    }
+    match open_options.open(&written) {
+        Ok(file) => Ok((file, written)),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            Err(FstWriteError::FileExists(written))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Forces written data to disk, see [`FstOpenOptions::sync_on_flush`] and
+/// [`FstOpenOptions::sync_on_finish`].
+#[cfg(feature = "fs")]
+#[derive(Default)]
+struct FileSync {
+    /// handle of the written file, only kept when syncing after every block
+    on_flush: Option<std::fs::File>,
+    on_finish: bool,
+}
+
+#[cfg(feature = "fs")]
+impl FileSync {
+    fn new(file: &std::fs::File, options: &FstOpenOptions) -> Result<Self> {
         Ok(Self {
-            out,
-            hierarchy_buf: std::io::Cursor::new(Vec::new()),
-            signals: vec![],
-            scope_depth: 0,
-            var_count: 0,
-            scope_count: 0,
+            on_flush: options
+                .sync_on_flush
+                .then(|| file.try_clone())
+                .transpose()?,
+            on_finish: options.sync_on_finish,
         })
     }
 }
 
+/// Forces the file at `path` and its directory entry to disk.
+#[cfg(feature = "fs")]
+fn sync_path(path: &Path) -> Result<()> {
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)?
+        .sync_all()?;
+    // directories cannot be opened on Windows
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
 impl<W: std::io::Write + std::io::Seek> FstHeaderWriter<W> {
+    /// Writes the FST file into any output, e.g., a `Cursor` or an already opened file.
+    /// [`FstWriterConfig::checksums`] and [`FstWriterConfig::repack_on_close`] require a path
+    /// and are thus ignored.
+    pub fn from_writer(mut out: W, info: &FstInfo) -> Result<Self> {
+        // the first block starts at zero
+        write_header_meta_data(&mut out, info, 0)?;
+        Ok(Self {
+            out,
+            #[cfg(feature = "fs")]
+            path: None,
+            #[cfg(feature = "fs")]
+            rename_to: None,
+            #[cfg(feature = "fs")]
+            sync: FileSync::default(),
+            #[cfg(feature = "fs")]
+            reopen: None,
+            config: FstWriterConfig::default(),
+            hierarchy: Hierarchy::new(),
+            external_ids: HashMap::new(),
+            scope_paths: HashSet::new(),
+            stream_end_time: None,
+        })
+    }
+
+    #[cfg(feature = "fs")]
+    fn with_file_output(
+        create: fn(&Path, &FstOpenOptions) -> Result<FileOutput<W>>,
+        path: &Path,
+        options: &FstOpenOptions,
+        info: &FstInfo,
+    ) -> Result<Self> {
+        let (out, written, sync) = create(path, options)?;
+        let mut writer = Self::from_writer(out, info)?;
+        writer.rename_to = (written != path).then(|| path.to_path_buf());
+        writer.path = Some(written);
+        writer.sync = sync;
+        writer.reopen = Some(Reopen {
+            create,
+            options: options.clone(),
+            info: info.clone(),
+        });
+        Ok(writer)
+    }
+
+    /// Returns the output without finishing the file, which is left incomplete.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Stops writing and deletes the file if it was opened by path, e.g., with [`open_fst`].
+    /// Other outputs are dropped.
+    pub fn abort(self) -> Result<()> {
+        #[cfg(feature = "fs")]
+        if let Some(path) = self.path {
+            drop(self.out);
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Changes the writer configuration. Should be called before declaring the hierarchy.
+    pub fn with_config(mut self, config: FstWriterConfig) -> Result<Self> {
+        self.hierarchy.set_incremental_compression(
+            config
+                .incremental_hierarchy_compression
+                .then_some(config.compression.hierarchy),
+        )?;
+        self.hierarchy.set_signal_order(config.signal_order)?;
+        self.hierarchy.set_retain_declarations(
+            config.retain_declarations || !config.incremental_hierarchy_compression,
+        )?;
+        self.config = config;
+        Ok(self)
+    }
+
+    pub fn config(&self) -> &FstWriterConfig {
+        &self.config
+    }
+
+    /// Opens a scope and returns its index into [`FstHeaderWriter::scopes`].
     pub fn scope(
         &mut self,
         name: impl AsRef<str>,
         component: impl AsRef<str>,
         tpe: FstScopeType,
-    ) -> Result<()> {
-        self.scope_depth += 1;
-        self.scope_count += 1;
-        write_hierarchy_scope(&mut self.hierarchy_buf, name, component, tpe)
+    ) -> Result<usize> {
+        self.hierarchy.scope(name, component, tpe)
     }
     pub fn up_scope(&mut self) -> Result<()> {
-        debug_assert!(self.scope_depth > 0, "no scope to pop");
-        self.scope_depth -= 1;
-        write_hierarchy_up_scope(&mut self.hierarchy_buf)
+        self.hierarchy.up_scope()
+    }
+
+    /// Number of scopes that are currently open.
+    pub fn scope_depth(&self) -> usize {
+        self.hierarchy.scope_depth()
+    }
+
+    /// Closes all open scopes.
+    pub fn up_scope_all(&mut self) -> Result<()> {
+        while self.scope_depth() > 0 {
+            self.up_scope()?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure that all scopes of a dot separated `path` like `top.core.alu.a` are open
+    /// and returns the leaf name (`a`). Scopes that are already open are reused,
+    /// open scopes that are not part of the path are closed and new scopes get `component`.
+    /// Since FST declares the hierarchy in order, a closed scope cannot be extended again.
+    /// Thus paths need to be declared grouped by their prefix, e.g., `top.core.c` before
+    /// `top.d`. Reopening a scope that this method already closed fails with
+    /// [`FstWriteError::ScopeAlreadyClosed`].
+    pub fn scope_path<'a>(
+        &mut self,
+        path: &'a str,
+        component: impl AsRef<str>,
+        tpe: FstScopeType,
+    ) -> Result<&'a str> {
+        let (scopes, leaf) = match path.rsplit_once('.') {
+            Some((scopes, leaf)) => (scopes.split('.').collect::<Vec<_>>(), leaf),
+            None => (vec![], path),
+        };
+        let common = self
+            .hierarchy
+            .open_scope_names()
+            .zip(scopes.iter())
+            .take_while(|(open, name)| open == *name)
+            .count();
+        let new_paths = (common..scopes.len())
+            .map(|depth| scopes[..=depth].join("."))
+            .collect::<Vec<_>>();
+        if let Some(closed) = new_paths.iter().find(|p| self.scope_paths.contains(*p)) {
+            return Err(FstWriteError::ScopeAlreadyClosed(closed.clone()));
+        }
+        while self.scope_depth() > common {
+            self.up_scope()?;
+        }
+        for (name, path) in scopes[common..].iter().zip(new_paths) {
+            self.scope(name, component.as_ref(), tpe)?;
+            self.scope_paths.insert(path);
+        }
+        Ok(leaf)
+    }
+
+    /// Opens a scope, runs `f` inside of it and closes the scope again, even if `f` fails.
+    /// Scopes that `f` left open are closed as well, scopes that it closed itself are not
+    /// closed twice.
+    pub fn with_scope<T>(
+        &mut self,
+        name: impl AsRef<str>,
+        component: impl AsRef<str>,
+        tpe: FstScopeType,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let depth = self.scope_depth();
+        self.scope(name, component, tpe)?;
+        let res = f(self);
+        let mut up_scope_res = Ok(());
+        while up_scope_res.is_ok() && self.scope_depth() > depth {
+            up_scope_res = self.up_scope();
+        }
+        let value = res?;
+        up_scope_res?;
+        Ok(value)
     }
 
     pub fn var(
@@ -69,78 +530,1420 @@ impl<W: std::io::Write + std::io::Seek> FstHeaderWriter<W> {
         dir: FstVarDirection,
         alias: Option<FstSignalId>,
     ) -> Result<FstSignalId> {
-        self.var_count += 1;
-        write_hierarchy_var(&mut self.hierarchy_buf, tpe, dir, name, signal_tpe, alias)?;
-        if let Some(alias) = alias {
-            debug_assert!(alias.to_index() <= self.signals.len() as u32);
-            Ok(alias)
-        } else {
-            self.signals.push(signal_tpe);
-            let id = FstSignalId::from_index(self.signals.len() as u32);
-            Ok(id)
+        self.hierarchy.var(name, signal_tpe, tpe, dir, alias)
+    }
+
+    /// Opens a scope called `name` that mirrors the already closed scope `source`, an index
+    /// into [`FstHeaderWriter::scopes`]. All variables and sub-scopes of `source` are declared
+    /// again as aliases, which is handy for modules that are instantiated many times.
+    /// Returns the index of the new scope.
+    pub fn alias_scope(
+        &mut self,
+        name: impl AsRef<str>,
+        component: impl AsRef<str>,
+        source: usize,
+    ) -> Result<usize> {
+        self.hierarchy.alias_scope(name, component, source)
+    }
+
+    /// Returns a shared copy of `name`. Scope and variable names are interned in the same
+    /// table, so converters can keep names around without allocating them again.
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        self.hierarchy.intern(name)
+    }
+
+    /// Declares a variable for an id chosen by the caller, e.g., the index of a signal in
+    /// another waveform library. The first variable declared for an `external_id` creates a
+    /// new signal, all later ones become an alias of it.
+    /// Value changes can then be recorded with [`FstBodyWriter::signal_change_external`].
+    pub fn var_with_external_id(
+        &mut self,
+        name: impl AsRef<str>,
+        external_id: usize,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+    ) -> Result<FstSignalId> {
+        let alias = self.external_ids.get(&external_id).cloned();
+        let id = self.var(name, signal_tpe, tpe, dir, alias)?;
+        self.external_ids.insert(external_id, id);
+        Ok(id)
+    }
+
+    /// Maps every external id to the FST signal id that was assigned to it.
+    pub fn external_ids(&self) -> &HashMap<usize, FstSignalId> {
+        &self.external_ids
+    }
+
+    /// All scopes declared so far, in declaration order. Empty if declarations are not
+    /// retained, see [`FstWriterConfig::retain_declarations`].
+    pub fn scopes(&self) -> &[FstScopeInfo] {
+        self.hierarchy.scopes()
+    }
+
+    /// All variables declared so far, in declaration order. Empty if declarations are not
+    /// retained, see [`FstWriterConfig::retain_declarations`]. Every var carries the id that
+    /// declaring it returned, i.e., a provisional id with [`crate::FstSignalOrder::Width`].
+    pub fn vars(&self) -> &[FstVarInfo] {
+        self.hierarchy.vars()
+    }
+
+    /// Number of distinct signals, i.e., variables that are not an alias.
+    pub fn num_signals(&self) -> usize {
+        self.hierarchy.num_declared_signals()
+    }
+
+    /// Type of the signal behind `id`, `None` if the signal was not declared.
+    pub fn signal_type(&self, id: FstSignalId) -> Option<FstSignalType> {
+        self.hierarchy.declared_signal_type(id)
+    }
+
+    /// Dot separated names of all scopes surrounding the variable and the variable itself.
+    pub fn full_name(&self, var: &FstVarInfo) -> String {
+        self.hierarchy.full_name(var)
+    }
+
+    /// Starts declaring a variable through a builder, defaulting to a 1-bit implicit wire.
+    pub fn var_builder(&mut self, name: impl Into<String>) -> FstVarBuilder<'_, W> {
+        FstVarBuilder {
+            writer: self,
+            name: name.into(),
+            signal_tpe: FstSignalType::bit_vec(1),
+            tpe: FstVarType::Wire,
+            dir: FstVarDirection::Implicit,
+            alias: None,
+            range: None,
         }
     }
 
+    /// Records where the next scope is declared in the source code.
+    pub fn source_stem(&mut self, path: impl AsRef<Path>, line: u64) -> Result<()> {
+        self.hierarchy.source_stem(path.as_ref(), line, false)
+    }
+
+    /// Records where the next scope is instantiated in the source code.
+    pub fn source_instantiation_stem(&mut self, path: impl AsRef<Path>, line: u64) -> Result<()> {
+        self.hierarchy.source_stem(path.as_ref(), line, true)
+    }
+
+    /// Writes the hierarchy and geometry, unless they are deferred until
+    /// [`FstBodyWriter::finish`] through [`FstWriterConfig::deferred_hierarchy`].
     pub fn finish(mut self) -> Result<FstBodyWriter<W>> {
-        debug_assert_eq!(
-            self.scope_depth, 0,
-            "missing calls to up-scope to close all scopes!"
-        );
-        write_hierarchy_bytes(&mut self.out, &self.hierarchy_buf.into_inner())?;
-        write_geometry(&mut self.out, &self.signals)?;
-        let buffer = SignalBuffer::new(&self.signals)?;
-        let finish_info = HeaderFinishInfo {
+        self.hierarchy.flush_pending()?;
+        let signal_remap = self.hierarchy.signal_remap();
+        let buffer = SignalBuffer::new(
+            self.hierarchy.signals(),
+            self.config.pack_type,
+            self.config.compression,
+            self.config.spill_threshold,
+            self.config.compress_buffer,
+        )?;
+        let mut finish_info = HeaderFinishInfo {
             end_time: 0, // currently unknown
-            scope_count: self.scope_count,
-            var_count: self.var_count,
-            num_signals: self.signals.len() as u64,
+            scope_count: 0,
+            var_count: 0,
+            num_signals: 0,
             num_value_change_sections: 0, // currently unknown
+            memory_used: 0,
         };
+        let streaming = self.stream_end_time.is_some();
+        if streaming && self.config.deferred_hierarchy {
+            return Err(FstWriteError::UnsupportedWhenStreaming(
+                "deferred_hierarchy",
+            ));
+        }
+        if self.config.live && self.config.deferred_hierarchy {
+            return Err(FstWriteError::IncompatibleConfig(
+                "live",
+                "deferred_hierarchy",
+            ));
+        }
+        if self.config.rollover_size.is_some() && self.config.deferred_hierarchy {
+            return Err(FstWriteError::IncompatibleConfig(
+                "rollover_size",
+                "deferred_hierarchy",
+            ));
+        }
+        #[cfg(feature = "fs")]
+        let mut rollover = self.rollover();
+        let hierarchy = std::mem::replace(&mut self.hierarchy, Hierarchy::new());
+        let (hierarchy, scope_tree) = if self.config.deferred_hierarchy {
+            (Some(hierarchy), ScopeTree::default())
+        } else {
+            let scope_tree = hierarchy.scope_tree();
+            #[cfg(feature = "fs")]
+            let copy = rollover.as_mut().map(|rollover| &mut rollover.hierarchy);
+            #[cfg(not(feature = "fs"))]
+            let copy: Option<&mut Vec<u8>> = None;
+            match copy {
+                Some(copy) => {
+                    // every following file starts with the same hierarchy
+                    let mut bytes = std::io::Cursor::new(vec![]);
+                    write_hierarchy(&mut bytes, hierarchy, &mut finish_info)?;
+                    self.out.write_all(bytes.get_ref())?;
+                    *copy = bytes.into_inner();
+                }
+                None => write_hierarchy(&mut self.out, hierarchy, &mut finish_info)?,
+            }
+            (None, scope_tree)
+        };
+        if let Some(end_time) = self.stream_end_time {
+            // the header is final once the hierarchy was written
+            let info = HeaderFinishInfo {
+                end_time,
+                ..finish_info
+            };
+            update_header(&mut self.out, &info)?;
+            self.out.seek(std::io::SeekFrom::End(0))?;
+            self.out.flush()?;
+        } else if self.config.live {
+            // viewers can open the file before the first block was written
+            update_header(&mut self.out, &finish_info)?;
+            self.out.seek(std::io::SeekFrom::End(0))?;
+            self.out.flush()?;
+        }
+        #[allow(unused_mut)]
+        let mut body = self.into_body(buffer, finish_info, hierarchy, scope_tree, signal_remap)?;
+        #[cfg(feature = "fs")]
+        {
+            body.rollover = rollover;
+        }
+        Ok(body)
+    }
+
+    /// Prepares further files for [`FstWriterConfig::rollover_size`], only possible for
+    /// files opened by path.
+    #[cfg(feature = "fs")]
+    fn rollover(&mut self) -> Option<Rollover<W>> {
+        let size = self.config.rollover_size?;
+        let reopen = self.reopen.take()?;
+        let path = self.rename_to.clone().or(self.path.clone())?;
+        Some(Rollover {
+            reopen,
+            size,
+            path,
+            index: 0,
+            hierarchy: vec![],
+        })
+    }
+
+    /// Continues with the value changes once the header is complete.
+    fn into_body(
+        mut self,
+        buffer: SignalBuffer,
+        finish_info: HeaderFinishInfo,
+        hierarchy: Option<Hierarchy>,
+        scope_tree: ScopeTree,
+        signal_remap: Vec<FstSignalId>,
+    ) -> Result<FstBodyWriter<W>> {
+        let external_ids = self
+            .external_ids
+            .into_iter()
+            .map(|(external_id, id)| (external_id, signal_remap[id.to_array_index()]))
+            .collect();
+        let bytes_written = self.out.stream_position()?;
         let next = FstBodyWriter {
-            out: self.out,
+            out: Some(self.out),
+            pending_write: None,
+            spawn_write: None,
             buffer,
             finish_info,
+            hierarchy,
+            scope_tree,
+            external_ids,
+            signal_remap,
+            #[cfg(feature = "fs")]
+            repack: self
+                .path
+                .clone()
+                .filter(|_| self.config.repack_on_close)
+                .map(|path| (path, self.config.compression.repack)),
+            #[cfg(feature = "fs")]
+            checksums: self.path.clone().filter(|_| self.config.checksums),
+            #[cfg(feature = "fs")]
+            path: self.path,
+            #[cfg(feature = "fs")]
+            rename_to: self.rename_to,
+            #[cfg(feature = "fs")]
+            sync: self.sync,
+            #[cfg(feature = "fs")]
+            rollover: None,
+            dump_activity: vec![],
+            dump_size_limit: self.config.dump_size_limit,
+            bytes_written,
+            dump_size_limit_reached: false,
+            record_until: self.config.record_until,
+            recording_stopped: false,
+            auto_flush_size: self.config.auto_flush_size,
+            memory_limit: self.config.memory_limit,
+            step_start_size: 0,
+            max_step_size: 0,
+            auto_flush_interval: self.config.auto_flush_interval,
+            block_interval: None,
+            reorder: self
+                .config
+                .reorder_depth
+                .map(|depth| ReorderBuffer::new(depth, 0)),
+            canonical_order: self.config.canonical_order,
+            streaming: self.stream_end_time.is_some(),
+            live: self.config.live,
+            finish_on_drop: true,
+            stats: FstWriterStats::default(),
+            hooks: None,
+            notified_blocks: 0,
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::WriterMetrics::new(),
         };
         Ok(next)
     }
 }
 
+/// Collects all parameters of a variable declaration. Created by [`FstHeaderWriter::var_builder`].
+pub struct FstVarBuilder<'a, W: std::io::Write + std::io::Seek> {
+    writer: &'a mut FstHeaderWriter<W>,
+    name: String,
+    signal_tpe: FstSignalType,
+    tpe: FstVarType,
+    dir: FstVarDirection,
+    alias: Option<FstSignalId>,
+    range: Option<BitRange>,
+}
+
+enum BitRange {
+    /// `[width-1:0]`
+    Default,
+    Custom {
+        msb: i64,
+        lsb: i64,
+    },
+}
+
+impl<W: std::io::Write + std::io::Seek> FstVarBuilder<'_, W> {
+    /// Bit-vector signal of `width` bits.
+    pub fn width(mut self, width: u32) -> Self {
+        self.signal_tpe = FstSignalType::bit_vec(width);
+        self
+    }
+
+    pub fn signal_type(mut self, signal_tpe: FstSignalType) -> Self {
+        self.signal_tpe = signal_tpe;
+        self
+    }
+
+    pub fn typ(mut self, tpe: FstVarType) -> Self {
+        self.tpe = tpe;
+        self
+    }
+
+    pub fn dir(mut self, dir: FstVarDirection) -> Self {
+        self.dir = dir;
+        self
+    }
+
+    pub fn alias(mut self, alias: FstSignalId) -> Self {
+        self.alias = Some(alias);
+        self
+    }
+
+    /// Appends the default `[width-1:0]` range to the name of multi-bit variables,
+    /// e.g., `data [15:0]`, like `vcd2fst` does.
+    pub fn bit_range(mut self) -> Self {
+        self.range = Some(BitRange::Default);
+        self
+    }
+
+    /// Appends a custom ` [msb:lsb]` range to the name.
+    pub fn range(mut self, msb: i64, lsb: i64) -> Self {
+        self.range = Some(BitRange::Custom { msb, lsb });
+        self
+    }
+
+    /// Declares the variable.
+    pub fn build(self) -> Result<FstSignalId> {
+        let name = match self.range {
+            None => self.name,
+            Some(BitRange::Default) => match self.signal_tpe.bit_vec_len() {
+                // reals and strings have no bit range
+                Some(len) if len > 1 => format!("{} [{}:0]", self.name, len - 1),
+                _ => self.name,
+            },
+            Some(BitRange::Custom { msb, lsb }) => format!("{} [{msb}:{lsb}]", self.name),
+        };
+        self.writer
+            .var(name, self.signal_tpe, self.tpe, self.dir, self.alias)
+    }
+}
+
+fn write_hierarchy(
+    out: &mut (impl std::io::Write + std::io::Seek),
+    hierarchy: Hierarchy,
+    finish_info: &mut HeaderFinishInfo,
+) -> Result<()> {
+    finish_info.scope_count = hierarchy.num_scopes() as u64;
+    finish_info.var_count = hierarchy.num_vars() as u64;
+    finish_info.num_signals = hierarchy.signals().len() as u64;
+    finish_info.memory_used = finish_info.memory_used.max(hierarchy.buffer_size() as u64);
+    hierarchy.write_blocks(out)
+}
+
+/// Block size at which [`FstBodyWriter::append_sorted`] flushes, unless configured otherwise.
+pub const DEFAULT_FLUSH_SIZE: usize = 128 * 1024 * 1024;
+
+/// Records the changes of a contiguous range of signals, e.g., on its own simulation thread.
+/// Created by [`FstBodyWriter::shards`] and written through [`FstBodyWriter::flush_shards`].
+pub struct FstShard {
+    buffer: SignalBuffer,
+    signals: std::ops::Range<usize>,
+}
+
+impl FstShard {
+    pub fn time_change(&mut self, time: u64) -> Result<()> {
+        self.buffer.time_change(time)
+    }
+
+    pub fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
+        if !self.contains(signal_id) {
+            return Err(FstWriteError::InvalidSignalId(signal_id));
+        }
+        let local = signal_id.to_array_index() - self.signals.start;
+        self.buffer
+            .signal_change(FstSignalId::from_index(local as u32 + 1), value)
+    }
+
+    /// Returns whether the changes of this signal are recorded by this shard.
+    pub fn contains(&self, signal_id: FstSignalId) -> bool {
+        self.signals.contains(&signal_id.to_array_index())
+    }
+}
+
+/// Records the changes of all signals for a time range, e.g., on its own conversion thread,
+/// into a temporary file. Created by [`FstBodyWriter::part`] and appended to the file with
+/// [`FstBodyWriter::append_part`]. Like for a new file, changes before the first call to
+/// [`FstPart::time_change`] set the initial values.
+#[cfg(feature = "fs")]
+pub struct FstPart {
+    buffer: SignalBuffer,
+    file: TempFile,
+    first_time: Option<u64>,
+    stats: FstWriterStats,
+}
+
+#[cfg(feature = "fs")]
+impl FstPart {
+    pub fn time_change(&mut self, time: u64) -> Result<()> {
+        self.buffer.time_change(time)?;
+        if !self.buffer.is_empty() {
+            self.first_time.get_or_insert(time);
+        }
+        Ok(())
+    }
+
+    pub fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
+        self.buffer.signal_change(signal_id, value)
+    }
+
+    /// Writes all changes recorded so far as a block to the temporary file.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut out = std::io::BufWriter::new(&self.file.file);
+        out.seek(std::io::SeekFrom::End(0))?;
+        self.buffer.flush(&mut out, &mut self.stats)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Returns the number of bytes held in memory.
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+}
+
+/// Value change block that is written on a background thread, which hands back the output.
+type PendingWrite<W> = JoinHandle<(W, Block, Result<(FstBlockStats, u64)>)>;
+
+fn spawn_write<W: std::io::Write + std::io::Seek + Send + 'static>(
+    mut out: W,
+    mut block: Block,
+) -> PendingWrite<W> {
+    std::thread::spawn(move || {
+        let result = block
+            .write(&mut out)
+            .and_then(|stats| Ok((stats, out.stream_position()?)));
+        (out, block, result)
+    })
+}
+
+/// Writes value changes once the hierarchy was declared.
+///
+/// The writer is [`Send`] whenever the output `W` is, so it can be moved to a dedicated
+/// thread. It is not meant to be shared: use [`FstBodyWriter::into_shared`],
+/// [`FstBodyWriter::into_sender`] or [`FstBodyWriter::shards`] to record changes from
+/// several threads.
 pub struct FstBodyWriter<W: std::io::Write + std::io::Seek> {
-    out: W,
+    /// owned by the background thread while a block is written
+    out: Option<W>,
+    pending_write: Option<PendingWrite<W>>,
+    /// only available with [`FstBodyWriter::set_background_flush`]
+    spawn_write: Option<fn(W, Block) -> PendingWrite<W>>,
     buffer: SignalBuffer,
     finish_info: HeaderFinishInfo,
+    /// only available with [`FstWriterConfig::deferred_hierarchy`]
+    hierarchy: Option<Hierarchy>,
+    /// used instead of the hierarchy once it was written
+    scope_tree: ScopeTree,
+    external_ids: HashMap<usize, FstSignalId>,
+    signal_remap: Vec<FstSignalId>,
+    /// file and zlib level to compress with once all data is written
+    #[cfg(feature = "fs")]
+    repack: Option<(PathBuf, u8)>,
+    /// file to append checksums to once all data is written
+    #[cfg(feature = "fs")]
+    checksums: Option<PathBuf>,
+    /// file to delete in [`FstBodyWriter::abort`]
+    #[cfg(feature = "fs")]
+    path: Option<PathBuf>,
+    /// final location of an atomically written file, see [`FstOpenOptions::atomic`]
+    #[cfg(feature = "fs")]
+    rename_to: Option<PathBuf>,
+    #[cfg(feature = "fs")]
+    sync: FileSync,
+    /// see [`FstWriterConfig::rollover_size`]
+    #[cfg(feature = "fs")]
+    rollover: Option<Rollover<W>>,
+    /// times at which dumping was switched off (`false`) or back on (`true`)
+    dump_activity: Vec<(u64, bool)>,
+    dump_size_limit: Option<u64>,
+    /// file size at the last flush
+    bytes_written: u64,
+    dump_size_limit_reached: bool,
+    record_until: Option<u64>,
+    /// set once the size limit was reached or the time passed `record_until`
+    recording_stopped: bool,
+    auto_flush_size: Option<usize>,
+    /// see [`FstWriterConfig::memory_limit`]
+    memory_limit: Option<usize>,
+    /// buffer size at the start of the current time step
+    step_start_size: usize,
+    /// largest number of bytes a single time step took up so far
+    max_step_size: usize,
+    auto_flush_interval: Option<u64>,
+    /// interval that the first time step of the current block falls into
+    block_interval: Option<u64>,
+    /// only available with [`FstWriterConfig::reorder_depth`]
+    reorder: Option<ReorderBuffer>,
+    /// see [`FstWriterConfig::canonical_order`]
+    canonical_order: bool,
+    /// the output is flushed after every section, see [`open_fst_stream`]
+    streaming: bool,
+    /// see [`FstWriterConfig::live`]
+    live: bool,
+    /// see [`FstBodyWriter::set_finish_on_drop`]
+    finish_on_drop: bool,
+    stats: FstWriterStats,
+    /// see [`FstBodyWriter::set_hooks`]
+    hooks: Option<Box<dyn FstWriterHooks>>,
+    /// number of blocks in `stats` that the hooks were notified about
+    notified_blocks: usize,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::WriterMetrics,
 }
 
 impl<W: std::io::Write + std::io::Seek> FstBodyWriter<W> {
+    /// Maps the ids returned while declaring the header to the ids used for value changes.
+    /// Only differs from the identity when reordering through [`FstWriterConfig::signal_order`].
+    pub fn signal_remap(&self) -> &[FstSignalId] {
+        &self.signal_remap
+    }
+
     pub fn time_change(&mut self, time: u64) -> Result<()> {
-        self.buffer.time_change(time)
+        match &mut self.reorder {
+            Some(reorder) => {
+                let ready = reorder.time_change(time)?;
+                self.commit(ready)
+            }
+            None => self.commit_time_change(time),
+        }
     }
 
     pub fn signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
-        self.buffer.signal_change(signal_id, value)
+        match &mut self.reorder {
+            Some(reorder) => {
+                if signal_id.to_array_index() >= self.buffer.num_signals() {
+                    return Err(FstWriteError::InvalidSignalId(signal_id));
+                }
+                reorder.signal_change(signal_id, value);
+                Ok(())
+            }
+            None => self.commit_signal_change(signal_id, value),
+        }
+    }
+
+    /// Records changes that are sorted by time, e.g., when replaying a trace. Blocks are
+    /// flushed automatically, by default once they grow beyond [`DEFAULT_FLUSH_SIZE`] bytes,
+    /// or as configured through [`FstWriterConfig::auto_flush_size`].
+    pub fn append_sorted<V: AsRef<[u8]>>(
+        &mut self,
+        changes: impl IntoIterator<Item = (u64, FstSignalId, V)>,
+    ) -> Result<()> {
+        for (time, signal_id, value) in changes {
+            if time > self.buffer.time()
+                && self.auto_flush_size.is_none()
+                && self.buffer.size() >= DEFAULT_FLUSH_SIZE
+            {
+                self.flush()?;
+            }
+            self.time_change(time)?;
+            self.signal_change(signal_id, value.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Writes changes that are sorted by time for every signal, but not across signals,
+    /// e.g., when converting from a format that stores every signal separately. The streams
+    /// are merged into time order. Changes of different signals at the same time are recorded
+    /// in the order of `streams`.
+    /// The streams are consumed lazily, thus an unsorted stream is only detected with
+    /// [`FstWriteError::TimeDecrease`] after the changes before it were recorded, which may
+    /// leave a partial time step behind. The writer needs to be dropped after such an error.
+    pub fn write_signal_streams<I, V>(
+        &mut self,
+        streams: impl IntoIterator<Item = (FstSignalId, I)>,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = (u64, V)>,
+        V: AsRef<[u8]>,
+    {
+        let mut streams: Vec<_> = streams
+            .into_iter()
+            .map(|(signal_id, stream)| (signal_id, stream.into_iter()))
+            .collect();
+        // the next change of every stream, ordered by time and stream index
+        let mut heads: Vec<Option<V>> = Vec::with_capacity(streams.len());
+        let mut heap = BinaryHeap::with_capacity(streams.len());
+        for (idx, (_, stream)) in streams.iter_mut().enumerate() {
+            let head = stream.next().map(|(time, value)| {
+                heap.push(Reverse((time, idx)));
+                value
+            });
+            heads.push(head);
+        }
+        while let Some(Reverse((time, idx))) = heap.pop() {
+            let value = heads[idx].take().unwrap();
+            self.time_change(time)?;
+            self.signal_change(streams[idx].0, value.as_ref())?;
+            if let Some((next_time, value)) = streams[idx].1.next() {
+                if next_time < time {
+                    return Err(FstWriteError::TimeDecrease(time, next_time));
+                }
+                heads[idx] = Some(value);
+                heap.push(Reverse((next_time, idx)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Records time steps that left the reorder window.
+    fn commit(&mut self, steps: Vec<TimeStep>) -> Result<()> {
+        for (time, mut changes) in steps {
+            self.sort_changes(&mut changes);
+            self.commit_time_change(time)?;
+            for (signal_id, value) in changes {
+                self.commit_signal_change(signal_id, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records all changes of a time step that were collected from several threads.
+    pub(crate) fn time_step(
+        &mut self,
+        time: u64,
+        mut changes: Vec<(FstSignalId, Vec<u8>)>,
+    ) -> Result<()> {
+        self.sort_changes(&mut changes);
+        self.time_change(time)?;
+        for (signal_id, value) in changes {
+            self.signal_change(signal_id, &value)?;
+        }
+        Ok(())
+    }
+
+    /// Brings changes into signal id order with [`FstWriterConfig::canonical_order`].
+    fn sort_changes(&self, changes: &mut [(FstSignalId, Vec<u8>)]) {
+        if self.canonical_order {
+            // stable, thus changes of the same signal keep their order
+            changes.sort_by_key(|(signal_id, _)| *signal_id);
+        }
+    }
+
+    fn commit_time_change(&mut self, time: u64) -> Result<()> {
+        if self
+            .pending_write
+            .as_ref()
+            .is_some_and(|pending| pending.is_finished())
+        {
+            // report errors of the background thread as early as possible
+            self.wait_for_write()?;
+        }
+        if let Some(limit) = self.dump_size_limit {
+            // only the changes end up in the file
+            let size = self.buffer.size_breakdown();
+            let buffered = (size.value_changes + size.time_table) as u64 + self.buffer.spilled();
+            self.dump_size_limit_reached |= self.bytes_written + buffered >= limit;
+        }
+        let too_late = self.record_until.is_some_and(|until| time > until);
+        self.recording_stopped |= self.dump_size_limit_reached || too_late;
+        if self.recording_stopped {
+            return Ok(());
+        }
+        let interval = self
+            .auto_flush_interval
+            .map(|interval| time / interval.max(1));
+        if self.memory_limit.is_some() && time > self.buffer.time() {
+            let step_size = self.buffer.size().saturating_sub(self.step_start_size);
+            self.max_step_size = self.max_step_size.max(step_size);
+        }
+        if time > self.buffer.time() && self.should_auto_flush(interval) {
+            self.flush()?;
+        }
+        if self.buffer.is_empty() {
+            self.block_interval = interval;
+        }
+        #[cfg(feature = "metrics")]
+        self.metrics.set_buffered(self.buffer.size());
+        if self.memory_limit.is_some() && time > self.buffer.time() {
+            self.step_start_size = self.buffer.size();
+        }
+        self.buffer.time_change(time)
+    }
+
+    /// Blocks can only be started right before a new time step.
+    fn should_auto_flush(&self, interval: Option<u64>) -> bool {
+        !self.buffer.is_empty()
+            && (self.buffer.is_full()
+                || self
+                    .auto_flush_size
+                    .is_some_and(|limit| self.buffer.size() >= limit)
+                || self
+                    .memory_limit
+                    .is_some_and(|limit| self.buffer.size() + self.memory_headroom() >= limit)
+                || interval != self.block_interval)
+    }
+
+    /// Bytes that a block under the memory limit keeps free for the next time step: as many
+    /// as the largest time step so far and a new chunk, since value lists grow in jumps.
+    fn memory_headroom(&self) -> usize {
+        self.max_step_size + MAX_CHUNK_ALLOCATION
+    }
+
+    fn commit_signal_change(&mut self, signal_id: FstSignalId, value: &[u8]) -> Result<()> {
+        if self.recording_stopped {
+            return Ok(());
+        }
+        if let Some(limit) = self.memory_limit {
+            let required = self.buffer.size() + self.buffer.max_change_size(signal_id, value.len());
+            if required > limit {
+                return Err(FstWriteError::MemoryLimitExceeded { limit, required });
+            }
+        }
+        self.buffer
+            .signal_change(signal_id, value)
+            .map_err(|e| match e {
+                // errors that do not say which signal they are about
+                FstWriteError::InvalidCharacter(_)
+                | FstWriteError::InvalidValueLength { .. }
+                | FstWriteError::MissingTimeStep => e.with_context(|| FstErrorContext {
+                    signal: Some(signal_id),
+                    signal_name: self.signal_name(signal_id),
+                    ..self.error_context("recording a value change")
+                }),
+                e => e,
+            })
+    }
+
+    /// Returns true once [`FstWriterConfig::dump_size_limit`] was reached.
+    /// From then on, all time and value changes are ignored.
+    pub fn dump_size_limit_reached(&self) -> bool {
+        self.dump_size_limit_reached
+    }
+
+    /// Stops (`false`) or resumes (`true`) recording the changes of a signal.
+    /// A disabled signal keeps its last recorded value.
+    pub fn set_signal_enabled(&mut self, signal_id: FstSignalId, enabled: bool) -> Result<()> {
+        self.buffer.set_signal_enabled(signal_id, enabled)
+    }
+
+    /// Records only some of the changes of a signal. `None` records all changes again.
+    pub fn set_signal_decimation(
+        &mut self,
+        signal_id: FstSignalId,
+        decimation: Option<FstDecimation>,
+    ) -> Result<()> {
+        self.buffer.set_signal_decimation(signal_id, decimation)
+    }
+
+    /// Stops (`false`) or resumes (`true`) recording all signals of a scope and its sub-scopes.
+    /// `scope` is the index returned by [`FstHeaderWriter::scope`].
+    pub fn set_scope_enabled(&mut self, scope: usize, enabled: bool) -> Result<()> {
+        let signals = match &self.hierarchy {
+            Some(hierarchy) => hierarchy.scope_tree().signals(scope)?,
+            None => self.scope_tree.signals(scope)?,
+        };
+        for signal_id in signals {
+            self.buffer.set_signal_enabled(signal_id, enabled)?;
+        }
+        Ok(())
+    }
+
+    /// Records a value change for a signal declared with
+    /// [`FstHeaderWriter::var_with_external_id`].
+    pub fn signal_change_external(&mut self, external_id: usize, value: &[u8]) -> Result<()> {
+        match self.external_ids.get(&external_id).cloned() {
+            Some(signal_id) => self.signal_change(signal_id, value),
+            None => Err(FstWriteError::UnknownExternalId(external_id)),
+        }
+    }
+
+    fn deferred_hierarchy(&mut self) -> Result<&mut Hierarchy> {
+        self.hierarchy
+            .as_mut()
+            .ok_or(FstWriteError::HierarchyAlreadyWritten)
+    }
+
+    /// Opens a scope. Requires [`FstWriterConfig::deferred_hierarchy`].
+    /// Returns the index of the new scope.
+    pub fn scope(
+        &mut self,
+        name: impl AsRef<str>,
+        component: impl AsRef<str>,
+        tpe: FstScopeType,
+    ) -> Result<usize> {
+        self.deferred_hierarchy()?.scope(name, component, tpe)
+    }
+
+    /// Closes a scope. Requires [`FstWriterConfig::deferred_hierarchy`].
+    pub fn up_scope(&mut self) -> Result<()> {
+        self.deferred_hierarchy()?.up_scope()
+    }
+
+    /// Declares a variable. Requires [`FstWriterConfig::deferred_hierarchy`].
+    /// The new signal starts out as `x` and can be changed right away.
+    pub fn var(
+        &mut self,
+        name: impl AsRef<str>,
+        signal_tpe: FstSignalType,
+        tpe: FstVarType,
+        dir: FstVarDirection,
+        alias: Option<FstSignalId>,
+    ) -> Result<FstSignalId> {
+        let hierarchy = self.deferred_hierarchy()?;
+        let num_signals = hierarchy.signals().len();
+        let id = hierarchy.var_immediate(name, signal_tpe, tpe, dir, alias)?;
+        if hierarchy.signals().len() > num_signals {
+            self.buffer.add_signal(signal_tpe)?;
+        }
+        Ok(id)
+    }
+
+    /// Marks the start of a region in which dumping was disabled, beginning at the current time.
+    /// Viewers like GTKWave shade these regions. Value changes are still recorded.
+    pub fn dump_off(&mut self) {
+        if self.is_dumping() {
+            self.dump_activity.push((self.buffer.time(), false));
+        }
+    }
+
+    /// Ends a region started with [`FstBodyWriter::dump_off`] at the current time.
+    pub fn dump_on(&mut self) {
+        if !self.is_dumping() {
+            self.dump_activity.push((self.buffer.time(), true));
+        }
+    }
+
+    fn is_dumping(&self) -> bool {
+        self.dump_activity.last().is_none_or(|&(_, active)| active)
     }
 
     /// flushes all value change data to disk
     pub fn flush(&mut self) -> Result<()> {
-        self.buffer.flush(&mut self.out)?;
+        if self.skip_flush() {
+            return Ok(());
+        }
+        self.track_memory(self.buffer.size());
+        self.wait_for_write()?;
+        #[cfg(feature = "fs")]
+        self.roll_over().map_err(|e| {
+            e.with_context(|| self.error_context("rolling over into the next file"))
+        })?;
+        let context = self.error_context("writing a value change block");
         self.finish_info.num_value_change_sections += 1;
+        let out = self.out.as_mut().unwrap();
+        match self.spawn_write {
+            Some(spawn_write) => {
+                let block = self.buffer.take_block()?;
+                self.pending_write = Some(spawn_write(self.out.take().unwrap(), block));
+            }
+            None => {
+                self.buffer
+                    .flush(out, &mut self.stats)
+                    .map_err(|e| e.with_context(|| context))?;
+                self.bytes_written = out.stream_position()?;
+                self.blocks_written()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the background thread is done writing and takes back the output.
+    fn wait_for_write(&mut self) -> Result<()> {
+        if let Some(pending) = self.pending_write.take() {
+            let (out, block, result) = pending
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            self.out = Some(out);
+            let (block_stats, bytes_written) = result.map_err(|e| {
+                e.with_context(|| FstErrorContext {
+                    time: Some(block.end_time()),
+                    block: Some(self.finish_info.num_value_change_sections.saturating_sub(1)),
+                    ..self.error_context("writing a value change block")
+                })
+            })?;
+            block.count_signal_changes(&mut self.stats.signal_changes, 0);
+            self.stats.blocks.push(block_stats);
+            self.buffer.recycle(block)?;
+            self.bytes_written = bytes_written;
+            self.blocks_written()?;
+        }
         Ok(())
     }
 
-    /// Returns the estimated size of all data structures that grow over time.
+    /// Where the writer currently is, to add to errors.
+    fn error_context(&self, phase: &'static str) -> FstErrorContext {
+        FstErrorContext {
+            phase,
+            time: Some(self.buffer.time()),
+            block: Some(self.finish_info.num_value_change_sections),
+            offset: Some(self.bytes_written),
+            ..Default::default()
+        }
+    }
+
+    /// Full name of a var of the signal, if the hierarchy was kept.
+    fn signal_name(&self, signal_id: FstSignalId) -> Option<String> {
+        match &self.hierarchy {
+            Some(hierarchy) => hierarchy.signal_name(signal_id),
+            None => self.scope_tree.signal_name(signal_id),
+        }
+    }
+
+    /// Returns the output once all pending blocks were written.
+    pub(crate) fn out(&mut self) -> Result<&mut W> {
+        self.wait_for_write()?;
+        Ok(self.out.as_mut().unwrap())
+    }
+
+    /// Remembers the peak memory use for the header. Blocks are largest right before they are
+    /// written, in addition to the hierarchy if it is deferred.
+    fn track_memory(&mut self, buffered: usize) {
+        let hierarchy = self.hierarchy.as_ref().map_or(0, |h| h.buffer_size());
+        let used = (buffered + hierarchy) as u64;
+        self.finish_info.memory_used = self.finish_info.memory_used.max(used);
+    }
+
+    /// Once all changes were flushed, e.g., after recording stopped or when changes are
+    /// recorded through shards, there is nothing left to write.
+    fn skip_flush(&self) -> bool {
+        self.buffer.is_empty() && self.finish_info.num_value_change_sections > 0
+    }
+
+    /// Splits the signals into `num_shards` contiguous ranges that can be recorded
+    /// independently, e.g., by different simulation threads. From then on, all changes
+    /// need to be recorded through the shards, which must see the same time steps.
+    /// Changes that were not flushed yet are flushed first.
+    pub fn shards(&mut self, num_shards: usize) -> Result<Vec<FstShard>> {
+        if !self.buffer.is_empty() {
+            self.flush()?;
+        }
+        let num_signals = self.buffer.num_signals();
+        let shard_size = num_signals.div_ceil(num_shards.max(1));
+        let shards = (0..num_shards)
+            .map(|ii| {
+                let start = (ii * shard_size).min(num_signals);
+                let signals = start..(start + shard_size).min(num_signals);
+                FstShard {
+                    buffer: self.buffer.shard(signals.clone()),
+                    signals,
+                }
+            })
+            .collect();
+        Ok(shards)
+    }
+
+    /// Writes the changes recorded by all shards as a single value change block.
+    pub fn flush_shards(&mut self, shards: &mut [FstShard]) -> Result<()> {
+        if shards.iter().all(|shard| shard.buffer.is_empty()) {
+            return Ok(());
+        }
+        self.track_memory(shards.iter().map(|shard| shard.buffer.size()).sum());
+        let mut blocks: Vec<_> = shards
+            .iter_mut()
+            .map(|shard| shard.buffer.take_block())
+            .collect::<Result<_>>()?;
+        let out = self.out()?;
+        let block_stats = Block::write_shards(&mut blocks, out)?;
+        self.bytes_written = out.stream_position()?;
+        self.finish_info.num_value_change_sections += 1;
+        self.finish_info.end_time = blocks[0].end_time();
+        self.stats.blocks.extend(block_stats);
+        for (shard, block) in shards.iter_mut().zip(blocks) {
+            block.count_signal_changes(&mut self.stats.signal_changes, shard.signals.start);
+            shard.buffer.recycle(block)?;
+        }
+        self.blocks_written()?;
+        Ok(())
+    }
+
+    /// Reserves `padding` bytes in the file, e.g., for tools that post-process the file in
+    /// place. Readers skip over the reserved region. Value changes that were not flushed yet
+    /// are written after it. Returns the file offset of the reserved bytes.
+    pub fn reserve(&mut self, padding: u64) -> Result<u64> {
+        let out = self.out()?;
+        let start = write_skip(out, padding)?;
+        self.bytes_written = out.stream_position()?;
+        Ok(start)
+    }
+
+    /// Creates a part that records changes into its own temporary file, so that several
+    /// threads can convert different time ranges in parallel, similar to the parallel mode
+    /// of `fstapi`.
+    #[cfg(feature = "fs")]
+    pub fn part(&self) -> Result<FstPart> {
+        let mut buffer = self.buffer.shard(0..self.buffer.num_signals());
+        buffer.set_first_buffer();
+        Ok(FstPart {
+            buffer,
+            file: TempFile::new()?,
+            first_time: None,
+            stats: FstWriterStats::default(),
+        })
+    }
+
+    /// Copies all blocks of a part into the file. Parts need to be appended in time order,
+    /// after all changes that were recorded directly. Afterwards, changes can only be
+    /// recorded through further parts.
+    #[cfg(feature = "fs")]
+    pub fn append_part(&mut self, mut part: FstPart) -> Result<()> {
+        part.flush()?;
+        let Some(first_time) = part.first_time else {
+            return Ok(());
+        };
+        if !self.buffer.is_empty() {
+            self.flush()?;
+        }
+        let end_time = self.finish_info.end_time.max(self.buffer.time());
+        if first_time < end_time {
+            return Err(FstWriteError::TimeDecrease(end_time, first_time));
+        }
+        let out = self.out()?;
+        let mut file = &part.file.file;
+        file.seek(std::io::SeekFrom::Start(0))?;
+        std::io::copy(&mut file, out)?;
+        self.bytes_written = out.stream_position()?;
+        self.finish_info.num_value_change_sections += part.stats.blocks.len() as u64;
+        self.finish_info.end_time = part.buffer.time();
+        self.stats.append(part.stats);
+        self.blocks_written()?;
+        Ok(())
+    }
+
+    /// Turns the writer into a handle that can be cloned and sent to other threads.
+    /// See [`SharedFstWriter`].
+    pub fn into_shared(self) -> SharedFstWriter<W> {
+        let time = self.buffer.time();
+        SharedFstWriter::new(self, time)
+    }
+
+    /// Returns the number of bytes needed to record the current block, which is what
+    /// [`FstWriterConfig::auto_flush_size`] is compared against. Value changes moved to a
+    /// temporary file through [`FstWriterConfig::spill_threshold`] are not included.
+    /// See [`FstBodyWriter::size_breakdown`] for all memory held by the writer.
     pub fn size(&self) -> usize {
         self.buffer.size()
     }
 
-    pub fn finish(mut self) -> Result<()> {
+    /// Returns the number of bytes held in memory, split by their use, e.g., to find out
+    /// whether a wide design is dominated by per-signal data.
+    pub fn size_breakdown(&self) -> FstSizeBreakdown {
+        self.buffer.size_breakdown()
+    }
+
+    /// Size of the output after the last flush, like `fstWriterGetFseekOffset`, e.g., to enforce
+    /// a disk quota. Blocks that are still written in the background are not included.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Statistics of all value change blocks that were written so far, e.g., to find the
+    /// signals that dominate the size of the file.
+    pub fn stats(&self) -> &FstWriterStats {
+        &self.stats
+    }
+
+    /// Installs callbacks that are notified about every value change block that is written and
+    /// when the file is finished. With [`FstBodyWriter::set_background_flush`], a block is only
+    /// reported once the writer waited for it, i.e., with the next flush.
+    pub fn set_hooks(&mut self, hooks: impl FstWriterHooks + 'static) {
+        self.hooks = Some(Box::new(hooks));
+        self.notified_blocks = self.stats.blocks.len();
+    }
+
+    /// Updates the header once blocks were written, so that a file that is never finished,
+    /// e.g., because the simulation crashed, can still be read up to the last block.
+    /// Streams and files written with [`FstWriterConfig::live`] are flushed.
+    fn blocks_written(&mut self) -> Result<()> {
+        self.publish_blocks().map_err(|e| {
+            e.with_context(|| FstErrorContext {
+                block: Some(self.finish_info.num_value_change_sections.saturating_sub(1)),
+                ..self.error_context("writing a value change block")
+            })
+        })?;
+        self.notify_blocks();
+        Ok(())
+    }
+
+    fn publish_blocks(&mut self) -> Result<()> {
+        let info = self.header_info();
+        let out = self.out.as_mut().unwrap();
+        if self.streaming {
+            // the header of a stream was already written
+            out.flush()?;
+        } else {
+            if self.live {
+                // readers must not see the new header before the blocks are complete
+                out.flush()?;
+            }
+            let end = out.stream_position()?;
+            update_header(out, &info)?;
+            out.seek(std::io::SeekFrom::Start(end))?;
+            if self.live {
+                out.flush()?;
+            }
+        }
+        #[cfg(feature = "fs")]
+        if let Some(file) = &self.sync.on_flush {
+            out.flush()?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Header of the current file once all of its blocks are written.
+    fn header_info(&self) -> HeaderFinishInfo {
+        HeaderFinishInfo {
+            end_time: self
+                .stats
+                .blocks
+                .last()
+                .map_or(self.finish_info.end_time, |b| b.end_time),
+            ..self.finish_info
+        }
+    }
+
+    /// Finishes the current file and continues in the next one, once the current file grew
+    /// beyond [`FstWriterConfig::rollover_size`]. The next block is recorded with the values
+    /// at its start, which thus end up in the frame of the new file.
+    #[cfg(feature = "fs")]
+    fn roll_over(&mut self) -> Result<()> {
+        let Some(mut rollover) = self.rollover.take() else {
+            return Ok(());
+        };
+        let full = self.bytes_written >= rollover.size;
+        if !full || self.finish_info.num_value_change_sections == 0 {
+            self.rollover = Some(rollover);
+            return Ok(());
+        }
+
+        // open the next file first, so that the current one stays in place if that fails
+        let index = rollover.index + 1;
+        let path = rollover_path(&rollover.path, index);
+        let reopen = &rollover.reopen;
+        // the new file starts with the block that is about to be written
+        let start_time = self.buffer.start_time();
+        let next = (reopen.create)(&path, &reopen.options).and_then(|(mut out, written, sync)| {
+            let result = write_header_meta_data(&mut out, &reopen.info, start_time)
+                .and_then(|_| Ok(out.write_all(&rollover.hierarchy)?));
+            if result.is_err() {
+                let _ = std::fs::remove_file(&written);
+            }
+            result.map(|_| (out, written, sync))
+        });
+        let (out, written, sync) = match next {
+            Ok(next) => next,
+            Err(e) => {
+                self.rollover = Some(rollover);
+                return Err(e);
+            }
+        };
+
+        // regions without dumping only cover the file they were recorded in, changes during
+        // the block that is about to be written belong to the next file
+        let split = self
+            .dump_activity
+            .partition_point(|&(time, _)| time < start_time);
+        let later = self.dump_activity.split_off(split);
+
+        // finish the current file like `finish` does, including post-processing
+        if let Err(e) = self.finish_header() {
+            drop(out);
+            let _ = std::fs::remove_file(&written);
+            self.dump_activity.extend(later);
+            self.rollover = Some(rollover);
+            return Err(e);
+        }
+        drop(self.out.replace(out));
+        let dumping = self.is_dumping();
+        self.dump_activity.clear();
+        if !dumping {
+            self.dump_activity.push((start_time, false));
+        }
+        self.dump_activity.extend(later);
+        let checksums = self.checksums.is_some();
+        let repack_level = self.repack.as_ref().map(|(_, level)| *level);
+        // the writer continues in the next file, even if post-processing the current one fails
+        let finished = self.finish_file();
+
+        rollover.index = index;
+        self.finish_info.num_value_change_sections = 0;
+        self.checksums = checksums.then(|| written.clone());
+        self.repack = repack_level.map(|level| (written.clone(), level));
+        self.rename_to = (written != path).then_some(path);
+        self.path = Some(written);
+        self.sync = sync;
+        self.rollover = Some(rollover);
+        finished?;
+
+        let info = self.header_info();
+        let out = self.out.as_mut().unwrap();
+        if self.live {
+            update_header(out, &info)?;
+            out.seek(std::io::SeekFrom::End(0))?;
+            out.flush()?;
+        }
+        self.bytes_written = out.stream_position()?;
+        Ok(())
+    }
+
+    /// Writes the blackout block and the final header of the current file. On failure, the
+    /// output is left at the end of the last value change block.
+    #[cfg(feature = "fs")]
+    fn finish_header(&mut self) -> Result<()> {
+        let info = self.header_info();
+        let out = self.out.as_mut().unwrap();
+        let end = out.stream_position()?;
+        let result = (|| {
+            if !self.dump_activity.is_empty() {
+                write_blackout(out, &self.dump_activity)?;
+            }
+            update_header(out, &info)?;
+            Ok(out.flush()?)
+        })();
+        if result.is_err() {
+            let _ = out.seek(std::io::SeekFrom::Start(end));
+        }
+        result
+    }
+
+    /// Notifies the hooks about all blocks that were written since the last notification.
+    fn notify_blocks(&mut self) {
+        let blocks = &self.stats.blocks[self.notified_blocks..];
+        if let Some(hooks) = &mut self.hooks {
+            for block in blocks {
+                hooks.on_block_flushed(block);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        {
+            for block in blocks {
+                self.metrics.block_written(block);
+            }
+            self.metrics.set_buffered(self.buffer.size());
+        }
+        self.notified_blocks = self.stats.blocks.len();
+    }
+
+    /// The most recent time step, including time steps that are held back through
+    /// [`FstWriterConfig::reorder_depth`].
+    pub fn current_time(&self) -> u64 {
+        match &self.reorder {
+            Some(reorder) => reorder.time(),
+            None => self.buffer.time(),
+        }
+    }
+
+    /// The first time step of the value change block that is currently recorded, `None` if
+    /// there was no time step since the last flush.
+    pub fn block_start_time(&self) -> Option<u64> {
+        self.buffer.block_start_time()
+    }
+
+    /// Number of value changes recorded since the last flush, e.g., to decide when to call
+    /// [`FstBodyWriter::flush`]. Does not include changes that are still held back or that
+    /// did not change the value of a signal.
+    pub fn num_buffered_changes(&self) -> usize {
+        self.buffer.num_changes()
+    }
+
+    /// A writer that is dropped without calling [`FstBodyWriter::finish`], e.g., because of a
+    /// panic or an early return, still writes its value changes and updates the header.
+    /// Errors are ignored and checksums or repacking are skipped. Enabled by default.
+    pub fn set_finish_on_drop(&mut self, enabled: bool) {
+        self.finish_on_drop = enabled;
+    }
+
+    /// Returns the output without finishing the file, which can only be read up to the last
+    /// flushed block. Waits for blocks that are written in the background.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.finish_on_drop = false;
+        self.wait_for_write()?;
+        Ok(self.out.take().unwrap())
+    }
+
+    /// Stops writing and deletes the file if it was opened by path, e.g., with [`open_fst`].
+    /// Other outputs are dropped, and files continued with `append_fst` are kept.
+    pub fn abort(mut self) -> Result<()> {
+        self.finish_on_drop = false;
+        // the background thread might still be writing to the file
+        let _ = self.wait_for_write();
+        drop(self.out.take());
+        #[cfg(feature = "fs")]
+        if let Some(path) = self.path.take() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the file and returns the output, e.g., to sync a file to disk or to take the
+    /// bytes of a file written with [`open_fst_in_memory`]. Checksums and repacking are applied
+    /// to the file at its path, which the returned output does not reflect.
+    pub fn finish(self) -> Result<W> {
+        Ok(self.finish_with_stats()?.0)
+    }
+
+    /// Like [`FstBodyWriter::finish`], but also returns the statistics of all blocks.
+    pub fn finish_with_stats(mut self) -> Result<(W, FstWriterStats)> {
+        self.finish_on_drop = false;
+        let out = self
+            .finish_output()
+            .map_err(|e| e.with_context(|| self.error_context("finishing the file")))?;
+        self.finish_file()
+            .map_err(|e| e.with_context(|| self.error_context("post-processing the file")))?;
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_finish(&self.stats);
+        }
+        Ok((out, std::mem::take(&mut self.stats)))
+    }
+
+    /// Writes everything that is still missing and flushes the output.
+    fn finish_output(&mut self) -> Result<W> {
+        if let Some(reorder) = &mut self.reorder {
+            let remaining = reorder.drain();
+            self.commit(remaining)?;
+        }
         // write value change section
-        let end_time = self.buffer.flush(&mut self.out)?;
+        self.flush()?;
+        self.wait_for_write()?;
+        let mut out = self.out.take().unwrap();
 
-        // update info
-        self.finish_info.num_value_change_sections += 1;
-        self.finish_info.end_time = end_time;
-        update_header(&mut self.out, &self.finish_info)?;
+        if !self.dump_activity.is_empty() {
+            write_blackout(&mut out, &self.dump_activity)?;
+        }
+
+        // write deferred hierarchy
+        if let Some(hierarchy) = self.hierarchy.take() {
+            write_hierarchy(&mut out, hierarchy, &mut self.finish_info)?;
+        }
+
+        // update info, the header of a stream was already written
+        self.finish_info.end_time = self.finish_info.end_time.max(self.buffer.time());
+        if !self.streaming {
+            update_header(&mut out, &self.finish_info)?;
+        }
+
+        out.flush()?;
+        Ok(out)
+    }
+
+    /// Post-processes the finished file on disk.
+    #[cfg(feature = "fs")]
+    fn finish_file(&mut self) -> Result<()> {
+        if let Some(path) = self.checksums.take() {
+            append_checksums(&path)?;
+        }
+
+        if let Some((path, level)) = self.repack.take() {
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".repack");
+            repack_fst_with_level(&path, &tmp, level)?;
+            std::fs::rename(&tmp, &path)?;
+        }
+
+        let mut path = self.path.clone();
+        if let (Some(written), Some(target)) = (&self.path, self.rename_to.take()) {
+            std::fs::rename(written, &target)?;
+            path = Some(target);
+        }
+
+        if let Some(path) = path.filter(|_| self.sync.on_finish) {
+            // post-processing might have replaced or renamed the file
+            sync_path(&path)?;
+        }
 
         Ok(())
     }
+
+    /// Without file system access, there is no file to post-process.
+    #[cfg(not(feature = "fs"))]
+    fn finish_file(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
+
+impl<W: std::io::Write + std::io::Seek> Drop for FstBodyWriter<W> {
+    fn drop(&mut self) {
+        // the output is gone once the file was finished
+        let unfinished = self.out.is_some() || self.pending_write.is_some();
+        if self.finish_on_drop && unfinished {
+            let _ = self.finish_output();
+        }
+    }
+}
+
+impl<W: std::io::Write + std::io::Seek + Send + 'static> FstBodyWriter<W> {
+    /// Writes and compresses value change blocks on a background thread, so that new
+    /// changes can be recorded while a block is written. Errors are returned by the next
+    /// call to [`FstBodyWriter::time_change`], [`FstBodyWriter::flush`] or
+    /// [`FstBodyWriter::finish`].
+    pub fn set_background_flush(&mut self, enabled: bool) {
+        self.spawn_write = enabled.then_some(spawn_write::<W>);
+    }
+
+    /// Moves the writer to its own thread that receives value changes from the returned
+    /// handle and all of its clones. The thread finishes the file once all handles are
+    /// dropped and returns the first error it encountered.
+    pub fn into_sender(self) -> (FstSignalSender, std::thread::JoinHandle<Result<()>>) {
+        let time = self.buffer.time();
+        crate::sender::spawn_writer(self, time)
+    }
+}
+
+// the writers can be moved to another thread
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<FstHeaderWriter<std::io::BufWriter<std::fs::File>>>();
+    assert_send::<FstBodyWriter<std::io::BufWriter<std::fs::File>>>();
+    assert_send::<FstShard>();
+    assert_send::<SignalBuffer>();
+    assert_send::<Block>();
+};
@@ -5,8 +5,8 @@
 // write FST files with fst-writer and read them again with the wellen library
 // (using fst-native as the backend)
 
+use ::wellen::{self, GetItem, SignalRef, Time};
 use fst_writer::*;
-use wellen::{GetItem, SignalRef, Time};
 
 #[test]
 fn write_read_simple() {
@@ -120,19 +120,4582 @@ fn write_read_simple() {
     );
 }
 
-use std::fmt::Write;
-fn signal_values_to_string(signal: &wellen::Signal, time_table: &[Time]) -> String {
-    let mut out = String::new();
-    for (time, value) in signal.iter_changes() {
-        write!(
-            out,
-            "({}: {}), ",
-            time_table[time as usize],
-            value.to_bit_string().unwrap()
+#[test]
+fn write_read_source_stems() {
+    let filename = "tests/source_stems.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    writer.source_stem("rtl/top.v", 1).unwrap();
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    // both child scopes refer to the same source file
+    writer.source_stem("rtl/child.v", 3).unwrap();
+    writer.source_instantiation_stem("rtl/top.v", 10).unwrap();
+    writer.scope("c0", "Child", FstScopeType::Module).unwrap();
+    writer.up_scope().unwrap();
+    writer.source_stem("rtl/child.v", 3).unwrap();
+    writer.source_instantiation_stem("rtl/top.v", 11).unwrap();
+    writer.scope("c1", "Child", FstScopeType::Module).unwrap();
+    writer.up_scope().unwrap();
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let top = h.first_scope().unwrap();
+    assert_eq!(top.source_loc(h), Some(("rtl/top.v", 1)));
+    let children = top.scopes(h).map(|r| h.get(r)).collect::<Vec<_>>();
+    assert_eq!(children[0].source_loc(h), Some(("rtl/child.v", 3)));
+    assert_eq!(
+        children[0].instantiation_source_loc(h),
+        Some(("rtl/top.v", 10))
+    );
+    assert_eq!(children[1].source_loc(h), Some(("rtl/child.v", 3)));
+    assert_eq!(
+        children[1].instantiation_source_loc(h),
+        Some(("rtl/top.v", 11))
+    );
+}
+
+#[test]
+fn write_read_with_scope() {
+    let filename = "tests/with_scope.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let tpe = FstSignalType::bit_vec(1);
+    let (a, b) = writer
+        .with_scope("top", "Top", FstScopeType::Module, |w| {
+            let a = w.var("a", tpe, FstVarType::Wire, FstVarDirection::Implicit, None)?;
+            let a_wide = w.var_builder("a_wide").width(8).build()?;
+            let b = w.with_scope("child", "Child", FstScopeType::Module, |w| {
+                let b = w.var_builder("b").width(8).typ(FstVarType::Logic).build()?;
+                w.var_builder("b_alias")
+                    .width(8)
+                    .dir(FstVarDirection::Output)
+                    .alias(a_wide)
+                    .build()?;
+                Ok(b)
+            })?;
+            Ok((a, b))
+        })
+        .unwrap();
+    // the scope is closed even if the closure returns early with an error
+    let res = writer.with_scope("failing", "Failing", FstScopeType::Module, |w| {
+        w.source_stem("x".repeat(100_000), 1)?;
+        w.var("c", tpe, FstVarType::Wire, FstVarDirection::Implicit, None)
+    });
+    assert!(res.is_err());
+    assert_eq!(writer.scope_depth(), 0);
+    // nested scopes that are still open when the closure fails are closed as well
+    let res = writer.with_scope("outer", "", FstScopeType::Module, |w| {
+        w.scope("inner", "", FstScopeType::Module)?;
+        w.source_stem("x".repeat(100_000), 1)
+    });
+    assert!(res.is_err());
+    assert_eq!(writer.scope_depth(), 0);
+    // a closure that closes its own scope does not close the parent
+    writer.scope("parent", "", FstScopeType::Module).unwrap();
+    writer
+        .with_scope("closed", "", FstScopeType::Module, |w| w.up_scope())
+        .unwrap();
+    assert_eq!(writer.scope_depth(), 1);
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(a, b"1").unwrap();
+    writer.signal_change(b, b"00000000").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["top.a", "top.a_wide", "top.child.b", "top.child.b_alias"]
+    );
+    let b_alias = h.iter_vars().last().unwrap();
+    assert_eq!(b_alias.direction(), wellen::VarDirection::Output);
+    assert_eq!(b_alias.signal_ref().index(), 1);
+    assert_eq!(h.iter_vars().nth(2).unwrap().signal_ref().index(), 2);
+    let top_scopes = h.scopes().map(|r| h.get(r).name(h)).collect::<Vec<_>>();
+    assert_eq!(top_scopes, ["top", "failing", "outer", "parent"]);
+}
+
+#[test]
+fn write_read_scope_path() {
+    let filename = "tests/scope_path.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let tpe = FstSignalType::bit_vec(1);
+    let paths = [
+        "top.core.alu.a",
+        "top.core.alu.b",
+        "top.core.c",
+        "top.d",
+        "top.e.f",
+    ];
+    let mut ids = vec![];
+    for path in paths {
+        let name = writer
+            .scope_path(path, "Cell", FstScopeType::Module)
+            .unwrap();
+        let id = writer
+            .var(name, tpe, FstVarType::Wire, FstVarDirection::Implicit, None)
+            .unwrap();
+        ids.push(id);
+    }
+    // `top.core` was closed by `top.d` and cannot be extended anymore
+    let err = writer
+        .scope_path("top.core.g", "", FstScopeType::Module)
+        .unwrap_err();
+    assert!(matches!(err, FstWriteError::ScopeAlreadyClosed(ref p) if p == "top.core"));
+    assert_eq!(writer.scope_depth(), 2);
+    // every scope is only declared once
+    let scopes = writer
+        .scopes()
+        .iter()
+        .map(|s| s.name.as_ref())
+        .collect::<Vec<_>>();
+    assert_eq!(scopes, ["top", "core", "alu", "e"]);
+    assert!(
+        writer
+            .scopes()
+            .iter()
+            .all(|s| s.component.as_ref() == "Cell")
+    );
+    writer.up_scope_all().unwrap();
+    assert_eq!(writer.scope_depth(), 0);
+    let mut writer = writer.finish().unwrap();
+    for id in ids {
+        writer.signal_change(id, b"0").unwrap();
+    }
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "top.core.alu.a",
+            "top.core.alu.b",
+            "top.core.c",
+            "top.d",
+            "top.e.f"
+        ]
+    );
+    #[cfg(feature = "testing")]
+    {
+        let blocks = testing::read_blocks(filename).unwrap();
+        assert!(blocks.contains(&testing::FstBlock::Hierarchy { scopes: 4, vars: 5 }));
+    }
+}
+
+#[test]
+fn write_read_bit_range() {
+    let filename = "tests/bit_range.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    writer.scope("top", "", FstScopeType::Module).unwrap();
+    let data = writer
+        .var_builder("data")
+        .width(16)
+        .bit_range()
+        .build()
+        .unwrap();
+    writer.var_builder("bit").bit_range().build().unwrap();
+    writer
+        .var_builder("addr")
+        .width(4)
+        .range(7, 4)
+        .build()
+        .unwrap();
+    writer
+        .var_builder("r")
+        .signal_type(FstSignalType::real())
+        .typ(FstVarType::Real)
+        .bit_range()
+        .build()
+        .unwrap();
+    writer
+        .var_builder("s")
+        .width(0)
+        .typ(FstVarType::GenericString)
+        .bit_range()
+        .build()
+        .unwrap();
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(data, b"0").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let vars = h.iter_vars().collect::<Vec<_>>();
+    let names = vars.iter().map(|v| v.name(h)).collect::<Vec<_>>();
+    assert_eq!(names, ["data", "bit", "addr", "r", "s"]);
+    let ranges = vars
+        .iter()
+        .map(|v| v.index().map(|i| (i.msb(), i.lsb())))
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, [Some((15, 0)), None, Some((7, 4)), None, None]);
+}
+
+#[test]
+fn hierarchy_name_too_long() {
+    let mut writer = open_fst("tests/name_too_long.fst", &test_info()).unwrap();
+    let long_name = "a".repeat(512);
+    assert!(matches!(
+        writer.scope(&long_name, "", FstScopeType::Module),
+        Err(FstWriteError::StringTooLong(512, _))
+    ));
+    assert!(matches!(
+        writer.scope("top", &long_name, FstScopeType::Module),
+        Err(FstWriteError::StringTooLong(512, _))
+    ));
+    assert_eq!(writer.scope_depth(), 0);
+    assert!(matches!(
+        writer.var_builder(long_name).build(),
+        Err(FstWriteError::StringTooLong(512, _))
+    ));
+    // the longest legal name
+    writer.var_builder("a".repeat(511)).build().unwrap();
+}
+
+#[test]
+fn unbalanced_scopes() {
+    let mut writer = open_fst("tests/unbalanced_scopes.fst", &test_info()).unwrap();
+    writer.scope("top", "", FstScopeType::Module).unwrap();
+    writer.scope("child", "", FstScopeType::Module).unwrap();
+    writer.up_scope().unwrap();
+    assert!(matches!(
+        writer.finish(),
+        Err(FstWriteError::UnbalancedScopes { open: 1 })
+    ));
+}
+
+#[test]
+fn extra_up_scope() {
+    let mut writer = open_fst("tests/extra_up_scope.fst", &test_info()).unwrap();
+    writer.scope("top", "", FstScopeType::Module).unwrap();
+    writer.up_scope().unwrap();
+    assert!(matches!(writer.up_scope(), Err(FstWriteError::NoOpenScope)));
+    // the failed call did not write anything
+    let a = writer.var_builder("a").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(a, b"1").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+    let wave = wellen::simple::read("tests/extra_up_scope.fst").unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(names, ["a"]);
+}
+
+#[test]
+fn hierarchy_introspection() {
+    let mut writer = open_fst("tests/introspection.fst", &test_info()).unwrap();
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    writer.scope("child", "Child", FstScopeType::Task).unwrap();
+    writer
+        .var_builder("a_alias")
+        .width(4)
+        .alias(a)
+        .build()
+        .unwrap();
+    let r = writer
+        .var_builder("r")
+        .signal_type(FstSignalType::real())
+        .typ(FstVarType::Real)
+        .build()
+        .unwrap();
+    writer.up_scope_all().unwrap();
+
+    assert_eq!(writer.num_signals(), 2);
+    assert_eq!(writer.signal_type(a).unwrap().bit_vec_len(), Some(4));
+    assert!(writer.signal_type(r).unwrap().is_real());
+    let scopes = writer.scopes();
+    assert_eq!(scopes.len(), 2);
+    assert_eq!(&*scopes[1].name, "child");
+    assert_eq!(scopes[1].tpe, FstScopeType::Task);
+    assert_eq!(scopes[1].parent, Some(0));
+    let vars = writer.vars();
+    let names = vars.iter().map(|v| writer.full_name(v)).collect::<Vec<_>>();
+    assert_eq!(names, ["top.a", "top.child.a_alias", "top.child.r"]);
+    assert!(vars[1].is_alias);
+    assert_eq!(vars[1].signal_id, a);
+    assert_eq!(vars[2].scope, Some(1));
+}
+
+#[test]
+fn write_read_incremental_hierarchy_compression() {
+    let filename = "tests/incremental_hierarchy.fst";
+    let config = FstWriterConfig {
+        incremental_hierarchy_compression: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    // declarations before the configuration change are kept
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let mut writer = writer.with_config(config).unwrap();
+    let mut ids = vec![];
+    for ii in 0..1000 {
+        let name = format!("s{ii}");
+        ids.push(writer.var_builder(name).width(8).build().unwrap());
+    }
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for id in ids.iter() {
+        writer.signal_change(*id, b"0").unwrap();
+    }
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(names.len(), 1000);
+    assert_eq!(names[0], "top.s0");
+    assert_eq!(names[999], "top.s999");
+}
+
+#[test]
+fn incremental_hierarchy_retention() {
+    let filename = "tests/incremental_hierarchy_retention.fst";
+    let config = FstWriterConfig {
+        incremental_hierarchy_compression: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config.clone())
+        .unwrap();
+    for path in ["top.core.a", "top.core.b", "top.alu.c"] {
+        let name = writer.scope_path(path, "", FstScopeType::Module).unwrap();
+        writer.var_builder(name).width(8).build().unwrap();
+    }
+    // open scopes are still known, all other declarations are only encoded
+    assert_eq!(writer.scope_depth(), 2);
+    writer.up_scope_all().unwrap();
+    assert!(writer.scopes().is_empty() && writer.vars().is_empty());
+    assert!(matches!(
+        writer.alias_scope("core2", "", 1),
+        Err(FstWriteError::DeclarationsNotRetained)
+    ));
+    // retention cannot be turned on after the declarations were dropped
+    let retain = FstWriterConfig {
+        retain_declarations: true,
+        ..config.clone()
+    };
+    let Err(err) = writer.with_config(retain.clone()) else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.kind(), FstErrorKind::ConfigAfterDeclarations);
+
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let mut ids = vec![];
+    for path in ["top.core.a", "top.core.b", "top.alu.c"] {
+        let name = writer.scope_path(path, "", FstScopeType::Module).unwrap();
+        ids.push(writer.var_builder(name).width(8).build().unwrap());
+    }
+    writer.up_scope_all().unwrap();
+    let mut writer = writer.finish().unwrap();
+    assert!(matches!(
+        writer.set_scope_enabled(1, false),
+        Err(FstWriteError::DeclarationsNotRetained)
+    ));
+    for id in ids.iter() {
+        writer.signal_change(*id, b"00000001").unwrap();
+    }
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(names, ["top.core.a", "top.core.b", "top.alu.c"]);
+
+    // opting in keeps everything around
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(retain)
+        .unwrap();
+    let core = writer.scope("core", "Core", FstScopeType::Module).unwrap();
+    writer.var_builder("a").width(8).build().unwrap();
+    writer.up_scope().unwrap();
+    writer.alias_scope("core2", "Core", core).unwrap();
+    assert_eq!((writer.scopes().len(), writer.vars().len()), (2, 2));
+}
+
+#[test]
+fn write_read_deferred_hierarchy() {
+    let filename = "tests/deferred_hierarchy.fst";
+    let config = FstWriterConfig {
+        deferred_hierarchy: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let a = writer.var_builder("a").build().unwrap();
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(a, b"0").unwrap();
+    writer.time_change(1).unwrap();
+    // discovered while writing value changes
+    writer.scope("probes", "", FstScopeType::Module).unwrap();
+    let b = writer
+        .var(
+            "b",
+            FstSignalType::bit_vec(4),
+            FstVarType::Wire,
+            FstVarDirection::Implicit,
+            None,
         )
         .unwrap();
+    writer.up_scope().unwrap();
+    writer.signal_change(b, b"1010").unwrap();
+    writer.signal_change(a, b"1").unwrap();
+    writer.time_change(2).unwrap();
+    writer.signal_change(b, b"0000").unwrap();
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(names, ["top.a", "probes.b"]);
+    let refs = h.iter_vars().map(|v| v.signal_ref()).collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    let time_table = wave.time_table().to_vec();
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(refs[0]).unwrap(), &time_table),
+        "(0: 0), (1: 1)"
+    );
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(refs[1]).unwrap(), &time_table),
+        "(0: xxxx), (1: 1010), (2: 0000)"
+    );
+}
+
+#[test]
+fn hierarchy_already_written() {
+    let filename = "tests/hierarchy_already_written.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap().finish().unwrap();
+    assert!(matches!(
+        writer.scope("late", "", FstScopeType::Module),
+        Err(FstWriteError::HierarchyAlreadyWritten)
+    ));
+}
+
+#[test]
+fn write_read_external_ids() {
+    let filename = "tests/external_ids.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let tpe = FstSignalType::bit_vec(1);
+    let (wire, dir) = (FstVarType::Wire, FstVarDirection::Implicit);
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let a = writer.var_with_external_id("a", 7, tpe, wire, dir).unwrap();
+    let b = writer.var_with_external_id("b", 3, tpe, wire, dir).unwrap();
+    // ids can be sparse
+    let c = writer
+        .var_with_external_id("c", usize::MAX, tpe, wire, dir)
+        .unwrap();
+    // same external id results in an alias
+    let a2 = writer
+        .var_with_external_id("a2", 7, tpe, wire, dir)
+        .unwrap();
+    writer.up_scope().unwrap();
+    assert_eq!(a, a2);
+    assert_ne!(a, b);
+    assert_eq!(writer.num_signals(), 3);
+    assert_eq!(writer.external_ids().get(&7), Some(&a));
+    assert_eq!(writer.external_ids().get(&3), Some(&b));
+    assert_eq!(writer.external_ids().get(&usize::MAX), Some(&c));
+    assert_eq!(writer.external_ids().get(&0), None);
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change_external(7, b"0").unwrap();
+    writer.signal_change_external(3, b"1").unwrap();
+    writer.signal_change_external(usize::MAX, b"1").unwrap();
+    assert!(matches!(
+        writer.signal_change_external(4, b"1"),
+        Err(FstWriteError::UnknownExternalId(4))
+    ));
+    writer.time_change(1).unwrap();
+    writer.signal_change_external(7, b"1").unwrap();
+    writer.time_change(2).unwrap();
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let refs = h.iter_vars().map(|v| v.signal_ref()).collect::<Vec<_>>();
+    assert_eq!(refs[0], refs[3]);
+    wave.load_signals(&refs);
+    let time_table = wave.time_table().to_vec();
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(refs[0]).unwrap(), &time_table),
+        "(0: 0), (1: 1)"
+    );
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(refs[1]).unwrap(), &time_table),
+        "(0: 1)"
+    );
+}
+
+#[test]
+fn write_read_signal_order_width() {
+    let filename = "tests/signal_order_width.fst";
+    let config = FstWriterConfig {
+        signal_order: FstSignalOrder::Width,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let b = writer.var_builder("b").build().unwrap();
+    let c = writer.var_builder("c").width(8).build().unwrap();
+    let d = writer.var_builder("d").build().unwrap();
+    let a2 = writer.var_builder("a2").width(8).alias(a).build().unwrap();
+    assert_eq!(a, a2);
+    assert_eq!(writer.num_signals(), 4);
+    assert_eq!(writer.signal_type(c), Some(FstSignalType::bit_vec(8)));
+    // pending vars are listed in declaration order with the ids that were returned
+    let declared = |writer: &FstHeaderWriter<_>| {
+        writer
+            .vars()
+            .iter()
+            .map(|v| (v.name.to_string(), v.signal_id))
+            .collect::<Vec<_>>()
+    };
+    let expected = [("a", a), ("b", b), ("c", c), ("d", d), ("a2", a)]
+        .map(|(name, id)| (name.to_string(), id))
+        .to_vec();
+    assert_eq!(declared(&writer), expected);
+    writer.up_scope().unwrap();
+    assert_eq!(declared(&writer), expected);
+    // aliases refer to the reordered signals
+    writer.alias_scope("top2", "Top", 0).unwrap();
+    let mut writer = writer.finish().unwrap();
+    let remap = writer.signal_remap().to_vec();
+    let [a, b, c, d] = [a, b, c, d].map(|id| remap[id.to_array_index()]);
+    writer.signal_change(a, b"00000001").unwrap();
+    writer.signal_change(b, b"0").unwrap();
+    writer.signal_change(c, b"00000011").unwrap();
+    writer.signal_change(d, b"1").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "top.b", "top.d", "top.a", "top.c", "top.a2", "top2.a", "top2.b", "top2.c", "top2.d",
+            "top2.a2"
+        ]
+    );
+    let refs = h.iter_vars().map(|v| v.signal_ref()).collect::<Vec<_>>();
+    assert_eq!(refs[2], refs[4]);
+    assert_eq!(refs[5..], [refs[2], refs[0], refs[3], refs[1], refs[2]]);
+    wave.load_signals(&refs);
+    let time_table = wave.time_table().to_vec();
+    let values = refs[..4]
+        .iter()
+        .map(|r| signal_values_to_string(wave.get_signal(*r).unwrap(), &time_table))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        ["(0: 0)", "(0: 1)", "(0: 00000001)", "(0: 00000011)"]
+    );
+}
+
+#[test]
+fn signal_order_invalid_alias() {
+    // an id that only exists in another writer
+    let mut other = open_fst_in_memory(&test_info()).unwrap();
+    other.var_builder("a").build().unwrap();
+    let unknown = other.var_builder("b").build().unwrap();
+
+    for order in [FstSignalOrder::Declaration, FstSignalOrder::Width] {
+        let config = FstWriterConfig {
+            signal_order: order,
+            ..Default::default()
+        };
+        let mut writer = open_fst_in_memory(&test_info())
+            .unwrap()
+            .with_config(config.clone())
+            .unwrap();
+        let a = writer.var_builder("a").build().unwrap();
+        let err = writer.var_builder("b").alias(unknown).build().unwrap_err();
+        assert_eq!(err.kind(), FstErrorKind::InvalidSignalId, "{order:?}");
+        writer.var_builder("a2").alias(a).build().unwrap();
+        // applying the same order again is fine, changing it is not
+        let writer = writer.with_config(config).unwrap();
+        let config = FstWriterConfig {
+            signal_order: match order {
+                FstSignalOrder::Declaration => FstSignalOrder::Width,
+                _ => FstSignalOrder::Declaration,
+            },
+            ..Default::default()
+        };
+        let err = writer.with_config(config).err().unwrap();
+        assert_eq!(err.kind(), FstErrorKind::ConfigAfterDeclarations);
+    }
+}
+
+#[test]
+fn write_read_alias_scope() {
+    let filename = "tests/alias_scope.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    writer.scope("inst0", "Core", FstScopeType::Module).unwrap();
+    let a = writer.var_builder("a").build().unwrap();
+    writer.scope("alu", "Alu", FstScopeType::Module).unwrap();
+    let b = writer.var_builder("b").width(4).build().unwrap();
+    writer.up_scope().unwrap();
+    writer.up_scope().unwrap();
+    writer.alias_scope("inst1", "Core", 1).unwrap();
+    writer.up_scope().unwrap();
+    assert_eq!(writer.num_signals(), 2);
+    // names are interned
+    let b_name = writer.intern("b");
+    let vars = writer.vars();
+    assert!(std::sync::Arc::ptr_eq(&vars[0].name, &vars[2].name));
+    assert!(std::sync::Arc::ptr_eq(&b_name, &vars[3].name));
+    let aliases = writer.vars()[2..]
+        .iter()
+        .map(|v| (writer.full_name(v), v.signal_id, v.is_alias))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        aliases,
+        [
+            ("top.inst1.a".to_string(), a, true),
+            ("top.inst1.alu.b".to_string(), b, true)
+        ]
+    );
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(a, b"1").unwrap();
+    writer.signal_change(b, b"1010").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let vars = h
+        .iter_vars()
+        .map(|v| (v.full_name(h), v.signal_ref()))
+        .collect::<Vec<_>>();
+    assert_eq!(vars.len(), 4);
+    assert_eq!(vars[2].0, "top.inst1.a");
+    assert_eq!(vars[0].1, vars[2].1);
+    assert_eq!(vars[3].0, "top.inst1.alu.b");
+    assert_eq!(vars[1].1, vars[3].1);
+}
+
+#[test]
+fn alias_scope_invalid_source() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let top = writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let inst0 = writer.scope("inst0", "Core", FstScopeType::Module).unwrap();
+    writer.var_builder("a").build().unwrap();
+    // a scope cannot contain a copy of itself
+    let err = writer.alias_scope("inst1", "Core", inst0).unwrap_err();
+    assert_eq!(err.kind(), FstErrorKind::ScopeStillOpen);
+    writer.up_scope().unwrap();
+    let err = writer.alias_scope("top2", "Top", top).unwrap_err();
+    assert_eq!(err.kind(), FstErrorKind::ScopeStillOpen);
+    let err = writer.alias_scope("inst1", "Core", 5).unwrap_err();
+    assert_eq!(err.kind(), FstErrorKind::InvalidScope);
+    // nothing was declared by the failed calls
+    assert_eq!(writer.scopes().len(), 2);
+    writer.alias_scope("inst1", "Core", inst0).unwrap();
+    writer.up_scope().unwrap();
+    writer.finish().unwrap().finish().unwrap();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn write_read_hierarchy_description() {
+    let filename = "tests/hierarchy_description.fst";
+    let json = r#"[
+        {"kind": "scope", "name": "top", "items": [
+            {"kind": "var", "name": "clk"},
+            {"kind": "scope", "name": "cpu", "tpe": "Interface", "items": [
+                {"kind": "var", "name": "pc", "width": 32, "tpe": "Reg", "dir": "Output"},
+                {"kind": "var", "name": "clk", "alias": "top.clk"}
+            ]}
+        ]}
+    ]"#;
+    let items: Vec<FstHierarchyItem> = serde_json::from_str(json).unwrap();
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let ids = writer.declare_hierarchy(&items).unwrap();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(ids["top.clk"], ids["top.cpu.clk"]);
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(ids["top.cpu.pc"], b"1").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let names = h.iter_vars().map(|v| v.full_name(h)).collect::<Vec<_>>();
+    assert_eq!(names, ["top.clk", "top.cpu.pc", "top.cpu.clk"]);
+    let pc = h.lookup_var(&["top", "cpu"], &"pc").unwrap();
+    assert_eq!(h.get(pc).length(), Some(32));
+}
+
+#[test]
+fn write_read_import_vcd_header() {
+    let filename = "tests/import_vcd_header.fst";
+    let vcd = std::fs::read("test-inputs/SPI_Write.vcd").unwrap();
+    let mut input = std::io::Cursor::new(vcd.as_slice());
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let ids = writer.import_vcd_header(&mut input).unwrap();
+    // the input is left right behind the declarations
+    let rest = &vcd[input.position() as usize..];
+    assert!(rest.trim_ascii_start().starts_with(b"$dumpvars"));
+    assert_eq!(writer.num_signals(), ids.len());
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(ids["-"], b"00001111").unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let addr = h.lookup_var(&["tb", "t"], &"AddrBus").unwrap();
+    let alias = h.lookup_var(&["tb", "t", "reg_mag_i"], &"AddrBus").unwrap();
+    assert_eq!(h.get(addr).signal_ref(), h.get(alias).signal_ref());
+    assert_eq!(h.get(addr).length(), Some(8));
+    let signal_ref = h.get(addr).signal_ref();
+    wave.load_signals(&[signal_ref]);
+    let time_table = wave.time_table().to_vec();
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(signal_ref).unwrap(), &time_table),
+        "(0: 00001111)"
+    );
+}
+
+#[test]
+fn write_read_strings() {
+    let filename = "tests/strings.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    writer.scope("top", "", FstScopeType::Module).unwrap();
+    let state = writer
+        .var_builder("state")
+        .width(0)
+        .typ(FstVarType::GenericString)
+        .build()
+        .unwrap();
+    let count = writer.var_builder("count").width(4).build().unwrap();
+    let mode = writer.var_builder("mode").width(2).build().unwrap();
+    writer.up_scope().unwrap();
+    let mut writer = writer
+        .with_config(FstWriterConfig {
+            auto_flush_size: Some(1),
+            ..Default::default()
+        })
+        .unwrap()
+        .finish()
+        .unwrap();
+    // strings are not part of the frame, but can still be set before the first time step
+    writer.signal_change(count, b"0000").unwrap();
+    writer.signal_change(state, b"idle").unwrap();
+    // readers skip the frame once the time table starts with time 0, so this value is only
+    // visible if it is recorded as a change as well
+    writer.signal_change(mode, b"01").unwrap();
+    for (time, value) in [(1, "busy"), (2, ""), (3, "with spaces")] {
+        writer.time_change(time).unwrap();
+        writer.signal_change(state, value.as_bytes()).unwrap();
+        writer
+            .signal_change(count, format!("{time:04b}").as_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let changes = changes_by_var(filename);
+    let changes = |name: &str| -> Vec<_> {
+        changes[name]
+            .iter()
+            .map(|(time, value)| format!("{value} at {time}"))
+            .collect()
+    };
+    assert_eq!(
+        changes("top.state"),
+        ["idle at 0", "busy at 1", " at 2", "with spaces at 3"]
+    );
+    assert_eq!(
+        changes("top.count"),
+        ["0000 at 0", "0001 at 1", "0010 at 2", "0011 at 3"]
+    );
+    assert_eq!(changes("top.mode"), ["01 at 0"]);
+}
+
+#[cfg(feature = "vcd")]
+#[test]
+fn convert_vcd_file() {
+    let filename = "tests/convert_vcd.fst";
+    let input = std::io::BufReader::new(std::fs::File::open("test-inputs/SPI_Write.vcd").unwrap());
+    vcd::vcd_to_fst(input, filename, FstWriterConfig::default()).unwrap();
+    assert_eq!(
+        changes_by_var(filename),
+        changes_by_var("test-inputs/SPI_Write.vcd")
+    );
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    assert_eq!(h.date(), "Thu Dec 17 17:19:03 2020");
+    assert_eq!(h.version(), "Aldec HDL Simulator Version 10.03.3558");
+    assert_eq!(h.timescale().unwrap().unit.to_exponent(), Some(-12));
+}
+
+#[cfg(feature = "wellen")]
+#[test]
+fn convert_wellen() {
+    let vcd = "tests/convert_wellen.vcd";
+    std::fs::write(
+        vcd,
+        "$timescale 10 ns $end
+$scope module top $end
+$var wire 4 ! data [3:0] $end
+$var wire 4 ! data_alias [3:0] $end
+$var real 64 \" r $end
+$var string 1 # s $end
+$var parameter 1 $ p $end
+$upscope $end
+$enddefinitions $end
+#0
+bx !
+r0 \"
+sidle #
+1$
+#5
+b101 !
+r-2.5 \"
+sbusy #
+#8
+#9
+bz1 !
+s #
+",
+    )
+    .unwrap();
+    let filename = "tests/convert_wellen.fst";
+    let mut wave = wellen::simple::read(vcd).unwrap();
+    let info = fst_writer::wellen::info(&wave);
+    assert_eq!(info.timescale_exponent, -8);
+    let mut out = open_fst(filename, &info).unwrap();
+    let signals = fst_writer::wellen::write_hierarchy(wave.hierarchy(), &mut out).unwrap();
+    assert_eq!(signals.len(), 4);
+    let mut out = out.finish().unwrap();
+    let refs: Vec<_> = signals.keys().copied().collect();
+    assert_eq!(
+        fst_writer::wellen::write_signals(&wave, &signals, &mut out)
+            .unwrap_err()
+            .kind(),
+        FstErrorKind::SignalNotLoaded
+    );
+    wave.load_signals(&refs);
+    fst_writer::wellen::write_signals(&wave, &signals, &mut out).unwrap();
+    out.finish().unwrap();
+    assert_eq!(changes_by_var(filename), changes_by_var(vcd));
+
+    let wave = wellen::simple::read(filename).unwrap();
+    let h = wave.hierarchy();
+    let types: Vec<_> = h.iter_vars().map(|var| var.var_type()).collect();
+    assert_eq!(
+        types,
+        [
+            wellen::VarType::Wire,
+            wellen::VarType::Wire,
+            wellen::VarType::Real,
+            wellen::VarType::String,
+            wellen::VarType::Parameter,
+        ]
+    );
+    assert_eq!(wave.time_table(), [0, 5, 8, 9]);
+}
+
+#[cfg(feature = "vcd")]
+#[test]
+fn convert_vcd_values() {
+    let vcd = "$date today $end
+$version test $end
+$timescale 10 ns $end
+$scope module top $end
+$var wire 8 ! data [7:0] $end
+$var real 64 \" r $end
+$var string 1 # s $end
+$var wire 1 $ clk $end
+$upscope $end
+$enddefinitions $end
+$comment #5 is not a time step $end
+#0
+$dumpvars
+bx !
+r0 \"
+sidle #
+0$
+$end
+#5
+b1 !
+r1.5 \"
+sbusy #
+1$
+#7
+bz0 !
+sidle #
+$dumpoff
+#9
+B11110000 !
+R-2.25 \"
+0$
+$dumpon
+";
+    std::fs::write("tests/convert_vcd_values.vcd", vcd).unwrap();
+    let filename = "tests/convert_vcd_values.fst";
+    let output = vcd::convert_vcd(vcd.as_bytes(), |info| {
+        let config = FstWriterConfig {
+            auto_flush_size: Some(1),
+            ..Default::default()
+        };
+        open_fst_in_memory(info)?.with_config(config)
+    })
+    .unwrap();
+    std::fs::write(filename, output.into_inner()).unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table(), [0, 5, 7, 9]);
+    let h = wave.hierarchy();
+    // wellen splits the exponent into a factor and a unit
+    let timescale = h.timescale().unwrap();
+    assert_eq!(
+        (timescale.factor, timescale.unit.to_exponent()),
+        (10, Some(-9))
+    );
+    let changes = changes_by_var(filename);
+    let expected: [(&str, &[(Time, &str)]); 4] = [
+        ("top.clk", &[(0, "0"), (5, "1"), (9, "0")]),
+        // shorter values are extended
+        (
+            "top.data",
+            &[
+                (0, "xxxxxxxx"),
+                (5, "00000001"),
+                (7, "zzzzzzz0"),
+                (9, "11110000"),
+            ],
+        ),
+        ("top.r", &[(0, "0"), (5, "1.5"), (9, "-2.25")]),
+        ("top.s", &[(0, "idle"), (5, "busy"), (7, "idle")]),
+    ];
+    for (name, expected) in expected {
+        let expected: Vec<_> = expected.iter().map(|(t, v)| (*t, v.to_string())).collect();
+        assert_eq!(changes[name], expected, "{name}");
+    }
+}
+
+#[test]
+fn write_read_repack_on_close() {
+    let filename = "tests/repack_on_close.fst";
+    let config = FstWriterConfig {
+        repack_on_close: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 1..1000u64 {
+        let value = format!("{:032b}", time % 7);
+        writer.signal_change(a, value.as_bytes()).unwrap();
+        writer.time_change(time).unwrap();
+    }
+    writer.finish().unwrap();
+
+    // the whole file is wrapped
+    let bytes = std::fs::read(filename).unwrap();
+    assert_eq!(bytes[0], 254);
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(wave.time_table().len(), 1000);
+    assert!(wave.get_signal(signal_ref).unwrap().iter_changes().count() > 900);
+}
+
+#[test]
+fn write_read_pack_types() {
+    for (pack_type, name) in [(FstPackType::Zlib, "zlib"), (FstPackType::FastLz, "fastlz")] {
+        let filename = format!("tests/pack_type_{name}.fst");
+        let config = FstWriterConfig {
+            pack_type,
+            ..Default::default()
+        };
+        let mut writer = open_fst(&filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        let a = writer.var_builder("a").width(16).build().unwrap();
+        let b = writer.var_builder("b").build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for time in 1..200u64 {
+            let value = format!("{:016b}", time % 5);
+            writer.signal_change(a, value.as_bytes()).unwrap();
+            writer
+                .signal_change(b, if time % 2 == 0 { b"0" } else { b"1" })
+                .unwrap();
+            writer.time_change(time).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut wave = wellen::simple::read(&filename).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        wave.load_signals(&refs);
+        let a = wave.get_signal(refs[0]).unwrap();
+        let values = a
+            .iter_changes()
+            .map(|(_, v)| v.to_bit_string().unwrap())
+            .collect::<Vec<_>>();
+        let expected = (1..200u64)
+            .map(|t| format!("{:016b}", t % 5))
+            .collect::<Vec<_>>();
+        assert_eq!(values, expected, "{name}");
+        assert_eq!(
+            wave.get_signal(refs[1]).unwrap().iter_changes().count(),
+            199
+        );
+    }
+}
+
+#[test]
+fn write_read_compressed_frame() {
+    let filename = "tests/compressed_frame.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let ids = (0..1000)
+        .map(|ii| {
+            writer
+                .var_builder(format!("s{ii}"))
+                .width(8)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut writer = writer.finish().unwrap();
+    for (ii, id) in ids.iter().enumerate() {
+        writer
+            .signal_change(*id, format!("{:08b}", ii % 3).as_bytes())
+            .unwrap();
+    }
+    writer.time_change(1).unwrap();
+    writer.signal_change(ids[7], b"11111111").unwrap();
+    writer.time_change(2).unwrap();
+    writer.finish().unwrap();
+    // the frame alone would take 8000 bytes uncompressed
+    assert!(std::fs::metadata(filename).unwrap().len() < 8000);
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    let time_table = wave.time_table().to_vec();
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(refs[7]).unwrap(), &time_table),
+        "(0: 00000001), (1: 11111111)"
+    );
+    assert_eq!(
+        signal_values_to_string(wave.get_signal(refs[998]).unwrap(), &time_table),
+        "(0: 00000010)"
+    );
+}
+
+#[test]
+fn write_read_from_writer() {
+    fn record<W: std::io::Write + std::io::Seek>(mut writer: FstHeaderWriter<W>) {
+        let a = writer.var_builder("a").width(4).build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for time in 0..50u64 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{:04b}", time % 16).as_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    }
+    let expected = "tests/from_writer_expected.fst";
+    record(open_fst(expected, &test_info()).unwrap());
+    let mut out = std::io::Cursor::new(vec![]);
+    record(FstHeaderWriter::from_writer(&mut out, &test_info()).unwrap());
+    assert_eq!(out.into_inner(), std::fs::read(expected).unwrap());
+}
+
+#[test]
+fn write_read_in_memory() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..50u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:04b}", time % 16).as_bytes())
+            .unwrap();
+    }
+    let bytes = writer.finish().unwrap().into_inner();
+
+    let mut wave = wellen::simple::read_from_reader(std::io::Cursor::new(bytes)).unwrap();
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(wave.time_table().len(), 50);
+    let signal = wave.get_signal(signal_ref).unwrap();
+    assert_eq!(signal.iter_changes().count(), 50);
+}
+
+#[test]
+fn write_read_stream() {
+    fn record<W: std::io::Write + std::io::Seek + Send + 'static>(
+        writer: FstHeaderWriter<W>,
+    ) -> FstBodyWriter<W> {
+        let config = FstWriterConfig {
+            auto_flush_size: Some(1024),
+            ..Default::default()
+        };
+        let mut writer = writer.with_config(config).unwrap();
+        let a = writer.var_builder("a").width(8).build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        writer.set_background_flush(true);
+        for time in 0..2000u64 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{:08b}", time % 251).as_bytes())
+                .unwrap();
+        }
+        writer
+    }
+    let expected = "tests/stream_expected.fst";
+    record(open_fst(expected, &test_info()).unwrap())
+        .finish()
+        .unwrap();
+    // a `Vec` cannot seek
+    let writer = record(open_fst_stream(vec![], &test_info(), 1999).unwrap());
+    let bytes = writer.finish().unwrap().into_inner();
+    let expected = std::fs::read(expected).unwrap();
+    assert_eq!(bytes.len(), expected.len());
+    assert!(count_value_change_blocks("tests/stream_expected.fst") > 1);
+
+    let load = |bytes: Vec<u8>| {
+        let mut wave = wellen::simple::read_from_reader(std::io::Cursor::new(bytes)).unwrap();
+        let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+        wave.load_signals(&[signal_ref]);
+        let signal = wave.get_signal(signal_ref).unwrap();
+        signal_values_to_string(signal, wave.time_table())
+    };
+    assert_eq!(load(bytes), load(expected));
+
+    let config = FstWriterConfig {
+        deferred_hierarchy: true,
+        ..Default::default()
+    };
+    let writer = open_fst_stream(vec![], &test_info(), u64::MAX).unwrap();
+    assert!(matches!(
+        writer.with_config(config).unwrap().finish(),
+        Err(FstWriteError::UnsupportedWhenStreaming(_))
+    ));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn write_read_async() {
+    let filename = "tests/async.fst";
+    let out = tokio::fs::File::create(filename).await.unwrap();
+    let config = FstWriterConfig {
+        auto_flush_size: Some(1024),
+        ..Default::default()
+    };
+    let mut writer = open_fst_async(&test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish_async(out).unwrap();
+    for time in 0..2000u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time % 251).as_bytes())
+            .unwrap();
+        if time % 500 == 499 {
+            writer.flush().await.unwrap();
+        }
+    }
+    writer.finish().await.unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(wave.time_table().len(), 2000);
+    let signal = wave.get_signal(signal_ref).unwrap();
+    assert_eq!(signal.iter_changes().count(), 2000);
+}
+
+#[test]
+fn buffered_block_info() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    // initial values are not value changes
+    writer.signal_change(a, b"0000").unwrap();
+    assert_eq!(writer.block_start_time(), None);
+    assert_eq!(writer.num_buffered_changes(), 0);
+    for time in 10..20u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:04b}", time / 2).as_bytes())
+            .unwrap();
+    }
+    assert_eq!(writer.current_time(), 19);
+    assert_eq!(writer.block_start_time(), Some(10));
+    // only every other time step changes the value
+    assert_eq!(writer.num_buffered_changes(), 5);
+    writer.flush().unwrap();
+    assert_eq!(writer.current_time(), 19);
+    assert_eq!(writer.block_start_time(), None);
+    assert_eq!(writer.num_buffered_changes(), 0);
+    writer.time_change(25).unwrap();
+    assert_eq!(writer.block_start_time(), Some(25));
+}
+
+#[test]
+fn bytes_written_after_flush() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let header = writer.bytes_written();
+    assert!(header > 0);
+    for time in 0..100u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time).as_bytes())
+            .unwrap();
+    }
+    // buffered changes are not written yet
+    assert_eq!(writer.bytes_written(), header);
+    writer.flush().unwrap();
+    let flushed = writer.bytes_written();
+    assert!(flushed > header);
+    let bytes = writer.finish().unwrap().into_inner();
+    assert!(bytes.len() as u64 >= flushed);
+}
+
+#[test]
+fn write_stats() {
+    for background in [false, true] {
+        let mut writer = open_fst_in_memory(&test_info()).unwrap();
+        let a = writer.var_builder("a").width(8).build().unwrap();
+        let b = writer.var_builder("b").build().unwrap();
+        let c = writer.var_builder("c").build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        writer.set_background_flush(background);
+        for time in 0..100u64 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{:08b}", time).as_bytes())
+                .unwrap();
+            writer
+                .signal_change(b, &[b'0' + (time / 10 % 2) as u8])
+                .unwrap();
+            writer.signal_change(c, b"0").unwrap();
+            if time == 49 {
+                writer.flush().unwrap();
+            }
+        }
+        // buffered changes are only counted once they were written, a block written in the
+        // background is picked up by the next time step after it is done
+        let num_written = writer.stats().blocks.len();
+        if background {
+            assert!(num_written <= 1);
+        } else {
+            assert_eq!(num_written, 1);
+        }
+        let (bytes, stats) = writer.finish_with_stats().unwrap();
+        let bytes = bytes.into_inner();
+        // the values at the first time step are initial values, not changes
+        assert_eq!(stats.signal_changes, [99, 9, 0]);
+        assert_eq!(stats.blocks.len(), 2);
+        assert_eq!(stats.blocks[1].end_time, 99);
+        assert!(stats.blocks.iter().all(|b| b.uncompressed_bytes > 0));
+        let total: u64 = stats.blocks.iter().map(|b| b.bytes).sum();
+        assert!(total < bytes.len() as u64);
+    }
+}
+
+/// Records the events that the writer reports.
+#[derive(Clone, Default)]
+struct RecordHooks(std::sync::Arc<std::sync::Mutex<Vec<(u64, u64, bool)>>>);
+
+impl FstWriterHooks for RecordHooks {
+    fn on_block_flushed(&mut self, block: &FstBlockStats) {
+        let mut events = self.0.lock().unwrap();
+        events.push((block.start_time, block.end_time, false));
+    }
+
+    fn on_finish(&mut self, stats: &FstWriterStats) {
+        let mut events = self.0.lock().unwrap();
+        assert_eq!(events.len(), stats.blocks.len());
+        events.push((0, 0, true));
+    }
+}
+
+#[test]
+fn writer_hooks() {
+    for background in [false, true] {
+        let mut writer = open_fst_in_memory(&test_info()).unwrap();
+        let a = writer.var_builder("a").width(8).build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        writer.set_background_flush(background);
+        let hooks = RecordHooks::default();
+        writer.set_hooks(hooks.clone());
+        for time in 0..30u64 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{:08b}", time).as_bytes())
+                .unwrap();
+            if time % 10 == 9 {
+                writer.flush().unwrap();
+            }
+        }
+        // with background flush, the last block is reported by the next flush
+        assert_eq!(hooks.0.lock().unwrap().len(), 3 - usize::from(background));
+        writer.finish().unwrap();
+        // like in the file, every block starts at the end time of the previous one
+        let events = hooks.0.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            [(0, 9, false), (9, 19, false), (19, 29, false), (0, 0, true)]
+        );
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn writer_metrics() {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    metrics::with_local_recorder(&recorder, || {
+        let mut writer = open_fst_in_memory(&test_info()).unwrap();
+        let a = writer.var_builder("a").width(8).build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for time in 0..30u64 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{:08b}", time).as_bytes())
+                .unwrap();
+            if time % 10 == 9 {
+                writer.flush().unwrap();
+            }
+        }
+        writer.finish().unwrap();
+    });
+    let values: std::collections::HashMap<_, _> = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+        .collect();
+    assert_eq!(
+        values["fst_writer_blocks_written_total"],
+        DebugValue::Counter(3)
+    );
+    // the value at the first time step is the initial value
+    assert_eq!(
+        values["fst_writer_value_changes_total"],
+        DebugValue::Counter(29)
+    );
+    // nothing is buffered once the writer is gone
+    assert_eq!(
+        values["fst_writer_buffered_bytes"],
+        DebugValue::Gauge(0.0.into())
+    );
+}
+
+#[test]
+fn header_memory_used() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let mut peak = 0;
+    for time in 0..300u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time % 256).as_bytes())
+            .unwrap();
+        peak = peak.max(writer.size() as u64);
+        if time == 199 {
+            writer.flush().unwrap();
+        }
+    }
+    let bytes = writer.finish().unwrap().into_inner();
+    let memory_used = u64::from_be_bytes(bytes[33..41].try_into().unwrap());
+    assert!(memory_used >= peak, "{memory_used} < {peak}");
+}
+
+#[test]
+fn size_breakdown() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let ids = (0..1000)
+        .map(|ii| {
+            writer
+                .var_builder(format!("s{ii}"))
+                .width(64)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut writer = writer.finish().unwrap();
+    // the current values and the frame of the block
+    let empty = writer.size_breakdown();
+    assert!(empty.frames >= 2 * 64 * 1000);
+    assert!(empty.fixed >= 1000);
+    assert_eq!(empty.value_changes, 0);
+    // scratch buffers are not part of the current block
+    assert!(writer.size() <= empty.total());
+
+    let value = [b'1'; 64];
+    for time in 0..10u64 {
+        writer.time_change(time).unwrap();
+        writer.signal_change(ids[time as usize], &value).unwrap();
+    }
+    let size = writer.size_breakdown();
+    assert!(size.value_changes > 0);
+    assert!(size.time_table > 0);
+    assert_eq!(size.frames, empty.frames);
+    assert!(writer.size() <= size.total());
+}
+
+#[test]
+fn abort_deletes_file() {
+    let filename = "tests/abort_header.fst";
+    let writer = open_fst(filename, &test_info()).unwrap();
+    writer.abort().unwrap();
+    assert!(!std::path::Path::new(filename).exists());
+
+    let filename = "tests/abort_body.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.set_background_flush(true);
+    for time in 0..100u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time).as_bytes())
+            .unwrap();
+    }
+    writer.flush().unwrap();
+    writer.abort().unwrap();
+    assert!(!std::path::Path::new(filename).exists());
+}
+
+#[test]
+fn write_read_finish_on_drop() {
+    let filename = "tests/finish_on_drop.fst";
+    let record = || -> Result<(), FstWriteError> {
+        let mut writer = open_fst(filename, &test_info())?;
+        let a = writer.var_builder("a").width(8).build()?;
+        let mut writer = writer.finish()?;
+        for time in 0..100u64 {
+            writer.time_change(time)?;
+            writer.signal_change(a, format!("{:08b}", time).as_bytes())?;
+        }
+        // an early return drops the writer without finishing it
+        Err(FstWriteError::WriterStopped)
+    };
+    assert!(record().is_err());
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(wave.time_table().len(), 100);
+    let signal = wave.get_signal(signal_ref).unwrap();
+    assert_eq!(signal.iter_changes().count(), 100);
+}
+
+#[test]
+fn into_inner_without_finishing() {
+    let writer = open_fst_in_memory(&test_info()).unwrap();
+    let header = writer.into_inner().into_inner();
+    assert!(!header.is_empty());
+
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.signal_change(a, b"0101").unwrap();
+    let unfinished = writer.into_inner().unwrap().into_inner();
+    // the hierarchy was written, but the value change was discarded
+    assert!(unfinished.len() > header.len());
+    let wave = wellen::simple::read_from_reader(std::io::Cursor::new(unfinished)).unwrap();
+    assert!(wave.time_table().is_empty());
+}
+
+#[test]
+fn write_read_sync() {
+    let filename = "tests/sync.fst";
+    let options = FstOpenOptions {
+        sync_on_flush: true,
+        sync_on_finish: true,
+        atomic: true,
+        ..Default::default()
+    };
+    let config = FstWriterConfig {
+        checksums: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst_with_options(filename, &test_info(), &options)
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..100u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time).as_bytes())
+            .unwrap();
+        if time == 49 {
+            writer.flush().unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    verify_fst_checksums(filename).unwrap();
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 100);
+}
+
+#[cfg(feature = "append")]
+#[test]
+fn write_read_append() {
+    let filename = "tests/append.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let b = writer.var_builder("b").build().unwrap();
+    let r = writer
+        .var_builder("r")
+        .signal_type(FstSignalType::real())
+        .typ(FstVarType::Real)
+        .build()
+        .unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..100u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time).as_bytes())
+            .unwrap();
+        if time == 0 {
+            writer.signal_change(b, b"1").unwrap();
+            writer.signal_change(r, &1.5f64.to_le_bytes()).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    // like a simulation that continues from a checkpoint
+    let config = FstWriterConfig {
+        auto_flush_interval: Some(50),
+        checksums: true,
+        ..Default::default()
+    };
+    let mut writer = append_fst(filename, config).unwrap();
+    assert_eq!(writer.signal_remap(), &[a, b, r]);
+    assert_eq!(writer.current_time(), 99);
+    assert!(matches!(
+        writer.time_change(98),
+        Err(FstWriteError::TimeDecrease(99, 98))
+    ));
+    for time in 100..200u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time).as_bytes())
+            .unwrap();
+        if time == 150 {
+            writer.signal_change(r, &2.5f64.to_le_bytes()).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    // the appended blocks follow the configuration
+    assert_eq!(count_value_change_blocks(filename), 3);
+    assert!(verify_fst_checksums(filename).unwrap().is_empty());
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 200);
+    assert_eq!(*wave.time_table().last().unwrap(), 199);
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    let a_changes = wave.get_signal(refs[0]).unwrap().iter_changes().count();
+    assert_eq!(a_changes, 200);
+    // the values of signals that did not change are carried over into the new block
+    let b_changes = wave
+        .get_signal(refs[1])
+        .unwrap()
+        .iter_changes()
+        .map(|(t, v)| (wave.time_table()[t as usize], v.to_bit_string().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(b_changes, [(0, "1".to_string())]);
+    let r_changes = wave
+        .get_signal(refs[2])
+        .unwrap()
+        .iter_changes()
+        .map(|(t, v)| match v {
+            wellen::SignalValue::Real(v) => (wave.time_table()[t as usize], v),
+            other => panic!("{other:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(r_changes, [(0, 1.5), (150, 2.5)]);
+}
+
+#[test]
+fn read_unfinished_file() {
+    let filename = "tests/unfinished.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..300u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time % 256).as_bytes())
+            .unwrap();
+        if time % 100 == 99 && time < 200 {
+            writer.flush().unwrap();
+        }
+    }
+    // like a simulation that is killed, the last changes never make it into the file
+    drop(writer.into_inner().unwrap());
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 200);
+    assert_eq!(*wave.time_table().last().unwrap(), 199);
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    let signal = wave.get_signal(signal_ref).unwrap();
+    assert_eq!(signal.iter_changes().count(), 200);
+}
+
+#[test]
+fn rollover_create_fails() {
+    let filename = "tests/rollover_fail.fst";
+    // a directory in the place of the next file makes creating it fail
+    let blocker = "tests/rollover_fail_0001.fst";
+    let _ = std::fs::remove_file(blocker);
+    let _ = std::fs::remove_dir(blocker);
+    std::fs::create_dir(blocker).unwrap();
+    let config = FstWriterConfig {
+        rollover_size: Some(100),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let write_block = |writer: &mut FstBodyWriter<_>, start: u64| {
+        for time in start..start + 10 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{time:032b}").as_bytes())
+                .unwrap();
+        }
+        writer.flush()
+    };
+    write_block(&mut writer, 0).unwrap();
+    // later calls keep failing instead of panicking
+    assert_eq!(
+        write_block(&mut writer, 10).unwrap_err().kind(),
+        FstErrorKind::Io
+    );
+    assert_eq!(writer.flush().unwrap_err().kind(), FstErrorKind::Io);
+    std::fs::remove_dir(blocker).unwrap();
+
+    // once the file can be created, the writer continues in it
+    write_block(&mut writer, 20).unwrap();
+    writer.finish().unwrap();
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table(), (0..10).collect::<Vec<_>>());
+    // the next file repeats the last time step of the previous one
+    let wave = wellen::simple::read(blocker).unwrap();
+    assert_eq!(wave.time_table(), (9..30).collect::<Vec<_>>());
+}
+
+#[test]
+fn write_read_rollover() {
+    let filename = "tests/rollover.fst";
+    for index in 1..10 {
+        let _ = std::fs::remove_file(format!("tests/rollover_{index:04}.fst"));
+    }
+    let config = FstWriterConfig {
+        auto_flush_interval: Some(100),
+        rollover_size: Some(2000),
+        checksums: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let b = writer.var_builder("b").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..1000u64 {
+        writer.time_change(time).unwrap();
+        let value = format!("{:032b}", time.wrapping_mul(0x9e37_79b9) as u32);
+        writer.signal_change(a, value.as_bytes()).unwrap();
+        if time == 0 {
+            writer.signal_change(b, b"1").unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let mut files = vec![filename.to_string()];
+    files.extend(
+        (1..10)
+            .map(|index| format!("tests/rollover_{index:04}.fst"))
+            .filter(|name| std::path::Path::new(name).exists()),
+    );
+    assert!(files.len() > 2, "{files:?}");
+    let mut times = vec![];
+    for name in &files {
+        assert!(verify_fst_checksums(name).unwrap().is_empty());
+        let mut wave = wellen::simple::read(name).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        wave.load_signals(&refs);
+        // every file starts with the current values, including the constant signal
+        let first = wave.time_table()[0];
+        assert_eq!(header_start_time(name), first, "{name}");
+        #[cfg(feature = "verify")]
+        {
+            let report = verify_fst(name).unwrap();
+            assert!(report.problems.is_empty(), "{name}: {:?}", report.problems);
+        }
+        let b_values = wave
+            .get_signal(refs[1])
+            .unwrap()
+            .iter_changes()
+            .map(|(t, v)| (wave.time_table()[t as usize], v.to_bit_string().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(b_values, [(first, "1".to_string())], "{name}");
+        // only the first time step of a file repeats the last one of the previous file
+        let skip = usize::from(!times.is_empty());
+        times.extend(wave.time_table()[skip..].iter().copied());
+    }
+    assert_eq!(times, (0..1000).collect::<Vec<_>>());
+}
+
+/// Start time in the header, which wellen ignores.
+fn header_start_time(filename: &str) -> u64 {
+    let bytes = std::fs::read(filename).unwrap();
+    // block type and section length come first
+    u64::from_be_bytes(bytes[9..17].try_into().unwrap())
+}
+
+#[test]
+fn rollover_dump_activity() {
+    let filename = "tests/rollover_dump.fst";
+    for index in 1..10 {
+        let _ = std::fs::remove_file(format!("tests/rollover_dump_{index:04}.fst"));
+    }
+    let config = FstWriterConfig {
+        auto_flush_interval: Some(100),
+        rollover_size: Some(1),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..300u64 {
+        writer.time_change(time).unwrap();
+        match time {
+            20 | 150 => writer.dump_off(),
+            40 => writer.dump_on(),
+            _ => {}
+        }
+        writer
+            .signal_change(a, if time % 2 == 0 { b"0" } else { b"1" })
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut files = vec![filename.to_string()];
+    files.extend(
+        (1..10)
+            .map(|index| format!("tests/rollover_dump_{index:04}.fst"))
+            .filter(|name| std::path::Path::new(name).exists()),
+    );
+    let starts = files
+        .iter()
+        .map(|f| header_start_time(f))
+        .collect::<Vec<_>>();
+    assert_eq!(starts, [0, 99, 199]);
+    // block type, section length, count, then activity and time delta for each entry
+    let blackouts: [&[u8]; 3] = [
+        &[2, 0, 0, 0, 0, 0, 0, 0, 13, 2, 0, 20, 1, 20],
+        // earlier regions are not repeated
+        &[2, 0, 0, 0, 0, 0, 0, 0, 12, 1, 0, 150, 1],
+        // dumping is still off when the last file starts
+        &[2, 0, 0, 0, 0, 0, 0, 0, 12, 1, 0, 199, 1],
+    ];
+    for (name, blackout) in files.iter().zip(blackouts) {
+        let bytes = std::fs::read(name).unwrap();
+        assert!(
+            bytes.windows(blackout.len()).any(|w| w == blackout),
+            "{name}"
+        );
+    }
+}
+
+#[cfg(feature = "verify")]
+#[test]
+fn verify_finished_file() {
+    let filename = "tests/verify.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    writer.scope("top", "", FstScopeType::Module).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    writer.var_builder("b").alias(a).build().unwrap();
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..300u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time % 256).as_bytes())
+            .unwrap();
+        if time % 100 == 99 {
+            writer.flush().unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let report = verify_fst(filename).unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.value_change_blocks, 3);
+    assert_eq!((report.scope_count, report.var_count), (1, 2));
+    assert_eq!(report.num_signals, 1);
+    assert_eq!((report.first_time, report.last_time), (Some(0), Some(299)));
+    assert_eq!(report.value_changes, 300);
+
+    // a header that does not match the blocks
+    let finished = std::fs::read(filename).unwrap();
+    let mut bytes = finished.clone();
+    bytes[65..73].copy_from_slice(&2u64.to_be_bytes());
+    std::fs::write(filename, &bytes).unwrap();
+    let report = verify_fst(filename).unwrap();
+    assert!(
+        report
+            .problems
+            .contains(&"header says 2 value change blocks, but the file has 3".to_string())
+    );
+
+    // a truncated file
+    std::fs::write(filename, &finished[..finished.len() - 10]).unwrap();
+    let report = verify_fst(filename).unwrap();
+    assert!(!report.is_ok());
+    assert!(report.problems[0].starts_with("incomplete or unknown block"));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn compare_equivalent_files() {
+    let write = |filename: &str, config: FstWriterConfig, glitch: bool| {
+        let mut writer = open_fst(filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        writer.scope("top", "", FstScopeType::Module).unwrap();
+        let a = writer.var_builder("a").width(8).build().unwrap();
+        let b = writer.var_builder("b").width(1).build().unwrap();
+        writer.up_scope().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for time in 0..500u64 {
+            writer.time_change(time).unwrap();
+            let value = if glitch && time == 321 { 0 } else { time % 256 };
+            writer
+                .signal_change(a, format!("{value:08b}").as_bytes())
+                .unwrap();
+            writer
+                .signal_change(b, &[b'0' + (time / 7 % 2) as u8])
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    };
+    write("tests/compare_a.fst", FstWriterConfig::default(), false);
+    // different blocks and compression, but the same waveform
+    let config = FstWriterConfig {
+        auto_flush_size: Some(200),
+        pack_type: FstPackType::Zlib,
+        ..Default::default()
+    };
+    write("tests/compare_b.fst", config, false);
+    testing::assert_fst_equivalent("tests/compare_a.fst", "tests/compare_b.fst");
+
+    write("tests/compare_b.fst", FstWriterConfig::default(), true);
+    let differences = testing::compare_fst("tests/compare_a.fst", "tests/compare_b.fst").unwrap();
+    assert_eq!(
+        differences,
+        ["signal top.a[321]: expected 01000001 at 321, found 00000000 at 321"]
+    );
+}
+
+/// Replays every log in `tests/corpus`, once with the default configuration and once with
+/// small blocks, which needs to result in the same waveform.
+#[cfg(feature = "testing")]
+#[test]
+fn replay_corpus() {
+    let mut logs: Vec<_> = std::fs::read_dir("tests/corpus")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+        .collect();
+    logs.sort();
+    assert!(!logs.is_empty());
+    for log in logs {
+        let name = log.file_stem().unwrap().to_str().unwrap();
+        let (default, small) = (
+            format!("tests/replay_{name}.fst"),
+            format!("tests/replay_{name}_small.fst"),
+        );
+        let read = || std::io::BufReader::new(std::fs::File::open(&log).unwrap());
+        testing::replay_fst(read(), &default, FstWriterConfig::default()).unwrap();
+        let config = FstWriterConfig {
+            auto_flush_size: Some(64),
+            ..Default::default()
+        };
+        testing::replay_fst(read(), &small, config).unwrap();
+        wellen::simple::read(&default).unwrap();
+        testing::assert_fst_equivalent(&default, &small);
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn record_and_replay() {
+    let filename = "tests/record.fst";
+    let writer = open_fst(filename, &test_info()).unwrap();
+    let mut writer = testing::FstRecordingWriter::new(writer, &test_info(), vec![]).unwrap();
+    writer
+        .call(testing::FstCall::Scope {
+            name: "top".to_string(),
+            component: "".to_string(),
+            tpe: FstScopeType::Module,
+        })
+        .unwrap();
+    let var = |name: &str, signal_tpe, alias| testing::FstCall::Var {
+        name: name.to_string(),
+        signal_tpe,
+        tpe: FstVarType::Wire,
+        dir: FstVarDirection::Implicit,
+        alias,
+    };
+    let a = writer
+        .call(var("a", FstSignalType::bit_vec(8), None))
+        .unwrap()
+        .unwrap();
+    let r = writer
+        .call(var("r", FstSignalType::real(), None))
+        .unwrap()
+        .unwrap();
+    writer
+        .call(var("a_alias", FstSignalType::bit_vec(8), Some(a)))
+        .unwrap();
+    writer.call(testing::FstCall::UpScope).unwrap();
+    writer.call(testing::FstCall::FinishHeader).unwrap();
+    for time in 0..100u64 {
+        writer.call(testing::FstCall::TimeChange(time)).unwrap();
+        let value = format!("{:08b}", time * 3 % 256).into_bytes();
+        writer
+            .call(testing::FstCall::SignalChange(a, value))
+            .unwrap();
+        let value = (time as f64 / 4.0).to_le_bytes().to_vec();
+        writer
+            .call(testing::FstCall::SignalChange(r, value))
+            .unwrap();
+        if time % 30 == 29 {
+            writer.call(testing::FstCall::Flush).unwrap();
+        }
+    }
+    // a var after the header was finished is rejected, but still recorded
+    assert!(
+        writer
+            .call(var("late", FstSignalType::bit_vec(1), None))
+            .is_err()
+    );
+    writer.call(testing::FstCall::Finish).unwrap();
+    let log = writer.into_log().unwrap();
+    assert!(log.starts_with(b"fst-replay 1\ninfo\t0\t0\t0\ttest\ttoday\n"));
+
+    let replayed = "tests/record_replayed.fst";
+    let err = testing::replay_fst(log.as_slice(), replayed, FstWriterConfig::default());
+    assert!(err.is_err(), "the rejected var is rejected again");
+    let log: Vec<u8> = String::from_utf8(log)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.ends_with("\tlate"))
+        .flat_map(|line| [line, "\n"])
+        .collect::<String>()
+        .into_bytes();
+    testing::replay_fst(log.as_slice(), replayed, FstWriterConfig::default()).unwrap();
+    assert_eq!(
+        without_memory_used(std::fs::read(filename).unwrap()),
+        without_memory_used(std::fs::read(replayed).unwrap())
+    );
+}
+
+#[test]
+fn recover_truncated_file() {
+    let filename = "tests/recover.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let mut block_ends = vec![];
+    for time in 0..300u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time % 256).as_bytes())
+            .unwrap();
+        if time % 100 == 99 {
+            writer.flush().unwrap();
+            block_ends.push(writer.bytes_written());
+        }
+    }
+    drop(writer.into_inner().unwrap());
+    // the simulation was killed while the last block was written
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(filename)
+        .unwrap();
+    file.set_len((block_ends[1] + block_ends[2]) / 2).unwrap();
+    drop(file);
+
+    let recovery = recover_fst(filename).unwrap();
+    assert_eq!(recovery.value_change_blocks, 2);
+    assert_eq!(recovery.end_time, 199);
+    assert_eq!(
+        recovery.truncated_bytes,
+        (block_ends[2] - block_ends[1]) / 2
+    );
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 200);
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(
+        wave.get_signal(signal_ref).unwrap().iter_changes().count(),
+        200
+    );
+
+    // the header is recomputed from the blocks, e.g., when the writer crashed before it was
+    // updated for the first time
+    let recovered = std::fs::read(filename).unwrap();
+    let mut bytes = recovered.clone();
+    bytes[17..25].fill(0);
+    bytes[41..73].fill(0);
+    std::fs::write(filename, &bytes).unwrap();
+    let again = recover_fst(filename).unwrap();
+    assert_eq!(again.truncated_bytes, 0);
+    assert_eq!(std::fs::read(filename).unwrap(), recovered);
+
+    // without a hierarchy, there is nothing to recover
+    std::fs::write(filename, &recovered[..330]).unwrap();
+    assert!(matches!(
+        recover_fst(filename),
+        Err(FstWriteError::InvalidFile(_))
+    ));
+
+    // a finished file is left unchanged, independent of how its hierarchy is compressed
+    for incremental in [false, true] {
+        let config = FstWriterConfig {
+            incremental_hierarchy_compression: incremental,
+            ..Default::default()
+        };
+        let mut writer = open_fst(filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        writer.scope("top", "", FstScopeType::Module).unwrap();
+        let a = writer.var_builder("a").width(8).build().unwrap();
+        writer.var_builder("b").alias(a).build().unwrap();
+        writer.source_stem("top.v", 3).unwrap();
+        writer.up_scope().unwrap();
+        let mut writer = writer.finish().unwrap();
+        writer.time_change(5).unwrap();
+        writer.signal_change(a, b"00000001").unwrap();
+        writer.finish().unwrap();
+        let finished = std::fs::read(filename).unwrap();
+        let recovery = recover_fst(filename).unwrap();
+        assert_eq!(recovery.end_time, 5);
+        assert_eq!(std::fs::read(filename).unwrap(), finished);
+    }
+}
+
+#[test]
+fn read_while_writing_live() {
+    let filename = "tests/live.fst";
+    let config = FstWriterConfig {
+        live: true,
+        auto_flush_interval: Some(100),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config.clone())
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    // the hierarchy can be read before any value change was written
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.hierarchy().iter_vars().count(), 1);
+    for time in 0..400u64 {
+        if time > 0 && time % 100 == 0 {
+            // a new block is started and the previous one becomes visible
+            writer.time_change(time).unwrap();
+            let wave = wellen::simple::read(filename).unwrap();
+            assert_eq!(*wave.time_table().last().unwrap(), time - 1);
+        }
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time % 256).as_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let deferred = FstWriterConfig {
+        deferred_hierarchy: true,
+        ..config
+    };
+    let writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(deferred)
+        .unwrap();
+    assert!(matches!(
+        writer.finish(),
+        Err(FstWriteError::IncompatibleConfig(
+            "live",
+            "deferred_hierarchy"
+        ))
+    ));
+}
+
+#[test]
+fn write_read_atomic() {
+    let filename = "tests/atomic.fst";
+    let tmp = "tests/atomic.fst.tmp";
+    let _ = std::fs::remove_file(filename);
+    let options = FstOpenOptions {
+        atomic: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst_with_options(filename, &test_info(), &options).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..100u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:08b}", time).as_bytes())
+            .unwrap();
+    }
+    writer.flush().unwrap();
+    assert!(!std::path::Path::new(filename).exists());
+    assert!(std::path::Path::new(tmp).exists());
+    writer.finish().unwrap();
+    assert!(!std::path::Path::new(tmp).exists());
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(wave.time_table().len(), 100);
+
+    // the finished file is protected, even though a temporary file is written
+    let options = FstOpenOptions {
+        atomic: true,
+        create_new: true,
+        ..Default::default()
+    };
+    assert!(matches!(
+        open_fst_with_options(filename, &test_info(), &options),
+        Err(FstWriteError::FileExists(_))
+    ));
+    assert!(!std::path::Path::new(tmp).exists());
+}
+
+#[test]
+fn write_read_open_options() {
+    let filename = "tests/open_options.fst";
+    let _ = std::fs::remove_file(filename);
+    let options = FstOpenOptions {
+        buffer_capacity: Some(1 << 20),
+        create_new: true,
+        mode: Some(0o640),
+        ..Default::default()
+    };
+    let write = || {
+        let mut writer = open_fst_with_options(filename, &test_info(), &options)?;
+        let a = writer.var_builder("a").width(8).build()?;
+        let mut writer = writer.finish()?;
+        for time in 0..100u64 {
+            writer.time_change(time)?;
+            writer.signal_change(a, format!("{:08b}", time).as_bytes())?;
+        }
+        writer.finish()
+    };
+    // the finished file is handed back
+    let file = write().unwrap().into_inner().unwrap();
+    file.sync_all().unwrap();
+    assert_eq!(
+        file.metadata().unwrap().len(),
+        std::fs::metadata(filename).unwrap().len()
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(filename).unwrap().permissions().mode();
+        // the umask can only remove permissions
+        assert_eq!(mode & 0o777 & !0o640, 0);
+    }
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let signal_ref = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[signal_ref]);
+    assert_eq!(wave.time_table().len(), 100);
+
+    // the existing file is not overwritten
+    let err = write().unwrap_err();
+    assert!(
+        matches!(&err, FstWriteError::FileExists(path) if path.as_path() == std::path::Path::new(filename)),
+        "{err:?}"
+    );
+}
+
+/// Clears the header field with the memory used by the writer, which follows the block type,
+/// section length, start time, end time and endian test.
+fn without_memory_used(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes[33..41].fill(0);
+    bytes
+}
+
+fn test_info() -> FstInfo {
+    FstInfo {
+        start_time: 0,
+        timescale_exponent: 0,
+        version: "test".to_string(),
+        date: "today".to_string(),
+        file_type: FstFileType::Verilog,
+    }
+}
+
+use std::fmt::Write;
+fn signal_values_to_string(signal: &wellen::Signal, time_table: &[Time]) -> String {
+    let mut out = String::new();
+    for (time, value) in signal.iter_changes() {
+        write!(
+            out,
+            "({}: {}), ",
+            time_table[time as usize],
+            value.to_bit_string().unwrap()
+        )
+        .unwrap();
+    }
+    out.pop().unwrap();
+    out.pop().unwrap();
+    out
+}
+
+#[test]
+fn write_read_compression_levels() {
+    let mut sizes = vec![];
+    for level in [0u8, 10] {
+        let filename = format!("tests/compression_level_{level}.fst");
+        let config = FstWriterConfig {
+            incremental_hierarchy_compression: true,
+            pack_type: FstPackType::Zlib,
+            compression: FstCompressionLevels {
+                hierarchy: level,
+                value_changes: level,
+                frame: level,
+                time_table: level,
+                repack: level,
+            },
+            ..Default::default()
+        };
+        let mut writer = open_fst(&filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        let ids = (0..100)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let mut writer = writer.finish().unwrap();
+        for time in 1..100u64 {
+            for (ii, id) in ids.iter().enumerate() {
+                let value = format!("{:08b}", (time + ii as u64) % 3);
+                writer.signal_change(*id, value.as_bytes()).unwrap();
+            }
+            writer.time_change(time).unwrap();
+        }
+        writer.finish().unwrap();
+        sizes.push(std::fs::metadata(&filename).unwrap().len());
+
+        let mut wave = wellen::simple::read(&filename).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(refs.len(), 100);
+        wave.load_signals(&refs);
+        let values = wave
+            .get_signal(refs[7])
+            .unwrap()
+            .iter_changes()
+            .map(|(_, v)| v.to_bit_string().unwrap())
+            .collect::<Vec<_>>();
+        let expected = (1..100u64)
+            .map(|t| format!("{:08b}", (t + 7) % 3))
+            .collect::<Vec<_>>();
+        assert_eq!(values, expected, "level {level}");
+    }
+    assert!(sizes[1] < sizes[0], "{sizes:?}");
+}
+
+#[test]
+fn write_read_idle_signals_across_blocks() {
+    let filename = "tests/idle_signals.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let ids = (0..6)
+        .map(|ii| {
+            writer
+                .var_builder(format!("s{ii}"))
+                .width(4)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut writer = writer.finish().unwrap();
+    for id in ids.iter() {
+        writer.signal_change(*id, b"0000").unwrap();
+    }
+    // every block only changes a few signals, all others are idle
+    for block in 0..3u64 {
+        for step in 1..=10u64 {
+            let time = block * 10 + step;
+            writer.time_change(time).unwrap();
+            let value = format!("{:04b}", step % 16);
+            writer
+                .signal_change(ids[block as usize * 2], value.as_bytes())
+                .unwrap();
+        }
+        writer.flush().unwrap();
+    }
+    writer.time_change(31).unwrap();
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    for (ii, r) in refs.iter().enumerate() {
+        let changes = wave
+            .get_signal(*r)
+            .unwrap()
+            .iter_changes()
+            .map(|(t, v)| (wave.time_table()[t as usize], v.to_bit_string().unwrap()))
+            .collect::<Vec<_>>();
+        let mut expected = vec![(0, "0000".to_string())];
+        if ii % 2 == 0 {
+            let block = ii as u64 / 2;
+            expected.extend((1..=10u64).map(|s| (block * 10 + s, format!("{:04b}", s % 16))));
+        }
+        assert_eq!(changes, expected, "s{ii}");
+    }
+}
+
+#[test]
+fn write_read_deduplicated_value_changes() {
+    let filename = "tests/deduplicated_value_changes.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let ids = (0..9)
+        .map(|ii| {
+            writer
+                .var_builder(format!("s{ii}"))
+                .width(8)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut writer = writer.finish().unwrap();
+    // signals in the same group have identical value changes, group 3 never changes
+    let groups = [0u64, 1, 0, 2, 0, 1, 3, 1, 0];
+    let value = |group: u64, time: u64| match group {
+        3 => "00000000".to_string(),
+        g => format!("{:08b}", (time * (g + 1)) % 256),
+    };
+    for block in 0..2u64 {
+        for step in 0..20u64 {
+            let time = block * 20 + step + 1;
+            writer.time_change(time).unwrap();
+            for (id, group) in ids.iter().zip(groups) {
+                writer
+                    .signal_change(*id, value(group, time).as_bytes())
+                    .unwrap();
+            }
+        }
+        writer.flush().unwrap();
+    }
+    writer.time_change(41).unwrap();
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    for (ii, (r, group)) in refs.iter().zip(groups).enumerate() {
+        let changes = wave
+            .get_signal(*r)
+            .unwrap()
+            .iter_changes()
+            .map(|(t, v)| (wave.time_table()[t as usize], v.to_bit_string().unwrap()))
+            .collect::<Vec<_>>();
+        let mut expected = vec![(0, "xxxxxxxx".to_string())];
+        if group != 3 {
+            expected.extend((1..=40u64).map(|t| (t, value(group, t))));
+        } else {
+            expected.push((1, value(group, 1)));
+        }
+        assert_eq!(changes, expected, "s{ii}");
+    }
+}
+
+#[test]
+fn write_read_blackout() {
+    let filename = "tests/blackout.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 1..100u64 {
+        writer.time_change(time).unwrap();
+        match time {
+            20 | 60 => writer.dump_off(),
+            // switching dumping off twice does not start a new region
+            30 => writer.dump_off(),
+            40 | 80 => writer.dump_on(),
+            _ => {}
+        }
+        writer
+            .signal_change(a, if time % 2 == 0 { b"0" } else { b"1" })
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    // the blackout block is parsed when opening the file
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    assert_eq!(wave.get_signal(a).unwrap().iter_changes().count(), 100);
+    let bytes = std::fs::read(filename).unwrap();
+    // block type, section length, count, then activity and time delta for each entry
+    let blackout = [2u8, 0, 0, 0, 0, 0, 0, 0, 17, 4, 0, 20, 1, 20, 0, 20, 1, 20];
+    assert!(bytes.windows(blackout.len()).any(|w| w == blackout));
+}
+
+#[test]
+fn write_read_signal_enabled() {
+    let filename = "tests/signal_enabled.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").build().unwrap();
+    let b = writer.var_builder("b").width(4).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 1..=10u64 {
+        writer.time_change(time).unwrap();
+        match time {
+            4 => writer.set_signal_enabled(b, false).unwrap(),
+            8 => writer.set_signal_enabled(b, true).unwrap(),
+            _ => {}
+        }
+        let value = format!("{:04b}", time);
+        writer.signal_change(a, &value.as_bytes()[3..]).unwrap();
+        writer.signal_change(b, value.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    let times = |r: SignalRef| {
+        wave.get_signal(r)
+            .unwrap()
+            .iter_changes()
+            .map(|(t, _)| wave.time_table()[t as usize])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(times(refs[0]), (0..=10).collect::<Vec<_>>());
+    assert_eq!(times(refs[1]), [0, 1, 2, 3, 8, 9, 10]);
+}
+
+#[test]
+fn write_read_scope_enabled() {
+    for deferred_hierarchy in [false, true] {
+        let filename = format!("tests/scope_enabled_{deferred_hierarchy}.fst");
+        let config = FstWriterConfig {
+            deferred_hierarchy,
+            ..Default::default()
+        };
+        let mut writer = open_fst(&filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        let top = writer.scope("top", "Top", FstScopeType::Module).unwrap();
+        let a = writer.var_builder("a").build().unwrap();
+        let core = writer.scope("core", "Core", FstScopeType::Module).unwrap();
+        let b = writer.var_builder("b").build().unwrap();
+        let alu = writer.scope("alu", "Alu", FstScopeType::Module).unwrap();
+        let c = writer.var_builder("c").build().unwrap();
+        writer.up_scope().unwrap();
+        writer.up_scope().unwrap();
+        let d = writer.var_builder("d").build().unwrap();
+        writer.up_scope().unwrap();
+        assert_eq!((top, core, alu), (0, 1, 2));
+        let mut writer = writer.finish().unwrap();
+        assert!(matches!(
+            writer.set_scope_enabled(3, false),
+            Err(FstWriteError::InvalidScope(3))
+        ));
+        for time in 1..=10u64 {
+            writer.time_change(time).unwrap();
+            match time {
+                3 => writer.set_scope_enabled(core, false).unwrap(),
+                6 => writer.set_scope_enabled(alu, true).unwrap(),
+                _ => {}
+            }
+            let value = if time % 2 == 0 { b"0" } else { b"1" };
+            for id in [a, b, c, d] {
+                writer.signal_change(id, value).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+
+        let mut wave = wellen::simple::read(&filename).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        wave.load_signals(&refs);
+        let times = |r: SignalRef| {
+            wave.get_signal(r)
+                .unwrap()
+                .iter_changes()
+                .map(|(t, _)| wave.time_table()[t as usize])
+                .collect::<Vec<_>>()
+        };
+        let all = (0..=10).collect::<Vec<_>>();
+        // vars are listed in declaration order: a, b, c, d
+        assert_eq!(times(refs[0]), all);
+        assert_eq!(times(refs[1]), [0, 1, 2]);
+        // c was 0 before it was disabled and at time 6, thus the first change is at 7
+        assert_eq!(times(refs[2]), [0, 1, 2, 7, 8, 9, 10]);
+        assert_eq!(times(refs[3]), all);
+    }
+}
+
+#[test]
+fn write_read_dump_size_limit() {
+    let filename = "tests/dump_size_limit.fst";
+    let limit = 4000;
+    let config = FstWriterConfig {
+        dump_size_limit: Some(limit),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 1..10_000u64 {
+        writer.time_change(time).unwrap();
+        let value = format!("{:032b}", time * 7919);
+        writer.signal_change(a, value.as_bytes()).unwrap();
+        if time % 100 == 0 {
+            writer.flush().unwrap();
+        }
+    }
+    assert!(writer.dump_size_limit_reached());
+    writer.finish().unwrap();
+    assert!(std::fs::metadata(filename).unwrap().len() < 2 * limit);
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let end = *wave.time_table().last().unwrap();
+    assert!(end > 1 && end < 1000, "{end}");
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    let (last_idx, last_value) = wave.get_signal(a).unwrap().iter_changes().last().unwrap();
+    assert_eq!(wave.time_table()[last_idx as usize], end);
+    assert_eq!(
+        last_value.to_bit_string().unwrap(),
+        format!("{:032b}", end * 7919)
+    );
+}
+
+#[test]
+fn write_read_record_until() {
+    let filename = "tests/record_until.fst";
+    let config = FstWriterConfig {
+        record_until: Some(50),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in (1..200u64).step_by(3) {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, if time % 2 == 0 { b"0" } else { b"1" })
+            .unwrap();
+        if time % 30 == 0 {
+            writer.flush().unwrap();
+        }
+    }
+    assert!(!writer.dump_size_limit_reached());
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(*wave.time_table().last().unwrap(), 49);
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    let times = wave
+        .get_signal(a)
+        .unwrap()
+        .iter_changes()
+        .map(|(t, _)| wave.time_table()[t as usize])
+        .collect::<Vec<_>>();
+    let expected = std::iter::once(0)
+        .chain((1..=49).step_by(3))
+        .collect::<Vec<_>>();
+    assert_eq!(times, expected);
+}
+
+#[test]
+fn write_read_signal_decimation() {
+    let filename = "tests/signal_decimation.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let ids = (0..3)
+        .map(|ii| {
+            writer
+                .var_builder(format!("s{ii}"))
+                .width(8)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut writer = writer.finish().unwrap();
+    writer
+        .set_signal_decimation(ids[0], Some(FstDecimation::EveryNth(3)))
+        .unwrap();
+    writer
+        .set_signal_decimation(ids[1], Some(FstDecimation::MinTimeDelta(5)))
+        .unwrap();
+    writer
+        .set_signal_decimation(ids[2], Some(FstDecimation::EveryNth(2)))
+        .unwrap();
+    for time in 1..=20u64 {
+        writer.time_change(time).unwrap();
+        if time == 10 {
+            writer.set_signal_decimation(ids[2], None).unwrap();
+        }
+        for id in ids.iter() {
+            let value = format!("{time:08b}");
+            writer.signal_change(*id, value.as_bytes()).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    let times = |r: SignalRef| {
+        wave.get_signal(r)
+            .unwrap()
+            .iter_changes()
+            .map(|(t, _)| wave.time_table()[t as usize])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(times(refs[0]), [0, 1, 4, 7, 10, 13, 16, 19]);
+    assert_eq!(times(refs[1]), [0, 1, 6, 11, 16]);
+    let s2 = [0, 1, 3, 5, 7, 9].into_iter().chain(10..=20);
+    assert_eq!(times(refs[2]), s2.collect::<Vec<_>>());
+}
+
+#[test]
+fn write_read_clock() {
+    let filename = "tests/clock.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let clocks = (0..4)
+        .map(|ii| writer.var_builder(format!("clk{ii}")).build().unwrap())
+        .collect::<Vec<_>>();
+    let mut writer = writer.finish().unwrap();
+    for time in 1..100_000u64 {
+        writer.time_change(time).unwrap();
+        for clk in clocks.iter() {
+            writer
+                .signal_change(*clk, if time % 2 == 0 { b"0" } else { b"1" })
+                .unwrap();
+        }
+        if time % 25_000 == 0 {
+            writer.flush().unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    // toggles compress to almost nothing and identical clocks share their data
+    assert!(std::fs::metadata(filename).unwrap().len() < 4000);
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    for r in refs {
+        let signal = wave.get_signal(r).unwrap();
+        assert_eq!(signal.iter_changes().count(), 100_000);
+    }
+}
+
+#[test]
+fn write_read_constant_signals() {
+    let filename = "tests/constant_signals.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let params = (0..10_000)
+        .map(|ii| {
+            writer
+                .var_builder(format!("p{ii}"))
+                .width(16)
+                .typ(FstVarType::Parameter)
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for (ii, p) in params.iter().enumerate() {
+        writer
+            .signal_change(*p, format!("{ii:016b}").as_bytes())
+            .unwrap();
+    }
+    for time in 1..=100u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:08b}").as_bytes())
+            .unwrap();
+        if time % 10 == 0 {
+            writer.flush().unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&[refs[1234], refs[10_000]]);
+    let p = wave.get_signal(refs[1234]).unwrap();
+    let p_values = p
+        .iter_changes()
+        .map(|(_, v)| v.to_bit_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(p_values, [format!("{:016b}", 1234)]);
+    assert_eq!(
+        wave.get_signal(refs[10_000])
+            .unwrap()
+            .iter_changes()
+            .count(),
+        101
+    );
+}
+
+#[test]
+fn write_read_reserve() {
+    use std::io::{Seek, SeekFrom, Write};
+    let filename = "tests/reserve.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let first = writer.reserve(64).unwrap();
+    let mut second = 0;
+    for time in 1..=20u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:08b}").as_bytes())
+            .unwrap();
+        if time == 10 {
+            writer.flush().unwrap();
+            second = writer.reserve(1000).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    // fill the reserved regions in place
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(filename)
+        .unwrap();
+    for (start, len) in [(first, 64), (second, 1000)] {
+        file.seek(SeekFrom::Start(start)).unwrap();
+        file.write_all(&vec![0xab; len]).unwrap();
+    }
+    drop(file);
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 21);
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    let values = wave
+        .get_signal(a)
+        .unwrap()
+        .iter_changes()
+        .skip(1)
+        .map(|(_, v)| v.to_bit_string().unwrap())
+        .collect::<Vec<_>>();
+    let expected = (1..=20u64).map(|t| format!("{t:08b}")).collect::<Vec<_>>();
+    assert_eq!(values, expected);
+}
+
+#[test]
+fn write_read_checksums() {
+    let filename = "tests/checksums.fst";
+    let config = FstWriterConfig {
+        checksums: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let mut block_start = 0;
+    for time in 1..=20u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:08b}").as_bytes())
+            .unwrap();
+        if time == 10 {
+            writer.flush().unwrap();
+            // the reserved region is followed by the second value change block
+            block_start = writer.reserve(0).unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    // the checksum block does not bother other readers
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 21);
+    assert_eq!(verify_fst_checksums(filename).unwrap(), Vec::<u64>::new());
+
+    // flip a bit in the second value change block
+    let mut bytes = std::fs::read(filename).unwrap();
+    bytes[block_start as usize + 20] ^= 1;
+    std::fs::write(filename, &bytes).unwrap();
+    assert_eq!(verify_fst_checksums(filename).unwrap(), [block_start]);
+
+    // a corrupted number of checksums
+    let magic = bytes.windows(8).position(|w| w == b"FSTCRC32").unwrap();
+    bytes[magic + 8..magic + 16].fill(0xff);
+    std::fs::write(filename, &bytes).unwrap();
+    assert!(matches!(
+        verify_fst_checksums(filename),
+        Err(FstWriteError::InvalidChecksums(_))
+    ));
+
+    // files without checksums cannot be verified
+    let filename = "tests/checksums_none.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    writer.var_builder("a").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.time_change(1).unwrap();
+    writer.finish().unwrap();
+    assert!(matches!(
+        verify_fst_checksums(filename),
+        Err(FstWriteError::InvalidChecksums(_))
+    ));
+}
+
+#[cfg(feature = "append")]
+#[test]
+fn append_checksums() {
+    let filename = "tests/append_checksums.fst";
+    let config = FstWriterConfig {
+        checksums: true,
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..10u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:08b}").as_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut writer = append_fst(filename, FstWriterConfig::default()).unwrap();
+    for time in 10..20u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:08b}").as_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+    // the appended blocks are covered by new checksums
+    assert_eq!(verify_fst_checksums(filename).unwrap(), Vec::<u64>::new());
+    assert_eq!(count_value_change_blocks(filename), 2);
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table(), (0..20).collect::<Vec<_>>());
+}
+
+#[test]
+fn write_read_spill() {
+    let write = |filename: &str, spill_threshold: Option<usize>, compress_buffer: bool| {
+        let config = FstWriterConfig {
+            spill_threshold,
+            compress_buffer,
+            ..Default::default()
+        };
+        let mut writer = open_fst(filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        let ids = (0..4)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let mut writer = writer.finish().unwrap();
+        for time in 0..100u64 {
+            writer.time_change(time).unwrap();
+            for (ii, id) in ids.iter().enumerate() {
+                // signals change at different rates
+                let value = format!("{:08b}", (time / (ii as u64 + 1)) % 256);
+                writer.signal_change(*id, value.as_bytes()).unwrap();
+            }
+            if time == 50 {
+                writer.flush().unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        let mut wave = wellen::simple::read(filename).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        wave.load_signals(&refs);
+        refs.iter()
+            .map(|r| signal_values_to_string(wave.get_signal(*r).unwrap(), wave.time_table()))
+            .collect::<Vec<_>>()
+    };
+    let expected = write("tests/spill_none.fst", None, false);
+    assert_eq!(write("tests/spill.fst", Some(16), false), expected);
+    assert_eq!(
+        write("tests/spill_compressed.fst", Some(16), true),
+        expected
+    );
+    assert_eq!(write("tests/compress_buffer.fst", None, true), expected);
+}
+
+/// Counts the value change blocks by walking over all blocks of the file.
+fn count_value_change_blocks(filename: &str) -> usize {
+    let bytes = std::fs::read(filename).unwrap();
+    let mut pos = 0;
+    let mut count = 0;
+    while pos < bytes.len() {
+        let block_type = bytes[pos];
+        let len = u64::from_be_bytes(bytes[pos + 1..pos + 9].try_into().unwrap());
+        if matches!(block_type, 1 | 5 | 8) {
+            count += 1;
+        }
+        pos += 1 + len as usize;
+    }
+    count
+}
+
+#[test]
+fn write_read_auto_flush_size() {
+    let filename = "tests/auto_flush_size.fst";
+    let config = FstWriterConfig {
+        auto_flush_size: Some(1000),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..1000u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:032b}").as_bytes())
+            .unwrap();
+        // the size of the buffer is bounded
+        assert!(writer.size() < 2000);
+    }
+    writer.finish().unwrap();
+
+    assert!(count_value_change_blocks(filename) > 5);
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 1000);
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    let values = wave
+        .get_signal(a)
+        .unwrap()
+        .iter_changes()
+        .map(|(_, v)| v.to_bit_string().unwrap())
+        .collect::<Vec<_>>();
+    let expected = (0..1000u64)
+        .map(|t| format!("{t:032b}"))
+        .collect::<Vec<_>>();
+    assert_eq!(values, expected);
+}
+
+#[test]
+fn write_read_memory_limit() {
+    let filename = "tests/memory_limit.fst";
+    let config = FstWriterConfig {
+        memory_limit: Some(20_000),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    // blocks are written before the limit is exceeded
+    for time in 0..10_000u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:032b}").as_bytes())
+            .unwrap();
+        assert!(writer.size() <= 20_000);
+    }
+
+    // a single time step cannot be split into several blocks
+    writer.time_change(10_000).unwrap();
+    let result = (0..10_000u64)
+        .try_for_each(|ii| writer.signal_change(a, format!("{:032b}", ii % 2).as_bytes()));
+    assert!(matches!(
+        result,
+        Err(FstWriteError::MemoryLimitExceeded { limit: 20_000, required }) if required > 20_000
+    ));
+    assert!(writer.size() <= 20_000);
+    // the next time step starts a new block
+    writer.time_change(10_001).unwrap();
+    writer.signal_change(a, &[b'1'; 32]).unwrap();
+    writer.finish().unwrap();
+    assert!(count_value_change_blocks(filename) > 5);
+
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 10_002);
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    let signal = wave.get_signal(a).unwrap();
+    let (_, last) = signal.iter_changes().last().unwrap();
+    assert_eq!(last.to_bit_string().unwrap(), "1".repeat(32));
+}
+
+/// Accepts a limited number of bytes, like a disk that is about to be full.
+struct FullDisk(usize);
+
+impl std::io::Write for FullDisk {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.0 == 0 {
+            return Err(std::io::ErrorKind::StorageFull.into());
+        }
+        let len = buf.len().min(self.0);
+        self.0 -= len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn errors_with_context() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    writer.scope("top", "", FstScopeType::Module).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    writer.up_scope().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.time_change(7).unwrap();
+    let err = writer.signal_change(a, b"10101").unwrap_err();
+    assert!(matches!(
+        err.root(),
+        FstWriteError::InvalidValueLength { len: 4, .. }
+    ));
+    let context = err.context().unwrap();
+    assert_eq!(context.signal, Some(a));
+    assert_eq!(context.signal_name.as_deref(), Some("top.a"));
+    assert_eq!(context.time, Some(7));
+    assert!(err.to_string().contains("signal 1 (top.a), time 7"));
+
+    // the writer says which block it failed to write
+    let mut writer = open_fst_stream(FullDisk(2000), &test_info(), u64::MAX).unwrap();
+    let a = writer.var_builder("a").width(32).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..1000u64 {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{:032b}", time * 7919).as_bytes())
+            .unwrap();
+    }
+    let err = writer.flush().unwrap_err();
+    assert!(matches!(err.root(), FstWriteError::Io(_)));
+    let context = err.context().unwrap();
+    assert_eq!(context.phase, "writing a value change block");
+    assert_eq!(context.block, Some(0));
+    assert_eq!(context.time, Some(999));
+    assert!(context.offset.is_some());
+}
+
+#[test]
+fn error_codes() {
+    let mut writer = open_fst_in_memory(&test_info()).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    writer.time_change(7).unwrap();
+    let err = writer.time_change(6).unwrap_err();
+    assert_eq!(err.kind(), FstErrorKind::TimeDecrease);
+    assert_eq!(err.code(), 4);
+    // context does not change the code
+    let err = writer.signal_change(a, b"10101").unwrap_err();
+    assert!(err.context().is_some());
+    assert_eq!(err.kind(), FstErrorKind::InvalidValueLength);
+    assert_eq!(err.code(), 21);
+    let err = FstWriteError::from(std::io::Error::other("test"));
+    assert_eq!(err.code(), 1);
+}
+
+#[test]
+fn write_read_auto_flush_interval() {
+    let filename = "tests/auto_flush_interval.fst";
+    let config = FstWriterConfig {
+        auto_flush_interval: Some(10),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    for time in (0..100u64).step_by(3) {
+        writer.time_change(time).unwrap();
+        writer
+            .signal_change(a, format!("{time:08b}").as_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    assert_eq!(count_value_change_blocks(filename), 10);
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let expected_times = (0..100u64).step_by(3).collect::<Vec<_>>();
+    assert_eq!(wave.time_table(), expected_times);
+    let a = wave.hierarchy().iter_vars().next().unwrap().signal_ref();
+    wave.load_signals(&[a]);
+    assert_eq!(
+        wave.get_signal(a).unwrap().iter_changes().count(),
+        expected_times.len()
+    );
+}
+
+#[test]
+fn write_read_background_flush() {
+    let write = |filename: &str, background: bool| {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let ids = (0..8)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(16)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let mut writer = writer.finish().unwrap();
+        writer.set_background_flush(background);
+        for time in 0..2000u64 {
+            writer.time_change(time).unwrap();
+            for (ii, id) in ids.iter().enumerate() {
+                let value = format!("{:016b}", (time * (ii as u64 + 1)) % 1000);
+                writer.signal_change(*id, value.as_bytes()).unwrap();
+            }
+            if time % 300 == 299 {
+                writer.flush().unwrap();
+            }
+            if time == 1000 {
+                // waits for the background thread
+                writer.reserve(16).unwrap();
+            }
+        }
+        writer.finish().unwrap();
+        let mut wave = wellen::simple::read(filename).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        wave.load_signals(&refs);
+        refs.iter()
+            .map(|r| signal_values_to_string(wave.get_signal(*r).unwrap(), wave.time_table()))
+            .collect::<Vec<_>>()
+    };
+    let expected = write("tests/background_flush_none.fst", false);
+    assert_eq!(write("tests/background_flush.fst", true), expected);
+    assert_eq!(
+        std::fs::read("tests/background_flush.fst").unwrap(),
+        std::fs::read("tests/background_flush_none.fst").unwrap()
+    );
+}
+
+#[test]
+fn write_read_shards() {
+    let value = |time: u64, ii: usize| format!("{:08b}", (time / (ii as u64 + 1)) % 256);
+    let declare = |filename: &str| {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let ids = (0..7)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        (writer.finish().unwrap(), ids)
+    };
+
+    // reference written by a single thread
+    let (mut writer, ids) = declare("tests/shards_none.fst");
+    for time in 0..200u64 {
+        writer.time_change(time).unwrap();
+        for (ii, id) in ids.iter().enumerate() {
+            writer
+                .signal_change(*id, value(time, ii).as_bytes())
+                .unwrap();
+        }
+        if time == 99 {
+            writer.flush().unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let (mut writer, ids) = declare("tests/shards.fst");
+    let mut shards = writer.shards(3).unwrap();
+    assert!(shards[0].contains(ids[0]) && !shards[0].contains(ids[3]));
+    assert!(matches!(
+        shards[0].signal_change(ids[6], b"00000000"),
+        Err(FstWriteError::InvalidSignalId(_))
+    ));
+    for times in [0..100u64, 100..200] {
+        std::thread::scope(|scope| {
+            for shard in shards.iter_mut() {
+                let (ids, times) = (&ids, times.clone());
+                scope.spawn(move || {
+                    for time in times {
+                        shard.time_change(time).unwrap();
+                        for (ii, id) in ids.iter().enumerate() {
+                            if shard.contains(*id) {
+                                shard
+                                    .signal_change(*id, value(time, ii).as_bytes())
+                                    .unwrap();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        writer.flush_shards(&mut shards).unwrap();
+    }
+    writer.finish().unwrap();
+    // only the memory used by the writers differs
+    assert_eq!(
+        without_memory_used(std::fs::read("tests/shards.fst").unwrap()),
+        without_memory_used(std::fs::read("tests/shards_none.fst").unwrap())
+    );
+
+    // shards that disagree on the time steps cannot be merged
+    let (mut writer, _) = declare("tests/shards_inconsistent.fst");
+    let mut shards = writer.shards(2).unwrap();
+    shards[0].time_change(1).unwrap();
+    shards[1].time_change(2).unwrap();
+    assert!(matches!(
+        writer.flush_shards(&mut shards),
+        Err(FstWriteError::InconsistentShards)
+    ));
+}
+
+#[test]
+fn write_read_signal_sender() {
+    let value = |time: u64, ii: usize| format!("{:08b}", (time * (ii as u64 + 3)) % 256);
+    let declare = |filename: &str| {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let ids = (0..4)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        (writer.finish().unwrap(), ids)
+    };
+
+    let (mut writer, ids) = declare("tests/signal_sender_none.fst");
+    for time in 0..500u64 {
+        writer.time_change(time).unwrap();
+        for (ii, id) in ids.iter().enumerate() {
+            writer
+                .signal_change(*id, value(time, ii).as_bytes())
+                .unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let (writer, ids) = declare("tests/signal_sender.fst");
+    let (sender, handle) = writer.into_sender();
+    let threads = ids
+        .iter()
+        .enumerate()
+        .map(|(ii, &id)| {
+            let mut sender = sender.clone();
+            std::thread::spawn(move || {
+                for time in 0..500u64 {
+                    sender.time_change(time).unwrap();
+                    sender
+                        .signal_change(id, value(time, ii).as_bytes())
+                        .unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    handle.join().unwrap().unwrap();
+    assert_eq!(
+        std::fs::read("tests/signal_sender.fst").unwrap(),
+        std::fs::read("tests/signal_sender_none.fst").unwrap()
+    );
+}
+
+#[test]
+fn write_read_shared_writer() {
+    let value = |time: u64, ii: usize| format!("{:08b}", (time * (ii as u64 + 5)) % 256);
+    let declare = |filename: &str| {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let ids = (0..4)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        (writer.finish().unwrap(), ids)
+    };
+
+    let (mut writer, ids) = declare("tests/shared_writer_none.fst");
+    for time in 0..3000u64 {
+        writer.time_change(time).unwrap();
+        for (ii, id) in ids.iter().enumerate() {
+            writer
+                .signal_change(*id, value(time, ii).as_bytes())
+                .unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    let (writer, ids) = declare("tests/shared_writer.fst");
+    let shared = writer.into_shared();
+    let threads = ids
+        .iter()
+        .enumerate()
+        .map(|(ii, &id)| {
+            let mut shared = shared.clone();
+            std::thread::spawn(move || {
+                for time in 0..3000u64 {
+                    shared.time_change(time).unwrap();
+                    shared
+                        .signal_change(id, value(time, ii).as_bytes())
+                        .unwrap();
+                }
+                shared.finish().unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    // the last handle to go away finishes the file
+    drop(shared);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(
+        std::fs::read("tests/shared_writer.fst").unwrap(),
+        std::fs::read("tests/shared_writer_none.fst").unwrap()
+    );
+}
+
+#[test]
+fn write_read_canonical_order() {
+    let value = |time: u64, ii: usize| format!("{:08b}", (time * (ii as u64 + 1)) % 256);
+    let declare = |filename: &str, canonical_order: bool| {
+        let config = FstWriterConfig {
+            canonical_order,
+            ..Default::default()
+        };
+        let mut writer = open_fst(filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        let ids = (0..4)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        (writer.finish().unwrap(), ids)
+    };
+
+    let (mut writer, ids) = declare("tests/canonical_order_none.fst", false);
+    for time in 0..1000u64 {
+        writer.time_change(time).unwrap();
+        for (ii, id) in ids.iter().enumerate() {
+            writer
+                .signal_change(*id, value(time, ii).as_bytes())
+                .unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    // every thread changes two signals, in an order that depends on the time
+    let (writer, ids) = declare("tests/canonical_order.fst", true);
+    let (sender, handle) = writer.into_sender();
+    let threads = [0, 2]
+        .into_iter()
+        .map(|first| {
+            let mut sender = sender.clone();
+            let ids = ids.clone();
+            std::thread::spawn(move || {
+                for time in 0..1000u64 {
+                    sender.time_change(time).unwrap();
+                    let mut signals = [first, first + 1];
+                    if time % 2 == 1 {
+                        signals.reverse();
+                    }
+                    for ii in signals {
+                        sender
+                            .signal_change(ids[ii], value(time, ii).as_bytes())
+                            .unwrap();
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    handle.join().unwrap().unwrap();
+    assert_eq!(
+        std::fs::read("tests/canonical_order.fst").unwrap(),
+        std::fs::read("tests/canonical_order_none.fst").unwrap()
+    );
+}
+
+#[test]
+fn write_read_in_other_thread() {
+    let filename = "tests/other_thread.fst";
+    let mut writer = open_fst(filename, &test_info()).unwrap();
+    let a = writer.var_builder("a").width(4).build().unwrap();
+    // the header writer as well as the body writer can be moved to another thread
+    let writer = std::thread::spawn(move || writer.finish().unwrap())
+        .join()
+        .unwrap();
+    std::thread::spawn(move || {
+        let mut writer = writer;
+        for time in 0..10u64 {
+            writer.time_change(time).unwrap();
+            writer
+                .signal_change(a, format!("{time:04b}").as_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+    })
+    .join()
+    .unwrap();
+
+    let wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 10);
+}
+
+#[test]
+fn write_read_parts() {
+    let value = |time: u64, ii: usize| format!("{:08b}", (time / (ii as u64 + 1)) % 256);
+    let declare = |filename: &str| {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let ids = (0..4)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        (writer.finish().unwrap(), ids)
+    };
+    let read = |filename: &str| {
+        let mut wave = wellen::simple::read(filename).unwrap();
+        let refs = wave
+            .hierarchy()
+            .iter_vars()
+            .map(|v| v.signal_ref())
+            .collect::<Vec<_>>();
+        wave.load_signals(&refs);
+        refs.iter()
+            .map(|r| signal_values_to_string(wave.get_signal(*r).unwrap(), wave.time_table()))
+            .collect::<Vec<_>>()
+    };
+
+    let (mut writer, ids) = declare("tests/parts_none.fst");
+    for time in 0..300u64 {
+        writer.time_change(time).unwrap();
+        for (ii, id) in ids.iter().enumerate() {
+            writer
+                .signal_change(*id, value(time, ii).as_bytes())
+                .unwrap();
+        }
+    }
+    writer.finish().unwrap();
+
+    // every thread converts its own time range
+    let (mut writer, ids) = declare("tests/parts.fst");
+    let threads = [0..100u64, 100..200, 200..300]
+        .into_iter()
+        .map(|times| {
+            let mut part = writer.part().unwrap();
+            let ids = ids.clone();
+            std::thread::spawn(move || {
+                for time in times {
+                    part.time_change(time).unwrap();
+                    for (ii, id) in ids.iter().enumerate() {
+                        part.signal_change(*id, value(time, ii).as_bytes()).unwrap();
+                    }
+                    if time % 40 == 0 {
+                        part.flush().unwrap();
+                    }
+                }
+                part
+            })
+        })
+        .collect::<Vec<_>>();
+    let parts = threads
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .collect::<Vec<_>>();
+    let last = writer.part().unwrap();
+    for part in parts {
+        writer.append_part(part).unwrap();
+    }
+    // parts that go back in time are rejected
+    let mut late = writer.part().unwrap();
+    late.time_change(5).unwrap();
+    assert!(matches!(
+        writer.append_part(late),
+        Err(FstWriteError::TimeDecrease(299, 5))
+    ));
+    // empty parts are ignored
+    writer.append_part(last).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(read("tests/parts.fst"), read("tests/parts_none.fst"));
+}
+
+#[test]
+fn write_read_reorder() {
+    let value = |time: u64, ii: usize| format!("{:08b}", (time * (ii as u64 + 1)) % 256);
+    let write = |filename: &str, reorder_depth: Option<usize>, times: &[u64]| {
+        let config = FstWriterConfig {
+            reorder_depth,
+            ..Default::default()
+        };
+        let mut writer = open_fst(filename, &test_info())
+            .unwrap()
+            .with_config(config)
+            .unwrap();
+        let ids = (0..3)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let mut writer = writer.finish().unwrap();
+        for &time in times {
+            writer.time_change(time).unwrap();
+            for (ii, id) in ids.iter().enumerate() {
+                writer
+                    .signal_change(*id, value(time, ii).as_bytes())
+                    .unwrap();
+            }
+        }
+        if reorder_depth.is_some() {
+            // too far in the past
+            assert!(matches!(
+                writer.time_change(1),
+                Err(FstWriteError::TimeDecrease(_, 1))
+            ));
+        }
+        writer.finish().unwrap();
+    };
+    let in_order = (0..100u64).collect::<Vec<_>>();
+    // every group of three time steps arrives in reverse order
+    let out_of_order = in_order
+        .chunks(3)
+        .flat_map(|c| c.iter().rev().copied())
+        .collect::<Vec<_>>();
+    write("tests/reorder_none.fst", None, &in_order);
+    write("tests/reorder.fst", Some(3), &out_of_order);
+    assert_eq!(
+        std::fs::read("tests/reorder.fst").unwrap(),
+        std::fs::read("tests/reorder_none.fst").unwrap()
+    );
+}
+
+#[test]
+fn write_read_signal_streams() {
+    let declare = |filename: &str| {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let ids = (0..3)
+            .map(|ii| {
+                writer
+                    .var_builder(format!("s{ii}"))
+                    .width(8)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        (writer.finish().unwrap(), ids)
+    };
+    // signal ii changes every ii + 1 time steps
+    let changes = |ii: usize| {
+        (0..100u64)
+            .filter(move |t| t % (ii as u64 + 1) == 0)
+            .map(move |t| (t, format!("{:08b}", t / (ii as u64 + 1))))
+    };
+
+    let (mut writer, ids) = declare("tests/signal_streams_none.fst");
+    for time in 0..100u64 {
+        writer.time_change(time).unwrap();
+        for (ii, id) in ids.iter().enumerate() {
+            if let Some((_, value)) = changes(ii).find(|(t, _)| *t == time) {
+                writer.signal_change(*id, value.as_bytes()).unwrap();
+            }
+        }
+    }
+    writer.finish().unwrap();
+
+    let (mut writer, ids) = declare("tests/signal_streams.fst");
+    writer
+        .write_signal_streams(ids.iter().enumerate().map(|(ii, id)| (*id, changes(ii))))
+        .unwrap();
+    writer.finish().unwrap();
+    assert_eq!(
+        std::fs::read("tests/signal_streams.fst").unwrap(),
+        std::fs::read("tests/signal_streams_none.fst").unwrap()
+    );
+
+    // streams need to be sorted by time
+    let (mut writer, ids) = declare("tests/signal_streams_unsorted.fst");
+    let unsorted = [(20u64, "00000000"), (15, "00000001")];
+    assert!(matches!(
+        writer.write_signal_streams([(ids[0], unsorted)]),
+        Err(FstWriteError::TimeDecrease(20, 15))
+    ));
+}
+
+#[test]
+fn write_read_append_sorted() {
+    let filename = "tests/append_sorted.fst";
+    let config = FstWriterConfig {
+        auto_flush_size: Some(200),
+        ..Default::default()
+    };
+    let mut writer = open_fst(filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    let a = writer.var_builder("a").width(8).build().unwrap();
+    let b = writer.var_builder("b").build().unwrap();
+    let mut writer = writer.finish().unwrap();
+    let values = (0..256u64)
+        .map(|t| format!("{t:08b}").into_bytes())
+        .collect::<Vec<_>>();
+    let changes = (0..256u64).flat_map(|t| {
+        let a_change = (t, a, values[t as usize].as_slice());
+        let b_change = (t, b, if t % 2 == 0 { b"0" } else { b"1" }.as_slice());
+        [a_change, b_change]
+    });
+    writer.append_sorted(changes).unwrap();
+    assert!(matches!(
+        writer.append_sorted([(10, a, b"00000000")]),
+        Err(FstWriteError::TimeDecrease(255, 10))
+    ));
+    writer.finish().unwrap();
+
+    assert!(count_value_change_blocks(filename) > 2);
+    let mut wave = wellen::simple::read(filename).unwrap();
+    assert_eq!(wave.time_table().len(), 256);
+    let refs = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|v| v.signal_ref())
+        .collect::<Vec<_>>();
+    wave.load_signals(&refs);
+    let a_values = wave
+        .get_signal(refs[0])
+        .unwrap()
+        .iter_changes()
+        .map(|(_, v)| v.to_bit_string().unwrap().into_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(a_values, values);
+    assert_eq!(
+        wave.get_signal(refs[1]).unwrap().iter_changes().count(),
+        256
+    );
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[test]
+fn write_read_uring() {
+    fn record<W: std::io::Write + std::io::Seek>(writer: FstHeaderWriter<W>) {
+        let config = FstWriterConfig {
+            auto_flush_size: Some(4096),
+            ..Default::default()
+        };
+        let mut writer = writer.with_config(config).unwrap();
+        let a = writer.var_builder("a").width(32).build().unwrap();
+        let b = writer.var_builder("b").build().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for time in 0..5000u64 {
+            writer.time_change(time).unwrap();
+            let value = format!("{:032b}", time.wrapping_mul(0x9e37_79b9) as u32);
+            writer.signal_change(a, value.as_bytes()).unwrap();
+            writer.signal_change(b, &[b'0' + (time % 2) as u8]).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+    // small writes, so that several of them are in flight
+    let options = FstOpenOptions {
+        buffer_capacity: Some(512),
+        ..Default::default()
+    };
+    let expected = "tests/uring_expected.fst";
+    record(open_fst_with_options(expected, &test_info(), &options).unwrap());
+    let filename = "tests/uring.fst";
+    match open_fst_uring(filename, &test_info(), &options) {
+        Ok(writer) => record(writer),
+        // io_uring might be disabled, e.g., in containers
+        Err(FstWriteError::Io(e)) => {
+            eprintln!("io_uring is not available: {e}");
+            return;
+        }
+        Err(e) => panic!("{e:?}"),
+    }
+    assert!(count_value_change_blocks(filename) > 1);
+    assert_eq!(
+        std::fs::read(filename).unwrap(),
+        std::fs::read(expected).unwrap()
+    );
+}
+
+/// GTKWave's converters, which are the de-facto reference for the format.
+const GTKWAVE_TOOLS: [&str; 2] = ["fst2vcd", "vcd2fst"];
+
+fn gtkwave_tools_available() -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    GTKWAVE_TOOLS
+        .iter()
+        .all(|tool| std::env::split_paths(&path).any(|dir| dir.join(tool).is_file()))
+}
+
+fn run_gtkwave_tool(tool: &str, args: &[&str]) {
+    let output = std::process::Command::new(tool)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "`{tool} {}` failed:\n{}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Converts the file with `fst2vcd` and returns the name of the VCD.
+fn fst2vcd(filename: &str) -> String {
+    let vcd = filename.replace(".fst", "_fst2vcd.vcd");
+    run_gtkwave_tool("fst2vcd", &["-f", filename, "-o", &vcd]);
+    vcd
+}
+
+/// Writes a file with `config` and, from the same values, a VCD. GTKWave's `fst2vcd` needs
+/// to read the same value changes from our file as wellen reads from the VCD.
+fn gtkwave_round_trip(name: &str, config: FstWriterConfig) {
+    let filename = format!("tests/gtkwave_{name}.fst");
+    let mut writer = open_fst(&filename, &test_info())
+        .unwrap()
+        .with_config(config)
+        .unwrap();
+    writer.scope("top", "Top", FstScopeType::Module).unwrap();
+    let clk = writer.var_builder("clk").build().unwrap();
+    let data = writer
+        .var_builder("data")
+        .width(8)
+        .range(7, 0)
+        .build()
+        .unwrap();
+    writer.scope("sub", "Sub", FstScopeType::Module).unwrap();
+    let r = writer
+        .var_builder("r")
+        .signal_type(FstSignalType::real())
+        .typ(FstVarType::Real)
+        .build()
+        .unwrap();
+    writer.var_builder("clk").alias(clk).build().unwrap();
+    writer.up_scope().unwrap();
+    writer.up_scope().unwrap();
+    let mut vcd = concat!(
+        "$timescale 1s $end\n",
+        "$scope module top $end\n",
+        "$var wire 1 ! clk $end\n",
+        "$var wire 8 \" data [7:0] $end\n",
+        "$scope module sub $end\n",
+        "$var real 64 # r $end\n",
+        "$var wire 1 ! clk $end\n",
+        "$upscope $end\n",
+        "$upscope $end\n",
+        "$enddefinitions $end\n",
+    )
+    .to_string();
+    let mut writer = writer.finish().unwrap();
+    for time in 0..1000u64 {
+        writer.time_change(time * 5).unwrap();
+        writeln!(vcd, "#{}", time * 5).unwrap();
+        writer
+            .signal_change(clk, &[b'0' + (time % 2) as u8])
+            .unwrap();
+        writeln!(vcd, "{}!", time % 2).unwrap();
+        let value = match time % 50 {
+            10 => "xxxxxxxx".to_string(),
+            20 => "zzzz0101".to_string(),
+            _ => format!("{:08b}", time / 3 % 256),
+        };
+        writer.signal_change(data, value.as_bytes()).unwrap();
+        writeln!(vcd, "b{value} \"").unwrap();
+        if time % 7 == 0 {
+            let value = time as f64 / 8.0;
+            writer.signal_change(r, &value.to_le_bytes()).unwrap();
+            writeln!(vcd, "r{value} #").unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    let expected = filename.replace(".fst", "_expected.vcd");
+    std::fs::write(&expected, vcd).unwrap();
+
+    let expected = changes_by_var(&expected);
+    assert_eq!(expected.len(), 4);
+    assert_eq!(changes_by_var(&fst2vcd(&filename)), expected, "{filename}");
+}
+
+#[test]
+#[ignore = "needs fst2vcd from GTKWave, run with `cargo test -- --ignored`"]
+fn gtkwave_golden_files() {
+    if !gtkwave_tools_available() {
+        eprintln!(
+            "skipping, {} are not on the PATH",
+            GTKWAVE_TOOLS.join(" and ")
+        );
+        return;
+    }
+    gtkwave_round_trip("default", FstWriterConfig::default());
+    for (name, pack_type) in [("zlib", FstPackType::Zlib), ("fastlz", FstPackType::FastLz)] {
+        let config = FstWriterConfig {
+            pack_type,
+            auto_flush_size: Some(1000),
+            ..Default::default()
+        };
+        gtkwave_round_trip(name, config);
+    }
+    let config = FstWriterConfig {
+        repack_on_close: true,
+        ..Default::default()
+    };
+    gtkwave_round_trip("repack", config);
+}
+
+/// A small waveform that is written by fst-writer and, through a VCD, by fstapi.
+#[cfg(feature = "testing")]
+struct CanonicalWaveform {
+    name: &'static str,
+    /// name, width and the var it aliases, reals have no width
+    vars: &'static [(&'static str, Option<u32>, Option<usize>)],
+    /// time, var and value
+    changes: &'static [(u64, usize, &'static str)],
+}
+
+#[cfg(feature = "testing")]
+impl CanonicalWaveform {
+    fn write_fst(&self, filename: &str) {
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        writer.scope("top", "", FstScopeType::Module).unwrap();
+        let mut ids: Vec<FstSignalId> = vec![];
+        for &(name, width, alias) in self.vars {
+            let mut var = writer.var_builder(name);
+            var = match width {
+                Some(width) => var.width(width),
+                None => var.signal_type(FstSignalType::real()).typ(FstVarType::Real),
+            };
+            if let Some(alias) = alias {
+                var = var.alias(ids[alias]);
+            }
+            ids.push(var.build().unwrap());
+        }
+        writer.up_scope().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for &(time, var, value) in self.changes {
+            writer.time_change(time).unwrap();
+            match self.vars[var].1 {
+                Some(_) => writer.signal_change(ids[var], value.as_bytes()).unwrap(),
+                None => {
+                    let value: f64 = value.parse().unwrap();
+                    writer
+                        .signal_change(ids[var], &value.to_le_bytes())
+                        .unwrap()
+                }
+            }
+        }
+        writer.finish().unwrap();
+    }
+
+    fn write_vcd(&self, filename: &str) {
+        let code = |var: usize| {
+            let var = self.vars[var].2.unwrap_or(var);
+            char::from(b'!' + var as u8)
+        };
+        let mut vcd = "$timescale 1s $end\n$scope module top $end\n".to_string();
+        for (index, &(name, width, _)) in self.vars.iter().enumerate() {
+            let (tpe, width) = match width {
+                Some(width) => ("wire", width),
+                None => ("real", 64),
+            };
+            writeln!(vcd, "$var {tpe} {width} {} {name} $end", code(index)).unwrap();
+        }
+        vcd += "$upscope $end\n$enddefinitions $end\n";
+        let mut prev_time = None;
+        for &(time, var, value) in self.changes {
+            if prev_time != Some(time) {
+                writeln!(vcd, "#{time}").unwrap();
+                prev_time = Some(time);
+            }
+            match self.vars[var].1 {
+                Some(1) => writeln!(vcd, "{value}{}", code(var)),
+                Some(_) => writeln!(vcd, "b{value} {}", code(var)),
+                None => writeln!(vcd, "r{value} {}", code(var)),
+            }
+            .unwrap();
+        }
+        std::fs::write(filename, vcd).unwrap();
+    }
+}
+
+#[cfg(feature = "testing")]
+const CANONICAL_WAVEFORMS: [CanonicalWaveform; 4] = [
+    CanonicalWaveform {
+        name: "clock",
+        vars: &[("clk", Some(1), None)],
+        changes: &[(0, 0, "0"), (5, 0, "1"), (10, 0, "0"), (15, 0, "1")],
+    },
+    CanonicalWaveform {
+        name: "vectors",
+        vars: &[
+            ("a", Some(4), None),
+            ("b", Some(8), None),
+            ("a_alias", Some(4), Some(0)),
+        ],
+        changes: &[
+            (0, 0, "0000"),
+            (0, 1, "xxxxxxxx"),
+            (3, 0, "1010"),
+            (3, 1, "zzzz0101"),
+            (7, 1, "11110000"),
+            (100, 0, "x1x0"),
+        ],
+    },
+    CanonicalWaveform {
+        name: "real",
+        vars: &[("r", None, None), ("en", Some(1), None)],
+        changes: &[
+            (0, 0, "0"),
+            (0, 1, "0"),
+            (2, 0, "1.5"),
+            (4, 1, "1"),
+            (9, 0, "-0.25"),
+        ],
+    },
+    CanonicalWaveform {
+        name: "repeated_values",
+        vars: &[("a", Some(1), None), ("b", Some(2), None)],
+        changes: &[
+            (0, 0, "1"),
+            (0, 1, "00"),
+            (1, 0, "1"),
+            (2, 1, "00"),
+            (3, 1, "01"),
+        ],
+    },
+];
+
+/// The only known difference to fstapi: the changes at the start of a block are part of our
+/// frame, while fstapi also records them at a first time table entry for the start time.
+#[cfg(feature = "testing")]
+fn without_start_time_entry(block: testing::FstBlock) -> testing::FstBlock {
+    match block {
+        testing::FstBlock::ValueChanges {
+            start_time,
+            end_time,
+            mut time_table,
+        } => {
+            if time_table.first() == Some(&start_time) {
+                time_table.remove(0);
+            }
+            testing::FstBlock::ValueChanges {
+                start_time,
+                end_time,
+                time_table,
+            }
+        }
+        other => other,
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[ignore = "needs vcd2fst from GTKWave, run with `cargo test --features testing -- --ignored`"]
+fn fstapi_differential() {
+    if !gtkwave_tools_available() {
+        eprintln!(
+            "skipping, {} are not on the PATH",
+            GTKWAVE_TOOLS.join(" and ")
+        );
+        return;
+    }
+    let mut differences = vec![];
+    for waveform in CANONICAL_WAVEFORMS.iter() {
+        let ours = format!("tests/fstapi_{}.fst", waveform.name);
+        waveform.write_fst(&ours);
+        let vcd = format!("tests/fstapi_{}.vcd", waveform.name);
+        waveform.write_vcd(&vcd);
+        let theirs = format!("tests/fstapi_{}_reference.fst", waveform.name);
+        run_gtkwave_tool("vcd2fst", &["-v", &vcd, "-f", &theirs]);
+        let theirs = testing::read_blocks(&theirs)
+            .unwrap()
+            .into_iter()
+            .map(without_start_time_entry)
+            .collect();
+        let ours = testing::read_blocks(&ours).unwrap();
+        for difference in testing::compare_block_lists(theirs, ours) {
+            differences.push(format!("{}: {difference}", waveform.name));
+        }
+    }
+    assert!(differences.is_empty(), "{}", differences.join("\n"));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn read_blocks() {
+    let filename = "tests/read_blocks.fst";
+    CANONICAL_WAVEFORMS[1].write_fst(filename);
+    let blocks = testing::read_blocks(filename).unwrap();
+    // the hierarchy and geometry are written before the value changes, unlike in fstapi
+    assert_eq!(blocks.len(), 4);
+    assert_eq!(
+        blocks[1],
+        testing::FstBlock::Hierarchy { scopes: 1, vars: 3 }
+    );
+    assert_eq!(
+        blocks[2],
+        testing::FstBlock::Geometry {
+            signals: vec![4, 8]
+        }
+    );
+    // changes at time 0 are part of the frame, which the time table does not list
+    assert_eq!(
+        blocks[3],
+        testing::FstBlock::ValueChanges {
+            start_time: 0,
+            end_time: 100,
+            time_table: vec![3, 7, 100],
+        }
+    );
+    assert_eq!(
+        testing::compare_blocks(filename, filename).unwrap(),
+        Vec::<String>::new()
+    );
+    // fstapi lists time 0 as well, which is the only difference that is left out
+    let mut fstapi = blocks.clone();
+    fstapi[3] = testing::FstBlock::ValueChanges {
+        start_time: 0,
+        end_time: 100,
+        time_table: vec![0, 3, 7, 100],
+    };
+    assert!(!testing::compare_block_lists(fstapi.clone(), blocks.clone()).is_empty());
+    let fstapi = fstapi.into_iter().map(without_start_time_entry).collect();
+    assert_eq!(
+        testing::compare_block_lists(fstapi, blocks),
+        Vec::<String>::new()
+    );
+}
+
+/// Hierarchy of a [`FuzzModel`], everything ends up inside of a `top` scope.
+#[derive(Debug, Clone, arbitrary::Arbitrary)]
+enum FuzzItem {
+    Scope,
+    UpScope,
+    /// bit-vector with 1 to 512 bits
+    Var(u16),
+    Real,
+    /// alias of an earlier signal
+    Alias(u16),
+}
+
+#[derive(Debug, Clone, arbitrary::Arbitrary)]
+enum FuzzStep {
+    Time(u8),
+    /// shifted up by 16 bits
+    TimeJump(u32),
+    /// a value derived from the seed
+    Change {
+        signal: u16,
+        seed: u64,
+    },
+    Flush,
+}
+
+/// Waveform built from arbitrary data, which covers the hierarchy, aliases, wide and real
+/// signals, explicit flushes and large time jumps.
+#[derive(Debug, Clone)]
+struct FuzzModel {
+    items: Vec<FuzzItem>,
+    steps: Vec<FuzzStep>,
+}
+
+impl<'a> arbitrary::Arbitrary<'a> for FuzzModel {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        // the default length of a `Vec` is too short to build an interesting hierarchy
+        let num_items = u.int_in_range(1..=64)?;
+        let items = (0..num_items)
+            .map(|_| u.arbitrary())
+            .collect::<arbitrary::Result<_>>()?;
+        let num_steps = u.int_in_range(0..=1024)?;
+        let steps = (0..num_steps)
+            .map(|_| u.arbitrary())
+            .collect::<arbitrary::Result<_>>()?;
+        Ok(Self { items, steps })
+    }
+}
+
+/// A declared var of a [`FuzzModel`].
+struct FuzzVar {
+    scopes: Vec<String>,
+    name: String,
+    signal: usize,
+}
+
+impl FuzzModel {
+    /// Vars and the width of every signal, zero for reals.
+    fn hierarchy(&self) -> (Vec<FuzzVar>, Vec<u32>) {
+        let (mut vars, mut widths) = (vec![], vec![]);
+        let mut scopes = vec!["top".to_string()];
+        let mut scope_count = 0;
+        for item in self.items.iter() {
+            let signal = match *item {
+                FuzzItem::Scope => {
+                    scopes.push(format!("s{scope_count}"));
+                    scope_count += 1;
+                    continue;
+                }
+                FuzzItem::UpScope => {
+                    if scopes.len() > 1 {
+                        scopes.pop();
+                    }
+                    continue;
+                }
+                FuzzItem::Alias(alias) if !widths.is_empty() => alias as usize % widths.len(),
+                FuzzItem::Var(width) => {
+                    widths.push(1 + width as u32 % 512);
+                    widths.len() - 1
+                }
+                FuzzItem::Real => {
+                    widths.push(0);
+                    widths.len() - 1
+                }
+                FuzzItem::Alias(_) => {
+                    widths.push(1);
+                    widths.len() - 1
+                }
+            };
+            let name = format!("v{}", vars.len());
+            let scopes = scopes.clone();
+            vars.push(FuzzVar {
+                scopes,
+                name,
+                signal,
+            });
+        }
+        if vars.is_empty() {
+            widths.push(1);
+            let (scopes, name) = (vec!["top".to_string()], "v0".to_string());
+            vars.push(FuzzVar {
+                scopes,
+                name,
+                signal: 0,
+            });
+        }
+        (vars, widths)
+    }
+
+    /// Changes of every time step, starting with initial values at time 0. Later changes of
+    /// a signal in the same time step replace earlier ones, `None` stands for a flush, which
+    /// happens right before the next time step. fst-reader cannot read the time table if the
+    /// first block has no time step besides the initial values, thus there always is one.
+    fn time_steps(&self, widths: &[u32]) -> Vec<(u64, Option<BTreeMap<usize, String>>)> {
+        let initial: BTreeMap<_, _> = widths
+            .iter()
+            .enumerate()
+            .map(|(signal, &width)| (signal, fuzz_value(width, 0)))
+            .collect();
+        let mut steps = vec![(0, Some(initial))];
+        let mut time = 0u64;
+        let mut flush = false;
+        for step in self.steps.iter() {
+            let delta = match *step {
+                FuzzStep::Time(delta) => delta as u64,
+                FuzzStep::TimeJump(delta) => (delta as u64) << 16,
+                FuzzStep::Change { signal, seed } => {
+                    let signal = signal as usize % widths.len();
+                    if let Some((_, Some(changes))) = steps.last_mut() {
+                        changes.insert(signal, fuzz_value(widths[signal], seed));
+                    }
+                    continue;
+                }
+                FuzzStep::Flush => {
+                    flush = time > 0;
+                    continue;
+                }
+            };
+            if delta > 0 {
+                if std::mem::take(&mut flush) {
+                    steps.push((time, None));
+                }
+                time += delta;
+                steps.push((time, Some(BTreeMap::new())));
+            }
+        }
+        if time == 0 {
+            steps.push((1, Some(BTreeMap::new())));
+        }
+        steps
+    }
+
+    fn write_fst(&self, filename: &str) {
+        let (vars, widths) = self.hierarchy();
+        let mut writer = open_fst(filename, &test_info()).unwrap();
+        let mut ids: Vec<FstSignalId> = vec![];
+        let mut open: Vec<String> = vec![];
+        for var in vars.iter() {
+            let common = open
+                .iter()
+                .zip(var.scopes.iter())
+                .take_while(|(a, b)| a == b);
+            let common = common.count();
+            while open.len() > common {
+                writer.up_scope().unwrap();
+                open.pop();
+            }
+            for scope in var.scopes[common..].iter() {
+                writer.scope(scope, "", FstScopeType::Module).unwrap();
+                open.push(scope.clone());
+            }
+            let builder = writer.var_builder(&var.name);
+            let builder = match widths[var.signal] {
+                0 => builder
+                    .signal_type(FstSignalType::real())
+                    .typ(FstVarType::Real),
+                width => builder.width(width),
+            };
+            match ids.get(var.signal) {
+                Some(&alias) => {
+                    builder.alias(alias).build().unwrap();
+                }
+                None => ids.push(builder.build().unwrap()),
+            }
+        }
+        writer.up_scope_all().unwrap();
+        let mut writer = writer.finish().unwrap();
+        for (time, changes) in self.time_steps(&widths) {
+            let Some(changes) = changes else {
+                writer.flush().unwrap();
+                continue;
+            };
+            writer.time_change(time).unwrap();
+            for (signal, value) in changes {
+                match widths[signal] {
+                    0 => {
+                        let value: f64 = value.parse().unwrap();
+                        writer.signal_change(ids[signal], &value.to_le_bytes())
+                    }
+                    _ => writer.signal_change(ids[signal], value.as_bytes()),
+                }
+                .unwrap();
+            }
+        }
+        writer.finish().unwrap();
+    }
+
+    fn write_vcd(&self, filename: &str) {
+        let (vars, widths) = self.hierarchy();
+        // printable identifiers in base 94
+        let code = |mut signal: usize| {
+            let mut code = String::new();
+            loop {
+                code.push(char::from(b'!' + (signal % 94) as u8));
+                signal /= 94;
+                if signal == 0 {
+                    break code;
+                }
+            }
+        };
+        let mut vcd = "$timescale 1s $end\n".to_string();
+        let mut open: Vec<String> = vec![];
+        for var in vars.iter() {
+            let common = open
+                .iter()
+                .zip(var.scopes.iter())
+                .take_while(|(a, b)| a == b);
+            let common = common.count();
+            while open.len() > common {
+                vcd += "$upscope $end\n";
+                open.pop();
+            }
+            for scope in var.scopes[common..].iter() {
+                writeln!(vcd, "$scope module {scope} $end").unwrap();
+                open.push(scope.clone());
+            }
+            let (tpe, width) = match widths[var.signal] {
+                0 => ("real", 64),
+                width => ("wire", width),
+            };
+            let (id, name) = (code(var.signal), &var.name);
+            writeln!(vcd, "$var {tpe} {width} {id} {name} $end").unwrap();
+        }
+        vcd += &"$upscope $end\n".repeat(open.len());
+        vcd += "$enddefinitions $end\n";
+        for (time, changes) in self.time_steps(&widths) {
+            let Some(changes) = changes else { continue };
+            writeln!(vcd, "#{time}").unwrap();
+            for (signal, value) in changes {
+                let id = code(signal);
+                match widths[signal] {
+                    0 => writeln!(vcd, "r{value} {id}"),
+                    1 => writeln!(vcd, "{value}{id}"),
+                    _ => writeln!(vcd, "b{value} {id}"),
+                }
+                .unwrap();
+            }
+        }
+        std::fs::write(filename, vcd).unwrap();
+    }
+}
+
+/// Bits that are mostly 0 and 1 with the occasional x or z, or a real for a width of zero.
+fn fuzz_value(width: u32, seed: u64) -> String {
+    if width == 0 {
+        return ((seed as i32) as f64 / 16.0).to_string();
+    }
+    let mut state = seed;
+    (0..width)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            match state >> 60 {
+                0 => 'x',
+                1 => 'z',
+                n if n % 2 == 0 => '0',
+                _ => '1',
+            }
+        })
+        .collect()
+}
+
+/// Value changes of every var by its full name, as read by wellen, without repeated values.
+fn changes_by_var(filename: &str) -> BTreeMap<String, Vec<(Time, String)>> {
+    let mut wave = wellen::simple::read(filename).unwrap();
+    let vars: Vec<_> = wave
+        .hierarchy()
+        .iter_vars()
+        .map(|var| (var.full_name(wave.hierarchy()), var.signal_ref()))
+        .collect();
+    let refs: Vec<_> = vars.iter().map(|(_, signal)| *signal).collect();
+    wave.load_signals(&refs);
+    vars.into_iter()
+        .map(|(name, signal)| {
+            let mut changes: Vec<(Time, String)> = vec![];
+            for (time, value) in wave.get_signal(signal).unwrap().iter_changes() {
+                let value = match value {
+                    wellen::SignalValue::Real(value) => value.to_string(),
+                    wellen::SignalValue::String(value) => value.to_string(),
+                    other => other.to_bit_string().unwrap(),
+                };
+                if changes.last().is_none_or(|(_, last)| *last != value) {
+                    changes.push((wave.time_table()[time as usize], value));
+                }
+            }
+            (name, changes)
+        })
+        .collect()
+}
+
+/// Writes the model as FST and as VCD and checks that wellen reads the same value changes.
+fn check_fuzz_model(model: &FuzzModel, name: &str) {
+    let (fst, vcd) = (format!("tests/{name}.fst"), format!("tests/{name}.vcd"));
+    model.write_fst(&fst);
+    model.write_vcd(&vcd);
+    assert_eq!(changes_by_var(&fst), changes_by_var(&vcd));
+}
+
+use std::collections::BTreeMap;
+
+proptest::proptest! {
+    #[test]
+    fn fuzz_model(bytes in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..2048)) {
+        use arbitrary::Arbitrary;
+        let mut input = arbitrary::Unstructured::new(&bytes);
+        if let Ok(model) = FuzzModel::arbitrary(&mut input) {
+            check_fuzz_model(&model, "fuzz_model");
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[ignore = "needs vcd2fst from GTKWave, run with `cargo test --features testing -- --ignored`"]
+fn fuzz_model_fstapi_differential() {
+    use arbitrary::Arbitrary;
+    if !gtkwave_tools_available() {
+        eprintln!(
+            "skipping, {} are not on the PATH",
+            GTKWAVE_TOOLS.join(" and ")
+        );
+        return;
+    }
+    // deterministic pseudo random inputs
+    let mut state = 1u64;
+    for _ in 0..32 {
+        let bytes: Vec<u8> = (0..2048)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect();
+        let model = FuzzModel::arbitrary(&mut arbitrary::Unstructured::new(&bytes)).unwrap();
+        check_fuzz_model(&model, "fuzz_model_fstapi");
+        let reference = "tests/fuzz_model_fstapi_reference.fst";
+        run_gtkwave_tool(
+            "vcd2fst",
+            &["-v", "tests/fuzz_model_fstapi.vcd", "-f", reference],
+        );
+        testing::assert_fst_equivalent(reference, "tests/fuzz_model_fstapi.fst");
     }
-    out.pop().unwrap();
-    out.pop().unwrap();
-    out
 }